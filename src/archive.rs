@@ -0,0 +1,117 @@
+use chrono::Datelike;
+use serde::Serialize;
+use crate::post::{Post, PostSummary};
+
+/// Version of the `posts.json` format, bumped when fields change meaning or are removed
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// Words of body text in a post summary
+const SUMMARY_WORDS: usize = 40;
+
+#[derive(Debug, Serialize)]
+pub struct Archive<'a> {
+    pub version: u32,
+    pub posts: Vec<ArchiveEntry<'a>>
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArchiveEntry<'a> {
+    pub id: &'a str,
+    pub url: String,
+    /// Only set when the site has a `base_url`
+    pub absolute_url: Option<String>,
+    pub title: &'a str,
+    pub date: String,
+    /// When the post was last changed, if it says
+    pub updated: Option<String>,
+    pub tags: Vec<&'a str>,
+    pub summary: String,
+    pub word_count: usize,
+    pub reading_time: f64
+}
+
+/// The summary from the front matter, or else the first words of the post body, with an
+/// ellipsis if it was cut short.
+pub fn summary(post: &Post) -> String {
+    if let Some(summary) = &post.summary_plain {
+        return summary.clone()
+    }
+    let mut words = post.sections.iter().flat_map(|s| s.text.split_whitespace());
+    let summary: Vec<&str> = words.by_ref().take(SUMMARY_WORDS).collect();
+    let mut summary = summary.join(" ");
+    if words.next().is_some() {
+        summary.push('…');
+    }
+    summary
+}
+
+/// Lists the posts newest first, with tags under their canonical names.
+pub fn archive<'a>(
+    posts: impl IntoIterator<Item=&'a Post>,
+    base_url: Option<&str>,
+    canonical_tag: impl Fn(&'a str) -> &'a str,
+    publish_time: impl Fn(&toml_datetime::Datetime) -> chrono::DateTime<chrono::FixedOffset>
+) -> Archive<'a> {
+    let mut entries: Vec<(chrono::DateTime<chrono::FixedOffset>, ArchiveEntry)> = posts.into_iter().map(|post| {
+        let url = post.url.clone();
+        let mut tags: Vec<&str> = Vec::new();
+        for tag in post.meta.tags.iter().map(|t| canonical_tag(t)) {
+            if !tags.contains(&tag) { tags.push(tag) }
+        }
+        let published = publish_time(&post.meta.date);
+        (published, ArchiveEntry {
+            id: &post.id,
+            absolute_url: base_url.map(|base| crate::jsonld::absolute_url(base, &url)),
+            url,
+            title: &post.title_plain,
+            date: crate::rfc3339(&published),
+            updated: post.meta.updated.as_ref().map(|dt| crate::rfc3339(&publish_time(dt))),
+            tags,
+            summary: summary(post),
+            word_count: post.words,
+            reading_time: post.reading_time
+        })
+    }).collect();
+    entries.sort_by(|(a, a_entry), (b, b_entry)| b.cmp(a).then_with(|| a_entry.id.cmp(b_entry.id)));
+
+    Archive { version: ARCHIVE_VERSION, posts: entries.into_iter().map(|(_, entry)| entry).collect() }
+}
+
+/// The posts of one year, as the `year` and `archive` templates see them
+#[derive(Debug, Serialize)]
+pub struct Year<'a> {
+    pub year: i32,
+    /// URL of the year's page, like `/archive/2024.html`
+    pub url: String,
+    /// Newest first
+    pub posts: Vec<PostSummary<'a>>,
+    /// The same posts by month, newest first
+    pub months: Vec<Month<'a>>
+}
+
+#[derive(Debug, Serialize)]
+pub struct Month<'a> {
+    /// From 1 for January
+    pub month: u32,
+    pub posts: Vec<PostSummary<'a>>
+}
+
+/// Groups `posts` by the year and month of their date, newest first. `base_path` is the path of
+/// `base_url`, put before the URLs of the year pages.
+pub fn years<'a>(mut posts: Vec<PostSummary<'a>>, base_path: &str) -> Vec<Year<'a>> {
+    posts.sort_by(|a, b| crate::dt_toml_to_chrono(&b.meta.date).cmp(&crate::dt_toml_to_chrono(&a.meta.date)).then_with(|| a.id.cmp(b.id)));
+    let mut years: Vec<Year> = Vec::new();
+    for post in posts {
+        let date = crate::dt_toml_to_chrono(&post.meta.date);
+        if years.last().is_none_or(|y| y.year != date.year()) {
+            years.push(Year { year: date.year(), url: format!("{}/archive/{}.html", base_path, date.year()), posts: Vec::new(), months: Vec::new() });
+        }
+        let year = years.last_mut().unwrap();
+        if year.months.last().is_none_or(|m| m.month != date.month()) {
+            year.months.push(Month { month: date.month(), posts: Vec::new() });
+        }
+        year.months.last_mut().unwrap().posts.push(post.clone());
+        year.posts.push(post);
+    }
+    years
+}
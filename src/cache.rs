@@ -0,0 +1,233 @@
+use std::{collections::BTreeMap, hash::{Hash, Hasher}, path::{Path, PathBuf}};
+use serde::{Deserialize, Serialize};
+use crate::{Args, config::SiteConfig, post::{Diagnostic, Post, Section}};
+
+/// Changed when what is cached changes shape, so that older caches aren't read
+const CACHE_VERSION: u32 = 18;
+
+/// What the posts of a build were made from, keyed by markdown file relative to the input directory
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+struct Manifest {
+    /// Hash of what every post depends on, like the config and templates
+    site: u64,
+    posts: BTreeMap<String, PostInputs>
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PostInputs {
+    /// Hash of the markdown file, its creation time and the defaults applied to it
+    pub source: u64,
+    /// Files the post referenced and hashes of their contents, `None` for ones that didn't exist
+    pub files: BTreeMap<PathBuf, Option<u64>>
+}
+
+/// A post as it was built, with the assets it stored
+#[derive(Debug, Deserialize)]
+pub struct CachedPost {
+    pub post: Post,
+    pub sections: Vec<Section>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Errors and warnings printed while building the post, printed again when it is reused
+    pub messages: Vec<String>,
+    pub assets: Vec<CachedAsset>
+}
+
+#[derive(Debug, Serialize)]
+struct CachedPostRef<'a> {
+    post: &'a Post,
+    sections: &'a [Section],
+    diagnostics: &'a [Diagnostic],
+    messages: &'a [String],
+    assets: Vec<CachedAsset>
+}
+
+/// An asset stored by a post, its content kept next to the post in the cache
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CachedAsset {
+    pub ext: String,
+    /// File it was made from
+    pub source: Option<PathBuf>
+}
+
+/// An asset stored while building a post, recorded to store it again when the post is reused
+#[derive(Debug)]
+pub struct RecordedAsset {
+    pub content: Vec<u8>,
+    pub asset: CachedAsset
+}
+
+/// Rendered posts of the last build, reused for posts whose inputs haven't changed since.
+/// Entries are only kept for the posts of the current build.
+#[derive(Debug)]
+pub struct PostCache {
+    dir: PathBuf,
+    previous: Manifest,
+    next: Manifest
+}
+
+/// Hasher for keys that outlive the build, unlike `DefaultHasher` stable across Rust releases.
+pub(crate) fn stable_hasher() -> siphasher::sip::SipHasher13 {
+    siphasher::sip::SipHasher13::new_with_keys(0, 0)
+}
+
+fn hash_bytes(data: &[u8]) -> u64 {
+    let mut hasher = stable_hasher();
+    hasher.write(data);
+    hasher.finish()
+}
+
+/// Hash of the contents of the file at `path`, `None` if it can't be read.
+pub fn file_hash(path: &Path) -> Option<u64> {
+    std::fs::read(path).ok().map(|data| hash_bytes(&data))
+}
+
+/// Hash of the markdown file `file` and what else decides its metadata.
+/// The modification time only counts with `updated_from_mtime`, so a checkout or `touch` keeps the cache.
+pub fn source_hash(file: &Path, defaults: &toml::Table, updated_from_mtime: bool) -> Option<u64> {
+    let mut hasher = stable_hasher();
+    std::fs::read(file).ok()?.hash(&mut hasher);
+    // Posts without a date are dated by their file's creation time, or its modification time without one
+    let created = file.metadata().and_then(|m| m.created()).ok();
+    created.hash(&mut hasher);
+    if updated_from_mtime || created.is_none() {
+        file.metadata().and_then(|m| m.modified()).ok().hash(&mut hasher);
+    }
+    defaults.to_string().hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// Hash of what every post depends on: the generator, the config, the templates and the arguments
+/// that change how posts are built.
+pub fn site_hash(config: &SiteConfig, args: &Args) -> u64 {
+    let mut hasher = stable_hasher();
+    CACHE_VERSION.hash(&mut hasher);
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    // Going through a table sorts the keys of maps like `tag_aliases`
+    toml::Table::try_from(config).map(|table| table.to_string()).unwrap_or_default().hash(&mut hasher);
+    (args.drafts, args.enable_processors, args.strict).hash(&mut hasher);
+    // Dates posts without one
+    std::env::var("SOURCE_DATE_EPOCH").ok().hash(&mut hasher);
+    let templates = walkdir::WalkDir::new(args.in_dir.join("templates")).sort_by_file_name();
+    for entry in templates.into_iter().filter_map(Result::ok).filter(|e| e.file_type().is_file()) {
+        entry.path().hash(&mut hasher);
+        file_hash(entry.path()).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+impl PostCache {
+    /// Opens the cache in `dir` for a build whose posts depend on `site`, see `site_hash`.
+    /// With `force` nothing is reused, but the cache is still written for the next build.
+    pub fn open(dir: PathBuf, site: u64, force: bool) -> PostCache {
+        let previous = std::fs::read(dir.join("manifest.json")).ok()
+            .and_then(|data| serde_json::from_slice::<Manifest>(&data)
+                .inspect_err(|e| log!("warning: ignoring unreadable post cache: {}", e))
+                .ok())
+            .filter(|manifest| !force && manifest.site == site)
+            .unwrap_or_default();
+        PostCache { dir, previous, next: Manifest { site, posts: BTreeMap::new() } }
+    }
+
+    /// Directory of the cached rendering of `source`.
+    fn entry(&self, source: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}", hash_bytes(source.as_bytes())))
+    }
+
+    /// The post built from `source` by the last build and the contents of its assets, if neither
+    /// the markdown, whose hash is `hash`, nor any file it referenced has changed since.
+    pub fn lookup(&self, source: &str, hash: u64) -> Option<(CachedPost, Vec<Vec<u8>>)> {
+        let inputs = self.previous.posts.get(source)?;
+        if inputs.source != hash { return None }
+        if let Some((changed, _)) = inputs.files.iter().find(|(path, hash)| file_hash(path) != **hash) {
+            log!("debug: rebuilding `{}`, `{}` changed", source, changed.display());
+            return None
+        }
+        let entry = self.entry(source);
+        let cached: CachedPost = std::fs::read(entry.join("post.json")).ok()
+            .and_then(|data| serde_json::from_slice(&data)
+                .inspect_err(|e| log!("warning: ignoring unreadable cached post `{}`: {}", source, e))
+                .ok())?;
+        let assets = (0..cached.assets.len())
+            .map(|i| std::fs::read(entry.join(format!("asset-{}", i))).ok())
+            .collect::<Option<Vec<_>>>()?;
+        Some((cached, assets))
+    }
+
+    /// Keeps the entry of `source` from the last build, after it was reused.
+    pub fn keep(&mut self, source: &str) {
+        if let Some(inputs) = self.previous.posts.get(source) {
+            self.next.posts.insert(source.to_string(), inputs.clone());
+        }
+    }
+
+    /// Caches `post`, built from `source` and the files in `inputs`, along with the assets it stored
+    /// and the errors and warnings printed meanwhile.
+    pub fn store(&mut self, source: &str, inputs: PostInputs, post: &Post, assets: &[RecordedAsset], messages: &[String]) {
+        let entry = self.entry(source);
+        let cached = CachedPostRef {
+            post,
+            sections: &post.sections,
+            diagnostics: &post.diagnostics,
+            messages,
+            assets: assets.iter().map(|a| a.asset.clone()).collect()
+        };
+        let result = serde_json::to_vec(&cached).map_err(std::io::Error::other)
+            .and_then(|json| {
+                std::fs::create_dir_all(&entry)?;
+                for (i, asset) in assets.iter().enumerate() {
+                    std::fs::write(entry.join(format!("asset-{}", i)), &asset.content)?;
+                }
+                std::fs::write(entry.join("post.json"), json)
+            });
+        match result {
+            Ok(()) => { self.next.posts.insert(source.to_string(), inputs); },
+            Err(e) => log!("warning: could not cache post `{}`: {}", source, e)
+        }
+    }
+
+    /// Writes the manifest for the next build and removes the entries of posts it didn't keep.
+    pub fn save(self) {
+        let keep: Vec<PathBuf> = self.next.posts.keys().map(|source| self.entry(source)).collect();
+        if let Ok(entries) = self.dir.read_dir() {
+            for entry in entries.filter_map(Result::ok).map(|e| e.path()) {
+                if entry.is_dir() && !keep.contains(&entry) {
+                    let _ = std::fs::remove_dir_all(&entry);
+                }
+            }
+        }
+        let Ok(json) = serde_json::to_vec_pretty(&self.next)
+            .inspect_err(|e| log!("error: could not serialize post cache: {}", e))
+            else { return };
+        if let Err(e) = std::fs::create_dir_all(&self.dir).and_then(|_| std::fs::write(self.dir.join("manifest.json"), json)) {
+            log!("warning: could not write post cache: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_stable_across_builds() {
+        // The cache is reused by later builds, possibly made with another Rust release
+        assert_eq!(hash_bytes(b"hello"), 0xe2e77b41cb4e1f9e);
+    }
+
+    #[test]
+    fn modification_time_counts_only_when_it_dates_updates() {
+        let dir = crate::test_dir("cache-mtime");
+        let file = dir.join("post.md");
+        std::fs::write(&file, "# Post").unwrap();
+        let defaults = toml::Table::new();
+        let (kept, updated) = (source_hash(&file, &defaults, false), source_hash(&file, &defaults, true));
+        let touched = std::time::SystemTime::now() + std::time::Duration::from_secs(3600);
+        std::fs::File::options().write(true).open(&file).unwrap().set_modified(touched).unwrap();
+        // Without a creation time the modification time dates the post, so it has to count
+        if file.metadata().and_then(|m| m.created()).is_ok() {
+            assert_eq!(source_hash(&file, &defaults, false), kept);
+        }
+        assert_ne!(source_hash(&file, &defaults, true), updated);
+    }
+}
@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CodeConfig {
+    /// Soft-wrap long lines of code blocks that don't set `wrap` themselves
+    pub wrap: bool,
+    /// CSS length code blocks scroll beyond, like `"20em"`, unless they set `max_height` themselves
+    pub max_height: Option<String>,
+    /// Warn about lines of code longer than this many characters, 0 to allow any length
+    pub max_columns: usize
+}
+
+/// Whether `value` is a plain CSS length like `20em` or `300px`, safe to put in an attribute.
+fn is_css_length(value: &str) -> bool {
+    let number_end = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let (number, unit) = value.split_at(number_end);
+    !number.is_empty() && number.parse::<f64>().is_ok()
+        && ["em", "rem", "px", "vh", "lh", "ch", "%"].contains(&unit)
+}
+
+/// The opening tag of an element around a code block with the info string `info`, giving its
+/// wrapping and height to CSS, or `None` if neither applies. `</div>` closes it.
+pub fn wrapper(info: &str, config: &CodeConfig) -> Option<String> {
+    let (mut wrap, mut max_height) = (config.wrap, config.max_height.clone());
+    // Words after the language are flags, some with values like `max_height="20em"`
+    for word in info.split_whitespace().skip(1) {
+        let (key, value) = match word.split_once('=') {
+            Some((key, value)) => (key, value.trim_matches('"')),
+            None => (word, "true")
+        };
+        match key {
+            "wrap" => match value {
+                "true" => wrap = true,
+                "false" => wrap = false,
+                _ => log!("warning: code block has `wrap=\"{}\"`, expected `true` or `false`", value)
+            },
+            "max_height" => max_height = Some(value.to_string()),
+            _ => ()
+        }
+    }
+    let max_height = max_height.filter(|height| {
+        let valid = is_css_length(height);
+        if !valid { log!("warning: code block has `max_height=\"{}\"`, expected a CSS length like `20em`", height) }
+        valid
+    });
+    if !wrap && max_height.is_none() { return None }
+
+    let mut classes = vec!["code-block"];
+    let mut attributes = String::new();
+    if wrap {
+        classes.push("code-wrap");
+        attributes.push_str(" data-wrap=\"true\"");
+    }
+    if let Some(height) = &max_height {
+        classes.push("code-scroll");
+        attributes.push_str(&format!(" data-max-height=\"{0}\" style=\"--code-max-height: {0}\"", height));
+    }
+    Some(format!("<div class=\"{}\"{}>", classes.join(" "), attributes))
+}
+
+/// The 1-based numbers and lengths in characters of the lines of `source` longer than `max_columns`.
+pub fn long_lines(source: &str, max_columns: usize) -> Vec<(usize, usize)> {
+    if max_columns == 0 { return Vec::new() }
+    source.lines().enumerate()
+        .map(|(i, line)| (i + 1, line.chars().count()))
+        .filter(|&(_, columns)| columns > max_columns)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrapper_flags() {
+        let config = CodeConfig::default();
+        assert_eq!(wrapper("rust", &config), None);
+        assert_eq!(wrapper("rust wrap", &config).as_deref(), Some("<div class=\"code-block code-wrap\" data-wrap=\"true\">"));
+        assert_eq!(wrapper("rust max_height=\"12.5em\"", &config).as_deref(),
+            Some("<div class=\"code-block code-scroll\" data-max-height=\"12.5em\" style=\"--code-max-height: 12.5em\">"));
+        assert_eq!(wrapper("rust max_height=\"20em\\\"onload\"", &config), None);
+        assert_eq!(wrapper("rust wrap=\"maybe\"", &config), None);
+    }
+
+    #[test]
+    fn wrapper_defaults() {
+        let config = CodeConfig { wrap: true, max_height: Some("10em".into()), max_columns: 0 };
+        assert_eq!(wrapper("rust wrap=\"false\" max_height=\"5em\"", &config).as_deref(),
+            Some("<div class=\"code-block code-scroll\" data-max-height=\"5em\" style=\"--code-max-height: 5em\">"));
+        assert!(wrapper("", &config).is_some_and(|open| open.contains("code-wrap") && open.contains("10em")));
+    }
+
+    #[test]
+    fn long_lines_are_found() {
+        assert_eq!(long_lines("short\nthis line is long\nåäö åäö", 10), [(2, 17)]);
+        assert_eq!(long_lines("this line is long", 0), []);
+    }
+}
@@ -0,0 +1,349 @@
+use std::{collections::BTreeMap, path::Path};
+use serde::{Deserialize, Serialize};
+use crate::output::OutputSink;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompareConfig {
+    /// Regexes of content that changes on every build, like timestamps, replaced before comparing
+    pub masks: Vec<String>,
+    /// Compare text outputs line by line with surrounding whitespace trimmed and blank lines dropped
+    pub normalize_whitespace: bool
+}
+
+/// Outputs shown as text diffs, anything else is compared by size and hash
+const TEXT_EXTENSIONS: &[&str] = &["html", "htm", "css", "js", "json", "xml", "txt", "svg", "md"];
+
+/// Unchanged lines shown around each change
+const CONTEXT_LINES: usize = 3;
+
+/// Largest number of changed line pairs diffed line by line, beyond which the whole changed
+/// region is shown as removed and added
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Serialize)]
+struct DiffLine {
+    /// ` `, `-` or `+`
+    kind: char,
+    text: String
+}
+
+#[derive(Debug, Serialize)]
+struct Hunk {
+    old_start: usize,
+    old_len: usize,
+    new_start: usize,
+    new_len: usize,
+    lines: Vec<DiffLine>
+}
+
+#[derive(Debug, Serialize)]
+struct FileSummary {
+    size: u64,
+    hash: String
+}
+
+#[derive(Debug, Serialize)]
+struct Change {
+    path: String,
+    /// `text` with `hunks`, or `binary` with `old` and `new`
+    kind: &'static str,
+    hunks: Vec<Hunk>,
+    added: usize,
+    removed: usize,
+    old: Option<FileSummary>,
+    new: Option<FileSummary>
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Stats {
+    unchanged: usize,
+    changed_pages: usize,
+    changed_binary: usize,
+    added: usize,
+    removed: usize,
+    lines_added: usize,
+    lines_removed: usize
+}
+
+fn summary(content: &[u8]) -> FileSummary {
+    use std::hash::Hasher;
+    let mut hasher = std::hash::DefaultHasher::new();
+    hasher.write(content);
+    FileSummary { size: content.len() as u64, hash: format!("{:016x}", hasher.finish()) }
+}
+
+/// Output paths of all files under `dir`, with `/` separators.
+fn old_outputs(dir: &Path) -> Vec<String> {
+    walkdir::WalkDir::new(dir).into_iter()
+        .filter_map(|entry| entry.inspect_err(|e| log!("error: compare: cannot read old output: {}", e)).ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| {
+            let relative = entry.path().strip_prefix(dir).ok()?;
+            Some(relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/"))
+        })
+        .collect()
+}
+
+/// Masks and optionally normalizes `text`, returning its lines.
+fn prepare(text: &str, masks: &[regex::Regex], config: &CompareConfig) -> Vec<String> {
+    let mut text = text.to_string();
+    for mask in masks {
+        text = mask.replace_all(&text, "[masked]").into_owned();
+    }
+    if config.normalize_whitespace {
+        text.lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|line| !line.is_empty())
+            .collect()
+    } else {
+        text.lines().map(String::from).collect()
+    }
+}
+
+/// Line edits turning `old` into `new`, from their longest common subsequence.
+fn edits<'a>(old: &'a [String], new: &'a [String]) -> Vec<(char, &'a str)> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (a, b) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut edits: Vec<(char, &str)> = old[..prefix].iter().map(|l| (' ', l.as_str())).collect();
+    if a.len() * b.len() > MAX_DIFF_CELLS {
+        edits.extend(a.iter().map(|l| ('-', l.as_str())));
+        edits.extend(b.iter().map(|l| ('+', l.as_str())));
+    } else {
+        // lengths[i][j] is the length of the common subsequence of a[i..] and b[j..]
+        let width = b.len() + 1;
+        let mut lengths = vec![0u32; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lengths[i * width + j] = if a[i] == b[j] {
+                    lengths[(i + 1) * width + j + 1] + 1
+                } else {
+                    lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                edits.push((' ', &a[i]));
+                i += 1;
+                j += 1;
+            } else if i < a.len() && (j == b.len() || lengths[(i + 1) * width + j] >= lengths[i * width + j + 1]) {
+                edits.push(('-', &a[i]));
+                i += 1;
+            } else {
+                edits.push(('+', &b[j]));
+                j += 1;
+            }
+        }
+    }
+    edits.extend(old[old.len() - suffix..].iter().map(|l| (' ', l.as_str())));
+    edits
+}
+
+/// Groups `edits` into hunks with `CONTEXT_LINES` of unchanged lines around each change.
+fn hunks(edits: &[(char, &str)]) -> Vec<Hunk> {
+    let changed: Vec<usize> = edits.iter().enumerate().filter(|(_, (kind, _))| *kind != ' ').map(|(i, _)| i).collect();
+    let mut hunks = Vec::new();
+    let mut rest = changed.as_slice();
+    while let Some(&first) = rest.first() {
+        // Changes closer than twice the context share a hunk
+        let count = rest.windows(2).take_while(|w| w[1] - w[0] <= 2 * CONTEXT_LINES + 1).count() + 1;
+        let last = rest[count - 1];
+        rest = &rest[count..];
+
+        let (start, end) = (first.saturating_sub(CONTEXT_LINES), (last + CONTEXT_LINES + 1).min(edits.len()));
+        let old_start = edits[..start].iter().filter(|(kind, _)| *kind != '+').count() + 1;
+        let new_start = edits[..start].iter().filter(|(kind, _)| *kind != '-').count() + 1;
+        let lines: Vec<DiffLine> = edits[start..end].iter().map(|&(kind, text)| DiffLine { kind, text: text.to_string() }).collect();
+        hunks.push(Hunk {
+            old_start,
+            old_len: lines.iter().filter(|l| l.kind != '+').count(),
+            new_start,
+            new_len: lines.iter().filter(|l| l.kind != '-').count(),
+            lines
+        });
+    }
+    hunks
+}
+
+fn is_text(path: &str) -> bool {
+    path.rsplit_once('.').is_some_and(|(_, ext)| TEXT_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// How the output at `path` changed from `old` to `new`, or `None` if it didn't. Text outputs
+/// that aren't valid UTF-8 are compared like binary ones.
+fn change(path: &str, old: &[u8], new: &[u8], masks: &[regex::Regex], config: &CompareConfig) -> Option<Change> {
+    let texts = is_text(path).then(|| std::str::from_utf8(old).ok().zip(std::str::from_utf8(new).ok())).flatten();
+    if let Some((old_text, new_text)) = texts {
+        let (old_lines, new_lines) = (prepare(old_text, masks, config), prepare(new_text, masks, config));
+        if old_lines == new_lines { return None }
+        let edits = edits(&old_lines, &new_lines);
+        let added = edits.iter().filter(|(kind, _)| *kind == '+').count();
+        let removed = edits.iter().filter(|(kind, _)| *kind == '-').count();
+        Some(Change { path: path.to_string(), kind: "text", hunks: hunks(&edits), added, removed, old: None, new: None })
+    } else if old == new {
+        None
+    } else {
+        Some(Change { path: path.to_string(), kind: "binary", hunks: Vec::new(), added: 0, removed: 0, old: Some(summary(old)), new: Some(summary(new)) })
+    }
+}
+
+/// Compares the outputs in `sink` against the files in `old_dir` and writes an HTML report of the
+/// differences to `report`.
+pub fn compare(old_dir: &Path, report: &Path, sink: &dyn OutputSink, outputs: &BTreeMap<String, u64>, config: &CompareConfig) {
+    log!("info: comparing the build against `{}`", old_dir.display());
+    let masks: Vec<regex::Regex> = config.masks.iter()
+        .filter_map(|mask| regex::Regex::new(mask).inspect_err(|e| log!("error: compare: invalid mask `{}`: {}", mask, e)).ok())
+        .collect();
+
+    let mut stats = Stats::default();
+    let mut changes = Vec::new();
+    let mut added = Vec::new();
+    for path in outputs.keys() {
+        let Ok(new) = sink.read(path)
+            .inspect_err(|e| log!("error: compare: cannot read output `{}`: {}", path, e))
+            else { continue };
+        let Ok(old) = std::fs::read(old_dir.join(path)) else {
+            added.push(path.as_str());
+            continue
+        };
+
+        match change(path, &old, &new, &masks, config) {
+            None => stats.unchanged += 1,
+            Some(change) if change.kind == "text" => {
+                stats.changed_pages += 1;
+                stats.lines_added += change.added;
+                stats.lines_removed += change.removed;
+                changes.push(change);
+            },
+            Some(change) => {
+                stats.changed_binary += 1;
+                changes.push(change);
+            }
+        }
+    }
+    let removed: Vec<String> = old_outputs(old_dir).into_iter().filter(|path| !outputs.contains_key(path)).collect();
+    stats.added = added.len();
+    stats.removed = removed.len();
+
+    let mut env = minijinja::Environment::new();
+    let Ok(()) = env.add_template("compare", include_str!("fallback/compare.html"))
+        .inspect_err(|e| log!("error: cannot parse built-in template `compare`: {}", e))
+        else { return };
+    let Ok(document) = env.get_template("compare")
+        .and_then(|t| t.render(minijinja::context! {
+            old_dir => old_dir.display().to_string(),
+            stats => &stats,
+            changes => &changes,
+            added => &added,
+            removed => &removed,
+            masks => &config.masks
+        }))
+        .inspect_err(|e| log!("error: could not render the comparison report: {}", e))
+        else { return };
+    let Ok(()) = std::fs::write(report, document)
+        .inspect_err(|e| log!("error: could not write `{}`: {}", report.display(), e))
+        else { return };
+
+    log!(
+        "info: compare: {} unchanged, {} pages and {} other files changed (+{} -{} lines), {} added, {} removed, report in `{}`",
+        stats.unchanged, stats.changed_pages, stats.changed_binary, stats.lines_added, stats.lines_removed,
+        stats.added, stats.removed, report.display()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    fn numbered(count: usize) -> Vec<String> {
+        (1..=count).map(|n| n.to_string()).collect()
+    }
+
+    /// Headers of the hunks turning `old` into `new`, as `(old_start, old_len, new_start, new_len)`
+    fn headers(old: &[String], new: &[String]) -> Vec<(usize, usize, usize, usize)> {
+        hunks(&edits(old, new)).iter().map(|h| (h.old_start, h.old_len, h.new_start, h.new_len)).collect()
+    }
+
+    #[test]
+    fn edits_follow_the_common_subsequence() {
+        let (old, new) = (lines("a\nb\nc"), lines("a\nc\nd"));
+        assert_eq!(edits(&old, &new), vec![(' ', "a"), ('-', "b"), (' ', "c"), ('+', "d")]);
+        assert_eq!(edits(&old, &old), vec![(' ', "a"), (' ', "b"), (' ', "c")]);
+        assert_eq!(edits(&[], &lines("a")), vec![('+', "a")]);
+    }
+
+    #[test]
+    fn hunk_ranges() {
+        let old = numbered(20);
+        let mut new = old.clone();
+        new[0] = "x".into();
+        assert_eq!(headers(&old, &new), vec![(1, 4, 1, 4)]);
+        let hunk = &hunks(&edits(&old, &new))[0];
+        assert_eq!(hunk.lines.iter().map(|l| l.kind).collect::<String>(), "-+   ");
+
+        let mut new = old.clone();
+        new[19] = "y".into();
+        assert_eq!(headers(&old, &new), vec![(17, 4, 17, 4)]);
+
+        // A removed and an added line shift the new side
+        let mut new = old.clone();
+        new.remove(9);
+        assert_eq!(headers(&old, &new), vec![(7, 7, 7, 6)]);
+        new.insert(0, "first".into());
+        assert_eq!(headers(&old, &new), vec![(1, 3, 1, 4), (7, 7, 8, 6)]);
+    }
+
+    #[test]
+    fn hunks_merge_when_their_context_touches() {
+        let old = numbered(20);
+        let mut new = old.clone();
+        new[4] = "a".into();
+        new[11] = "b".into();
+        // Six unchanged lines in between fit in the context of both changes
+        assert_eq!(headers(&old, &new), vec![(2, 14, 2, 14)]);
+
+        let mut new = old.clone();
+        new[4] = "a".into();
+        new[12] = "b".into();
+        assert_eq!(headers(&old, &new), vec![(2, 7, 2, 7), (10, 7, 10, 7)]);
+    }
+
+    #[test]
+    fn masks_and_whitespace() {
+        let masks = [regex::Regex::new(r"\d{2}:\d{2}").unwrap()];
+        let config = CompareConfig::default();
+        assert_eq!(prepare("built at 12:30\nok", &masks, &config), lines("built at [masked]\nok"));
+        assert!(change("a.html", b"built at 12:30", b"built at 13:45", &masks, &config).is_none());
+        assert!(change("a.html", b"built at 12:30", b"built at 13:45", &[], &config).is_some());
+
+        let normalized = CompareConfig { normalize_whitespace: true, ..CompareConfig::default() };
+        assert_eq!(prepare("  a \t b  \n\n   \nc", &[], &normalized), lines("a b\nc"));
+        assert!(change("a.html", b"<p>a b</p>\n", b"  <p>a  b</p>\n\n", &[], &normalized).is_none());
+        let change = change("a.html", b"<p>a b</p>\n", b"  <p>a  b</p>\n\n", &[], &config).unwrap();
+        assert_eq!((change.kind, change.added, change.removed), ("text", 2, 1));
+    }
+
+    #[test]
+    fn binary_and_non_utf8_outputs() {
+        let config = CompareConfig::default();
+        let change_of = |path, old: &[u8], new: &[u8]| change(path, old, new, &[], &config);
+        assert!(change_of("a.png", b"\x89PNG", b"\x89PNG").is_none());
+        let png = change_of("a.png", b"\x89PNG", b"\x89PNG\0").unwrap();
+        assert_eq!(png.kind, "binary");
+        assert!(png.hunks.is_empty());
+        assert_eq!((png.old.unwrap().size, png.new.unwrap().size), (4, 5));
+
+        // A text extension doesn't help when the content isn't UTF-8
+        assert!(change_of("a.html", b"caf\xe9", b"caf\xe9").is_none());
+        assert_eq!(change_of("a.html", b"caf\xe9", b"cafe").unwrap().kind, "binary");
+        assert_eq!(change_of("a.HTML", b"a", b"b").unwrap().kind, "text");
+    }
+}
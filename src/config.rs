@@ -0,0 +1,318 @@
+use std::{collections::BTreeMap, io::Read, path::Path};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::{code::CodeConfig, compare::CompareConfig, feed::FeedConfig, freshness::FreshnessConfig, output::{AssetHashConfig, AssetNaming}, jsonld::AuthorConfig, raster::ImageConfig, related::RelatedConfig, search::SearchGranularity, slug::SlugConfig, toc::ReadingConfig, typography::TypographyConfig};
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteConfig {
+    pub hardbreaks: bool,
+    pub collapse_hardbreaks: bool,
+    pub tag_aliases: BTreeMap<String, String>,
+    /// Inline markdown describing tags, by tag name, given to their pages as `description_html`
+    pub tag_descriptions: BTreeMap<String, String>,
+    /// Inline markdown describing categories, by category name, like `tag_descriptions`
+    pub category_descriptions: BTreeMap<String, String>,
+    pub canonicalize_display: bool,
+    pub processors: Vec<ProcessorConfig>,
+    pub heaviest_pages: usize,
+    pub max_page_weight_kb: Option<u64>,
+    pub max_asset_kb: Option<u64>,
+    #[serde(deserialize_with = "deserialize_offset", serialize_with = "serialize_offset")]
+    pub timezone: Option<chrono::FixedOffset>,
+    /// Posts per page of the index, the rest going to `page/2.html` and on, 0 to list them all on one page
+    pub paginate: usize,
+    /// Posts per tag or category page, 0 to list them all on one page
+    pub tag_paginate: usize,
+    pub images: ImageConfig,
+    /// How posts are split into search index entries, no index is written if unset
+    pub search_granularity: Option<SearchGranularity>,
+    /// Longest text of a search index entry in bytes, cut at a word, 0 for no limit
+    pub search_max_bytes: usize,
+    pub slugs: SlugConfig,
+    /// Globs, relative to the input directory, of files whose changes don't trigger a rebuild
+    pub watch_ignore: Vec<String>,
+    /// Warn about and flag old posts with time-sensitive tags, off if unset
+    pub freshness: Option<FreshnessConfig>,
+    /// Address the site is served from, e.g. `https://example.com`, used for absolute links
+    pub base_url: Option<String>,
+    /// Output path of posts, like `{year}/{month}/{slug}/`, where a trailing `/` writes `index.html`
+    pub permalink: String,
+    pub author: Option<AuthorConfig>,
+    pub reading: ReadingConfig,
+    pub related: RelatedConfig,
+    /// The RSS and Atom feeds written when `base_url` is set
+    pub feed: FeedConfig,
+    /// Write `sitemap.xml` when `base_url` is set
+    pub sitemap: bool,
+    /// Sync outputs to disk and verify them after writing, retrying transient errors
+    pub durable_writes: bool,
+    /// Most figures placed side by side when image-only paragraphs follow each other, 0 to leave them apart
+    pub figure_row_max: usize,
+    /// Date posts without an `updated` key by the modification time of their file
+    pub updated_from_mtime: bool,
+    /// Write `posts.json`, a versioned list of the posts' metadata for external tools
+    pub archive_index: bool,
+    /// Keep numbers with their units and French punctuation with its word, off if unset
+    pub typography: Option<TypographyConfig>,
+    /// Give index and tag pages the full HTML of each post, not just its metadata
+    pub listing_content: bool,
+    /// Leave posts with a passphrase out of index and tag pages and `posts.json`, instead of listing their titles
+    pub hide_encrypted: bool,
+    pub asset_names: AssetNaming,
+    /// Hash that asset names are made from, and how much of it they keep
+    pub asset_hash: AssetHashConfig,
+    /// Default strftime format of the `format_datetime` filter, unless a template sets `FORMAT_DATETIME`
+    pub datetime_format: String,
+    /// Static files larger than this many MiB are copied in the background and resumed if interrupted, 0 to copy everything in place
+    pub large_file_mb: u64,
+    /// Most images decoded or encoded at once, 0 to pick from the core count and available memory
+    pub max_concurrent_images: usize,
+    /// How `--compare` tells pages apart from a previous build
+    pub compare: CompareConfig,
+    /// Defaults for wrapping and scrolling code blocks, and the longest lines allowed in them
+    pub code: CodeConfig,
+    /// Values of any shape given to every template as `site`, like `site.title`
+    pub site: toml::Table
+}
+
+impl Default for SiteConfig {
+    fn default() -> Self {
+        SiteConfig {
+            hardbreaks: false,
+            collapse_hardbreaks: false,
+            tag_aliases: BTreeMap::new(),
+            tag_descriptions: BTreeMap::new(),
+            category_descriptions: BTreeMap::new(),
+            canonicalize_display: false,
+            processors: Vec::new(),
+            heaviest_pages: 10,
+            max_page_weight_kb: None,
+            max_asset_kb: None,
+            timezone: None,
+            paginate: 0,
+            tag_paginate: 0,
+            images: ImageConfig::default(),
+            search_granularity: None,
+            search_max_bytes: 10_000,
+            slugs: SlugConfig::default(),
+            watch_ignore: Vec::new(),
+            freshness: None,
+            base_url: None,
+            permalink: crate::permalink::DEFAULT_PATTERN.into(),
+            author: None,
+            reading: ReadingConfig::default(),
+            related: RelatedConfig::default(),
+            feed: FeedConfig::default(),
+            sitemap: true,
+            durable_writes: false,
+            figure_row_max: 0,
+            updated_from_mtime: false,
+            archive_index: false,
+            typography: None,
+            listing_content: false,
+            hide_encrypted: false,
+            asset_names: AssetNaming::Hash,
+            asset_hash: AssetHashConfig::default(),
+            // `%-d` rather than `%e`, which pads single digit days with a space
+            datetime_format: "%B %-d %Y at %H:%M".into(),
+            large_file_mb: 64,
+            max_concurrent_images: 0,
+            compare: CompareConfig::default(),
+            code: CodeConfig::default(),
+            site: toml::Table::new()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessorConfig {
+    pub language: String,
+    pub command: Vec<String>,
+    #[serde(default = "default_processor_timeout")]
+    pub timeout: u64
+}
+
+fn deserialize_offset<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<chrono::FixedOffset>, D::Error> {
+    let Some(offset) = Option::<String>::deserialize(deserializer)? else { return Ok(None) };
+    offset.parse().map(Some).map_err(|_| serde::de::Error::custom(format!("invalid utc offset `{}`, expected e.g. \"+02:00\"", offset)))
+}
+
+fn serialize_offset<S: Serializer>(offset: &Option<chrono::FixedOffset>, serializer: S) -> Result<S::Ok, S::Error> {
+    offset.map(|o| o.to_string()).serialize(serializer)
+}
+
+fn default_processor_timeout() -> u64 { 30 }
+
+/// Optional settings that are left out of a dump when unset, with an example value for each
+const OPTIONAL_EXAMPLES: &[(&str, &str)] = &[
+    ("max_page_weight_kb", "500"),
+    ("max_asset_kb", "200"),
+    ("timezone", "\"+02:00\""),
+    ("search_granularity", "\"passage\""),
+    ("base_url", "\"https://example.com\""),
+    ("author", "{ name = \"Jane Doe\", url = \"/about.html\" }"),
+    ("freshness", "{ max_age_days = 1095, tags = [\"rust\"], git = false }"),
+    ("typography", "{ units = [\"GB\", \"km\"], prefixes = [\"fig.\"], phrases = [\"Mac OS\"] }")
+];
+
+/// Suggests the closest of the expected fields in a serde "unknown field" message.
+fn suggest_field(message: &str) -> Option<String> {
+    let rest = message.strip_prefix("unknown field `")?;
+    let (unknown, expected) = rest.split_once('`')?;
+    expected.split('`').skip(1).step_by(2)
+        .map(|field| (crate::lint::edit_distance(unknown, field), field))
+        .filter(|&(distance, field)| distance <= 2.max(field.len() / 3))
+        .min()
+        .map(|(_, field)| format!("did you mean `{}`?", field))
+}
+
+impl SiteConfig {
+    /// Reads `site.toml` in `in_dir`, or the defaults if there is none. Fails if the file
+    /// can't be read or parsed, with the location of the problem, or has invalid values.
+    pub fn load(in_dir: &Path) -> Result<SiteConfig, String> {
+        let path = in_dir.join("site.toml");
+        if !path.is_file() {
+            log!("warning: no site config at `{}`, using the defaults", path.display());
+            return Ok(SiteConfig::default())
+        }
+
+        log!("info: processing site config `{}`", path.display());
+        let mut source = String::new();
+        std::fs::File::open(&path)
+            .and_then(|mut file| file.read_to_string(&mut source))
+            .map_err(|e| format!("cannot read site config: {e}"))?;
+
+        let config: SiteConfig = toml::from_str(&source).map_err(|e| {
+            let mut message = format!("could not parse site config `{}`: {}", path.display(), e);
+            if let Some(suggestion) = suggest_field(e.message()) {
+                message = format!("{}\n    {}", message, suggestion);
+            }
+            message
+        })?;
+        let problems = config.validate();
+        if !problems.is_empty() {
+            return Err(format!("invalid site config `{}`:\n    {}", path.display(), problems.join("\n    ")))
+        }
+        Ok(config)
+    }
+
+    /// Path of `base_url` without a trailing slash, like `/blog`, or empty when the site is served from the root.
+    pub fn base_path(&self) -> String {
+        self.base_url.as_deref()
+            .and_then(|base_url| url::Url::parse(base_url).ok())
+            .map(|url| url.path().trim_end_matches('/').to_string())
+            .unwrap_or_default()
+    }
+
+    /// Checks constraints that the types alone don't express.
+    pub fn validate(&self) -> Vec<String> {
+        use std::fmt::Write;
+        let mut problems = Vec::new();
+        if let Some(base_url) = &self.base_url {
+            match url::Url::parse(base_url) {
+                Ok(url) if url.cannot_be_a_base() || !matches!(url.scheme(), "http" | "https") =>
+                    problems.push(format!("`base_url` must be an http or https address, got `{}`", base_url)),
+                Ok(_) => (),
+                Err(e) => problems.push(format!("`base_url` is not a valid address: {}", e))
+            }
+        }
+        // Aliases are matched ignoring case, so these would leave the tag to whichever comes first
+        let mut seen: BTreeMap<String, (&str, &str)> = BTreeMap::new();
+        for (alias, target) in &self.tag_aliases {
+            match seen.get(&alias.to_lowercase()) {
+                Some((other, other_target)) if other_target != target => problems.push(format!(
+                    "`tag_aliases` has `{}` and `{}`, which differ only in case, for different tags", other, alias
+                )),
+                Some(_) => (),
+                None => { seen.insert(alias.to_lowercase(), (alias, target)); }
+            }
+        }
+        if !(1..=100).contains(&self.images.quality) {
+            problems.push(format!("`images.quality` must be between 1 and 100, got {}", self.images.quality));
+        }
+        for processor in &self.processors {
+            if processor.command.is_empty() {
+                problems.push(format!("the processor for `{}` has an empty `command`", processor.language));
+            }
+            if processor.timeout == 0 {
+                problems.push(format!("the processor for `{}` has a `timeout` of 0 seconds", processor.language));
+            }
+        }
+        if let Some(freshness) = &self.freshness && freshness.max_age_days < 0 {
+            problems.push(format!("`freshness.max_age_days` must not be negative, got {}", freshness.max_age_days));
+        }
+        let mut formatted = String::new();
+        if write!(formatted, "{}", chrono::DateTime::UNIX_EPOCH.format(&self.datetime_format)).is_err() {
+            problems.push(format!("`datetime_format` is not a valid strftime format: `{}`", self.datetime_format));
+        }
+        if let Err(e) = crate::permalink::check(&self.permalink) {
+            problems.push(format!("`permalink` is invalid: {}", e));
+        }
+        for mask in &self.compare.masks {
+            if let Err(e) = regex::Regex::new(mask) {
+                problems.push(format!("`compare.masks` has an invalid regex `{}`: {}", mask, e));
+            }
+        }
+        if self.reading.words_per_minute <= 0.0 || self.reading.code_lines_per_minute <= 0.0 {
+            problems.push("reading speeds in `reading` must be positive".into());
+        }
+        problems
+    }
+
+    /// The configuration as TOML, with commented-out examples for optional settings that aren't set.
+    pub fn dump(&self) -> Result<String, String> {
+        let mut dump = toml::to_string(self).map_err(|e| e.to_string())?;
+        let table: toml::Table = toml::from_str(&dump).map_err(|e| e.to_string())?;
+        let unset: Vec<String> = OPTIONAL_EXAMPLES.iter()
+            .filter(|(key, _)| !table.contains_key(*key))
+            .map(|(key, example)| format!("# {} = {}\n", key, example))
+            .collect();
+        if !unset.is_empty() {
+            dump = format!("# Unset optional settings:\n{}\n{}", unset.concat(), dump);
+        }
+        Ok(dump)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_configs_fail_to_load() {
+        let dir = crate::test_dir("invalid-config");
+        std::fs::write(dir.join("site.toml"), "[images]\nquality = 0").unwrap();
+        let error = SiteConfig::load(&dir).unwrap_err();
+        assert!(error.contains("`images.quality` must be between 1 and 100"), "{}", error);
+        std::fs::write(dir.join("site.toml"), "[images]\nquality = 80").unwrap();
+        assert!(SiteConfig::load(&dir).is_ok());
+    }
+
+    #[test]
+    fn unknown_fields_suggest_the_closest_one() {
+        let suggest = |source: &str| suggest_field(toml::from_str::<SiteConfig>(source).unwrap_err().message());
+        assert_eq!(suggest("pagniate = 10").as_deref(), Some("did you mean `paginate`?"));
+        assert_eq!(suggest("[images]\nqualty = 80").as_deref(), Some("did you mean `quality`?"));
+        // Nothing is close enough to a made up field
+        assert_eq!(suggest("frobnicate = true"), None);
+        assert_eq!(suggest_field("invalid type: integer `1`, expected a string"), None);
+
+        let dir = crate::test_dir("config-suggestion");
+        std::fs::write(dir.join("site.toml"), "sitemapp = true").unwrap();
+        let error = SiteConfig::load(&dir).unwrap_err();
+        assert!(error.ends_with("\n    did you mean `sitemap`?"), "{}", error);
+    }
+
+    #[test]
+    fn aliases_differing_in_case_are_a_problem_only_for_different_tags() {
+        let mut config = SiteConfig::default();
+        config.tag_aliases.insert("rust".into(), "Rust".into());
+        config.tag_aliases.insert("RUST".into(), "Rust".into());
+        assert!(config.validate().is_empty());
+        config.tag_aliases.insert("Rust".into(), "Rust language".into());
+        let problems = config.validate();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("`RUST` and `Rust`"), "{:?}", problems);
+    }
+}
@@ -0,0 +1,64 @@
+use std::{collections::BTreeMap, path::Path};
+use serde::Serialize;
+
+/// Contents of the data directory, given to templates as `data`. Files are keyed by their stem
+/// and directories by their name.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Data {
+    Toml(toml::Value),
+    Json(serde_json::Value),
+    Dir(BTreeMap<String, Data>)
+}
+
+/// Reads every `*.toml` and `*.json` file under `dir`. Files that can't be read or parsed are
+/// reported as errors and left out.
+pub fn load(dir: &Path) -> BTreeMap<String, Data> {
+    let mut data = BTreeMap::new();
+    if !dir.is_dir() { return data }
+    let Ok(entries) = dir.read_dir()
+        .inspect_err(|e| log!("error: cannot read data directory `{}`: {}", dir.display(), e))
+        else { return data };
+    let mut entries: Vec<_> = entries.filter_map(|entry| entry
+        .inspect_err(|e| log!("error: cannot read data file: {e}"))
+        .ok()
+        .map(|e| e.path()))
+        .collect();
+    // Which of two files with the same stem wins shouldn't depend on the directory order
+    entries.sort();
+
+    for path in entries {
+        let (key, value) = if path.is_dir() {
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else { continue };
+            (name.to_string(), Data::Dir(load(&path)))
+        } else {
+            let Some(key) = path.file_stem().and_then(|s| s.to_str()) else { continue };
+            let Some(value) = read(&path) else { continue };
+            (key.to_string(), value)
+        };
+        if data.contains_key(&key) {
+            log!("warning: `{}` has the same name as another data file, ignoring it", path.display());
+            continue
+        }
+        data.insert(key, value);
+    }
+    data
+}
+
+/// Reads and parses the data file at `path`, `None` for files that aren't TOML or JSON.
+fn read(path: &Path) -> Option<Data> {
+    let ext = path.extension().and_then(|e| e.to_str());
+    if !matches!(ext, Some("toml" | "json")) {
+        log!("warning: ignoring `{}`, data files must be TOML or JSON", path.display());
+        return None
+    }
+    log!("info: processing data file `{}`", path.display());
+    let Ok(source) = std::fs::read_to_string(path)
+        .inspect_err(|e| log!("error: cannot read data file `{}`: {}", path.display(), e))
+        else { return None };
+    let parsed = match ext {
+        Some("toml") => toml::from_str::<toml::Table>(&source).map(|t| Data::Toml(toml::Value::Table(t))).map_err(|e| e.to_string()),
+        _ => serde_json::from_str(&source).map(Data::Json).map_err(|e| e.to_string())
+    };
+    parsed.inspect_err(|e| log!("error: could not parse data file `{}`: {}", path.display(), e)).ok()
+}
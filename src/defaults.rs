@@ -0,0 +1,80 @@
+use std::path::Path;
+
+pub const DEFAULTS_FILE: &str = "_defaults.toml";
+
+/// Merges `overlay` into `base`, with `overlay` taking precedence: scalars are replaced, arrays
+/// are joined without duplicates (items of `base` first) and tables are merged recursively.
+pub fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge(base, overlay),
+            (Some(toml::Value::Array(base)), toml::Value::Array(overlay)) => {
+                for item in overlay {
+                    if !base.contains(&item) {
+                        base.push(item);
+                    }
+                }
+            },
+            (_, value) => { base.insert(key, value); }
+        }
+    }
+}
+
+/// Reads `dir/_defaults.toml` and merges it into a copy of `inherited`.
+pub fn load(dir: &Path, inherited: &toml::Table) -> toml::Table {
+    let mut defaults = inherited.clone();
+    let path = dir.join(DEFAULTS_FILE);
+    if !path.is_file() { return defaults }
+
+    log!("info: processing post defaults `{}`", path.display());
+    let Ok(source) = std::fs::read_to_string(&path)
+        .inspect_err(|e| log!("error: cannot read post defaults: {e}"))
+        else { return defaults };
+    let Ok(table) = toml::from_str::<toml::Table>(&source)
+        .inspect_err(|e| log!("error: could not parse post defaults: {e}"))
+        else { return defaults };
+    merge(&mut defaults, table);
+    defaults
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(source: &str) -> toml::Table {
+        toml::from_str(source).unwrap()
+    }
+
+    #[test]
+    fn scalars_are_overridden_lists_joined_and_tables_merged() {
+        let mut base = table("template = \"photo\"\ndraft = true\ntags = [\"photography\", \"travel\"]\n[extra]\ncamera = \"X100\"\n[extra.lens]\nmm = 23\nf = 2.0");
+        merge(&mut base, table("template = \"post\"\ntags = [\"travel\", \"japan\"]\n[extra]\nfilm = \"Acros\"\n[extra.lens]\nmm = 35"));
+        assert_eq!(base, table(concat!(
+            "template = \"post\"\ndraft = true\ntags = [\"photography\", \"travel\", \"japan\"]\n",
+            "[extra]\ncamera = \"X100\"\nfilm = \"Acros\"\n[extra.lens]\nmm = 35\nf = 2.0"
+        )));
+    }
+
+    #[test]
+    fn values_of_another_type_replace() {
+        let mut base = table("tags = [\"a\"]\nextra = { a = 1 }\nsummary = \"text\"");
+        merge(&mut base, table("tags = \"b\"\nextra = 2\nsummary = [\"list\"]"));
+        assert_eq!(base, table("tags = \"b\"\nextra = 2\nsummary = [\"list\"]"));
+    }
+
+    #[test]
+    fn nearest_defaults_win() {
+        let dir = crate::test_dir("defaults-nesting");
+        let nested = dir.join("photography/japan");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(dir.join(DEFAULTS_FILE), "category = \"Blog\"\ntags = [\"all\"]").unwrap();
+        std::fs::write(dir.join("photography").join(DEFAULTS_FILE), "template = \"photo\"\ntags = [\"photography\"]").unwrap();
+        std::fs::write(nested.join(DEFAULTS_FILE), "category = \"Travel\"\ntags = [\"japan\", \"all\"]").unwrap();
+        let top = load(&dir, &toml::Table::new());
+        let middle = load(&dir.join("photography"), &top);
+        let bottom = load(&nested, &middle);
+        assert_eq!(bottom, table("category = \"Travel\"\ntemplate = \"photo\"\ntags = [\"all\", \"photography\", \"japan\"]"));
+        // Directories without defaults inherit them as they are
+        assert_eq!(load(&nested.join("missing"), &middle), middle);
+    }
+}
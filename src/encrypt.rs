@@ -0,0 +1,99 @@
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce, aead::Aead};
+use base64::Engine;
+use hmac::Mac;
+use sha2::Digest;
+use serde::{Deserialize, Serialize};
+
+/// PBKDF2-HMAC-SHA256 rounds used to derive the key from the passphrase
+pub const KDF_ITERATIONS: u32 = 600_000;
+
+/// Script that decrypts posts in the browser, stored as an asset
+pub const DECRYPT_SCRIPT: &str = include_str!("fallback/decrypt.js");
+
+/// An encrypted post body and what the browser needs to decrypt it, all base64 encoded
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EncryptedContent {
+    pub ciphertext: String,
+    pub salt: String,
+    pub nonce: String,
+    pub iterations: u32,
+    /// URL of the decryption script
+    pub script: String
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0; 32];
+    pbkdf2::pbkdf2_hmac::<sha2::Sha256>(passphrase.as_bytes(), salt, KDF_ITERATIONS, &mut key);
+    key
+}
+
+/// Encrypts `plaintext` with AES-256-GCM, under a key derived from `passphrase`. Identical builds
+/// give identical output: the salt comes from `scope`, which names the site and post so that the
+/// same passphrase gives other posts other keys, and the nonce is a MAC of the plaintext under
+/// the key, so that it only repeats along with the plaintext.
+pub fn encrypt(plaintext: &str, passphrase: &str, scope: &str, script: String) -> Result<EncryptedContent, String> {
+    let salt = &sha2::Sha256::digest(format!("static-site-gen salt\0{}", scope))[..16];
+    let key = derive_key(passphrase, salt);
+    let mut mac = <hmac::Hmac<sha2::Sha256> as Mac>::new_from_slice(&key).map_err(|e| e.to_string())?;
+    mac.update(plaintext.as_bytes());
+    let nonce = *Nonce::from_slice(&mac.finalize().into_bytes()[..12]);
+    let cipher = Aes256Gcm::new(&key.into());
+    let ciphertext = cipher.encrypt(&nonce, plaintext.as_bytes()).map_err(|e| e.to_string())?;
+
+    let base64 = base64::engine::general_purpose::STANDARD;
+    Ok(EncryptedContent {
+        ciphertext: base64.encode(ciphertext),
+        salt: base64.encode(salt),
+        nonce: base64.encode(nonce),
+        iterations: KDF_ITERATIONS,
+        script
+    })
+}
+
+/// Markup shown in place of the post body, replaced by the decrypted body once the passphrase is entered.
+pub fn placeholder(content: &EncryptedContent) -> String {
+    format!(
+        r#"<div class="encrypted-post" data-ciphertext="{}" data-salt="{}" data-nonce="{}" data-iterations="{}">
+<form><label>This post is protected. Passphrase: <input type="password" name="passphrase" autocomplete="off"></label> <button>Unlock</button></form>
+<p class="encrypted-post-error" hidden>Wrong passphrase.</p>
+<noscript><p>Decrypting this post requires JavaScript.</p></noscript>
+</div>
+<script src="{}" defer></script>
+"#,
+        content.ciphertext, content.salt, content.nonce, content.iterations, content.script
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decrypt(content: &EncryptedContent, passphrase: &str) -> Option<String> {
+        let base64 = base64::engine::general_purpose::STANDARD;
+        let salt = base64.decode(&content.salt).ok()?;
+        let nonce = base64.decode(&content.nonce).ok()?;
+        let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt).into());
+        let plaintext = cipher.decrypt(Nonce::from_slice(&nonce), base64.decode(&content.ciphertext).ok()?.as_slice()).ok()?;
+        String::from_utf8(plaintext).ok()
+    }
+
+    #[test]
+    fn encryption_is_reproducible_and_reversible() {
+        let first = encrypt("<p>Secret</p>", "hunter2", "https://example.com#post", "/decrypt.js".into()).unwrap();
+        let second = encrypt("<p>Secret</p>", "hunter2", "https://example.com#post", "/decrypt.js".into()).unwrap();
+        assert_eq!(placeholder(&first), placeholder(&second));
+        assert_eq!(decrypt(&first, "hunter2").as_deref(), Some("<p>Secret</p>"));
+        assert_eq!(decrypt(&first, "hunter3"), None);
+    }
+
+    #[test]
+    fn other_posts_and_contents_get_other_salts_and_nonces() {
+        let post = encrypt("<p>Secret</p>", "hunter2", "#a", String::new()).unwrap();
+        let other_post = encrypt("<p>Secret</p>", "hunter2", "#b", String::new()).unwrap();
+        let edited = encrypt("<p>Secret!</p>", "hunter2", "#a", String::new()).unwrap();
+        assert_ne!(post.salt, other_post.salt);
+        assert_ne!(post.ciphertext, other_post.ciphertext);
+        assert_eq!(post.salt, edited.salt);
+        assert_ne!(post.nonce, edited.nonce);
+    }
+}
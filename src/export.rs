@@ -0,0 +1,92 @@
+use base64::Engine;
+use crate::html;
+
+/// Resources bigger than this are rejected instead of being inlined.
+pub const MAX_INLINE_BYTES: usize = 8 * 1024 * 1024;
+
+fn data_uri(link: &str, data: &[u8]) -> Result<String, String> {
+    if data.len() > MAX_INLINE_BYTES {
+        return Err(format!(
+            "`{}` is {:.1} MiB, too large to inline (the limit is {} MiB), link to it instead",
+            link, data.len() as f64 / (1024.0 * 1024.0), MAX_INLINE_BYTES / (1024 * 1024)
+        ))
+    }
+    let mime = mime_guess::from_path(link.split(['#', '?']).next().unwrap_or_default()).first_or_octet_stream();
+    Ok(format!("data:{};base64,{}", mime.essence_str(), base64::engine::general_purpose::STANDARD.encode(data)))
+}
+
+/// Replaces local stylesheet links with `<style>` tags and local `src` attributes with data URIs,
+/// using `load` to read the resource a link in the page at `page` points to.
+pub fn inline_resources(page: &str, document: &str, base_path: &str, load: impl Fn(&str) -> Option<Vec<u8>>) -> Result<String, String> {
+    let load_link = |link: &str| html::resolve_local_link(page, link, base_path).and_then(|path| load(&path));
+
+    let mut output = String::new();
+    let mut rest = document;
+    while let Some(start) = rest.find("<link") {
+        let end = rest[start..].find('>').map(|e| start + e + 1).unwrap_or(rest.len());
+        let tag = &rest[start..end];
+        output.push_str(&rest[..start]);
+        let stylesheet = tag.contains("stylesheet").then(|| html::link_attributes(tag).into_iter().next()).flatten();
+        match stylesheet.and_then(&load_link) {
+            Some(css) => {
+                output.push_str("<style>");
+                output.push_str(&String::from_utf8_lossy(&css));
+                output.push_str("</style>");
+            },
+            None => output.push_str(tag)
+        }
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+
+    let document = output;
+    let mut output = String::new();
+    let mut copied = 0;
+    for link in html::link_attributes(&document) {
+        let offset = link.as_ptr() as usize - document.as_ptr() as usize;
+        let is_src = document[..offset].trim_end_matches(['"', '\'']).ends_with("src=");
+        if !is_src { continue }
+        let Some(data) = load_link(link) else { continue };
+
+        output.push_str(&document[copied..offset]);
+        output.push_str(&data_uri(link, &data)?);
+        copied = offset + link.len();
+    }
+    output.push_str(&document[copied..]);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(path: &str) -> Option<Vec<u8>> {
+        match path {
+            "style.css" => Some(b"body { color: red; }".to_vec()),
+            "posts/pic.png" => Some(vec![1, 2, 3]),
+            "big.png" => Some(vec![0; MAX_INLINE_BYTES + 1]),
+            _ => None
+        }
+    }
+
+    #[test]
+    fn local_stylesheets_and_sources_are_inlined() {
+        let document = concat!(
+            "<link rel=\"stylesheet\" href=\"/blog/style.css\"><link rel=\"icon\" href=\"/blog/style.css\">",
+            "<img src=\"pic.png\" alt=\"Pic\"><img src=\"https://example.com/a.png\"><img src=\"missing.png\">",
+            "<a href=\"pic.png\">Link</a>"
+        );
+        assert_eq!(inline_resources("posts/post.html", document, "/blog", load).unwrap(), concat!(
+            "<style>body { color: red; }</style><link rel=\"icon\" href=\"/blog/style.css\">",
+            "<img src=\"data:image/png;base64,AQID\" alt=\"Pic\"><img src=\"https://example.com/a.png\"><img src=\"missing.png\">",
+            "<a href=\"pic.png\">Link</a>"
+        ));
+    }
+
+    #[test]
+    fn resources_over_the_limit_are_rejected() {
+        let error = inline_resources("index.html", "<img src=\"big.png\">", "", load).unwrap_err();
+        assert!(error.contains("`big.png` is 8.0 MiB, too large to inline"), "{}", error);
+        assert!(data_uri("exact.png", &vec![0; MAX_INLINE_BYTES]).is_ok());
+    }
+}
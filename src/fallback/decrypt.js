@@ -0,0 +1,27 @@
+// Decrypts the posts rendered with `encrypt = "..."` in their front matter once the reader enters the passphrase
+(() => {
+    const bytes = text => Uint8Array.from(atob(text), c => c.charCodeAt(0));
+
+    async function decrypt(data, passphrase) {
+        const material = await crypto.subtle.importKey("raw", new TextEncoder().encode(passphrase), "PBKDF2", false, ["deriveKey"]);
+        const key = await crypto.subtle.deriveKey(
+            { name: "PBKDF2", hash: "SHA-256", salt: bytes(data.salt), iterations: Number(data.iterations) },
+            material, { name: "AES-GCM", length: 256 }, false, ["decrypt"]
+        );
+        const plaintext = await crypto.subtle.decrypt({ name: "AES-GCM", iv: bytes(data.nonce) }, key, bytes(data.ciphertext));
+        return new TextDecoder().decode(plaintext);
+    }
+
+    for (const post of document.querySelectorAll(".encrypted-post")) {
+        const form = post.querySelector("form");
+        const error = post.querySelector(".encrypted-post-error");
+        form.addEventListener("submit", async event => {
+            event.preventDefault();
+            try {
+                post.outerHTML = await decrypt(post.dataset, form.elements.passphrase.value);
+            } catch {
+                error.hidden = false;
+            }
+        });
+    }
+})();
@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+use crate::post::Post;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedConfig {
+    /// Most recent posts in each feed, 0 for no feeds
+    pub count: usize,
+    /// Give the whole body of each post, not just its excerpt
+    pub full_content: bool,
+    /// Write the RSS 2.0 feed `feed.xml`
+    pub rss: bool,
+    /// Write the Atom 1.0 feed `atom.xml`
+    pub atom: bool,
+    /// Write an Atom feed of the posts of each tag to `tags/<slug>.atom.xml`
+    pub tag_feeds: bool
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        FeedConfig { count: 20, full_content: true, rss: true, atom: true, tag_feeds: true }
+    }
+}
+
+/// A post as the feed lists it
+pub struct FeedItem<'a> {
+    pub post: &'a Post,
+    pub published: chrono::DateTime<chrono::FixedOffset>,
+    /// When the post last changed, `published` if it doesn't say
+    pub updated: chrono::DateTime<chrono::FixedOffset>,
+    /// Canonical names of its tags, without repeats
+    pub tags: Vec<&'a str>
+}
+
+/// What a feed is of, named from `site.title` and `site.description` if set
+pub struct Channel<'a> {
+    pub title: &'a str,
+    pub description: &'a str,
+    /// Absolute URL of the page the feed follows, like the front page
+    pub link: &'a str,
+    /// Absolute URL of the feed itself
+    pub self_url: &'a str
+}
+
+/// The body a feed gives for `post`: all of it or its excerpt. Encrypted posts only give their
+/// summary, as their body is just the form to decrypt it.
+fn body<'a>(post: &'a Post, config: &FeedConfig) -> &'a str {
+    match (&post.encrypted, config.full_content) {
+        (Some(_), _) => post.summary_html.as_deref().unwrap_or_default(),
+        (None, true) => &post.source,
+        (None, false) => &post.excerpt
+    }
+}
+
+/// An RSS 2.0 document listing `items`, which are newest first. `base_url` makes links absolute.
+pub fn rss(channel: &Channel, items: &[FeedItem], base_url: &str, config: &FeedConfig) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n");
+    xml.push_str(&format!("<title>{}</title>\n", escape(channel.title)));
+    xml.push_str(&format!("<link>{}</link>\n", escape(channel.link)));
+    xml.push_str(&format!("<description>{}</description>\n", escape(channel.description)));
+    xml.push_str(&format!("<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n", escape(channel.self_url)));
+    // The newest post rather than the build time, so that unchanged feeds don't change
+    if let Some(newest) = items.first() {
+        xml.push_str(&format!("<lastBuildDate>{}</lastBuildDate>\n", newest.published.to_rfc2822()));
+    }
+
+    for item in items {
+        let post = item.post;
+        let link = crate::jsonld::absolute_url(base_url, &post.url);
+        xml.push_str("<item>\n");
+        xml.push_str(&format!("<title>{}</title>\n", escape(&post.title_plain)));
+        xml.push_str(&format!("<link>{}</link>\n", escape(&link)));
+        xml.push_str(&format!("<guid isPermaLink=\"true\">{}</guid>\n", escape(&link)));
+        xml.push_str(&format!("<pubDate>{}</pubDate>\n", item.published.to_rfc2822()));
+        for tag in &item.tags {
+            xml.push_str(&format!("<category>{}</category>\n", escape(tag)));
+        }
+        xml.push_str(&format!("<description>{}</description>\n", cdata(&absolute_links(body(post, config), &link))));
+        xml.push_str("</item>\n");
+    }
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+/// An Atom 1.0 document listing `items`, which are newest first. Entries are identified by the
+/// site and the post id rather than the post's URL, so they stay the same if it moves.
+pub fn atom(channel: &Channel, items: &[FeedItem], base_url: &str, site_author: &str, config: &FeedConfig) -> String {
+    let site = crate::jsonld::absolute_url(base_url, "/");
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str(&format!("<id>{}</id>\n", escape(channel.link)));
+    xml.push_str(&format!("<title type=\"text\">{}</title>\n", escape(channel.title)));
+    xml.push_str(&format!("<subtitle type=\"text\">{}</subtitle>\n", escape(channel.description)));
+    xml.push_str(&format!("<link rel=\"self\" type=\"application/atom+xml\" href=\"{}\"/>\n", escape(channel.self_url)));
+    xml.push_str(&format!("<link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>\n", escape(channel.link)));
+    // Entries without an author of their own take this one
+    xml.push_str(&format!("<author><name>{}</name></author>\n", escape(site_author)));
+    // The latest change rather than the build time, so that unchanged feeds don't change
+    let updated = items.iter().map(|item| item.updated).max();
+    xml.push_str(&format!("<updated>{}</updated>\n", updated.map_or_else(|| crate::rfc3339(&chrono::DateTime::UNIX_EPOCH.fixed_offset()), |u| crate::rfc3339(&u))));
+
+    for item in items {
+        let post = item.post;
+        let link = crate::jsonld::absolute_url(base_url, &post.url);
+        xml.push_str("<entry>\n");
+        xml.push_str(&format!("<id>{}#{}</id>\n", escape(&site), urlencoding::encode(&post.id)));
+        xml.push_str(&format!("<title type=\"text\">{}</title>\n", escape(&post.title_plain)));
+        xml.push_str(&format!("<link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>\n", escape(&link)));
+        xml.push_str(&format!("<published>{}</published>\n", crate::rfc3339(&item.published)));
+        xml.push_str(&format!("<updated>{}</updated>\n", crate::rfc3339(&item.updated)));
+        for author in &post.meta.authors {
+            xml.push_str(&format!("<author><name>{}</name></author>\n", escape(author)));
+        }
+        for tag in &item.tags {
+            xml.push_str(&format!("<category term=\"{}\"/>\n", escape(tag)));
+        }
+        let kind = if config.full_content && post.encrypted.is_none() { "content" } else { "summary" };
+        xml.push_str(&format!("<{0} type=\"html\">{1}</{0}>\n", kind, escape(&absolute_links(body(post, config), &link))));
+        xml.push_str("</entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+/// `text` escaped for XML character data and attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    let _ = pulldown_cmark_escape::escape_html(&mut escaped, text);
+    escaped
+}
+
+/// `html` in a CDATA section, split where it contains `]]>` itself.
+fn cdata(html: &str) -> String {
+    format!("<![CDATA[{}]]>", html.replace("]]>", "]]]]><![CDATA[>"))
+}
+
+/// `html` with `<picture>` elements replaced by the `<img>` in them, which has the fallback for
+/// readers without WebP support, as feed readers often drop `<source>` elements.
+fn plain_images(html: &str) -> String {
+    let mut plain = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find("<picture>") {
+        let picture = &rest[start..];
+        let (Some(img), Some(end)) = (picture.find("<img "), picture.find("</picture>")) else { break };
+        if img > end { break }
+        plain.push_str(&rest[..start]);
+        plain.push_str(&picture[img..end]);
+        rest = &picture[end + "</picture>".len()..];
+    }
+    plain.push_str(rest);
+    plain
+}
+
+/// `html` with its pictures made plain images and the `src`, `href` and `srcset` links resolved
+/// against `page`, since feed readers show it away from the site.
+fn absolute_links(html: &str, page: &str) -> String {
+    let html = &plain_images(html);
+    let Ok(page) = url::Url::parse(page) else { return html.to_string() };
+    let mut links = crate::html::link_attributes(html);
+    links.extend(crate::html::srcset_urls(html));
+    links.sort_by_key(|link| link.as_ptr() as usize);
+    let mut resolved = String::new();
+    let mut copied = 0;
+    for link in links {
+        let Ok(absolute) = page.join(link) else { continue };
+        let start = link.as_ptr() as usize - html.as_ptr() as usize;
+        resolved.push_str(&html[copied..start]);
+        resolved.push_str(absolute.as_str());
+        copied = start + link.len();
+    }
+    resolved.push_str(&html[copied..]);
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_are_made_absolute() {
+        let html = "<a href=\"/posts/b.html\">B</a> <img src=\"img.png\" srcset=\"/assets/a.png 1x, /assets/a2.png 2x\"> <a href=\"https://other.org/\">x</a>";
+        assert_eq!(
+            absolute_links(html, "https://example.com/blog/posts/a.html"),
+            "<a href=\"https://example.com/posts/b.html\">B</a> <img src=\"https://example.com/blog/posts/img.png\" srcset=\"https://example.com/assets/a.png 1x, https://example.com/assets/a2.png 2x\"> <a href=\"https://other.org/\">x</a>"
+        );
+    }
+
+    #[test]
+    fn pictures_become_their_fallback_image() {
+        let picture = crate::raster::img_markup("/assets/a.webp", Some("/assets/a.png"), 10, 20, "Alt");
+        let html = format!("<p>{}</p><p>{}</p>", picture, picture);
+        let image = "<img src=\"https://example.com/assets/a.png\" width=\"10\" height=\"20\" alt=\"Alt\">";
+        assert_eq!(absolute_links(&html, "https://example.com/posts/a.html"), format!("<p>{}</p><p>{}</p>", image, image));
+    }
+}
@@ -0,0 +1,110 @@
+use std::{collections::BTreeMap, path::Path};
+use serde::{Deserialize, Serialize};
+use crate::{lint::resolve_alias, post::Post};
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FreshnessConfig {
+    /// Posts last modified more than this many days before the build are stale
+    pub max_age_days: i64,
+    /// Only posts with one of these tags can become stale
+    pub tags: Vec<String>,
+    /// Use the time of the last git commit touching a post if it is later than its date
+    #[serde(default)]
+    pub git: bool
+}
+
+/// Whether something last modified at `modified` is more than `max_age_days` old at `now`.
+pub fn is_stale(modified: chrono::DateTime<chrono::FixedOffset>, now: chrono::DateTime<chrono::FixedOffset>, max_age_days: i64) -> bool {
+    now.signed_duration_since(modified) > chrono::Duration::days(max_age_days)
+}
+
+/// The time of the last commit touching `file`, if it is in a git repository.
+fn git_modified(file: &Path) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    let output = std::process::Command::new("git")
+        .arg("log").arg("-1").arg("--format=%cI").arg("--").arg(file.file_name()?)
+        .current_dir(file.parent()?)
+        .output()
+        .inspect_err(|e| log!("warning: could not run git for `{}`: {}", file.display(), e))
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    chrono::DateTime::parse_from_rfc3339(stdout.trim()).ok()
+}
+
+/// Marks time-sensitive posts whose last modification is too long ago as stale, warning about each.
+pub fn mark_stale(
+    posts: &mut [Post],
+    config: &FreshnessConfig,
+    aliases: &BTreeMap<String, String>,
+    now: chrono::DateTime<chrono::FixedOffset>,
+    publish_time: impl Fn(&toml_datetime::Datetime) -> chrono::DateTime<chrono::FixedOffset>
+) {
+    let key = |tag: &str| resolve_alias(tag, aliases).unwrap_or(tag).to_lowercase();
+    let sensitive: Vec<String> = config.tags.iter().map(|t| key(t)).collect();
+
+    for post in posts {
+        let Some(tag) = post.meta.tags.iter().find(|t| sensitive.contains(&key(t))) else { continue };
+
+        let mut modified = publish_time(&post.meta.date);
+        if let Some(updated) = &post.meta.updated {
+            modified = modified.max(publish_time(updated));
+        }
+        if config.git && let Some(committed) = git_modified(&post.file) {
+            modified = modified.max(committed);
+        }
+        if is_stale(modified, now, config.max_age_days) {
+            log!(
+                "warning: post `{}` is tagged `{}` and was last modified {} days ago, on {}",
+                post.id, tag, now.signed_duration_since(modified).num_days(), modified.date_naive()
+            );
+            post.is_stale = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::post::test_post;
+
+    fn at(datetime: &str) -> chrono::DateTime<chrono::FixedOffset> {
+        chrono::DateTime::parse_from_rfc3339(datetime).unwrap()
+    }
+
+    #[test]
+    fn staleness_starts_right_after_the_threshold() {
+        let now = at("2024-06-01T12:00:00+00:00");
+        assert!(!is_stale(at("2024-05-02T12:00:00+00:00"), now, 30));
+        assert!(!is_stale(now - chrono::Duration::days(30), now, 30));
+        assert!(is_stale(now - chrono::Duration::days(30) - chrono::Duration::seconds(1), now, 30));
+        // Offsets are accounted for: this is exactly 30 days before `now`
+        assert!(!is_stale(at("2024-05-02T15:00:00+03:00"), now, 30));
+        assert!(!is_stale(now, now, 0));
+        assert!(!is_stale(now + chrono::Duration::days(1), now, 0));
+    }
+
+    #[test]
+    fn only_time_sensitive_posts_go_stale_by_their_last_update() {
+        let config = FreshnessConfig { max_age_days: 365, tags: vec!["Rust".into()], git: false };
+        let aliases = BTreeMap::from([("rustlang".to_string(), "rust".to_string())]);
+        let post = |id: &str, date: &str, updated: Option<&str>, tags: &[&str]| {
+            let mut post = test_post(id, date);
+            post.meta.updated = updated.map(|updated| updated.parse().unwrap());
+            post.meta.tags = tags.iter().map(|t| t.to_string()).collect();
+            post
+        };
+        let mut posts = [
+            post("old", "2022-01-01T00:00:00Z", None, &["rust"]),
+            post("aliased", "2022-01-01T00:00:00Z", None, &["rustlang"]),
+            post("untagged", "2022-01-01T00:00:00Z", None, &["life"]),
+            post("updated", "2022-01-01T00:00:00Z", Some("2024-01-01T00:00:00Z"), &["Rust"]),
+            post("boundary", "2023-06-02T00:00:00Z", None, &["rust"]),
+            post("past", "2023-06-01T23:59:59Z", None, &["rust"])
+        ];
+        // The build time is passed in, so the outcome doesn't depend on when the test runs
+        let now = at("2024-06-01T00:00:00+00:00");
+        mark_stale(&mut posts, &config, &aliases, now, crate::dt_toml_to_chrono);
+        let stale: Vec<&str> = posts.iter().filter(|p| p.is_stale).map(|p| p.id.as_str()).collect();
+        assert_eq!(stale, ["old", "aliased", "past"]);
+    }
+}
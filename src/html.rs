@@ -0,0 +1,297 @@
+/// Extracts the values of `src` and `href` attributes from an HTML document.
+pub fn link_attributes(html: &str) -> Vec<&str> {
+    let mut links = Vec::new();
+    let mut rest = html;
+    while let Some(pos) = rest.find(['s', 'h']) {
+        let preceded_by_space = html[..html.len() - rest.len() + pos].ends_with(char::is_whitespace);
+        rest = &rest[pos..];
+        let Some(after) = rest.strip_prefix("src=").or_else(|| rest.strip_prefix("href="))
+            .filter(|_| preceded_by_space) else {
+                rest = &rest[1..];
+                continue
+            };
+
+        let (value, remainder) = match after.chars().next() {
+            Some(q @ ('"' | '\'')) => after[1..].split_once(q).unwrap_or((&after[1..], "")),
+            _ => after.split_once(|c: char| c.is_whitespace() || c == '>').unwrap_or((after, ""))
+        };
+        links.push(value);
+        rest = remainder;
+    }
+    links
+}
+
+/// Extracts the URLs in the `srcset` attributes of an HTML document, without their widths or
+/// densities.
+pub fn srcset_urls(html: &str) -> Vec<&str> {
+    let mut urls = Vec::new();
+    let mut rest = html;
+    while let Some(pos) = rest.find("srcset=") {
+        let preceded_by_space = html[..html.len() - rest.len() + pos].ends_with(char::is_whitespace);
+        rest = &rest[pos + "srcset=".len()..];
+        let Some(q @ ('"' | '\'')) = rest.chars().next().filter(|_| preceded_by_space) else { continue };
+        let (value, remainder) = rest[1..].split_once(q).unwrap_or((&rest[1..], ""));
+        for candidate in value.split(',') {
+            if let Some(url) = candidate.split_whitespace().next() {
+                urls.push(url);
+            }
+        }
+        rest = remainder;
+    }
+    urls
+}
+
+/// Resolves a link found in the page at `page` (relative to the output root) to an
+/// output-relative path, or `None` if it points outside of the site. `base_path` is the
+/// path the site is served under, like `/blog`, which root-relative links may start with.
+pub fn resolve_local_link(page: &str, link: &str, base_path: &str) -> Option<String> {
+    if link.is_empty() || link.starts_with('#') || link.starts_with("//") { return None }
+    if let Some((scheme, _)) = link.split_once(':') && !scheme.contains('/') { return None }
+    let link = match link.strip_prefix(base_path) {
+        Some(rest) if !base_path.is_empty() && rest.starts_with('/') => rest,
+        _ => link
+    };
+
+    let link = link.split(['#', '?']).next().unwrap_or_default();
+    let link = urlencoding::decode(link).map(|l| l.into_owned()).unwrap_or(link.to_string());
+    let joined = if let Some(absolute) = link.strip_prefix('/') {
+        absolute.to_string()
+    } else {
+        let dir = page.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+        format!("{}/{}", dir, link)
+    };
+
+    let mut parts: Vec<&str> = Vec::new();
+    for part in joined.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => { parts.pop(); },
+            part => parts.push(part)
+        }
+    }
+    let mut path = parts.join("/");
+    if path.is_empty() || joined.ends_with('/') {
+        path = if path.is_empty() { "index.html".into() } else { format!("{}/index.html", path) };
+    }
+    Some(path)
+}
+
+/// Rewrites the relative `src` and `href` links in the page at `page` (relative to the output
+/// root) to root-relative ones under `base_path`, so that they work wherever the page is served.
+pub fn root_relative_links(document: &str, page: &str, base_path: &str) -> String {
+    let mut rewritten = String::new();
+    let mut copied = 0;
+    for link in link_attributes(document) {
+        if link.is_empty() || link.starts_with(['/', '#', '?']) { continue }
+        if let Some((scheme, _)) = link.split_once(':') && !scheme.contains(['/', '?', '#']) { continue }
+
+        let (path, suffix) = link.split_at(link.find(['?', '#']).unwrap_or(link.len()));
+        let dir = page.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+        let mut parts: Vec<&str> = dir.split('/').filter(|part| !part.is_empty()).collect();
+        for part in path.split('/') {
+            match part {
+                "" | "." => (),
+                ".." => { parts.pop(); },
+                part => parts.push(part)
+            }
+        }
+        // Links to directories keep pointing at directories
+        let trailing = matches!(path.rsplit('/').next(), Some("" | "." | "..")) && !parts.is_empty();
+        let absolute = format!("{}/{}{}{}", base_path, parts.join("/"), if trailing { "/" } else { "" }, suffix);
+
+        let at = link.as_ptr() as usize - document.as_ptr() as usize;
+        rewritten.push_str(&document[copied..at]);
+        rewritten.push_str(&absolute);
+        copied = at + link.len();
+    }
+    rewritten.push_str(&document[copied..]);
+    rewritten
+}
+
+/// Inserts `html` right after the opening `<body>` tag, or at the start if there is none.
+pub fn insert_at_body_start(document: &str, html: &str) -> String {
+    let position = document.find("<body")
+        .and_then(|start| document[start..].find('>').map(|end| start + end + 1))
+        .unwrap_or(0);
+    format!("{}{}{}", &document[..position], html, &document[position..])
+}
+
+/// A box listing a post's build problems, shown at the top of its page when previewing.
+pub fn diagnostics_panel(diagnostics: &[crate::post::Diagnostic]) -> String {
+    let mut panel = String::from(concat!(
+        r#"<aside class="build-diagnostics" style="border: 2px solid #c60; background: #fff4e5; color: #000; padding: 8px 16px; margin: 8px; font: 14px sans-serif">"#,
+        "<strong>Build problems in this post</strong><ul>"
+    ));
+    for diagnostic in diagnostics {
+        panel.push_str(&format!("<li>{}: ", diagnostic.level));
+        let _ = pulldown_cmark_escape::escape_html(&mut panel, &diagnostic.message);
+        panel.push_str("</li>");
+    }
+    panel.push_str("</ul></aside>");
+    panel
+}
+
+/// Splits HTML into lines, closing the elements still open at the end of each line and
+/// reopening them on the next, so that every line can be wrapped on its own.
+pub fn split_lines(html: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut open: Vec<&str> = Vec::new();
+    let mut line = String::new();
+    let mut rest = html;
+    while let Some(c) = rest.chars().next() {
+        let end = match c {
+            '<' => rest.find('>').map_or(rest.len(), |i| i + 1),
+            '\n' => 1,
+            _ => rest.find(['<', '\n']).unwrap_or(rest.len())
+        };
+        let (token, after) = rest.split_at(end);
+        rest = after;
+        if token == "\n" {
+            for tag in open.iter().rev() {
+                let name = tag[1..].split([' ', '\t', '\n', '>']).next().unwrap_or_default();
+                line.push_str(&format!("</{}>", name));
+            }
+            lines.push(std::mem::take(&mut line));
+            line.extend(open.iter().copied());
+            continue
+        }
+        if token.starts_with("</") {
+            open.pop();
+        } else if c == '<' && !token.ends_with("/>") {
+            open.push(token);
+        }
+        line.push_str(token);
+    }
+    lines.push(line);
+    lines
+}
+
+/// Escapes the `<`, `>` and `&` that pulldown-latex copies from the TeX source into MathML as they
+/// are, like the `<` of `$a < b$`, keeping the MathML elements and character references.
+pub fn escape_mathml_text(mathml: &str) -> String {
+    let mut escaped = String::with_capacity(mathml.len());
+    let mut rest = mathml;
+    while let Some(i) = rest.find(['<', '>', '&']) {
+        escaped.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let markup = match rest.as_bytes()[0] {
+            b'<' => mathml_tag_len(rest),
+            b'&' => reference_len(rest),
+            _ => None
+        };
+        let len = markup.unwrap_or(1);
+        match markup {
+            Some(_) => escaped.push_str(&rest[..len]),
+            None => escaped.push_str(match rest.as_bytes()[0] { b'<' => "&lt;", b'>' => "&gt;", _ => "&amp;" })
+        }
+        rest = &rest[len..];
+    }
+    escaped.push_str(rest);
+    escaped
+}
+
+/// The length of the MathML start or end tag `html` starts with, if it does.
+fn mathml_tag_len(html: &str) -> Option<usize> {
+    let tag = html.strip_prefix('<')?;
+    let tag = tag.strip_prefix('/').unwrap_or(tag);
+    let name_len = tag.find(|c: char| !c.is_ascii_lowercase() && c != '-').unwrap_or(tag.len());
+    let name = &tag[..name_len];
+    if !(name.len() > 1 && name.starts_with('m') || matches!(name, "semantics" | "annotation" | "none")) {
+        return None
+    }
+    if !tag[name_len..].starts_with([' ', '/', '>']) { return None }
+    let mut quoted = false;
+    for (i, c) in html.char_indices().skip(1) {
+        match c {
+            '"' => quoted = !quoted,
+            '>' if !quoted => return Some(i + 1),
+            '<' if !quoted => return None,
+            _ => ()
+        }
+    }
+    None
+}
+
+/// The length of the character reference, like `&nbsp;` or `&#60;`, `html` starts with, if it does.
+fn reference_len(html: &str) -> Option<usize> {
+    let name = html.strip_prefix('&')?;
+    let end = name.find(';')?;
+    let valid = match name[..end].strip_prefix('#') {
+        Some(number) => match number.strip_prefix(['x', 'X']) {
+            Some(hex) => !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()),
+            None => !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+        },
+        None => end > 0 && name[..end].chars().all(|c| c.is_ascii_alphanumeric())
+    };
+    valid.then_some(end + 2)
+}
+
+/// Elements that separate words even without whitespace between them
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "br", "dd", "div", "dl", "dt", "figcaption", "figure",
+    "h1", "h2", "h3", "h4", "h5", "h6", "hr", "li", "ol", "p", "pre", "section", "table", "td", "th", "tr", "ul"
+];
+
+/// The text of an HTML fragment with tags removed, the common entities decoded and whitespace
+/// collapsed. Footnote references and the TeX source MathML keeps in `<annotation>` are left out.
+pub fn strip_tags(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    // End tag of the element being left out
+    let mut skip_until: Option<&str> = None;
+    while let Some(c) = rest.chars().next() {
+        let end = match c {
+            '<' => rest.find('>').map_or(rest.len(), |i| i + 1),
+            _ => rest.find('<').unwrap_or(rest.len())
+        };
+        let (token, after) = rest.split_at(end);
+        rest = after;
+        if let Some(end_tag) = skip_until {
+            if token == end_tag { skip_until = None }
+        } else if token.starts_with("<annotation") {
+            skip_until = Some("</annotation>");
+        } else if token.starts_with("<sup class=\"footnote-reference\"") {
+            skip_until = Some("</sup>");
+        } else if let Some(tag) = token.strip_prefix('<') {
+            let name = tag.trim_start_matches('/').split([' ', '\t', '\n', '/', '>']).next().unwrap_or_default();
+            if BLOCK_ELEMENTS.contains(&name) { text.push(' ') }
+        } else {
+            text.push_str(&token.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&#39;", "'").replace("&amp;", "&"));
+        }
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mathml_text_is_escaped_but_not_its_markup() {
+        assert_eq!(escape_mathml_text("<mo><</mo>"), "<mo>&lt;</mo>");
+        assert_eq!(escape_mathml_text("<mtext>a<b & c&gt;</mtext>"), "<mtext>a&lt;b &amp; c&gt;</mtext>");
+        assert_eq!(escape_mathml_text("<mo>></mo><mspace width=\"0.1667em\" />"), "<mo>&gt;</mo><mspace width=\"0.1667em\" />");
+        assert_eq!(escape_mathml_text("<merror style=\"border-color: #cc0\"><mtext>&nbsp;&#60;&#x3e;&;</mtext></merror>"),
+            "<merror style=\"border-color: #cc0\"><mtext>&nbsp;&#60;&#x3e;&amp;;</mtext></merror>");
+        // Only MathML elements are markup
+        assert_eq!(escape_mathml_text("<mi><b></b></mi>"), "<mi>&lt;b&gt;&lt;/b&gt;</mi>");
+    }
+
+    #[test]
+    fn split_lines_reopen_elements_across_lines() {
+        assert_eq!(split_lines("a\nb"), ["a", "b"]);
+        assert_eq!(split_lines("<span class=\"c\">/* one\ntwo */</span> x"),
+            ["<span class=\"c\">/* one</span>", "<span class=\"c\">two */</span> x"]);
+        assert_eq!(split_lines("<a><b>x\n</b>y<br/>\nz</a>"), ["<a><b>x</b></a>", "<a><b></b>y<br/></a>", "<a>z</a>"]);
+    }
+
+    #[test]
+    fn relative_links_become_root_relative() {
+        let page = "<a href=\"index.html\">x</a><img src='../img/a.png'><link href=style.css>";
+        assert_eq!(root_relative_links(page, "404.html", ""), "<a href=\"/index.html\">x</a><img src='/img/a.png'><link href=/style.css>");
+        assert_eq!(root_relative_links("<a href=\"../tags/?q=1#top\">", "posts/404.html", "/blog"), "<a href=\"/blog/tags/?q=1#top\">");
+        assert_eq!(root_relative_links("<a href=\".\">", "404.html", "/blog"), "<a href=\"/blog/\">");
+        let untouched = "<a href=\"/blog/index.html\"><a href=\"#top\"><a href=\"https://example.com/a\"><a href=\"mailto:a@b.c\"><a href=\"//cdn.example.com/a.js\">";
+        assert_eq!(root_relative_links(untouched, "404.html", "/blog"), untouched);
+    }
+}
@@ -0,0 +1,668 @@
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+
+/// Something in an imported post that has to be fixed by hand
+#[derive(Debug)]
+pub struct ImportIssue {
+    /// Post the issue is in, relative to the source directory
+    pub file: String,
+    /// Line in the source file, if the issue is in the body
+    pub line: Option<usize>,
+    pub message: String
+}
+
+/// What an import wrote and what it couldn't translate
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub written: Vec<PathBuf>,
+    pub skipped: Vec<PathBuf>,
+    pub issues: Vec<ImportIssue>
+}
+
+/// Front matter in this generator's format, with keys it doesn't know kept under `extra`
+#[derive(Debug, Default, Serialize)]
+struct ImportedMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date: Option<toml_datetime::Datetime>,
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lang: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    draft: bool,
+    #[serde(skip_serializing_if = "toml::Table::is_empty")]
+    extra: toml::Table
+}
+
+/// A YAML line with its indentation
+struct YamlLine<'a> {
+    indent: usize,
+    text: &'a str
+}
+
+/// Drops a ` # comment` after a plain YAML value.
+fn strip_comment(value: &str) -> &str {
+    if value.starts_with(['"', '\'']) { return value }
+    match value.find(" #") {
+        Some(at) => value[..at].trim_end(),
+        None => value
+    }
+}
+
+/// Splits a flow sequence like `[a, "b, c"]` into its items, without the brackets.
+fn split_flow(inner: &str) -> Vec<&str> {
+    let (mut items, mut start, mut quote) = (Vec::new(), 0, None);
+    for (i, c) in inner.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (',', None) => {
+                items.push(inner[start..i].trim());
+                start = i + 1;
+            },
+            _ => ()
+        }
+    }
+    items.push(inner[start..].trim());
+    items.into_iter().filter(|item| !item.is_empty()).collect()
+}
+
+/// Converts a YAML scalar or flow sequence. Dates stay strings, see `normalize_date`.
+fn yaml_scalar(value: &str) -> Result<Option<toml::Value>, String> {
+    let value = strip_comment(value.trim());
+    if let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        let mut unescaped = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                unescaped.push(c);
+                continue
+            }
+            match chars.next() {
+                Some('n') => unescaped.push('\n'),
+                Some('t') => unescaped.push('\t'),
+                Some(escaped) => unescaped.push(escaped),
+                None => unescaped.push(c)
+            }
+        }
+        return Ok(Some(toml::Value::String(unescaped)))
+    }
+    if let Some(inner) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        return Ok(Some(toml::Value::String(inner.replace("''", "'"))))
+    }
+    if let Some(inner) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+        let items = split_flow(inner).into_iter()
+            .filter_map(|item| yaml_scalar(item).transpose())
+            .collect::<Result<Vec<_>, _>>()?;
+        return Ok(Some(toml::Value::Array(items)))
+    }
+    if value.starts_with('{') {
+        return Err(format!("flow mappings like `{}` are not supported", value))
+    }
+    Ok(match value {
+        "" | "~" | "null" | "Null" | "NULL" => None,
+        "true" | "True" | "TRUE" => Some(toml::Value::Boolean(true)),
+        "false" | "False" | "FALSE" => Some(toml::Value::Boolean(false)),
+        _ => Some(value.parse::<i64>().map(toml::Value::Integer)
+            .or_else(|_| value.parse::<f64>().map(toml::Value::Float))
+            .unwrap_or_else(|_| toml::Value::String(value.to_string())))
+    })
+}
+
+fn is_list_item(line: &YamlLine) -> bool {
+    line.text.starts_with("- ") || line.text == "-"
+}
+
+/// Parses the list items at `indent`, starting at `*at`.
+fn yaml_list(lines: &[YamlLine], at: &mut usize, indent: usize) -> Result<Option<toml::Value>, String> {
+    let mut items = Vec::new();
+    while let Some(line) = lines.get(*at).filter(|l| l.indent == indent && is_list_item(l)) {
+        *at += 1;
+        let item = line.text[1..].trim();
+        if item.contains(": ") || item.ends_with(':') {
+            return Err(format!("lists of mappings like `{}` are not supported", line.text))
+        }
+        let value = if item.is_empty() { yaml_block(lines, at, Some(indent))? } else { yaml_scalar(item)? };
+        items.extend(value);
+    }
+    Ok(Some(toml::Value::Array(items)))
+}
+
+/// Parses the block of `lines` indented more than `parent`, starting at `*at`.
+fn yaml_block(lines: &[YamlLine], at: &mut usize, parent: Option<usize>) -> Result<Option<toml::Value>, String> {
+    let Some(first) = lines.get(*at).filter(|l| parent.is_none_or(|p| l.indent > p)) else { return Ok(None) };
+    let indent = first.indent;
+    if is_list_item(first) {
+        return yaml_list(lines, at, indent)
+    }
+
+    let mut table = toml::Table::new();
+    while let Some(line) = lines.get(*at).filter(|l| l.indent == indent) {
+        *at += 1;
+        let Some((key, value)) = line.text.split_once(':').filter(|(_, v)| v.is_empty() || v.starts_with(' ')) else {
+            return Err(format!("expected `key: value`, found `{}`", line.text))
+        };
+        let key = key.trim().trim_matches(['"', '\'']).to_string();
+        let value = value.trim();
+        let value = match strip_comment(value) {
+            // Lists under a key may be indented as much as the key itself
+            "" if lines.get(*at).is_some_and(|l| l.indent == indent && is_list_item(l)) => yaml_list(lines, at, indent)?,
+            "" => yaml_block(lines, at, Some(indent))?,
+            folding @ ("|" | ">" | "|-" | ">-") => {
+                let mut block = Vec::new();
+                while let Some(line) = lines.get(*at).filter(|l| l.indent > indent) {
+                    block.push(line.text);
+                    *at += 1;
+                }
+                let separator = if folding.starts_with('|') { "\n" } else { " " };
+                Some(toml::Value::String(block.join(separator)))
+            },
+            value => yaml_scalar(value)?
+        };
+        if let Some(value) = value {
+            table.insert(key, value);
+        }
+    }
+    if lines.get(*at).is_some_and(|l| parent.is_none_or(|p| l.indent > p)) {
+        return Err(format!("unexpected indentation at `{}`", lines[*at].text))
+    }
+    Ok(Some(toml::Value::Table(table)))
+}
+
+/// Parses YAML front matter, supporting the mappings, lists, scalars and block strings front
+/// matter usually has, but not anchors, tags or multiple documents.
+pub fn parse_yaml(source: &str) -> Result<toml::Table, String> {
+    let lines: Vec<YamlLine> = source.lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|line| YamlLine { indent: line.len() - line.trim_start().len(), text: line.trim() })
+        .collect();
+    if lines.iter().any(|l| l.text.starts_with(['&', '*', '!']) || l.text.contains(": &") || l.text.contains(": *")) {
+        return Err("anchors, aliases and tags are not supported".into())
+    }
+    match yaml_block(&lines, &mut 0, None)? {
+        Some(toml::Value::Table(table)) => Ok(table),
+        None => Ok(toml::Table::new()),
+        Some(_) => Err("the front matter is not a mapping".into())
+    }
+}
+
+/// Reads a date like `2024-01-02`, `2024-01-02 10:00:00 +0200` or `2024-01-02T10:00:00Z`
+/// as a TOML datetime, keeping any offset.
+pub fn normalize_date(value: &toml::Value) -> Option<toml_datetime::Datetime> {
+    let text = match value {
+        toml::Value::Datetime(datetime) => return Some(*datetime),
+        toml::Value::String(text) => text.trim(),
+        _ => return None
+    };
+    let mut parts = text.split_whitespace();
+    let date = parts.next()?;
+    let mut normalized = date.to_string();
+    if let Some(time) = parts.next() {
+        normalized.push('T');
+        normalized.push_str(time);
+    }
+    if let Some(offset) = parts.next() {
+        normalized.push_str(offset);
+    }
+    if parts.next().is_some() { return None }
+    // `+0200` and `+02` aren't valid TOML offsets, `+02:00` is
+    if let Some(sign) = normalized.rfind(['+', '-']).filter(|&at| at > 10) {
+        let offset = &normalized[sign + 1..];
+        let fixed = match offset.len() {
+            2 if offset.bytes().all(|b| b.is_ascii_digit()) => Some(format!("{}:00", offset)),
+            4 if offset.bytes().all(|b| b.is_ascii_digit()) => Some(format!("{}:{}", &offset[..2], &offset[2..])),
+            _ => None
+        };
+        if let Some(fixed) = fixed {
+            normalized.replace_range(sign + 1.., &fixed);
+        }
+    }
+    normalized.parse().ok()
+}
+
+/// Adds the strings in `value` to `tags`, skipping repeats.
+fn push_tags(tags: &mut Vec<String>, value: toml::Value) -> Result<(), toml::Value> {
+    let items = match value {
+        toml::Value::Array(items) => items,
+        toml::Value::String(tag) => vec![toml::Value::String(tag)],
+        value => return Err(value)
+    };
+    for item in items {
+        match item {
+            toml::Value::String(tag) => if !tags.contains(&tag) { tags.push(tag) },
+            item => return Err(item)
+        }
+    }
+    Ok(())
+}
+
+/// Maps Hugo and Jekyll front matter keys onto this generator's, keeping the others under `extra`.
+fn map_keys(front_matter: toml::Table, file: &str, issues: &mut Vec<ImportIssue>) -> ImportedMeta {
+    let mut meta = ImportedMeta::default();
+    let mut issue = |message: String| issues.push(ImportIssue { file: file.to_string(), line: None, message });
+    for (key, value) in front_matter {
+        match (key.as_str(), value) {
+            ("title", toml::Value::String(title)) => meta.title = Some(title),
+            ("date", value) => match normalize_date(&value) {
+                Some(date) => meta.date = Some(date),
+                None => {
+                    issue(format!("could not read the date `{}`, kept it under `extra`", value));
+                    meta.extra.insert(key, value);
+                }
+            },
+            ("tags" | "categories" | "category", value) => if let Err(value) = push_tags(&mut meta.tags, value) {
+                issue(format!("`{}` has a value that isn't a tag: `{}`", key, value));
+            },
+            ("summary" | "description" | "excerpt", toml::Value::String(summary)) if meta.summary.is_none() => meta.summary = Some(summary),
+            ("lang" | "language", toml::Value::String(lang)) => meta.lang = Some(lang),
+            ("draft", toml::Value::Boolean(draft)) => meta.draft = draft,
+            ("published", toml::Value::Boolean(published)) => meta.draft = !published,
+            ("aliases" | "redirect_from" | "slug" | "url" | "permalink", value) => {
+                issue(format!("`{}` changes the URL of the post, which isn't imported", key));
+                meta.extra.insert(key, value);
+            },
+            (_, value) => { meta.extra.insert(key, value); }
+        }
+    }
+    meta
+}
+
+/// Turns `{{< figure src="a.png" alt="A" >}}` and similar image shortcodes into markdown
+/// images, returning `None` for any other shortcode.
+fn image_shortcode(shortcode: &str) -> Option<String> {
+    let inner = shortcode.trim_start_matches("{{<").trim_start_matches("{{%")
+        .trim_end_matches(">}}").trim_end_matches("%}}").trim();
+    let (name, rest) = inner.split_once(char::is_whitespace)?;
+    if !matches!(name, "figure" | "img" | "image") { return None }
+
+    let mut attributes = std::collections::HashMap::new();
+    let mut rest = rest.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let value = value.trim_start();
+        let (value, remaining) = match value.chars().next()? {
+            quote @ ('"' | '\'') => {
+                let end = value[1..].find(quote)? + 1;
+                (&value[1..end], &value[end + 1..])
+            },
+            _ => value.split_once(char::is_whitespace).unwrap_or((value, ""))
+        };
+        attributes.insert(key.trim(), value);
+        rest = remaining.trim();
+    }
+    if !rest.is_empty() { return None }
+
+    let src = attributes.remove("src")?;
+    let alt = attributes.remove("alt").or_else(|| attributes.remove("caption")).unwrap_or_default();
+    let title = attributes.remove("title");
+    // Anything else, like a link or width, would be lost
+    if !attributes.is_empty() { return None }
+    Some(match title {
+        Some(title) => format!("![{}]({} \"{}\")", alt, src, title.replace('"', "\\\"")),
+        None => format!("![{}]({})", alt, src)
+    })
+}
+
+/// Rewrites image shortcodes in `body` and reports the shortcodes, Liquid tags and ref links
+/// it leaves in, with `first_line` being the line of the source file the body starts on.
+/// A shortcode that isn't closed on its line is carried over to the following lines.
+fn convert_body(body: &str, file: &str, first_line: usize, issues: &mut Vec<ImportIssue>) -> String {
+    let mut converted = String::new();
+    let mut in_code = false;
+    // An unclosed shortcode and the line it starts on
+    let mut open: Option<(String, usize)> = None;
+    for (i, line) in body.split_inclusive('\n').enumerate() {
+        let line_number = first_line + i;
+        let (text, text_line) = match open.take() {
+            Some((mut shortcode, start_line)) => {
+                shortcode.push_str(line);
+                (shortcode, start_line)
+            },
+            None => {
+                if line.trim_start().starts_with("```") || line.trim_start().starts_with("~~~") {
+                    in_code = !in_code;
+                }
+                if in_code {
+                    converted.push_str(line);
+                    continue
+                }
+                (line.to_string(), line_number)
+            }
+        };
+
+        let mut rest = text.as_str();
+        while let Some(start) = [rest.find("{{<"), rest.find("{{%")].into_iter().flatten().min() {
+            let shortcode_line = text_line + text[..text.len() - rest.len() + start].matches('\n').count();
+            let close = if rest[start..].starts_with("{{<") { ">}}" } else { "%}}" };
+            let Some(end) = rest[start..].find(close).map(|end| start + end + close.len()) else {
+                converted.push_str(&rest[..start]);
+                open = Some((rest[start..].to_string(), shortcode_line));
+                rest = "";
+                break
+            };
+            converted.push_str(&rest[..start]);
+            let shortcode = &rest[start..end];
+            match image_shortcode(shortcode) {
+                Some(image) => converted.push_str(&image),
+                None => {
+                    let message = if shortcode.contains("ref ") || shortcode.contains("relref ") {
+                        format!("ref link `{}` needs to be replaced with a link", shortcode)
+                    } else {
+                        format!("shortcode `{}` could not be translated", shortcode)
+                    };
+                    issues.push(ImportIssue { file: file.to_string(), line: Some(shortcode_line), message });
+                    converted.push_str(shortcode);
+                }
+            }
+            rest = &rest[end..];
+        }
+        converted.push_str(rest);
+
+        if line.contains("{%") || line.contains("{{ site.") || line.contains("{{site.") {
+            issues.push(ImportIssue { file: file.to_string(), line: Some(line_number), message: "Liquid tag needs to be replaced".into() });
+        }
+    }
+    if let Some((shortcode, line)) = open {
+        let message = format!("unterminated shortcode `{}`", shortcode.lines().next().unwrap_or_default());
+        issues.push(ImportIssue { file: file.to_string(), line: Some(line), message });
+        converted.push_str(&shortcode);
+    }
+    converted
+}
+
+/// Splits `source` into its front matter, parsed from YAML between `---` or TOML between
+/// `+++`, the body, and the line the body starts on.
+fn split_front_matter(source: &str) -> Result<(toml::Table, &str, usize), String> {
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+    let delimiter = match source.lines().next().map(str::trim_end) {
+        Some("---") => "---",
+        Some("+++") => "+++",
+        _ => return Ok((toml::Table::new(), source, 1))
+    };
+    let after_open = &source[source.find('\n').map_or(source.len(), |at| at + 1)..];
+    let mut offset = 0;
+    for line in after_open.split_inclusive('\n') {
+        if line.trim_end() == delimiter {
+            let front_matter = &after_open[..offset];
+            let body = &after_open[offset + line.len()..];
+            let table = if delimiter == "---" {
+                parse_yaml(front_matter)?
+            } else {
+                toml::from_str(front_matter).map_err(|e| e.to_string())?
+            };
+            return Ok((table, body, front_matter.lines().count() + 3))
+        }
+        offset += line.len();
+    }
+    Err(format!("the front matter is not closed with `{}`", delimiter))
+}
+
+/// Converts one post, returning the contents to write.
+fn convert(source: &str, file: &str, issues: &mut Vec<ImportIssue>) -> Result<String, String> {
+    let (front_matter, body, first_line) = split_front_matter(source)?;
+    let meta = map_keys(front_matter, file, issues);
+    let front_matter = toml::to_string(&meta).map_err(|e| e.to_string())?;
+    let body = convert_body(body, file, first_line, issues);
+    Ok(format!("+++\n{}+++\n{}", front_matter, body))
+}
+
+/// Writes `contents` to `path`, unless it exists and `force` isn't set.
+fn write_new(path: &Path, contents: &[u8], force: bool, report: &mut ImportReport) -> Result<(), String> {
+    if path.exists() && !force {
+        report.skipped.push(path.to_path_buf());
+        return Ok(())
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("could not create `{}`: {}", parent.display(), e))?;
+    }
+    std::fs::write(path, contents).map_err(|e| format!("could not write `{}`: {}", path.display(), e))?;
+    report.written.push(path.to_path_buf());
+    Ok(())
+}
+
+/// Converts the markdown posts under `source_dir` into posts under `posts_dir`, keeping their
+/// paths. Other files in the directory of an `index.md`, like images, are copied along.
+pub fn import(source_dir: &Path, posts_dir: &Path, force: bool) -> Result<ImportReport, String> {
+    let mut report = ImportReport::default();
+    let mut bundles = Vec::new();
+    for entry in walkdir::WalkDir::new(source_dir).sort_by_file_name() {
+        let entry = entry.map_err(|e| format!("could not read `{}`: {}", source_dir.display(), e))?;
+        let path = entry.path();
+        if !entry.file_type().is_file() || path.extension().and_then(|e| e.to_str()) != Some("md") { continue }
+        let Ok(relative) = path.strip_prefix(source_dir) else { continue };
+        let file = relative.to_string_lossy().replace('\\', "/");
+        if path.file_name().is_some_and(|name| name == "_index.md") {
+            report.issues.push(ImportIssue { file, line: None, message: "section pages are not imported".into() });
+            continue
+        }
+
+        let source = std::fs::read_to_string(path).map_err(|e| format!("could not read `{}`: {}", path.display(), e))?;
+        let converted = match convert(&source, &file, &mut report.issues) {
+            Ok(converted) => converted,
+            Err(e) => {
+                report.issues.push(ImportIssue { file, line: None, message: format!("not imported, {}", e) });
+                continue
+            }
+        };
+        write_new(&posts_dir.join(relative), converted.as_bytes(), force, &mut report)?;
+        if path.file_name().is_some_and(|name| name == "index.md") && let Some(dir) = path.parent() {
+            bundles.push(dir.to_path_buf());
+        }
+    }
+
+    for bundle in bundles {
+        for entry in walkdir::WalkDir::new(&bundle).sort_by_file_name() {
+            let entry = entry.map_err(|e| format!("could not read `{}`: {}", bundle.display(), e))?;
+            let path = entry.path();
+            if !entry.file_type().is_file() || path.extension().and_then(|e| e.to_str()) == Some("md") { continue }
+            let Ok(relative) = path.strip_prefix(source_dir) else { continue };
+            let contents = std::fs::read(path).map_err(|e| format!("could not read `{}`: {}", path.display(), e))?;
+            write_new(&posts_dir.join(relative), &contents, force, &mut report)?;
+        }
+    }
+    Ok(report)
+}
+
+/// Prints what `import` did and what has to be fixed by hand.
+pub fn print_report(report: &ImportReport) {
+    log!("info: imported {} file(s)", report.written.len());
+    if !report.skipped.is_empty() {
+        let mut message = format!("warning: skipped {} existing file(s), pass --force to overwrite them:", report.skipped.len());
+        for path in &report.skipped {
+            message.push_str(&format!("\n    {}", path.display()));
+        }
+        log!("{message}");
+    }
+    if !report.issues.is_empty() {
+        let mut message = format!("warning: {} construct(s) could not be translated:", report.issues.len());
+        for issue in &report.issues {
+            match issue.line {
+                Some(line) => message.push_str(&format!("\n    {}:{}: {}", issue.file, line, issue.message)),
+                None => message.push_str(&format!("\n    {}: {}", issue.file, issue.message))
+            }
+        }
+        log!("{message}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn imported(source: &str) -> (toml::Table, Vec<ImportIssue>) {
+        let mut issues = Vec::new();
+        let converted = convert(source, "post.md", &mut issues).unwrap();
+        let front_matter = converted.strip_prefix("+++\n").unwrap().split("+++\n").next().unwrap();
+        (toml::from_str(front_matter).unwrap(), issues)
+    }
+
+    #[test]
+    fn quoted_and_plain_scalars() {
+        let table = parse_yaml(concat!(
+            "double: \"a \\\"quoted\\\" # not a comment\\n\"\n",
+            "single: 'it''s'\n",
+            "plain: some text # a comment\n",
+            "number: 42\n",
+            "float: 1.5\n",
+            "yes: true\n",
+            "no: False\n",
+            "nothing: ~\n",
+            "\"quoted key\": value\n"
+        )).unwrap();
+        assert_eq!(table["double"].as_str(), Some("a \"quoted\" # not a comment\n"));
+        assert_eq!(table["single"].as_str(), Some("it's"));
+        assert_eq!(table["plain"].as_str(), Some("some text"));
+        assert_eq!(table["number"].as_integer(), Some(42));
+        assert_eq!(table["float"].as_float(), Some(1.5));
+        assert_eq!(table["yes"].as_bool(), Some(true));
+        assert_eq!(table["no"].as_bool(), Some(false));
+        assert!(!table.contains_key("nothing"));
+        assert_eq!(table["quoted key"].as_str(), Some("value"));
+    }
+
+    #[test]
+    fn lists_and_blocks() {
+        let table = parse_yaml(concat!(
+            "flow: [a, \"b, c\", 'd']\n",
+            "block:\n",
+            "  - one\n",
+            "  - \"two\"\n",
+            "unindented:\n",
+            "- x\n",
+            "- y\n",
+            "nested:\n",
+            "  inner: value\n",
+            "literal: |\n",
+            "  line one\n",
+            "  line two\n",
+            "folded: >\n",
+            "  folded\n",
+            "  text\n"
+        )).unwrap();
+        let strings = |key: &str| table[key].as_array().unwrap().iter().map(|v| v.as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(strings("flow"), ["a", "b, c", "d"]);
+        assert_eq!(strings("block"), ["one", "two"]);
+        assert_eq!(strings("unindented"), ["x", "y"]);
+        assert_eq!(table["nested"]["inner"].as_str(), Some("value"));
+        assert_eq!(table["literal"].as_str(), Some("line one\nline two"));
+        assert_eq!(table["folded"].as_str(), Some("folded text"));
+    }
+
+    #[test]
+    fn malformed_yaml() {
+        assert!(parse_yaml("title: [a, b]\nmeta: {a: 1}\n").is_err());
+        assert!(parse_yaml("base: &base\n  a: 1\n").is_err());
+        assert!(parse_yaml("no colon here\n").is_err());
+        assert!(parse_yaml("a: 1\n    b: 2\n").is_err());
+        assert!(parse_yaml("list:\n  - key: value\n").is_err());
+        assert!(parse_yaml("- just\n- a list\n").is_err());
+        assert!(parse_yaml("").unwrap().is_empty());
+
+        let mut issues = Vec::new();
+        assert!(convert("---\ntitle: unclosed\n", "post.md", &mut issues).is_err());
+    }
+
+    #[test]
+    fn jekyll_keys() {
+        let (meta, issues) = imported(concat!(
+            "---\n",
+            "title: Jekyll post\n",
+            "categories: [notes, rust]\n",
+            "tags: rust\n",
+            "excerpt: Short\n",
+            "published: false\n",
+            "redirect_from: /old/\n",
+            "layout: post\n",
+            "---\n",
+            "Body\n"
+        ));
+        assert_eq!(meta["title"].as_str(), Some("Jekyll post"));
+        let tags: Vec<_> = meta["tags"].as_array().unwrap().iter().map(|t| t.as_str().unwrap()).collect();
+        assert_eq!(tags, ["notes", "rust"]);
+        assert_eq!(meta["summary"].as_str(), Some("Short"));
+        assert_eq!(meta["draft"].as_bool(), Some(true));
+        assert_eq!(meta["extra"]["layout"].as_str(), Some("post"));
+        assert_eq!(meta["extra"]["redirect_from"].as_str(), Some("/old/"));
+        assert!(issues.iter().any(|issue| issue.message.contains("redirect_from")));
+    }
+
+    #[test]
+    fn hugo_keys() {
+        let (meta, issues) = imported(concat!(
+            "+++\n",
+            "title = \"Hugo post\"\n",
+            "description = \"Described\"\n",
+            "language = \"fi\"\n",
+            "draft = true\n",
+            "aliases = [\"/old\"]\n",
+            "+++\n",
+            "Body\n"
+        ));
+        assert_eq!(meta["title"].as_str(), Some("Hugo post"));
+        assert_eq!(meta["summary"].as_str(), Some("Described"));
+        assert_eq!(meta["lang"].as_str(), Some("fi"));
+        assert_eq!(meta["draft"].as_bool(), Some(true));
+        assert!(meta["extra"].get("aliases").is_some());
+        assert_eq!(issues.len(), 1);
+    }
+
+    #[test]
+    fn dates() {
+        let date = |text: &str| normalize_date(&toml::Value::String(text.into())).map(|d| d.to_string());
+        assert_eq!(date("2024-01-02").as_deref(), Some("2024-01-02"));
+        assert_eq!(date("2024-01-02 10:00:00").as_deref(), Some("2024-01-02T10:00:00"));
+        assert_eq!(date("2024-01-02 10:00:00 +0200").as_deref(), Some("2024-01-02T10:00:00+02:00"));
+        assert_eq!(date("2024-01-02 10:00:00 -05").as_deref(), Some("2024-01-02T10:00:00-05:00"));
+        assert_eq!(date("2024-01-02T10:00:00Z").as_deref(), Some("2024-01-02T10:00:00Z"));
+        assert_eq!(date("yesterday"), None);
+        assert_eq!(date("2024-01-02 10:00:00 +0200 extra"), None);
+        assert_eq!(normalize_date(&toml::Value::Integer(2024)), None);
+
+        let (meta, issues) = imported("---\ntitle: Dated\ndate: 2024-03-01 08:30:00 +0100\n---\n");
+        assert_eq!(meta["date"].as_datetime().map(|d| d.to_string()).as_deref(), Some("2024-03-01T08:30:00+01:00"));
+        assert!(issues.is_empty());
+        let (meta, issues) = imported("---\ntitle: Undated\ndate: someday\n---\n");
+        assert!(meta.get("date").is_none());
+        assert_eq!(meta["extra"]["date"].as_str(), Some("someday"));
+        assert_eq!(issues.len(), 1);
+    }
+
+    #[test]
+    fn image_shortcodes() {
+        let mut issues = Vec::new();
+        let body = convert_body("{{< figure src=\"a.png\" alt=\"An A\" >}}\n{{< ref \"other.md\" >}}\n", "post.md", 5, &mut issues);
+        assert!(body.starts_with("![An A](a.png)\n"));
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].line, Some(6));
+    }
+
+    #[test]
+    fn multi_line_shortcodes() {
+        let mut issues = Vec::new();
+        let body = convert_body("Intro {{< figure\n  src=\"a.png\"\n  alt=\"An A\" >}} after\n", "post.md", 5, &mut issues);
+        assert_eq!(body, "Intro ![An A](a.png) after\n");
+        assert!(issues.is_empty());
+
+        let body = convert_body("Intro {{< gist a\nb >}}\n", "post.md", 5, &mut issues);
+        assert_eq!(body, "Intro {{< gist a\nb >}}\n");
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].line, Some(5));
+
+        issues.clear();
+        let body = convert_body("Intro {{< figure\nsrc=\"a.png\"\n", "post.md", 5, &mut issues);
+        assert_eq!(body, "Intro {{< figure\nsrc=\"a.png\"\n");
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].line, Some(5));
+        assert!(issues[0].message.starts_with("unterminated shortcode"));
+    }
+
+    #[test]
+    fn earliest_shortcode_first() {
+        let mut issues = Vec::new();
+        let body = convert_body("{{% note %}} and {{< figure src=\"a.png\" >}}\n", "post.md", 1, &mut issues);
+        assert_eq!(body, "{{% note %}} and ![](a.png)\n");
+        assert!(issues.iter().any(|issue| issue.message.contains("{{% note %}}")));
+    }
+}
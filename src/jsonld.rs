@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+use crate::post::Post;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthorConfig {
+    pub name: String,
+    /// Link to the author's page, relative to the site root or absolute
+    pub url: Option<String>
+}
+
+#[derive(Debug, Serialize)]
+struct Person {
+    #[serde(rename = "@type")]
+    kind: &'static str,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>
+}
+
+/// A single author is given as an object, several as an array
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Authors {
+    One(Person),
+    Many(Vec<Person>)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BlogPosting<'a> {
+    #[serde(rename = "@context")]
+    context: &'static str,
+    #[serde(rename = "@type")]
+    kind: &'static str,
+    headline: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    date_published: String,
+    date_modified: String,
+    author: Authors,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    keywords: Vec<&'a str>,
+    url: String,
+    main_entity_of_page: String
+}
+
+/// Google ignores longer headlines
+const MAX_HEADLINE_CHARS: usize = 110;
+
+/// Makes `url` absolute by prefixing site-relative URLs with `base_url`. Links that already
+/// start with the path of `base_url`, like stored assets, only get its origin.
+pub fn absolute_url(base_url: &str, url: &str) -> String {
+    if url.contains("://") { return url.to_string() }
+    if let Ok(base) = url::Url::parse(base_url) {
+        let path = base.path().trim_end_matches('/');
+        if !path.is_empty() && url.strip_prefix(path).is_some_and(|rest| rest.starts_with('/')) {
+            return format!("{}{}", base.origin().ascii_serialization(), url)
+        }
+    }
+    format!("{}/{}", base_url.trim_end_matches('/'), url.trim_start_matches('/'))
+}
+
+/// Serializes `BlogPosting` structured data for the post, or returns the names of the missing
+/// or invalid fields. Authors named like the configured `author` get its URL. The result has `<`, `>` and `&` escaped so it can't end a `<script>` tag early.
+pub fn blog_posting(
+    post: &Post,
+    url: &str,
+    base_url: &str,
+    author: Option<&AuthorConfig>,
+    published: chrono::DateTime<chrono::FixedOffset>,
+    updated: Option<chrono::DateTime<chrono::FixedOffset>>
+) -> Result<String, Vec<&'static str>> {
+    let mut missing = Vec::new();
+    let headline = post.title_plain.trim();
+    if headline.is_empty() || headline.chars().count() > MAX_HEADLINE_CHARS { missing.push("headline") }
+    if post.meta.authors.is_empty() { missing.push("author") }
+    if !missing.is_empty() { return Err(missing) }
+
+    let mut people: Vec<Person> = post.meta.authors.iter().map(|name| Person {
+        kind: "Person",
+        name: name.clone(),
+        url: author.filter(|a| a.name == *name).and_then(|a| a.url.as_deref()).map(|u| absolute_url(base_url, u))
+    }).collect();
+
+    let posting = BlogPosting {
+        context: "https://schema.org",
+        kind: "BlogPosting",
+        headline,
+        description: Some(post.description.as_str()).filter(|d| !d.is_empty()),
+        date_published: crate::rfc3339(&published),
+        date_modified: crate::rfc3339(&updated.unwrap_or(published)),
+        author: if people.len() == 1 { Authors::One(people.remove(0)) } else { Authors::Many(people) },
+        image: post.cover_url.as_ref().or(post.images.first()).map(|i| absolute_url(base_url, i)),
+        keywords: post.meta.tags.iter().map(String::as_str).collect(),
+        url: absolute_url(base_url, url),
+        main_entity_of_page: absolute_url(base_url, url)
+    };
+    let json = serde_json::to_string(&posting).map_err(|_| vec!["serialization"])?;
+    Ok(json.replace('<', "\\u003c").replace('>', "\\u003e").replace('&', "\\u0026"))
+}
@@ -0,0 +1,2907 @@
+/// Prints a line of output like `println!`, at the level given by its prefix like `warning:`.
+#[macro_export]
+macro_rules! log {
+    ($($arg:tt)*) => { $crate::logging::print(&format!($($arg)*)) }
+}
+
+mod archive;
+mod cache;
+mod code;
+mod compare;
+mod config;
+mod data;
+mod defaults;
+mod encrypt;
+mod export;
+mod feed;
+mod freshness;
+mod html;
+mod import;
+mod jsonld;
+mod lint;
+pub mod logging;
+pub mod lock;
+pub mod output;
+mod paginate;
+mod permalink;
+mod post;
+mod processor;
+mod progress;
+mod raster;
+mod related;
+pub mod replay;
+mod report;
+mod search;
+mod series;
+mod sitemap;
+mod slug;
+mod stats;
+mod taxonomy;
+mod timings;
+mod toc;
+mod typography;
+mod urls;
+#[cfg(feature = "dev")]
+pub mod watch;
+mod weight;
+
+use std::{cell::RefCell, collections::{BTreeMap, HashMap}, io::Read, path::{Path, PathBuf}, sync::Arc};
+use clap::{Parser, Subcommand};
+use output::{AssetStore, FsSink, OutputSink};
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+use post::{Post, PostBuilder, PostSummary};
+use config::SiteConfig;
+
+fn parse_dir(s: &str) -> Result<PathBuf, String> {
+    let path = std::fs::canonicalize(s).map_err(|err| err.to_string())?;
+    if path.is_dir() {
+        Ok(path)
+    } else {
+        Err("The provided path must be a directory".into())
+    }
+}
+
+fn parse_now(s: &str) -> Result<chrono::DateTime<chrono::FixedOffset>, String> {
+    chrono::DateTime::parse_from_rfc3339(s).map_err(|err| err.to_string())
+}
+
+fn parse_base_url(s: &str) -> Result<String, String> {
+    let url = url::Url::parse(s).map_err(|err| err.to_string())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("the base URL must be an http or https address".into())
+    }
+    Ok(s.to_string())
+}
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about)]
+pub struct Args {
+    #[arg(help="Directory for input files", value_parser=parse_dir)]
+    pub in_dir: PathBuf,
+    #[arg(help="Directory for output files", value_parser=parse_dir)]
+    pub out_dir: PathBuf,
+    #[arg(long, help="Run the external commands configured as `processors` in site.toml")]
+    pub enable_processors: bool,
+    #[arg(long, help="Include posts dated in the future")]
+    pub future: bool,
+    #[arg(long, help="Include posts marked as drafts")]
+    pub drafts: bool,
+    #[arg(long, help="Build as if the current time was this RFC 3339 timestamp", value_parser=parse_now)]
+    pub now: Option<chrono::DateTime<chrono::FixedOffset>>,
+    #[arg(long, value_name="URL", help="Address the site will be hosted at, overriding `base_url` in site.toml", value_parser=parse_base_url)]
+    pub base_url: Option<String>,
+    #[arg(long, help="Exit with an error if anything went wrong during the build, and treat a missing `tag` template as an error instead of using a built-in one")]
+    pub strict: bool,
+    #[arg(long, help="Print the metadata of each post after applying the defaults from `_defaults.toml` files")]
+    pub debug_metadata: bool,
+    #[arg(long, value_name="OUTPUT", help="Print the template context of the page rendered to this output path (e.g. `index.html`) as JSON")]
+    pub dump_context: Option<String>,
+    #[arg(long, value_name="FILE", help="Write the context requested with --dump-context to a file instead", requires="dump_context")]
+    pub dump_context_to: Option<PathBuf>,
+    #[arg(long, help="Report how long each page took to render, how large its context was, and the slowest templates")]
+    pub timings: bool,
+    #[arg(short, long, help="Print debug lines, and a line for every post and page instead of a progress line, even in a terminal")]
+    pub verbose: bool,
+    #[arg(short, long, help="Only print errors", conflicts_with="verbose")]
+    pub quiet: bool,
+    #[cfg(feature = "dev")]
+    #[arg(short, long, help="Watch for changes to the input directory and recompile")]
+    pub watch: bool,
+    #[cfg(feature = "dev")]
+    #[arg(short, long, help="Start dev server and watch for changes")]
+    pub dev: bool,
+    #[cfg(feature = "dev")]
+    #[arg(long, help="Serve the output directory after building, until interrupted, without watching for changes")]
+    pub serve: bool,
+    #[cfg(feature = "dev")]
+    #[arg(short, long, help="Port to use for dev server", default_value="8080")]
+    pub port: u16,
+    #[cfg(feature = "dev")]
+    #[arg(long, value_name="DIR", help="Save every HTML page served by the dev server to this directory", requires="dev")]
+    pub record: Option<PathBuf>,
+    #[cfg(feature = "dev")]
+    #[arg(long, value_name="COUNT", help="Number of recordings to keep, the oldest are removed first, 0 keeps them all", default_value="200")]
+    pub record_max: usize,
+    #[arg(long, help="Write pages at the old URLs of posts that moved since the last build, redirecting to the new ones")]
+    pub write_aliases: bool,
+    #[arg(long, help="Rebuild every post instead of reusing the ones whose inputs haven't changed since the last build")]
+    pub force: bool,
+    #[arg(long, help="Build even if another build of the site seems to be running")]
+    pub break_lock: bool,
+    #[arg(long, value_name="DIR", help="Compare the built pages against the recordings in this directory")]
+    pub replay_check: Option<PathBuf>,
+    #[arg(long, value_name="OLD_OUT_DIR", help="Compare the outputs against a previous build in this directory and write an HTML report of the differences", value_parser=parse_dir)]
+    pub compare: Option<PathBuf>,
+    #[arg(long, value_name="FILE", help="File to write the --compare report to", default_value="compare-report.html", requires="compare")]
+    pub compare_report: PathBuf,
+    #[arg(long, value_name="FILE", help="Write the posts, scheduled posts, assets, output sizes, phase durations, warnings and errors of the build to this file as JSON")]
+    pub report: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Write a single post as a self-contained HTML file, with its images and stylesheets inlined
+    Export {
+        #[arg(help="Id of the post to export")]
+        id: String,
+        #[arg(long, help="File to write to, defaults to `<id>.html` in the current directory")]
+        out: Option<PathBuf>
+    },
+    /// Create a post with a metadata block for the given title and the current time
+    New {
+        #[arg(help="Title of the post, also used to name its file")]
+        title: String,
+        #[arg(long, help="Create `posts/<slug>/index.md` instead of `posts/<slug>.md`, for posts with their own images")]
+        dir: bool,
+        #[arg(long = "tag", value_name="TAG", help="Tag the post, can be given more than once")]
+        tags: Vec<String>
+    },
+    /// Convert Hugo or Jekyll posts into posts of this site, reporting what has to be fixed by hand
+    Import {
+        #[arg(help="Directory of posts to import, like Hugo's `content/posts`", value_parser=parse_dir)]
+        source: PathBuf,
+        #[arg(long, help="Overwrite posts and files that already exist")]
+        force: bool
+    },
+    /// Inspect the site configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommand {
+    /// Print the effective configuration, site.toml merged over the defaults, as TOML
+    DumpDefaults
+}
+
+#[derive(Debug, Serialize)]
+pub struct BuiltPost {
+    pub id: String,
+    pub source: PathBuf,
+    pub url: String,
+    pub title: String,
+    pub date: String
+}
+
+/// What a build produced, shared with the dev server.
+#[derive(Debug, Default, Serialize)]
+pub struct BuildResult {
+    pub posts: Vec<BuiltPost>,
+    pub outputs: BTreeMap<String, u64>,
+    /// Name and source hash of each template in the templates directory, to report changes between builds
+    pub templates: BTreeMap<String, u64>,
+    /// First line of every error printed during the build
+    pub errors: Vec<String>,
+    /// Whether the site couldn't be built properly at all, like without an `index` template
+    pub fatal: bool
+}
+
+impl BuildResult {
+    /// Whether the build should count as failed, with `strict` any error does.
+    pub fn failed(&self, strict: bool) -> bool {
+        self.fatal || (strict && !self.errors.is_empty())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduledPost {
+    pub id: String,
+    pub title: String,
+    pub publish: chrono::DateTime<chrono::FixedOffset>,
+    pub words: usize,
+    #[serde(skip)]
+    pub file: PathBuf
+}
+
+/// A post found in the posts directory, with the defaults for its metadata.
+/// Outputs only meant for previewing with `--dev`, like the dashboard, kept in the cache so that
+/// a later build into the same directory can remove them before they get deployed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DevOutputs {
+    out_dir: PathBuf,
+    outputs: Vec<String>
+}
+
+#[derive(Debug)]
+struct PostSource {
+    file: PathBuf,
+    dir: Option<PathBuf>,
+    defaults: toml::Table
+}
+
+#[derive(Debug)]
+pub struct SiteBuilder<'a> {
+    args: &'a Args,
+    sink: &'a dyn OutputSink,
+    config: SiteConfig,
+    assets: Arc<AssetStore>,
+    images: Arc<raster::ImagePipeline>,
+    /// Built posts, newest first once all are built. Every list of posts templates get is in
+    /// this order.
+    posts: Vec<Post>,
+    /// Standalone pages from `pages/`, built like posts but kept out of `posts`
+    pages: Vec<Post>,
+    scheduled: Vec<ScheduledPost>,
+    now: chrono::DateTime<chrono::FixedOffset>,
+    tag_names: HashMap<String, String>,
+    /// Canonical tag => slug used for its pages
+    tag_slugs: HashMap<String, String>,
+    /// Author name => slug used for their page
+    author_slugs: HashMap<String, String>,
+    /// Category => slug used for its pages
+    category_slugs: HashMap<String, String>,
+    /// Problems with tags, kept for the dashboard
+    tag_lints: Vec<lint::TagLint>,
+    outputs: RefCell<BTreeMap<String, u64>>,
+    /// Outputs that only redirect to another page, kept out of the sitemap
+    redirects: RefCell<std::collections::BTreeSet<String>>,
+    timings: RefCell<Vec<timings::PageTiming>>,
+    post_timings: Vec<timings::PostTiming>,
+    phases: RefCell<Vec<timings::PhaseTiming>>,
+    counts: RefCell<timings::BuildCounts>,
+    /// Output paths of the large files being copied in the background
+    large_copies: RefCell<Vec<(String, std::thread::JoinHandle<std::io::Result<u64>>)>>,
+    /// Name and source hash of each loaded template
+    templates: BTreeMap<String, u64>,
+    env: minijinja::Environment<'static>
+}
+
+impl<'a> SiteBuilder<'a> {
+    fn new(args: &'a Args, sink: &'a dyn OutputSink, mut config: SiteConfig) -> SiteBuilder<'a> {
+        if let Some(base_url) = &args.base_url {
+            config.base_url = Some(base_url.clone());
+        }
+        let images = Arc::new(raster::ImagePipeline::new(config.images.clone(), config.max_concurrent_images));
+        SiteBuilder {
+            args, sink,
+            assets: Arc::new(AssetStore::new(output::AssetNameSettings::new(config.asset_names, &config.asset_hash), config.slugs.clone())),
+            config, images,
+            posts: Vec::new(),
+            pages: Vec::new(),
+            scheduled: Vec::new(),
+            now: args.now.unwrap_or_else(|| chrono::Local::now().fixed_offset()),
+            tag_names: HashMap::new(),
+            tag_slugs: HashMap::new(),
+            author_slugs: HashMap::new(),
+            category_slugs: HashMap::new(),
+            tag_lints: Vec::new(),
+            outputs: RefCell::new(BTreeMap::new()),
+            redirects: RefCell::new(std::collections::BTreeSet::new()),
+            timings: RefCell::new(Vec::new()),
+            post_timings: Vec::new(),
+            phases: RefCell::new(Vec::new()),
+            counts: RefCell::new(timings::BuildCounts::default()),
+            large_copies: RefCell::new(Vec::new()),
+            templates: BTreeMap::new(),
+            env: minijinja::Environment::new()
+        }
+    }
+
+    fn cache_dir(&self) -> PathBuf {
+        cache_dir(&self.args.in_dir)
+    }
+
+    pub fn run_processor(&self, language: &str, input: &str) -> Option<Result<String, String>> {
+        let processor = self.config.processors.iter().find(|p| p.language == language)?;
+        if !self.args.enable_processors {
+            log!("warning: not running processor for `{}`, pass --enable-processors to allow it", language);
+            return None
+        }
+        Some(processor::run(processor, input, &self.cache_dir().join("processors")))
+    }
+
+    /// Stores an asset used by the post `owner`, made from the file `source`, returning its URL
+    /// under the path of `base_url`.
+    pub fn store_asset(&self, asset: Vec<u8>, ext: &str, owner: &str, source: Option<&Path>) -> String {
+        format!("{}/{}", self.config.base_path(), self.assets.store(asset, ext, Some(owner), source))
+    }
+
+    /// Finds the markdown file of every post, along with its directory for posts that have one.
+    /// Directories without an `index.md` are searched for more posts.
+    fn post_sources(&self) -> Vec<PostSource> {
+        let mut sources = Vec::new();
+        Self::find_posts(&self.args.in_dir.join("posts"), &toml::Table::new(), &mut sources);
+        // Of two posts with the same id, the one skipped shouldn't depend on the directory order
+        sources.sort_by(|a, b| a.file.cmp(&b.file));
+        sources
+    }
+
+    fn find_posts(posts_dir: &Path, inherited: &toml::Table, sources: &mut Vec<PostSource>) {
+        let defaults = defaults::load(posts_dir, inherited);
+        let Ok(entries) = posts_dir.read_dir()
+            .inspect_err(|e| {
+                log!("error: cannot read posts directory: {e}");
+                log!("warning: continuing without the posts in `{}`", posts_dir.display());
+            }) else { return };
+
+        for entry in entries {
+            let Ok(entry) = entry.map(|e| e.path())
+                .inspect_err(|e| {
+                    log!("error: cannot read post: {e}")
+                }) else { continue };
+
+            if entry.is_dir() {
+                let index = entry.join("index.md");
+                if index.is_file() {
+                    let defaults = defaults::load(&entry, &defaults);
+                    sources.push(PostSource { file: index, dir: Some(entry), defaults });
+                } else {
+                    Self::find_posts(&entry, &defaults, sources);
+                }
+            } else if entry.is_file() && entry.extension().and_then(|e| e.to_str()) == Some("md") {
+                sources.push(PostSource { file: entry, dir: None, defaults: defaults.clone() });
+            } else if entry.file_name().and_then(|n| n.to_str()) != Some(defaults::DEFAULTS_FILE) {
+                log!("error: unknown post type for `{}`", entry.display());
+            }
+        }
+    }
+
+    fn build_posts(&mut self) {
+        let sources = self.post_sources();
+        // Metadata is only printed for posts that are built
+        let force = self.args.force || self.args.debug_metadata;
+        let mut cache = cache::PostCache::open(self.cache_dir().join("posts"), cache::site_hash(&self.config, self.args), force);
+        progress::begin("building posts", Some(sources.len()));
+        for PostSource { file, dir, defaults } in sources {
+            let name = post::post_name(&file, dir.as_deref());
+            progress::step(&name);
+            let source = file.strip_prefix(&self.args.in_dir).unwrap_or(&file).to_string_lossy().replace('\\', "/");
+            let start = std::time::Instant::now();
+            let hash = cache::source_hash(&file, &defaults, self.config.updated_from_mtime);
+            let cached = hash.and_then(|hash| self.cached_post(&mut cache, &source, hash, &file));
+            let reused = cached.is_some();
+            let post = cached.or_else(|| {
+                let mut builder = PostBuilder { site: self, file, dir, defaults, meta: None, images: Vec::new(), diagnostics: Vec::new(), inputs: BTreeMap::new(), assets: Vec::new(), page: false, unpublishable: false };
+                let (post, messages) = logging::record(|| builder.build());
+                // Posts that went wrong, like with a failed processor, are built again next time
+                let failed = messages.iter().any(|message| message.starts_with("error:"));
+                if let Some(post) = &post && let Some(hash) = hash && !failed {
+                    let (files, assets) = (std::mem::take(&mut builder.inputs), std::mem::take(&mut builder.assets));
+                    cache.store(&source, cache::PostInputs { source: hash, files }, post, &assets, &messages);
+                }
+                post
+            });
+            if let Some(post) = post {
+                if self.args.timings {
+                    self.post_timings.push(timings::PostTiming { post: post.id.clone(), duration: start.elapsed(), reused });
+                }
+                let counts = self.counts.get_mut();
+                counts.posts += 1;
+                counts.reused += reused as usize;
+                let publish = self.publish_time(&post.meta.date);
+                if publish > self.now && !self.args.future {
+                    log!("info: skipping post `{}` scheduled for {}", post.id, publish.to_rfc3339());
+                    self.counts.get_mut().scheduled += 1;
+                    self.scheduled.push(ScheduledPost { id: post.id, title: post.meta.title, publish, words: post.words, file: post.file });
+                } else {
+                    self.posts.push(post);
+                }
+            }
+        }
+        cache.save();
+        self.posts.sort_by(|a, b| b.age.cmp(&a.age).then_with(|| a.id.cmp(&b.id)));
+        self.end_phase();
+    }
+
+    /// Finds the markdown file of every standalone page: `pages/*.md` and directories in `pages/`
+    /// with an `index.md`. Sites without a `pages/` directory have none.
+    fn page_sources(&self) -> Vec<PostSource> {
+        let pages_dir = self.args.in_dir.join("pages");
+        if !pages_dir.is_dir() { return Vec::new() }
+        let defaults = defaults::load(&pages_dir, &toml::Table::new());
+        let Ok(entries) = pages_dir.read_dir()
+            .inspect_err(|e| log!("error: cannot read pages directory: {e}"))
+            else { return Vec::new() };
+
+        let mut sources = Vec::new();
+        for entry in entries {
+            let Ok(entry) = entry.map(|e| e.path())
+                .inspect_err(|e| log!("error: cannot read page: {e}"))
+                else { continue };
+
+            if entry.is_dir() && entry.join("index.md").is_file() {
+                let defaults = defaults::load(&entry, &defaults);
+                sources.push(PostSource { file: entry.join("index.md"), dir: Some(entry), defaults });
+            } else if entry.is_file() && entry.extension().and_then(|e| e.to_str()) == Some("md") {
+                sources.push(PostSource { file: entry, dir: None, defaults: defaults.clone() });
+            } else if entry.file_name().and_then(|n| n.to_str()) != Some(defaults::DEFAULTS_FILE) {
+                log!("error: unknown page type for `{}`", entry.display());
+            }
+        }
+        // Directory order isn't stable, and the order is what templates list pages in
+        sources.sort_by(|a, b| a.file.cmp(&b.file));
+        sources
+    }
+
+    /// Builds the standalone pages through the same pipeline as posts. They aren't cached, scheduled
+    /// or listed with the posts.
+    fn build_standalone_pages(&mut self) {
+        let sources = self.page_sources();
+        if sources.is_empty() { return }
+        progress::begin("building pages", Some(sources.len()));
+        for PostSource { file, dir, defaults } in sources {
+            progress::step(&post::post_name(&file, dir.as_deref()));
+            let mut builder = PostBuilder { site: self, file, dir, defaults, meta: None, images: Vec::new(), diagnostics: Vec::new(), inputs: BTreeMap::new(), assets: Vec::new(), page: true, unpublishable: false };
+            if let Some(page) = builder.build() {
+                self.pages.push(page);
+            }
+        }
+        self.end_phase();
+    }
+
+    /// The post built from `source` by an earlier build, if its markdown, whose hash is `hash`, and
+    /// the files it referenced are unchanged, and no post built before it has its id. Building it
+    /// reports the clash otherwise. Its assets are stored again.
+    fn cached_post(&mut self, cache: &mut cache::PostCache, source: &str, hash: u64, file: &Path) -> Option<Post> {
+        let (cached, contents) = cache.lookup(source, hash)?;
+        let id = &cached.post.id;
+        if self.posts.iter().any(|p| p.id == *id) || self.scheduled.iter().any(|p| p.id == *id) { return None }
+
+        let mut post = cached.post;
+        post.file = file.to_path_buf();
+        post.sections = cached.sections;
+        post.diagnostics = cached.diagnostics;
+        for message in &cached.messages {
+            log!("{message}");
+        }
+        for (asset, content) in cached.assets.iter().zip(contents) {
+            self.store_asset(content, &asset.ext, &post.id, asset.source.as_deref());
+        }
+        cache.keep(source);
+        log!("info: reusing post `{}`, unchanged since the last build", post.id);
+        Some(post)
+    }
+
+    /// Clears the progress line, recording how long the phase took for `--timings`.
+    fn end_phase(&self) {
+        let Some((name, items, duration)) = progress::end() else { return };
+        self.phases.borrow_mut().push(timings::PhaseTiming { name, items, duration });
+    }
+
+    /// What the build produced for `--report`, given how long it took and what went wrong.
+    fn build_report(&self, duration: std::time::Duration, problems: logging::Problems) -> report::BuildReport {
+        let outputs = self.outputs.borrow();
+        let mut assets = BTreeMap::new();
+        self.assets.for_each(|path, _| {
+            if let Some(&size) = outputs.get(path) {
+                assets.insert(path.to_string(), size);
+            }
+        });
+        let mut scheduled: Vec<&ScheduledPost> = self.scheduled.iter().collect();
+        scheduled.sort_by_key(|post| post.publish);
+        report::BuildReport {
+            posts: self.posts.iter().map(|post| {
+                let output = post.output.clone();
+                report::ReportPost {
+                    id: post.id.clone(),
+                    title: post.meta.title.clone(),
+                    date: rfc3339(&self.publish_time(&post.meta.date)),
+                    tags: post.meta.tags.clone(),
+                    source: post.file.strip_prefix(&self.args.in_dir).unwrap_or(&post.file).to_string_lossy().replace('\\', "/"),
+                    bytes: outputs.get(&output).copied(),
+                    output
+                }
+            }).collect(),
+            scheduled: scheduled.iter()
+                .map(|post| report::ReportScheduled { id: post.id.clone(), title: post.title.clone(), publish: rfc3339(&post.publish) })
+                .collect(),
+            next_publish: scheduled.first().map(|post| rfc3339(&post.publish)),
+            assets,
+            outputs: outputs.clone(),
+            phases: self.phases.borrow().iter()
+                .map(|phase| report::ReportPhase { name: phase.name, items: phase.items, duration_ms: timings::millis(phase.duration) })
+                .collect(),
+            duration_ms: timings::millis(duration),
+            warnings: problems.warnings,
+            errors: problems.errors
+        }
+    }
+
+    /// Converts a post date to the moment it is published, interpreting dates and
+    /// times without an offset in the configured timezone.
+    pub fn publish_time(&self, dt: &toml_datetime::Datetime) -> chrono::DateTime<chrono::FixedOffset> {
+        if dt.offset.is_some() { return dt_toml_to_chrono(dt) }
+        let local = dt_toml_to_chrono(dt).naive_utc();
+        match self.config.timezone {
+            Some(offset) => resolve_local(local, &offset),
+            // The offset on that date rather than today's, which differs across daylight saving time
+            None => resolve_local(local, &chrono::Local)
+        }
+    }
+
+    fn report_scheduled(&mut self) {
+        if self.scheduled.is_empty() { return }
+        self.scheduled.sort_by_key(|p| p.publish);
+
+        let mut message = format!("info: {} post(s) scheduled for later:", self.scheduled.len());
+        for post in &self.scheduled {
+            message.push_str(&format!("\n    {}  {} ({:?})", post.publish.to_rfc3339(), post.id, post.title));
+        }
+        log!("{message}");
+
+        let next = &self.scheduled[0];
+        let wait = next.publish.signed_duration_since(self.now);
+        log!(
+            "info: next post `{}` publishes at {} (in {}h {}m)",
+            next.id, next.publish.to_rfc3339(), wait.num_hours(), wait.num_minutes() % 60
+        );
+    }
+
+    fn check_freshness(&mut self) {
+        let Some(config) = &self.config.freshness else { return };
+        let mut posts = std::mem::take(&mut self.posts);
+        freshness::mark_stale(&mut posts, config, &self.config.tag_aliases, self.now, |dt| self.publish_time(dt));
+        self.posts = posts;
+    }
+
+    fn add_structured_data(&mut self) {
+        let Some(base_url) = &self.config.base_url else {
+            log!("info: no base_url configured, not generating structured data");
+            return
+        };
+        let unattributed = self.posts.iter().filter(|post| post.meta.authors.is_empty()).count();
+        if unattributed > 0 {
+            log!("warning: {} post(s) have no author and no default `author` is configured, they will not have structured data", unattributed);
+        }
+
+        let mut posts = std::mem::take(&mut self.posts);
+        for post in &mut posts {
+            match jsonld::blog_posting(post, &post.url, base_url, self.config.author.as_ref(), self.publish_time(&post.meta.date), post.meta.updated.as_ref().map(|dt| self.publish_time(dt))) {
+                Ok(json) => post.jsonld = Some(json),
+                Err(missing) if missing == ["author"] => (),
+                Err(missing) => log!("warning: post `{}` has no structured data, missing or invalid: {}", post.id, missing.join(", "))
+            }
+        }
+        self.posts = posts;
+    }
+
+    fn collect_authors(&mut self) {
+        self.author_slugs = self.unique_slugs(self.posts.iter().flat_map(|post| &post.meta.authors), "author");
+    }
+
+    fn collect_categories(&mut self) {
+        self.category_slugs = self.unique_slugs(self.posts.iter().filter_map(|post| post.meta.category.as_ref()), "category");
+    }
+
+    /// A slug for each of `names`, unique among them. `kind` is what they are, for warnings, and
+    /// the slug of names that slugify to nothing.
+    fn unique_slugs<'n>(&self, names: impl IntoIterator<Item=&'n String>, kind: &str) -> HashMap<String, String> {
+        // Which of two names with the same slug gets a suffix shouldn't depend on the post order
+        let names: std::collections::BTreeSet<&String> = names.into_iter().collect();
+        let mut slugs: HashMap<String, String> = HashMap::new();
+        for name in names {
+            let taken = |slug: &str| slugs.values().any(|s| s == slug);
+            let slug = self.config.slugs.unique(name, kind, taken);
+            if slug != self.config.slugs.slugify_or(name, kind) {
+                log!("warning: {} `{}` has the same slug as another {}, using `{}` instead", kind, name, kind, slug);
+            }
+            slugs.insert(name.clone(), slug);
+        }
+        slugs
+    }
+
+    fn collect_tags(&mut self) {
+        self.tag_lints = lint::lint_tags(&self.posts, &self.config.tag_aliases);
+
+        let mut uses: BTreeMap<String, usize> = BTreeMap::new();
+        for variants in lint::tag_variants(&self.posts, &self.config.tag_aliases).values() {
+            let canonical = lint::canonical_tag(variants, &self.config.tag_aliases);
+            for (&variant, posts) in variants {
+                self.tag_names.insert(variant.to_string(), canonical.to_string());
+                *uses.entry(canonical.to_string()).or_default() += posts.len();
+            }
+        }
+
+        // Tags that only differ in what slugs leave out, like `C/C++` and `C C++`, would get the
+        // same page, so they become one tag under the most used name. Tags with nothing to slugify
+        // are kept apart.
+        let mut by_slug: BTreeMap<String, Vec<(&String, usize)>> = BTreeMap::new();
+        for (tag, &count) in &uses {
+            let slug = self.config.slugs.slugify(tag);
+            if !slug.is_empty() {
+                by_slug.entry(slug).or_default().push((tag, count));
+            }
+        }
+        for (slug, tags) in by_slug.iter().filter(|(_, tags)| tags.len() > 1) {
+            let &(kept, _) = tags.iter().max_by(|(a, a_uses), (b, b_uses)| a_uses.cmp(b_uses).then(b.cmp(a))).expect("only groups of two or more");
+            let merged: Vec<&str> = tags.iter().map(|(tag, _)| tag.as_str()).filter(|tag| tag != kept).collect();
+            log!("warning: tags `{}` and `{}` have the same slug `{}`, merging them into `{}`", kept, merged.join("`, `"), slug, kept);
+            for canonical in self.tag_names.values_mut().filter(|canonical| merged.contains(&canonical.as_str())) {
+                *canonical = kept.clone();
+            }
+        }
+
+        self.tag_slugs = self.unique_slugs(self.tag_names.values(), "tag");
+
+        if self.config.canonicalize_display {
+            for post in &mut self.posts {
+                let mut tags = Vec::new();
+                for tag in &post.meta.tags {
+                    let canonical = self.tag_names.get(tag).unwrap_or(tag);
+                    if !tags.contains(canonical) {
+                        tags.push(canonical.clone());
+                    }
+                }
+                post.meta.tags = tags;
+            }
+        }
+    }
+
+    /// Gives every post and page its output path and URL. Of two posts with the same output
+    /// path, the older one keeps it and the other is skipped.
+    fn assign_urls(&mut self) {
+        let mut posts = std::mem::take(&mut self.posts);
+        // Older posts first, so a new post can't take the URL of one that is already out there
+        posts.sort_by(|a, b| a.age.cmp(&b.age).then_with(|| a.id.cmp(&b.id)));
+        let mut taken: HashMap<String, String> = HashMap::new();
+        for mut post in posts {
+            post.output = self.output_path(&post, &self.config.permalink);
+            if let Some(other) = taken.get(&post.output) {
+                log!("error: post `{}` has the same output path `{}` as `{}`, skipping it", post.id, post.output, other);
+                continue
+            }
+            taken.insert(post.output.clone(), post.id.clone());
+            post.url = self.url(&post.output);
+            self.posts.push(post);
+        }
+        self.posts.sort_by(|a, b| b.age.cmp(&a.age).then_with(|| a.id.cmp(&b.id)));
+
+        let mut pages = std::mem::take(&mut self.pages);
+        for page in &mut pages {
+            page.output = self.output_path(page, "{slug}.html");
+            page.url = self.url(&page.output);
+        }
+        self.pages = pages;
+    }
+
+    /// The URL of the output `path`, under the path of `base_url`.
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.config.base_path(), permalink::url(path))
+    }
+
+    /// The output path of `post`: its `path`, or else `pattern` expanded for it, or else the
+    /// default pattern if neither can be.
+    fn output_path(&self, post: &Post, pattern: &str) -> String {
+        let first_tag = post.meta.tags.first().map(|tag| {
+            let tag = self.tag_names.get(tag).unwrap_or(tag);
+            self.tag_slugs.get(tag).cloned().unwrap_or_else(|| self.config.slugs.slugify_or(tag, "tag"))
+        });
+        let tokens = permalink::Tokens { date: self.publish_time(&post.meta.date).date_naive(), slug: &post.id, first_tag: first_tag.as_deref() };
+        let path = post.meta.path.as_ref().and_then(|path| permalink::expand(path, &tokens)
+            .inspect_err(|e| log!("error: `{}` has an invalid `path`: {}, ignoring it", post.id, e))
+            .ok());
+        path.or_else(|| permalink::expand(pattern, &tokens)
+            .inspect_err(|e| log!("error: cannot give `{}` an output path: {}, using `{}` instead", post.id, e, permalink::DEFAULT_PATTERN))
+            .ok())
+            .unwrap_or_else(|| permalink::expand(permalink::DEFAULT_PATTERN, &tokens).expect("the default pattern doesn't use tags"))
+    }
+
+    /// Registers built-in templates for listing pages whose template is missing. Pages without
+    /// their own look, like `index` and `post`, never get one.
+    fn add_fallback_templates(&mut self) {
+        const FALLBACKS: &[(&str, &str)] = &[
+            ("tag", include_str!("fallback/tag.html")),
+            ("category", include_str!("fallback/category.html")),
+            ("page", include_str!("fallback/page.html"))
+        ];
+
+        for &(name, source) in FALLBACKS {
+            if self.env.get_template(name).is_ok() { continue }
+            // Sites without standalone pages or categories don't need their templates
+            if name == "page" && self.pages.is_empty() { continue }
+            if name == "category" && self.category_slugs.is_empty() { continue }
+            if self.args.strict {
+                log!("error: template `{}` is missing, skipping the pages that use it", name);
+                continue
+            }
+            log!("warning: template `{}` is missing, using a minimal built-in one", name);
+            if let Err(e) = self.env.add_template(name, source) {
+                log!("error: cannot parse built-in template `{}`: {}", name, e);
+            }
+        }
+    }
+
+    fn load_templates(&mut self) {
+        let Ok(templates_dir) = self.args.in_dir.join("templates").read_dir()
+            .inspect_err(|e| {
+                log!("error: cannot read templates directory: {e}");
+            }) else { return };
+        
+        progress::begin("loading templates", None);
+        for entry in templates_dir {
+            let Ok(entry) = entry.map(|e| e.path())
+                .inspect_err(|e| {
+                    log!("error: cannot read template: {e}")
+                }) else { continue };
+
+            let Some(name) = entry.file_name()
+                .and_then(|s| s.to_str())
+                .map(|s| s.trim_end_matches(".html")) else {
+                    log!("error: unknown template name for: `{}`", entry.display());
+                    continue
+                };
+
+            log!("info: processing template `{}` at `{}`", name, entry.display());
+            progress::step(name);
+
+            let mut source = String::new();
+            let Ok(_) = std::fs::File::open(&entry)
+                .and_then(|mut file| file.read_to_string(&mut source))
+                .inspect_err(|e| {
+                    log!("error: cannot read template: {e}")
+                }) else { continue };
+
+            if ["ghcomment[0]", "ghcomment[1]", "ghcomment.0", "ghcomment.1"].iter().any(|old| source.contains(old)) {
+                log!("warning: template `{}` indexes `ghcomment` as a pair, use `ghcomment.issue` and `ghcomment.authors` instead", name);
+            }
+            self.templates.insert(name.to_string(), {
+                use std::hash::Hasher;
+                let mut hasher = std::hash::DefaultHasher::new();
+                hasher.write(source.as_bytes());
+                hasher.finish()
+            });
+            if let Err(e) = self.env.add_template_owned(name.to_string(), source) {
+                log!("error: cannot parse template: {e}");
+            }
+        }
+        self.end_phase();
+
+        let default_format = self.config.datetime_format.clone();
+        self.env.add_filter("format_datetime", move |s: &minijinja::State<'_, '_>, value: minijinja::Value, format: Option<String>| {
+            format_datetime_function(s, value, format, &default_format)
+        });
+        self.env.add_global("site", minijinja::Value::from_serialize(&self.config.site));
+        self.env.add_global("base_url", minijinja::Value::from(self.config.base_url.clone()));
+        let base_url = self.config.base_url.clone();
+        self.env.add_filter("absolute_url", move |path: String| match &base_url {
+            Some(base_url) => Ok(jsonld::absolute_url(base_url, &path)),
+            None => Err(minijinja::Error::new(
+                minijinja::ErrorKind::InvalidOperation,
+                format!("absolute_url(`{}`): set `base_url` in site.toml or pass --base-url", path)
+            ))
+        });
+        let listed: Vec<&Post> = self.posts.iter().filter(|post| self.is_listed(post)).collect();
+        let nav = taxonomy::navigation(&listed, &self.args.in_dir.join("posts"), &self.tag_names, &self.tag_slugs, &self.config.base_path());
+        self.env.add_global("nav", minijinja::Value::from_serialize(&nav));
+        let pages: Vec<minijinja::Value> = self.pages.iter().map(|page| context! {
+            id => page.id, title_html => page.title_html, title_plain => page.title_plain, url => page.url
+        }).collect();
+        self.env.add_global("pages", minijinja::Value::from(pages));
+        let data = data::load(&self.args.in_dir.join("data"));
+        self.env.add_global("data", minijinja::Value::from_serialize(&data));
+        self.env.add_filter("urlencode", |s: String| urlencoding::encode(&s).to_string());
+
+        let (author_slugs, base_path) = (self.author_slugs.clone(), self.config.base_path());
+        self.env.add_filter("author_url", move |author: String| {
+            let slug = author_slugs.get(&author).cloned().unwrap_or_default();
+            format!("{}/authors/{}.html", base_path, urlencoding::encode(&slug))
+        });
+
+        let (tag_names, tag_slugs) = (self.tag_names.clone(), self.tag_slugs.clone());
+        self.env.add_filter("tag_slug", move |tag: String| {
+            let canonical = tag_names.get(&tag).unwrap_or(&tag);
+            tag_slugs.get(canonical).cloned().unwrap_or_default()
+        });
+        let (tag_names, tag_slugs, base_path) = (self.tag_names.clone(), self.tag_slugs.clone(), self.config.base_path());
+        self.env.add_filter("tag_url", move |tag: String| {
+            let canonical = tag_names.get(&tag).unwrap_or(&tag);
+            let slug = tag_slugs.get(canonical).cloned().unwrap_or_default();
+            format!("{}/tags/{}.html", base_path, urlencoding::encode(&slug))
+        });
+
+        let (category_slugs, base_path) = (self.category_slugs.clone(), self.config.base_path());
+        self.env.add_filter("category_url", move |category: String| {
+            let slug = category_slugs.get(&category).cloned().unwrap_or_default();
+            format!("{}/categories/{}.html", base_path, urlencoding::encode(&slug))
+        });
+
+        let (in_dir, images, assets, base_path) = (self.args.in_dir.clone(), self.images.clone(), self.assets.clone(), self.config.base_path());
+        self.env.add_function("picture", move |path: String, kwargs: minijinja::value::Kwargs| {
+            let widths: Vec<u32> = kwargs.get::<Option<Vec<u32>>>("widths")?.unwrap_or_else(|| vec![480, 960]);
+            let alt: String = kwargs.get::<Option<String>>("alt")?.unwrap_or_default();
+            kwargs.assert_all_used()?;
+
+            let error = |detail: String| minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, format!("picture(`{}`): {}", path, detail));
+            if Path::new(&path).components().any(|c| !matches!(c, std::path::Component::Normal(_))) {
+                return Err(error("the path must be relative to the input directory, without `..`".into()))
+            }
+            let source = in_dir.join(&path);
+            if !source.is_file() {
+                return Err(error(format!("no such file `{}`", source.display())))
+            }
+            images.picture(&source, &widths, &alt, |image| format!("{}/{}", base_path, assets.store(image.data.clone(), image.ext, None, Some(&source))))
+                .map_err(error)
+        });
+    }
+
+    fn write_to_output(&self, outpath: &str, content: &[u8]) {
+        let written = if self.config.durable_writes {
+            self.sink.write_durable(outpath, content)
+        } else {
+            self.sink.write(outpath, content)
+        };
+        let Ok(()) = written
+            .inspect_err(|e| log!("error: could not write output `{}`: {}", outpath, e))
+            else { return };
+        self.outputs.borrow_mut().insert(outpath.to_string(), content.len() as u64);
+    }
+
+    fn build_pages(&self) {
+        progress::begin("rendering pages", None);
+        let stat_posts: Vec<&Post> = self.posts.iter().filter(|p| self.is_listed(p) && !p.meta.draft).collect();
+        let stats = stats::site_stats(&stat_posts, &self.tag_names);
+        // Terms only unlisted posts have get no pages
+        let listed: Vec<&Post> = self.posts.iter().filter(|post| self.is_listed(post)).collect();
+        let tags = taxonomy::group(listed.iter().copied(), |post| post.meta.tags.iter().map(|tag| self.tag_names.get(tag).unwrap_or(tag).as_str()).collect());
+        let categories = taxonomy::group(listed.iter().copied(), |post| post.meta.category.as_deref().into_iter().collect());
+        let authors = taxonomy::group(listed.iter().copied(), |post| post.meta.authors.iter().map(String::as_str).collect());
+        let category_counts: BTreeMap<&str, usize> = categories.iter().map(|(&category, posts)| (category, posts.len())).collect();
+        self.build_index(&stats, &category_counts);
+        if self.env.get_template("stats").is_ok() {
+            self.build_page("stats", "stats/index.html", || context! { stats => &stats });
+        }
+        if self.env.get_template("404").is_ok() {
+            self.build_page("404", "404.html", || context! { posts => self.listing(&self.posts), stats => &stats, categories => &category_counts });
+        }
+        
+        let chain = self.chronological();
+        let positions: HashMap<&str, usize> = chain.iter().enumerate().map(|(i, post)| (post.id.as_str(), i)).collect();
+        let mut related = related::related(&self.posts, &chain, &self.tag_names, &self.config.related);
+        let mut series = series::series(&chain);
+        for post in &self.posts {
+            let template = post.meta.template.as_deref().unwrap_or("post");
+            let position = positions.get(post.id.as_str()).copied();
+            let prev = position.and_then(|i| i.checked_sub(1)).map(|i| PostSummary::new(chain[i], false));
+            let next = position.and_then(|i| chain.get(i + 1)).map(|next| PostSummary::new(next, false));
+            let related = related.remove(post.id.as_str()).unwrap_or_default();
+            let series = series.remove(post.id.as_str());
+            if self.outputs.borrow().contains_key(&post.output) {
+                log!("error: post `{}` would overwrite `{}`, not rendering it", post.id, post.output);
+            } else {
+                self.build_page(template, &post.output, || context! {
+                    post => post, prev => prev, next => next, related => related, series => series
+                });
+            }
+        }
+
+        let taxonomies = [
+            (taxonomy::Taxonomy { template: "tag", dir: "tags", slugs: &self.tag_slugs, descriptions: &self.config.tag_descriptions }, &tags),
+            (taxonomy::Taxonomy { template: "category", dir: "categories", slugs: &self.category_slugs, descriptions: &self.config.category_descriptions }, &categories)
+        ];
+        for (taxonomy, terms) in &taxonomies {
+            if self.env.get_template(taxonomy.template).is_err() { continue }
+            for (term, term_posts) in terms.iter() {
+                self.build_term_pages(taxonomy, term, term_posts);
+            }
+        }
+        self.build_tags_index(&tags);
+        self.build_tag_feeds(&tags);
+        self.build_date_archives(&listed);
+        if self.env.get_template("author").is_ok() {
+            for (author, author_posts) in &authors {
+                let slug = &self.author_slugs[*author];
+                self.build_page("author", &format!("authors/{}.html", slug), || context! {
+                    posts => self.listing(author_posts.iter().copied()), all_posts => self.listing(&self.posts), author => author, slug => slug
+                });
+            }
+        }
+
+        for page in &self.pages {
+            if self.outputs.borrow().contains_key(&page.output) {
+                log!("error: page `{}` would overwrite `{}`, skipping it", page.id, page.output);
+                continue
+            }
+            let template = page.meta.template.as_deref().unwrap_or("page");
+            // Also given as `post`, so a page can use a post template
+            self.build_page(template, &page.output, || context! { page => page, post => page });
+        }
+        // Last, so that aliases can't take the place of anything else
+        self.build_aliases();
+        self.end_phase();
+
+        self.write_assets();
+        self.write_asset_manifest();
+    }
+
+    /// Writes a page redirecting to each post from every one of its `aliases`.
+    fn build_aliases(&self) {
+        for post in &self.posts {
+            for alias in &post.meta.aliases {
+                let Ok(outpath) = permalink::output_path(alias)
+                    .inspect_err(|e| log!("error: post `{}` has an invalid alias: {}", post.id, e))
+                    else { continue };
+                if outpath == post.output {
+                    log!("warning: post `{}` has its own URL `{}` as an alias, ignoring it", post.id, alias);
+                } else if self.outputs.borrow().contains_key(&outpath) {
+                    log!("error: alias `{}` of post `{}` would overwrite `{}`, skipping it", alias, post.id, outpath);
+                } else {
+                    self.write_redirect(&outpath, post);
+                }
+            }
+        }
+    }
+
+    /// Writes a page at `outpath` sending readers on to `post`, with the `redirect` template if
+    /// there is one.
+    fn write_redirect(&self, outpath: &str, post: &Post) {
+        log!("info: redirecting `/{}` to `{}`", outpath, post.url);
+        if self.env.get_template("redirect").is_ok() {
+            self.build_page("redirect", outpath, || context! { post => post, url => post.url });
+        } else {
+            self.write_to_output(outpath, urls::redirect_page(&post.url).as_bytes());
+        }
+        self.redirects.borrow_mut().insert(outpath.to_string());
+    }
+
+    /// Writes the assets the posts stored, leaving the asset manifest to `write_asset_manifest`.
+    fn write_assets(&self) {
+        self.assets.for_each(|path, content| {
+            log!("info: writing asset `{}`", path);
+            self.write_to_output(path, content);
+            let mut counts = self.counts.borrow_mut();
+            counts.assets += 1;
+            counts.asset_bytes += content.len() as u64;
+        });
+    }
+
+    /// Writes how assets are named and which posts and source files each asset belongs to into
+    /// the cache directory, outside of the output, warning if the naming changed since the last build.
+    fn write_asset_manifest(&self) {
+        let path = self.cache_dir().join("assets.json");
+        // Manifests from before the naming was recorded don't parse, and aren't compared
+        let previous = std::fs::read(&path).ok()
+            .and_then(|data| serde_json::from_slice::<output::PreviousAssetManifest>(&data).ok());
+        if let Some(previous) = previous && !previous.assets.is_empty() && previous.settings != self.assets.settings() {
+            log!("warning: asset naming changed since the last build, from {} to {}, so the URL of every asset changes", previous.settings, self.assets.settings());
+        }
+        let Ok(manifest) = self.assets.manifest()
+            .inspect_err(|e| log!("error: could not serialize asset manifest: {}", e))
+            else { return };
+        match std::fs::create_dir_all(self.cache_dir()).and_then(|_| std::fs::write(&path, manifest)) {
+            Ok(()) => log!("info: wrote asset manifest to `{}`", path.display()),
+            Err(e) => log!("error: could not write asset manifest: {}", e)
+        }
+    }
+
+    /// Reports posts whose URLs changed since the last build and writes redirects from their
+    /// old URLs, if asked for now or in an earlier build.
+    fn migrate_urls(&self) {
+        let path = self.cache_dir().join("urls.json");
+        let built: BTreeMap<String, String> = self.posts.iter()
+            .map(|post| {
+                let source = post.file.strip_prefix(&self.args.in_dir).unwrap_or(&post.file);
+                (source.to_string_lossy().replace('\\', "/"), post.output.clone())
+            })
+            .collect();
+        // Nothing has moved on the first build
+        let old = urls::load(&path).unwrap_or_default();
+        let moves = urls::moves(&old, &built);
+        urls::report(&moves, self.args.write_aliases);
+        let manifest = urls::next_manifest(&old, &built, &moves, self.args.write_aliases, |source| self.args.in_dir.join(source).is_file());
+        for (source, output) in &built {
+            // Moves without --write-aliases keep the entry, and aliases, of the last build
+            for alias in manifest.posts[source].aliases.iter().filter(|alias| *alias != output) {
+                if self.outputs.borrow().contains_key(alias) {
+                    log!("warning: not redirecting `/{}` to `/{}`, another page is there now", alias, output);
+                    continue
+                }
+                let Some(post) = self.posts.iter().find(|post| post.output == *output) else { continue };
+                self.write_redirect(alias, post);
+            }
+        }
+        urls::save(&path, &manifest);
+    }
+
+    /// Renders `index.html`, split into pages of `paginate` posts if that is set. Pages after the
+    /// first are `page/<n>.html`, so their URLs only ever get added to as posts are.
+    fn build_index(&self, stats: &stats::SiteStats<'_>, categories: &BTreeMap<&str, usize>) {
+        let posts = self.listing(&self.posts);
+        if self.config.paginate == 0 {
+            self.build_page("index", "index.html", || context! { posts => &posts, stats => stats, categories => categories });
+            return
+        }
+
+        let pages = paginate::paginate(&posts, self.config.paginate, &self.config.base_path(), |n| if n == 1 { String::new() } else { format!("page/{}.html", n) });
+        for page in pages {
+            self.build_page("index", &page.outpath, || context! {
+                posts => page.items, all_posts => &posts, stats => stats, categories => categories, pagination => page.pagination
+            });
+        }
+    }
+
+    /// Renders the pages listing the posts filed under `term`, split into pages of `tag_paginate`
+    /// posts if that is set. Pages after the first are `<dir>/<slug>/page/<n>.html`, like those of
+    /// the index. The template is given the term and its slug under its own name, like `tag` and
+    /// `tag_slug`, and its description as `description_html` and `description_plain` if it has one.
+    fn build_term_pages(&self, taxonomy: &taxonomy::Taxonomy, term: &str, term_posts: &[&Post]) {
+        let slug = &taxonomy.slugs[term];
+        let term_posts = self.listing(term_posts.iter().copied());
+        let mut named = BTreeMap::from([
+            (taxonomy.template.to_string(), minijinja::Value::from(term)),
+            (format!("{}_slug", taxonomy.template), minijinja::Value::from(slug))
+        ]);
+        let lower = term.to_lowercase();
+        if let Some((_, description)) = taxonomy.descriptions.iter().find(|(name, _)| name.to_lowercase() == lower) {
+            let (html, plain, blocks) = post::render_inline_checked(description);
+            if !blocks.is_empty() {
+                log!("warning: the description of {} `{}` can only hold inline markdown, flattening its {}", taxonomy.template, term, blocks.join(", "));
+            }
+            named.insert("description_html".into(), minijinja::Value::from(html));
+            named.insert("description_plain".into(), minijinja::Value::from(plain));
+        }
+        if self.config.tag_paginate == 0 {
+            // `tag_posts` is what `posts` was before it only held the tag's posts
+            named.insert(format!("{}_posts", taxonomy.template), minijinja::Value::from_serialize(&term_posts));
+            self.build_page(taxonomy.template, &taxonomy.outpath(term), || context! {
+                posts => &term_posts, all_posts => self.listing(&self.posts), slug => slug, ..minijinja::Value::from(named)
+            });
+            return
+        }
+
+        let pages = paginate::paginate(&term_posts, self.config.tag_paginate, &self.config.base_path(), |n| if n == 1 {
+            taxonomy.outpath(term)
+        } else {
+            format!("{}/{}/page/{}.html", taxonomy.dir, slug, n)
+        });
+        let named = minijinja::Value::from(named);
+        for page in pages {
+            self.build_page(taxonomy.template, &page.outpath, || context! {
+                posts => page.items, all_posts => self.listing(&self.posts), slug => slug, pagination => page.pagination, ..named.clone()
+            });
+        }
+    }
+
+    fn build_tags_index(&self, tags: &BTreeMap<&str, Vec<&Post>>) {
+        if self.env.get_template("tags").is_err() {
+            log!("info: no `tags` template, skipping the tag index");
+            return
+        }
+
+        let mut summaries: Vec<taxonomy::TagSummary> = tags.iter()
+            .map(|(&name, posts)| taxonomy::TagSummary {
+                name, count: posts.len(),
+                slug: &self.tag_slugs[name],
+                url: format!("{}/tags/{}.html", self.config.base_path(), urlencoding::encode(&self.tag_slugs[name]))
+            })
+            .collect();
+        // Most used first, `groups` has them by name
+        summaries.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase())).then_with(|| a.name.cmp(b.name)));
+        let groups = taxonomy::alphabetical_groups(&summaries);
+        self.build_page("tags", "tags/index.html", || context! { tags => &summaries, groups => groups });
+    }
+
+    /// Renders a page per year of posts to `archive/<year>.html` with the `year` template, and an
+    /// index of all years to `archive/index.html` with the `archive` template, if they exist.
+    fn build_date_archives(&self, posts: &[&Post]) {
+        let (year, archive) = (self.env.get_template("year").is_ok(), self.env.get_template("archive").is_ok());
+        if !year { log!("info: no `year` template, skipping the yearly archive pages") }
+        if !archive { log!("info: no `archive` template, skipping the archive index") }
+        if !year && !archive { return }
+
+        let years = archive::years(self.listing(posts.iter().copied()), &self.config.base_path());
+        if year {
+            for year in &years {
+                self.build_page("year", &format!("archive/{}.html", year.year), || context! {
+                    year => year.year, posts => &year.posts, months => &year.months
+                });
+            }
+        }
+        if archive {
+            self.build_page("archive", "archive/index.html", || context! { years => &years });
+        }
+    }
+
+    /// Writes `search-index.json`, and renders the `search` template with the same `documents` if
+    /// the site has one.
+    fn build_search_index(&self) {
+        let Some(granularity) = self.config.search_granularity else { return };
+        let posts: Vec<(&Post, Vec<&str>, String)> = self.posts.iter()
+            .filter(|post| post.encrypted.is_none() && !post.meta.unlisted && !post.meta.draft)
+            .map(|post| (post, self.post_tags(post), rfc3339(&self.publish_time(&post.meta.date))))
+            .collect();
+        let entries: Vec<search::SearchEntry> = posts.iter()
+            .flat_map(|(post, tags, date)| {
+                let info = search::PostInfo { tags, date, max_bytes: self.config.search_max_bytes };
+                search::entries(post, &info, granularity)
+            })
+            .collect();
+        let Ok(index) = serde_json::to_vec(&entries)
+            .inspect_err(|e| log!("error: could not serialize search index: {}", e))
+            else { return };
+        log!("info: search index has {} entries, {:.1} KiB", entries.len(), index.len() as f64 / 1024.0);
+        self.write_to_output("search-index.json", &index);
+        if self.env.get_template("search").is_ok() {
+            self.build_page("search", "search.html", || context! { documents => &entries });
+        }
+    }
+
+    /// Writes `feed.xml`, an RSS feed, and `atom.xml`, an Atom feed, of the most recent listed
+    /// posts, if the site has a `base_url`.
+    fn build_feed(&self) {
+        let config = &self.config.feed;
+        if config.count == 0 || !(config.rss || config.atom) { return }
+        let Some(base_url) = &self.config.base_url else {
+            log!("info: no base_url configured, not generating feeds");
+            return
+        };
+
+        let items = self.feed_items(&self.posts);
+        let link = jsonld::absolute_url(base_url, "/");
+        let (title, description) = self.feed_title();
+        if config.rss {
+            let self_url = jsonld::absolute_url(base_url, "/feed.xml");
+            let channel = feed::Channel { title, description, link: &link, self_url: &self_url };
+            self.write_feed("feed.xml", &feed::rss(&channel, &items, base_url, config));
+        }
+        if config.atom {
+            let self_url = jsonld::absolute_url(base_url, "/atom.xml");
+            let channel = feed::Channel { title, description, link: &link, self_url: &self_url };
+            self.write_feed("atom.xml", &feed::atom(&channel, &items, base_url, self.feed_author(), config));
+        }
+    }
+
+    /// Writes an Atom feed of the most recent posts of each tag in `tags` next to its page.
+    fn build_tag_feeds(&self, tags: &BTreeMap<&str, Vec<&Post>>) {
+        let config = &self.config.feed;
+        if config.count == 0 || !config.tag_feeds { return }
+        // Whether the site has no `base_url` is reported with the main feeds
+        let Some(base_url) = &self.config.base_url else { return };
+
+        let (site_title, _) = self.feed_title();
+        for (&tag, posts) in tags {
+            let slug = &self.tag_slugs[tag];
+            let link = jsonld::absolute_url(base_url, &format!("/tags/{}.html", urlencoding::encode(slug)));
+            let self_url = jsonld::absolute_url(base_url, &format!("/tags/{}.atom.xml", urlencoding::encode(slug)));
+            let title = format!("{}: {}", site_title, tag);
+            let description = format!("Posts tagged {}", tag);
+            let channel = feed::Channel { title: &title, description: &description, link: &link, self_url: &self_url };
+            let items = self.feed_items(posts.iter().copied());
+            self.write_feed(&format!("tags/{}.atom.xml", slug), &feed::atom(&channel, &items, base_url, self.feed_author(), config));
+        }
+    }
+
+    /// The listed, non-draft posts of `posts` as feeds list them, newest first, up to `feed.count`.
+    fn feed_items<'p>(&'p self, posts: impl IntoIterator<Item=&'p Post>) -> Vec<feed::FeedItem<'p>> {
+        let mut items: Vec<feed::FeedItem> = posts.into_iter()
+            .filter(|post| self.is_listed(post) && !post.meta.draft)
+            .map(|post| {
+                let tags = self.post_tags(post);
+                let published = self.publish_time(&post.meta.date);
+                let updated = post.meta.updated.as_ref().map_or(published, |dt| self.publish_time(dt));
+                feed::FeedItem { post, published, updated, tags }
+            })
+            .collect();
+        items.sort_by(|a, b| b.published.cmp(&a.published).then_with(|| a.post.id.cmp(&b.post.id)));
+        items.truncate(self.config.feed.count);
+        items
+    }
+
+    /// Canonical names of the tags of `post`, without repeats.
+    fn post_tags<'p>(&'p self, post: &'p Post) -> Vec<&'p str> {
+        let mut tags: Vec<&str> = Vec::new();
+        for tag in post.meta.tags.iter().map(|t| self.tag_names.get(t).unwrap_or(t).as_str()) {
+            if !tags.contains(&tag) { tags.push(tag) }
+        }
+        tags
+    }
+
+    /// The title and description of the site's feeds, from `site.title` and `site.description`.
+    fn feed_title(&self) -> (&str, &str) {
+        let site_string = |key: &str| self.config.site.get(key).and_then(|v| v.as_str());
+        let title = site_string("title").or(self.config.base_url.as_deref()).unwrap_or_default();
+        (title, site_string("description").unwrap_or(title))
+    }
+
+    /// Who Atom entries without authors of their own are by: the configured author, or else the site.
+    fn feed_author(&self) -> &str {
+        self.config.author.as_ref().map_or_else(|| self.feed_title().0, |author| author.name.as_str())
+    }
+
+    fn write_feed(&self, outpath: &str, xml: &str) {
+        if self.outputs.borrow().contains_key(outpath) {
+            log!("error: the feed `{}` would overwrite another page, skipping it", outpath);
+            return
+        }
+        log!("info: writing feed `{}`, {:.1} KiB", outpath, xml.len() as f64 / 1024.0);
+        self.write_to_output(outpath, xml.as_bytes());
+    }
+
+    /// Writes `robots.txt`: `static/robots.txt` if there is one, or else the `robots.txt` or
+    /// `robots` template, given the sitemap's URL as `sitemap_url`, or else one allowing everything
+    /// that points at the sitemap. A `robots.txt` in `root/` is copied as it is instead.
+    fn build_robots(&self) {
+        if self.outputs.borrow().contains_key("robots.txt") {
+            log!("info: the site has its own `robots.txt`, not generating one");
+            return
+        }
+        let sitemap = self.outputs.borrow().contains_key("sitemap.xml").then(|| {
+            jsonld::absolute_url(self.config.base_url.as_deref().unwrap_or_default(), "/sitemap.xml")
+        });
+
+        let custom = self.args.in_dir.join("static").join("robots.txt");
+        if custom.is_file() {
+            let Ok(content) = std::fs::read(&custom)
+                .inspect_err(|e| log!("error: cannot read `{}`: {}", custom.display(), e))
+                else { return };
+            log!("info: writing `robots.txt` from `{}`", custom.display());
+            self.write_to_output("robots.txt", &content);
+        } else if let Some(template) = ["robots.txt", "robots"].into_iter().find(|name| self.env.get_template(name).is_ok()) {
+            self.build_page(template, "robots.txt", || context! { sitemap_url => sitemap });
+        } else {
+            let mut robots = String::from("User-agent: *\nAllow: /\n");
+            if let Some(sitemap) = sitemap {
+                robots.push_str(&format!("\nSitemap: {}\n", sitemap));
+            }
+            self.write_to_output("robots.txt", robots.as_bytes());
+        }
+    }
+
+    /// Writes `sitemap.xml`, listing every HTML page the build wrote except redirects and
+    /// `404.html`, if the site has a `base_url`. Posts and standalone pages are dated by their
+    /// last change.
+    fn build_sitemap(&self) {
+        if !self.config.sitemap { return }
+        let Some(base_url) = &self.config.base_url else {
+            log!("info: no base_url configured, not generating a sitemap");
+            return
+        };
+        if self.outputs.borrow().contains_key("sitemap.xml") {
+            log!("info: the site has its own `sitemap.xml`, not generating one");
+            return
+        }
+
+        let lastmod: HashMap<&str, chrono::DateTime<chrono::FixedOffset>> = self.posts.iter().chain(&self.pages)
+            .map(|post| (post.output.as_str(), self.publish_time(post.meta.updated.as_ref().unwrap_or(&post.meta.date))))
+            .collect();
+        let redirects = self.redirects.borrow();
+        // Unlisted posts are only for those given the link, and drafts aren't published yet
+        let hidden: std::collections::HashSet<&str> = self.posts.iter().chain(&self.pages)
+            .filter(|post| !self.is_listed(post) || post.meta.draft)
+            .map(|post| post.output.as_str())
+            .collect();
+        let entries: Vec<sitemap::SitemapEntry> = self.outputs.borrow().keys()
+            .filter(|page| page.ends_with(".html") && !redirects.contains(*page) && !hidden.contains(page.as_str()) && *page != "404.html")
+            .map(|page| sitemap::SitemapEntry {
+                loc: jsonld::absolute_url(base_url, &permalink::url(page)),
+                lastmod: lastmod.get(page.as_str()).copied()
+            })
+            .collect();
+        log!("info: sitemap has {} page(s)", entries.len());
+        self.write_to_output("sitemap.xml", sitemap::sitemap(&entries).as_bytes());
+    }
+
+    fn build_archive_index(&self) {
+        if !self.config.archive_index { return }
+        let archive = archive::archive(
+            self.posts.iter().filter(|post| self.is_listed(post) && !post.meta.draft),
+            self.config.base_url.as_deref(),
+            |tag| self.tag_names.get(tag).map(String::as_str).unwrap_or(tag),
+            |dt| self.publish_time(dt)
+        );
+        let Ok(json) = serde_json::to_vec(&archive)
+            .inspect_err(|e| log!("error: could not serialize post archive: {}", e))
+            else { return };
+        log!("info: post archive has {} entries", archive.posts.len());
+        self.write_to_output("posts.json", &json);
+    }
+
+    /// With `--dev`, adds a panel listing the problems of each post that isn't published yet, a
+    /// draft or one dated in the future, to the top of its page. Broken links are only known once
+    /// everything is written, so this rewrites the pages at the end of the build.
+    fn add_preview_warnings(&self) {
+        #[cfg(feature = "dev")]
+        let dev = self.args.dev;
+        #[cfg(not(feature = "dev"))]
+        let dev = false;
+        if !dev { return }
+
+        let broken_links = lint::broken_links(self.sink, &self.outputs.borrow(), &self.config.base_path());
+        for post in self.posts.iter().filter(|post| post.meta.draft || self.publish_time(&post.meta.date) > self.now) {
+            let page = &post.output;
+            let mut diagnostics = post.diagnostics.clone();
+            if post.meta.tags.is_empty() {
+                diagnostics.push(post::Diagnostic { level: "warning", message: "post has no tags".into() });
+            }
+            for link in broken_links.get(page).into_iter().flatten() {
+                diagnostics.push(post::Diagnostic { level: "warning", message: format!("broken link `{}`", link) });
+            }
+            if diagnostics.is_empty() { continue }
+
+            let Ok(source) = self.sink.read(page)
+                .inspect_err(|e| log!("error: could not read output `{}`: {}", page, e))
+                else { continue };
+            log!("info: adding {} build problem(s) to the preview of `{}`", diagnostics.len(), post.id);
+            let source = html::insert_at_body_start(&String::from_utf8_lossy(&source), &html::diagnostics_panel(&diagnostics));
+            self.write_to_output(page, source.as_bytes());
+        }
+    }
+
+    /// Renders `__dashboard.html`, a page listing the site's editorial problems, when serving with `--dev`.
+    /// Broken links are found in the outputs, so this has to run after everything else is written.
+    fn build_dashboard(&mut self) {
+        #[cfg(feature = "dev")]
+        let dev = self.args.dev;
+        #[cfg(not(feature = "dev"))]
+        let dev = false;
+        if !dev { return }
+
+        if self.env.get_template("dashboard").is_err() {
+            log!("info: no `dashboard` template, using a minimal built-in one");
+            let Ok(()) = self.env.add_template("dashboard", include_str!("fallback/dashboard.html"))
+                .inspect_err(|e| log!("error: cannot parse built-in template `dashboard`: {}", e))
+                else { return };
+        }
+
+        let broken_links = lint::broken_links(self.sink, &self.outputs.borrow(), &self.config.base_path());
+        self.build_page("dashboard", "__dashboard.html", || context! {
+            scheduled => &self.scheduled,
+            // Unlisted drafts too, which `listing` would leave out
+            drafts => self.posts.iter().filter(|p| p.meta.draft).map(|p| PostSummary::new(p, false)).collect::<Vec<_>>(),
+            stale => self.listing(self.posts.iter().filter(|p| p.is_stale)),
+            untagged => self.listing(self.posts.iter().filter(|p| p.meta.tags.is_empty())),
+            unsummarized => self.listing(self.posts.iter().filter(|p| p.meta.summary.is_none())),
+            tag_lints => &self.tag_lints,
+            broken_links => &broken_links
+        });
+    }
+
+    /// With `--dev`, records the outputs that only a preview should have, the dashboard and the
+    /// pages of unpublished posts. Otherwise removes those a `--dev` build left in the same output
+    /// directory, unless this build wrote them too.
+    fn track_dev_outputs(&self) {
+        #[cfg(feature = "dev")]
+        let dev = self.args.dev;
+        #[cfg(not(feature = "dev"))]
+        let dev = false;
+        let path = self.cache_dir().join("dev-outputs.json");
+
+        if dev {
+            let mut outputs = vec!["__dashboard.html".to_string()];
+            outputs.extend(self.posts.iter()
+                .filter(|post| post.meta.draft || self.publish_time(&post.meta.date) > self.now)
+                .map(|post| post.output.clone()));
+            let record = DevOutputs { out_dir: self.args.out_dir.clone(), outputs };
+            let result = serde_json::to_vec(&record).map_err(std::io::Error::other)
+                .and_then(|json| std::fs::create_dir_all(self.cache_dir()).and_then(|_| std::fs::write(&path, json)));
+            if let Err(e) = result {
+                log!("warning: could not record the outputs of --dev in `{}`: {}", path.display(), e);
+            }
+            return
+        }
+
+        let Ok(data) = std::fs::read(&path) else { return };
+        let record: DevOutputs = serde_json::from_slice(&data)
+            .inspect_err(|e| log!("warning: ignoring unreadable `{}`: {}", path.display(), e))
+            .unwrap_or_default();
+        if record.out_dir == self.args.out_dir {
+            for output in record.outputs.iter().filter(|output| !self.outputs.borrow().contains_key(*output)) {
+                log!("info: removing `{}`, left over from a --dev build", output);
+                if let Err(e) = self.sink.remove(output) {
+                    log!("error: could not remove `{}`: {}", output, e);
+                }
+            }
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// Whether the post appears on listing pages and in navigation.
+    fn is_listed(&self, post: &Post) -> bool {
+        !post.meta.unlisted && (post.encrypted.is_none() || !self.config.hide_encrypted)
+    }
+
+    /// Listed posts other than drafts, oldest first, for the previous and next links of posts.
+    fn chronological(&self) -> Vec<&Post> {
+        let mut posts: Vec<&Post> = self.posts.iter().filter(|post| self.is_listed(post) && !post.meta.draft).collect();
+        posts.sort_by(|a, b| a.age.cmp(&b.age).then_with(|| a.id.cmp(&b.id)));
+        posts
+    }
+
+    /// Posts as listing pages see them, without their bodies unless `listing_content` is set,
+    /// and without encrypted posts if `hide_encrypted` is set.
+    fn listing<'p>(&self, posts: impl IntoIterator<Item=&'p Post>) -> Vec<PostSummary<'p>> {
+        posts.into_iter()
+            .filter(|post| self.is_listed(post))
+            .map(|post| PostSummary::new(post, self.config.listing_content))
+            .collect()
+    }
+
+    /// Renders `tname` to `outpath`. The context is built inside so that `--timings` can include it.
+    fn build_page<C: Serialize>(&self, tname: &str, outpath: &str, context: impl FnOnce() -> C) {
+        log!("info: rendering page `{}` with template `{}`", outpath, tname);
+        progress::step(outpath);
+        let start = std::time::Instant::now();
+        let context = context();
+        self.render_page(tname, outpath, &context);
+        if self.args.timings {
+            let duration = start.elapsed();
+            self.timings.borrow_mut().push(timings::PageTiming {
+                page: outpath.to_string(),
+                template: tname.to_string(),
+                duration,
+                context_bytes: timings::context_size(&context)
+            });
+        }
+    }
+
+    fn render_page<C: Serialize>(&self, tname: &str, outpath: &str, context: &C) {
+        if self.args.dump_context.as_deref() == Some(outpath) {
+            self.dump_context(outpath, context);
+        }
+
+        let Ok(template) = self.env.get_template(tname)
+            .inspect_err(|e| log!("error: cannot read template `{}`: {}", tname, e))
+            else { return };
+
+        let Ok(source) = template.render(context)
+            .inspect_err(|e| log!("error: could not render template `{}`: {}", tname, e))
+            else { return };
+        self.counts.borrow_mut().pages += 1;
+        // Served for any missing path, so relative links would resolve against the wrong directory
+        let source = if outpath == "404.html" { html::root_relative_links(&source, outpath, &self.config.base_path()) } else { source };
+
+        self.write_to_output(outpath, source.as_bytes());        
+    }
+
+    fn dump_context<C: Serialize>(&self, outpath: &str, context: &C) {
+        let value = minijinja::Value::from_serialize(context);
+        let Ok(json) = serde_json::to_string_pretty(&value)
+            .inspect_err(|e| log!("error: could not serialize context of `{}`: {}", outpath, e))
+            else { return };
+
+        if let Some(file) = &self.args.dump_context_to {
+            match std::fs::write(file, json) {
+                Ok(()) => log!("info: wrote context of `{}` to `{}`", outpath, file.display()),
+                Err(e) => log!("error: could not write context of `{}`: {}", outpath, e)
+            }
+        } else {
+            log!("info: context of `{}`:\n{}", outpath, json);
+        }
+    }
+
+    fn copy_static(&self) {
+        progress::begin("copying static files", None);
+        self.copy_tree("static", "static");
+        self.end_phase();
+    }
+
+    /// Copies `in_dir/root` to the root of the output, keeping dotfiles like `.well-known/` and
+    /// `.nojekyll`. Files that would replace a generated output are skipped.
+    fn copy_root_files(&self) {
+        self.copy_tree("root", "");
+    }
+
+    /// Waits for the large files being copied in the background.
+    fn finish_large_copies(&self) {
+        for (outpath, handle) in self.large_copies.take() {
+            let result = handle.join().unwrap_or_else(|_| Err(std::io::Error::other("copying thread panicked")));
+            if let Err(e) = result {
+                log!("error: could not copy `{}`: {}", outpath, e);
+                self.outputs.borrow_mut().remove(&outpath);
+            }
+        }
+    }
+
+    fn count_copied(&self, dir: &str) {
+        if dir == "static" {
+            self.counts.borrow_mut().static_files += 1;
+        }
+    }
+
+    fn copy_tree(&self, dir: &str, prefix: &str) {
+        let in_dir = self.args.in_dir.join(dir);
+        if !in_dir.is_dir() { return }
+
+        for entry in walkdir::WalkDir::new(&in_dir) {
+            let Ok(entry) = entry
+                .inspect_err(|e| {
+                    log!("error: could not read {} file: {e}", dir)
+                }) else { continue };
+            if !entry.file_type().is_file() { continue }
+
+            let Ok(relpath) = entry.path().strip_prefix(&in_dir) else { continue };
+            let outpath = Path::new(prefix).join(relpath).to_string_lossy().replace('\\', "/");
+            progress::step(&outpath);
+            if self.outputs.borrow().contains_key(&outpath) {
+                log!("error: `{}` would overwrite the generated `{}`, skipping it", entry.path().display(), outpath);
+                continue
+            }
+
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            if self.config.large_file_mb > 0 && size > self.config.large_file_mb << 20
+                && let Some(target) = self.sink.file_target(&outpath)
+            {
+                match target {
+                    Ok(target) => {
+                        log!("info: copying large {} file `{}` in the background", dir, entry.path().display());
+                        let source = entry.path().to_path_buf();
+                        let handle = std::thread::spawn(move || output::copy_resumable(&source, &target));
+                        self.large_copies.borrow_mut().push((outpath.clone(), handle));
+                        self.outputs.borrow_mut().insert(outpath, size);
+                        self.count_copied(dir);
+                    },
+                    Err(e) => log!("error: could not copy {} file: {e}", dir)
+                }
+                continue
+            }
+
+            log!("info: copying {} file `{}`", dir, entry.path().display());
+            match self.sink.copy(&outpath, entry.path()) {
+                Ok(size) => {
+                    self.outputs.borrow_mut().insert(outpath, size);
+                    self.count_copied(dir);
+                },
+                Err(e) => log!("error: could not copy {} file: {e}", dir)
+            }
+        }
+    }
+}
+
+/// Creates the markdown file of a new post titled `title`, returning its path. Refuses to
+/// replace an existing post with the same id.
+pub fn new_post(args: &Args, title: &str, dir: bool, tags: &[String]) -> Result<PathBuf, String> {
+    let config = SiteConfig::load(&args.in_dir)?;
+    let slug = config.slugs.slugify_or(title, "post");
+    let posts_dir = args.in_dir.join("posts");
+    let (file, other) = (posts_dir.join(format!("{}.md", slug)), posts_dir.join(&slug).join("index.md"));
+    let (file, other) = if dir { (other, file) } else { (file, other) };
+    if other.exists() {
+        return Err(format!("`{}` already has the id `{}`", other.display(), slug))
+    }
+
+    let now = args.now.unwrap_or_else(|| chrono::Local::now().fixed_offset());
+    let tags = toml::Value::Array(tags.iter().map(|tag| toml::Value::String(tag.trim().to_string())).collect());
+    let contents = format!(
+        "+++\ntitle = {}\ndate = {}\ntags = {}\n+++\n\n",
+        toml::Value::String(title.to_string()), now.format("%Y-%m-%dT%H:%M:%S%:z"), tags
+    );
+
+    if let Some(parent) = file.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("could not create `{}`: {}", parent.display(), e))?;
+    }
+    let mut out = std::fs::OpenOptions::new().write(true).create_new(true).open(&file)
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => format!("`{}` already exists, not overwriting it", file.display()),
+            _ => format!("could not create `{}`: {}", file.display(), e)
+        })?;
+    std::io::Write::write_all(&mut out, contents.as_bytes())
+        .map_err(|e| format!("could not write `{}`: {}", file.display(), e))?;
+    Ok(file)
+}
+
+/// Imports the posts in `source` into the posts directory, printing a report of what has to be fixed by hand.
+pub fn import_posts(args: &Args, source: &Path, force: bool) -> Result<(), String> {
+    let report = import::import(source, &args.in_dir.join("posts"), force)?;
+    import::print_report(&report);
+    Ok(())
+}
+
+/// Builds only the post with the given id and writes it to `out` as a single HTML file,
+/// rendered with the `export` template or a minimal built-in one.
+pub fn export_post(args: &Args, id: &str, out: &Path) -> Result<(), String> {
+    let sink = output::MemorySink::default();
+    let mut builder = SiteBuilder::new(args, &sink, SiteConfig::load(&args.in_dir)?);
+
+    let source = builder.post_sources().into_iter()
+        .find(|s| {
+            let name = post::slug_override(&s.file, &s.defaults).unwrap_or_else(|| post::post_name(&s.file, s.dir.as_deref()));
+            builder.config.slugs.slugify_or(&name, "post") == id
+        })
+        .ok_or_else(|| format!("no post with id `{}`", id))?;
+    let mut post = PostBuilder { site: &mut builder, file: source.file, dir: source.dir, defaults: source.defaults, meta: None, images: Vec::new(), diagnostics: Vec::new(), inputs: BTreeMap::new(), assets: Vec::new(), page: false, unpublishable: false }.build()
+        .ok_or_else(|| format!("could not build post `{}`", id))?;
+    // Only into the sink, the manifest of one post would overwrite the one of the whole site
+    builder.write_assets();
+    post.output = builder.output_path(&post, &builder.config.permalink);
+    post.url = builder.url(&post.output);
+
+    builder.load_templates();
+    if builder.env.get_template("export").is_err() {
+        log!("info: no `export` template, using a minimal built-in one");
+        builder.env.add_template("export", include_str!("fallback/export.html")).map_err(|e| e.to_string())?;
+    }
+    let highlight_css = arborium::theme::builtin::monokai().to_css("code");
+    let document = builder.env.get_template("export")
+        .and_then(|t| t.render(context! { post => &post, highlight_css => highlight_css }))
+        .map_err(|e| format!("could not render template `export`: {}", e))?;
+
+    let static_dir = args.in_dir.join("static");
+    let document = export::inline_resources(&post.output, &document, &builder.config.base_path(), |path| {
+        match path.strip_prefix("static/") {
+            Some(relative) => std::fs::read(static_dir.join(relative)).ok(),
+            None => sink.read(path).ok()
+        }
+    })?;
+
+    std::fs::write(out, &document).map_err(|e| format!("could not write `{}`: {}", out.display(), e))?;
+    log!("info: exported post `{}` to `{}`, {:.1} KiB", id, out.display(), document.len() as f64 / 1024.0);
+    Ok(())
+}
+
+/// Prints the effective site configuration of `args.in_dir` as TOML.
+pub fn dump_config(args: &Args) -> Result<(), String> {
+    print!("{}", SiteConfig::load(&args.in_dir)?.dump()?);
+    Ok(())
+}
+
+fn cache_dir(in_dir: &std::path::Path) -> PathBuf {
+    in_dir.join(".ssg-cache")
+}
+
+/// Input directories whose contents are read or copied on every build
+const SOURCE_DIRS: &[&str] = &["posts", "pages", "data", "templates", "static", "root"];
+
+/// Checks that writing to `args.out_dir` can't change the input, or the input the output. Both
+/// are canonical, so directories that are symlinks into each other are caught too.
+pub fn check_dirs(args: &Args) -> Result<(), String> {
+    let (in_dir, out_dir) = (&args.in_dir, &args.out_dir);
+    if out_dir == in_dir {
+        return Err(format!("the output directory is the input directory `{}`", in_dir.display()))
+    }
+    if in_dir.starts_with(out_dir) {
+        return Err(format!("the input directory `{}` is inside the output directory `{}`", in_dir.display(), out_dir.display()))
+    }
+    for name in SOURCE_DIRS {
+        // The directory itself may be a symlink elsewhere
+        let Ok(dir) = std::fs::canonicalize(in_dir.join(name)) else { continue };
+        if out_dir.starts_with(&dir) {
+            return Err(format!("the output directory `{}` is inside the input's `{}` directory, which is read on every build", out_dir.display(), name))
+        }
+    }
+    Ok(())
+}
+
+/// Keeps other builds of the site in `args.in_dir` from running until the lock is dropped.
+pub fn lock_build(args: &Args) -> Result<lock::BuildLock, String> {
+    lock::BuildLock::acquire(&cache_dir(&args.in_dir).join("build.lock"), args.break_lock)
+}
+
+#[cfg(feature = "dev")]
+/// Builds a filter for watch mode from the site config in `args.in_dir`.
+pub fn watch_filter(args: &Args) -> watch::WatchFilter {
+    // A config that doesn't parse is reported by the build
+    let config = SiteConfig::load(&args.in_dir).unwrap_or_default();
+    watch::WatchFilter::new(&args.in_dir, &args.out_dir, &cache_dir(&args.in_dir), &config.watch_ignore)
+}
+
+pub fn dt_toml_to_chrono(dt: &toml_datetime::Datetime) -> chrono::DateTime<chrono::FixedOffset> {
+    (|| {
+        let date = chrono::NaiveDate::from_ymd_opt(dt.date?.year as i32, dt.date?.month as u32, dt.date?.day as u32)?;
+        let datetime = (|| date.and_hms_opt(dt.time?.hour as u32, dt.time?.minute as u32, dt.time?.second as u32))()
+            .unwrap_or(date.and_time(chrono::NaiveTime::MIN));
+        let mapped = (|| datetime.and_local_timezone(chrono::FixedOffset::east_opt(match dt.offset? {
+            toml_datetime::Offset::Z => 0,
+            toml_datetime::Offset::Custom { minutes } => (minutes as i32) * 60
+        })?).single())().unwrap_or(datetime.and_utc().fixed_offset());
+        Some(mapped)
+    })().unwrap_or(chrono::DateTime::UNIX_EPOCH.fixed_offset())
+}
+
+/// The moment a date and time without an offset is at in `tz`. Times that happen twice as the
+/// clocks go back are taken as the first, and times skipped as they go forward are read with the
+/// offset from before.
+fn resolve_local<Tz: chrono::TimeZone>(local: chrono::NaiveDateTime, tz: &Tz) -> chrono::DateTime<chrono::FixedOffset> {
+    use chrono::Offset;
+    match tz.from_local_datetime(&local) {
+        chrono::LocalResult::Single(dt) | chrono::LocalResult::Ambiguous(dt, _) => dt.fixed_offset(),
+        chrono::LocalResult::None => {
+            let before = tz.offset_from_utc_datetime(&(local - chrono::TimeDelta::days(1))).fix();
+            local.and_local_timezone(before).single().unwrap_or(local.and_utc().fixed_offset())
+        }
+    }
+}
+
+/// The time in the `SOURCE_DATE_EPOCH` environment variable, in seconds since 1970, which reproducible
+/// builds use in place of times that differ between checkouts, like file creation times.
+pub fn source_date_epoch() -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    let value = std::env::var("SOURCE_DATE_EPOCH").ok()?;
+    let epoch = value.trim().parse().ok().and_then(|seconds| chrono::DateTime::from_timestamp(seconds, 0));
+    if epoch.is_none() {
+        log!("warning: ignoring SOURCE_DATE_EPOCH `{}`, expected a number of seconds since 1970", value);
+    }
+    epoch.map(|dt| dt.fixed_offset())
+}
+
+/// Formats a datetime as RFC 3339, which is also valid W3C datetime, for machine-readable outputs.
+/// This never depends on the locale, unlike the human-readable `format_datetime` filter.
+pub fn rfc3339(dt: &chrono::DateTime<chrono::FixedOffset>) -> String {
+    dt.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, false)
+}
+
+/// Reads a TOML datetime or an RFC 3339 string, `None` for undefined and none values.
+fn datetime_value(value: &minijinja::Value) -> Result<Option<chrono::DateTime<chrono::FixedOffset>>, String> {
+    use serde::Deserialize;
+    if value.is_undefined() || value.is_none() { return Ok(None) }
+    if let Some(s) = value.as_str() {
+        return chrono::DateTime::parse_from_rfc3339(s)
+            .map(Some)
+            .or_else(|_| s.parse::<toml_datetime::Datetime>().map(|dt| Some(dt_toml_to_chrono(&dt))))
+            .map_err(|_| format!("`{}` is not an RFC 3339 or TOML datetime", s))
+    }
+    toml_datetime::Datetime::deserialize(value.clone())
+        .map(|dt| Some(dt_toml_to_chrono(&dt)))
+        .map_err(|e| e.to_string())
+}
+
+fn format_datetime_function(s: &minijinja::State<'_, '_>, value: minijinja::Value, format: Option<String>, default_format: &str) -> String {
+    use std::fmt::Write;
+    let placeholder = || s.lookup("DATETIME_PLACEHOLDER")
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default();
+    let cdt = match datetime_value(&value) {
+        Ok(Some(cdt)) => cdt,
+        Ok(None) => return placeholder(),
+        Err(e) => {
+            log!("warning: cannot format datetime in template `{}`: {}", s.name(), e);
+            return placeholder()
+        }
+    };
+
+    let format_value = s.lookup("FORMAT_DATETIME");
+    let format = format.as_deref()
+        .or(format_value.as_ref().and_then(|v| v.as_str()))
+        .unwrap_or(default_format);
+    let mut readable = String::new();
+    if write!(readable, "{}", cdt.format(format)).is_err() {
+        log!("warning: invalid datetime format `{}` in template `{}`", format, s.name());
+        return placeholder()
+    }
+    format!("<time datetime=\"{}\">{}</time>", rfc3339(&cdt), readable)
+}
+
+fn first_lines(messages: &[String]) -> Vec<String> {
+    messages.iter().map(|m| m.lines().next().unwrap_or_default().to_string()).collect()
+}
+
+/// Templates without which the site has no point of entry
+const REQUIRED_TEMPLATES: &[&str] = &["index", "post"];
+
+/// Builds the site described by `args` into `sink`, returning the built posts and the path and size of every output.
+pub fn build_to_sink(args: &Args, sink: &dyn OutputSink) -> BuildResult {
+    let started = std::time::Instant::now();
+    // Errors from before this build, like in watch mode, don't count against it
+    logging::take_problems();
+    let Ok(config) = SiteConfig::load(&args.in_dir)
+        .inspect_err(|e| log!("error: {}", e))
+        else {
+            log!("error: not building until the site config is fixed");
+            return BuildResult { errors: first_lines(&logging::take_problems().errors), fatal: true, ..BuildResult::default() }
+        };
+    use std::io::IsTerminal;
+    progress::enable(std::io::stdout().is_terminal() && !args.verbose && !args.quiet && !args.debug_metadata);
+    let mut builder = SiteBuilder::new(args, sink, config);
+    builder.build_posts();
+    builder.build_standalone_pages();
+    builder.collect_tags();
+    builder.assign_urls();
+    builder.collect_authors();
+    builder.collect_categories();
+    builder.check_freshness();
+    builder.add_structured_data();
+    builder.load_templates();
+    builder.add_fallback_templates();
+    let missing: Vec<&str> = REQUIRED_TEMPLATES.iter().copied().filter(|name| builder.env.get_template(name).is_err()).collect();
+    for name in &missing {
+        log!("error: required template `{}` is missing", name);
+    }
+    builder.build_pages();
+    builder.migrate_urls();
+    builder.build_search_index();
+    builder.build_archive_index();
+    builder.build_feed();
+    builder.copy_static();
+    builder.copy_root_files();
+    builder.build_sitemap();
+    builder.build_robots();
+    builder.report_scheduled();
+    builder.finish_large_copies();
+    if let Some(page) = &args.dump_context && !builder.outputs.borrow().contains_key(page) {
+        log!("error: no page was rendered to `{}`, cannot dump its context", page);
+    }
+    weight::report_page_weights(sink, &builder.outputs.borrow(), &builder.config);
+    if args.timings {
+        timings::report(&builder.timings.borrow(), &builder.post_timings, &builder.phases.borrow(), builder.config.heaviest_pages);
+    }
+    if let Some(dir) = &args.replay_check {
+        replay::check(dir, sink);
+    }
+    if let Some(dir) = &args.compare {
+        compare::compare(dir, &args.compare_report, sink, &builder.outputs.borrow(), &builder.config.compare);
+    }
+    builder.add_preview_warnings();
+    builder.build_dashboard();
+    builder.track_dev_outputs();
+    let problems = logging::take_problems();
+    let errors = first_lines(&problems.errors);
+    if args.strict && !errors.is_empty() {
+        let mut message = format!("error: the build had {} error(s):", errors.len());
+        for error in &errors {
+            message.push_str(&format!("\n    {}", error));
+        }
+        log!("{message}");
+    }
+    timings::summary(&builder.counts.borrow(), &builder.phases.borrow(), problems.warnings.len(), problems.errors.len(), started.elapsed());
+    if let Some(path) = &args.report {
+        report::write(path, &builder.build_report(started.elapsed(), problems));
+    }
+    BuildResult {
+        posts: builder.posts.into_iter().map(|post| BuiltPost {
+            url: post.url,
+            id: post.id,
+            source: post.file,
+            title: post.meta.title,
+            date: post.meta.date.to_string()
+        }).collect(),
+        outputs: builder.outputs.into_inner(),
+        templates: builder.templates,
+        fatal: !missing.is_empty(),
+        errors
+    }
+}
+
+pub fn recompile(args: &Args) -> BuildResult {
+    build_to_sink(args, &FsSink { dir: args.out_dir.clone() })
+}
+
+/// A fresh, empty directory under the system temp directory for the test `name`.
+#[cfg(test)]
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+    let dir = test_path(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Where `test_dir` puts the directory for the test `name`.
+#[cfg(test)]
+pub(crate) fn test_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("static-site-gen-{}-{}", std::process::id(), name))
+}
+
+/// Builds the site made of `files`, given as paths relative to the input directory and their
+/// contents, in memory with the extra command line `flags`, giving its outputs.
+#[cfg(test)]
+pub(crate) fn build_test_site(name: &str, files: &[(&str, &str)], flags: &[&str]) -> BTreeMap<String, Vec<u8>> {
+    let dir = test_dir(name);
+    let (in_dir, out_dir) = (dir.join("in"), dir.join("out"));
+    for (path, contents) in files {
+        let path = in_dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+    std::fs::create_dir_all(&in_dir).unwrap();
+    std::fs::create_dir_all(&out_dir).unwrap();
+    rebuild_test_site(name, flags)
+}
+
+/// Builds the site of an earlier `build_test_site` for the test `name` again.
+#[cfg(test)]
+pub(crate) fn rebuild_test_site(name: &str, flags: &[&str]) -> BTreeMap<String, Vec<u8>> {
+    rebuild_test_site_result(name, flags).1
+}
+
+/// Like `rebuild_test_site`, also giving what the build reports.
+#[cfg(test)]
+pub(crate) fn rebuild_test_site_result(name: &str, flags: &[&str]) -> (BuildResult, BTreeMap<String, Vec<u8>>) {
+    let (in_dir, out_dir) = (test_path(name).join("in"), test_path(name).join("out"));
+    let dirs = [in_dir.to_str().unwrap(), out_dir.to_str().unwrap()];
+    let args = Args::parse_from(["static-site-gen"].into_iter().chain(dirs).chain(flags.iter().copied()));
+    logging::set_max_level(logging::Level::Error);
+    let sink = output::MemorySink::default();
+    let result = build_to_sink(&args, &sink);
+    (result, sink.into_outputs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The templates every test site needs
+    pub(crate) const TEMPLATES: &[(&str, &str)] = &[
+        ("templates/index.html", "{% for post in posts %}{{ post.url }}\n{% endfor %}"),
+        ("templates/post.html", "{{ post.title_plain }}")
+    ];
+
+    fn text(outputs: &BTreeMap<String, Vec<u8>>, path: &str) -> String {
+        String::from_utf8(outputs.get(path).unwrap_or_else(|| panic!("`{}` was not written", path)).clone()).unwrap()
+    }
+
+    /// Helsinki in 2024: +02:00, and +03:00 from 31 March to 27 October
+    #[derive(Clone, Copy)]
+    struct Helsinki;
+
+    impl Helsinki {
+        fn offset_at(utc: &chrono::NaiveDateTime) -> chrono::FixedOffset {
+            let start = chrono::NaiveDate::from_ymd_opt(2024, 3, 31).unwrap().and_hms_opt(1, 0, 0).unwrap();
+            let end = chrono::NaiveDate::from_ymd_opt(2024, 10, 27).unwrap().and_hms_opt(1, 0, 0).unwrap();
+            let hours = if (start..end).contains(utc) { 3 } else { 2 };
+            chrono::FixedOffset::east_opt(hours * 3600).unwrap()
+        }
+    }
+
+    impl chrono::TimeZone for Helsinki {
+        type Offset = chrono::FixedOffset;
+        fn from_offset(_: &chrono::FixedOffset) -> Self { Helsinki }
+        fn offset_from_local_date(&self, local: &chrono::NaiveDate) -> chrono::LocalResult<chrono::FixedOffset> {
+            self.offset_from_local_datetime(&local.and_time(chrono::NaiveTime::MIN))
+        }
+        fn offset_from_local_datetime(&self, local: &chrono::NaiveDateTime) -> chrono::LocalResult<chrono::FixedOffset> {
+            let fits: Vec<chrono::FixedOffset> = [2, 3].into_iter()
+                .map(|hours| chrono::FixedOffset::east_opt(hours * 3600).unwrap())
+                .filter(|offset| Self::offset_at(&(*local - *offset)) == *offset)
+                .collect();
+            match fits[..] {
+                [offset] => chrono::LocalResult::Single(offset),
+                [winter, summer] => chrono::LocalResult::Ambiguous(summer, winter),
+                _ => chrono::LocalResult::None
+            }
+        }
+        fn offset_from_utc_date(&self, utc: &chrono::NaiveDate) -> chrono::FixedOffset {
+            Self::offset_at(&utc.and_time(chrono::NaiveTime::MIN))
+        }
+        fn offset_from_utc_datetime(&self, utc: &chrono::NaiveDateTime) -> chrono::FixedOffset {
+            Self::offset_at(utc)
+        }
+    }
+
+    #[test]
+    fn local_times_use_the_offset_of_their_date() {
+        let resolve = |text: &str| resolve_local(text.parse().unwrap(), &Helsinki).to_rfc3339();
+        assert_eq!(resolve("2024-01-15T12:00:00"), "2024-01-15T12:00:00+02:00");
+        assert_eq!(resolve("2024-07-15T12:00:00"), "2024-07-15T12:00:00+03:00");
+        // Happens twice as the clocks go back from 04:00 to 03:00
+        assert_eq!(resolve("2024-10-27T03:30:00"), "2024-10-27T03:30:00+03:00");
+        // Skipped as the clocks go forward from 03:00 to 04:00
+        assert_eq!(resolve("2024-03-31T03:30:00"), "2024-03-31T03:30:00+02:00");
+        let fixed = chrono::FixedOffset::east_opt(-5 * 3600).unwrap();
+        assert_eq!(resolve_local("2024-07-15T12:00:00".parse().unwrap(), &fixed).to_rfc3339(), "2024-07-15T12:00:00-05:00");
+    }
+
+    #[cfg(feature = "dev")]
+    #[test]
+    fn unpublished_posts_show_their_problems() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "<body>{{ post.title_plain }}</body>"),
+            ("posts/draft.md", "+++\ntitle = \"Draft\"\ndate = 2024-01-01\ndraft = true\n+++\nText"),
+            ("posts/future.md", "+++\ntitle = \"Future\"\ndate = 2999-01-01\n+++\nText"),
+            ("posts/published.md", "+++\ntitle = \"Published\"\ndate = 2024-01-01\n+++\nText")
+        ]);
+        let outputs = build_test_site("preview-warnings", &files, &["--drafts", "--future", "--dev"]);
+        assert!(text(&outputs, "posts/draft.html").contains("post has no tags"));
+        assert!(text(&outputs, "posts/future.html").contains("post has no tags"));
+        assert!(!text(&outputs, "posts/published.html").contains("build-diagnostics"));
+    }
+
+    #[cfg(feature = "dev")]
+    #[test]
+    fn dashboard_lists_drafts() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("posts/draft.md", "+++\ntitle = \"A draft\"\ndate = 2024-01-01\ndraft = true\n+++\nText"),
+            ("posts/published.md", "+++\ntitle = \"Published\"\ndate = 2024-01-01\ntags = [\"a\"]\nsummary = \"About it\"\n+++\nText")
+        ]);
+        let outputs = build_test_site("dashboard-drafts", &files, &["--drafts", "--dev"]);
+        let dashboard = text(&outputs, "__dashboard.html");
+        assert!(dashboard.contains("Drafts (1)"), "{}", dashboard);
+        assert!(dashboard.contains("<a href=\"/posts/draft.html\">A draft</a>"), "{}", dashboard);
+        let unsummarized = dashboard.split("Posts without a summary (1)").nth(1).unwrap_or_else(|| panic!("{}", dashboard));
+        let unsummarized = unsummarized.split("<h2>").next().unwrap();
+        assert!(unsummarized.contains("A draft") && !unsummarized.contains("Published"), "{}", unsummarized);
+    }
+
+    #[cfg(feature = "dev")]
+    #[test]
+    fn dev_outputs_are_removed_by_later_builds() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("posts/draft.md", "+++\ntitle = \"A draft\"\ndate = 2024-01-01\ndraft = true\n+++\nText"),
+            ("posts/published.md", "+++\ntitle = \"Published\"\ndate = 2024-01-01\n+++\nText")
+        ]);
+        build_test_site("dev-outputs", &files, &[]);
+        let dir = test_path("dev-outputs");
+        let (in_dir, out_dir) = (dir.join("in"), dir.join("out"));
+        let build = |flags: &[&str]| {
+            let dirs = [in_dir.to_str().unwrap(), out_dir.to_str().unwrap()];
+            recompile(&Args::parse_from(["static-site-gen"].into_iter().chain(dirs).chain(flags.iter().copied())))
+        };
+
+        build(&["--drafts", "--dev"]);
+        assert!(out_dir.join("__dashboard.html").is_file());
+        assert!(out_dir.join("posts/draft.html").is_file());
+        build(&[]);
+        assert!(!out_dir.join("__dashboard.html").exists());
+        assert!(!out_dir.join("posts/draft.html").exists());
+        assert!(out_dir.join("posts/published.html").is_file());
+
+        // Drafts that a build writes itself stay
+        build(&["--drafts", "--dev"]);
+        build(&["--drafts"]);
+        assert!(!out_dir.join("__dashboard.html").exists());
+        assert!(out_dir.join("posts/draft.html").is_file());
+    }
+
+    #[test]
+    fn code_block_layout() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "{{ post.source }}"),
+            ("site.toml", "[code]\nmax_height = \"30em\""),
+            ("posts/code.md", concat!(
+                "+++\ntitle = \"Code\"\ndate = 2024-01-01\n+++\n",
+                "```text wrap=\"true\" max_height=\"20em\"\nwrapped\n```\n\n",
+                "```text\ndefault\n```\n\n",
+                "```text max_height=\"20em;color:red\"\ninvalid\n```\n"
+            ))
+        ]);
+        let outputs = build_test_site("code-layout", &files, &[]);
+        let html = text(&outputs, "posts/code.html");
+        assert!(html.contains("<div class=\"code-block code-wrap code-scroll\" data-wrap=\"true\" data-max-height=\"20em\" style=\"--code-max-height: 20em\">\n<pre"), "{}", html);
+        assert_eq!(html.matches("data-max-height=\"30em\"").count(), 1, "{}", html);
+        assert!(!html.contains("color:red"), "{}", html);
+        assert_eq!(html.matches("<div class=\"code-block").count(), html.matches("</div>").count(), "{}", html);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn code_block_layout_of_processor_output() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "{{ post.source }}"),
+            ("site.toml", "[[processors]]\nlanguage = \"chart\"\ncommand = [\"cat\"]"),
+            ("posts/chart.md", "+++\ntitle = \"Chart\"\ndate = 2024-01-01\n+++\n```chart wrap=\"true\"\n<svg></svg>\n```\n")
+        ]);
+        let outputs = build_test_site("code-layout-processor", &files, &["--enable-processors"]);
+        let html = text(&outputs, "posts/chart.html");
+        assert!(html.contains("<div class=\"code-block code-wrap\" data-wrap=\"true\"><svg></svg>\n</div>"), "{}", html);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn failed_processors_fall_back_unless_strict() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "{{ post.source }}"),
+            ("site.toml", "[[processors]]\nlanguage = \"chart\"\ncommand = [\"false\"]"),
+            ("posts/chart.md", "+++\ntitle = \"Chart\"\ndate = 2024-01-01\n+++\n```chart\nsource\n```\n")
+        ]);
+        let outputs = build_test_site("processor-failure", &files, &["--enable-processors"]);
+        assert!(text(&outputs, "posts/chart.html").contains("source"));
+        let outputs = build_test_site("processor-failure-strict", &files, &["--enable-processors", "--strict"]);
+        assert!(!outputs.contains_key("posts/chart.html"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn failed_processors_are_not_cached() {
+        let tool = test_dir("processor-retry-tool").join("tool.sh");
+        std::fs::write(&tool, "exit 1").unwrap();
+        let site = format!("[[processors]]\nlanguage = \"chart\"\ncommand = [\"sh\", {:?}]", tool.to_str().unwrap());
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "{{ post.source }}"),
+            ("site.toml", site.as_str()),
+            ("posts/chart.md", "+++\ntitle = \"Chart\"\ndate = 2024-01-01\n+++\n```chart\nsource\n```\n")
+        ]);
+        let outputs = build_test_site("processor-retry", &files, &["--enable-processors"]);
+        assert!(text(&outputs, "posts/chart.html").contains("source"));
+        // The post that fell back isn't reused by a strict build
+        let (result, outputs) = rebuild_test_site_result("processor-retry", &["--enable-processors", "--strict"]);
+        assert!(result.errors.iter().any(|e| e.contains("processor for `chart` failed")), "{:?}", result.errors);
+        assert!(!outputs.contains_key("posts/chart.html"));
+
+        std::fs::write(&tool, "echo '<svg>chart</svg>'").unwrap();
+        let (result, outputs) = rebuild_test_site_result("processor-retry", &["--enable-processors", "--strict"]);
+        assert!(!result.errors.iter().any(|e| e.contains("processor")), "{:?}", result.errors);
+        assert!(text(&outputs, "posts/chart.html").contains("<svg>chart</svg>"));
+    }
+
+    #[test]
+    fn tag_pages_share_one_url_scheme() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/tag.html", "{{ pagination.url }} {{ pagination.prev }} {{ pagination.next }}"),
+            ("site.toml", "tag_paginate = 1"),
+            ("posts/a.md", "+++\ntitle = \"A\"\ndate = 2024-01-01\ntags = [\"x\"]\n+++\nText"),
+            ("posts/b.md", "+++\ntitle = \"B\"\ndate = 2024-01-02\ntags = [\"x\"]\n+++\nText"),
+            ("posts/c.md", "+++\ntitle = \"C\"\ndate = 2024-01-03\ntags = [\"x\"]\n+++\nText")
+        ]);
+        let outputs = build_test_site("tag-pages", &files, &[]);
+        assert_eq!(text(&outputs, "tags/x.html"), "/tags/x.html none /tags/x/page/2.html");
+        assert_eq!(text(&outputs, "tags/x/page/2.html"), "/tags/x/page/2.html /tags/x.html /tags/x/page/3.html");
+        assert_eq!(text(&outputs, "tags/x/page/3.html"), "/tags/x/page/3.html /tags/x/page/2.html none");
+    }
+
+    #[test]
+    fn page_links_are_under_the_base_path() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/index.html", "{% for post in posts %}{{ post.url }} {{ post.meta.tags[0] | tag_url }} {{ post.meta.authors[0] | author_url }} {{ post.meta.category | category_url }}\n{% endfor %}{{ pagination.next }}"),
+            ("templates/tag.html", "{{ pagination.url }} {{ pagination.next }}"),
+            ("templates/archive.html", "{% for year in years %}{{ year.url }}{% endfor %}"),
+            ("site.toml", "base_url = \"https://example.com/blog/\"\npaginate = 1\ntag_paginate = 1"),
+            ("posts/a.md", "+++\ntitle = \"A\"\ndate = 2024-01-01\ntags = [\"x\"]\nauthors = [\"Ann\"]\ncategory = \"Notes\"\n+++\nText"),
+            ("posts/b.md", "+++\ntitle = \"B\"\ndate = 2024-01-02\ntags = [\"x\"]\nauthors = [\"Ann\"]\ncategory = \"Notes\"\n+++\nText")
+        ]);
+        let outputs = build_test_site("base-path-links", &files, &[]);
+        assert_eq!(
+            text(&outputs, "index.html"),
+            "/blog/posts/b.html /blog/tags/x.html /blog/authors/ann.html /blog/categories/notes.html\n/blog/page/2.html"
+        );
+        assert_eq!(text(&outputs, "tags/x.html"), "/blog/tags/x.html /blog/tags/x/page/2.html");
+        assert_eq!(text(&outputs, "archive/index.html"), "/blog/archive/2024.html");
+    }
+
+    #[test]
+    fn not_found_page_links_are_root_relative() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/404.html", "<a href=\"index.html\">Home</a>{% for post in posts %} <a href=\"{{ post.url }}\">{{ post.title_plain }}</a>{% endfor %} <img src=\"static/lost.png\">"),
+            ("site.toml", "base_url = \"https://example.com/blog/\""),
+            ("posts/a.md", "+++\ntitle = \"A\"\ndate = 2024-01-01\n+++\nText")
+        ]);
+        let outputs = build_test_site("not-found-links", &files, &[]);
+        let page = text(&outputs, "404.html");
+        assert_eq!(page, "<a href=\"/blog/index.html\">Home</a> <a href=\"/blog/posts/a.html\">A</a> <img src=\"/blog/static/lost.png\">");
+        // Served for a nested missing path, every link still reaches the page it means
+        for link in html::link_attributes(&page) {
+            assert_eq!(html::resolve_local_link("posts/2024/missing.html", link, "/blog"), html::resolve_local_link("404.html", link, "/blog"));
+        }
+    }
+
+    #[test]
+    fn nav_year_dropdown_renders_on_every_page() {
+        let base = "<select>{% for year in nav.years %}<option value=\"{{ year.url }}\">{{ year.year }} ({{ year.count }})</option>{% endfor %}</select>\
+            {% for tag in nav.tags %} <a href=\"{{ tag.url }}\">{{ tag.name }}</a>{% endfor %}|{% block content %}{% endblock %}";
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/base.html", base),
+            ("templates/index.html", "{% extends \"base\" %}{% block content %}index{% endblock %}"),
+            ("templates/post.html", "{% extends \"base\" %}{% block content %}{{ post.title_plain }}{% endblock %}"),
+            ("site.toml", "base_url = \"https://example.com/blog\""),
+            ("posts/a.md", "+++\ntitle = \"A\"\ndate = 2023-05-01\ntags = [\"x\"]\n+++\nText"),
+            ("posts/b.md", "+++\ntitle = \"B\"\ndate = 2024-01-02\ntags = [\"x\"]\n+++\nText"),
+            ("posts/c.md", "+++\ntitle = \"C\"\ndate = 2024-03-02\n+++\nText"),
+            ("posts/unlisted.md", "+++\ntitle = \"U\"\ndate = 2022-01-01\ntags = [\"y\"]\nunlisted = true\n+++\nText"),
+            ("posts/draft.md", "+++\ntitle = \"D\"\ndate = 2021-01-01\ntags = [\"z\"]\ndraft = true\n+++\nText")
+        ]);
+        let outputs = build_test_site("nav-years", &files, &[]);
+        let nav = "<select><option value=\"/blog/archive/2024.html\">2024 (2)</option><option value=\"/blog/archive/2023.html\">2023 (1)</option></select> <a href=\"/blog/tags/x.html\">x</a>|";
+        assert_eq!(text(&outputs, "index.html"), format!("{}index", nav));
+        assert_eq!(text(&outputs, "posts/a.html"), format!("{}A", nav));
+        assert_eq!(text(&outputs, "posts/unlisted.html"), format!("{}U", nav));
+    }
+
+    #[test]
+    fn tag_and_category_pages_get_their_descriptions() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/tag.html", "{{ description_html }}|{{ description_plain }}"),
+            ("templates/category.html", "{{ description_html }}|{{ description_plain }}"),
+            ("site.toml", "[tag_descriptions]\nrust = \"Posts about [Rust](https://rust-lang.org) & *speed*\"\n[category_descriptions]\nNotes = \"Short `notes`\""),
+            ("posts/a.md", "+++\ntitle = \"A\"\ndate = 2024-01-01\ntags = [\"Rust\", \"go\"]\ncategory = \"Notes\"\n+++\nText")
+        ]);
+        let outputs = build_test_site("term-descriptions", &files, &[]);
+        assert_eq!(
+            text(&outputs, "tags/rust.html"),
+            "Posts about <a href=\"https://rust-lang.org\">Rust</a> &amp; <em>speed</em>|Posts about Rust & speed"
+        );
+        assert_eq!(text(&outputs, "tags/go.html"), "|");
+        assert_eq!(text(&outputs, "categories/notes.html"), "Short <code>notes</code>|Short notes");
+    }
+
+    #[test]
+    fn sitemap_leaves_out_unlisted_posts_and_drafts() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("site.toml", "base_url = \"https://example.com\""),
+            ("posts/listed.md", "+++\ntitle = \"Listed\"\ndate = 2024-01-01\n+++\nText"),
+            ("posts/unlisted.md", "+++\ntitle = \"Unlisted\"\ndate = 2024-01-02\nunlisted = true\n+++\nText"),
+            ("posts/draft.md", "+++\ntitle = \"Draft\"\ndate = 2024-01-03\ndraft = true\n+++\nText")
+        ]);
+        let outputs = build_test_site("sitemap-hidden", &files, &["--drafts"]);
+        assert!(outputs.contains_key("posts/unlisted.html"));
+        assert!(outputs.contains_key("posts/draft.html"));
+        let sitemap = text(&outputs, "sitemap.xml");
+        assert!(sitemap.contains("https://example.com/posts/listed.html"), "{}", sitemap);
+        assert!(!sitemap.contains("unlisted"), "{}", sitemap);
+        assert!(!sitemap.contains("draft"), "{}", sitemap);
+    }
+
+    #[test]
+    fn single_file_posts_resolve_covers_next_to_them() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "{{ post.cover_url }}"),
+            ("posts/covered.md", "+++\ntitle = \"Covered\"\ndate = 2024-01-01\ncover = \"../images/cover.svg\"\n+++\nText"),
+            // Other files next to the post would be taken for posts of an unknown type
+            ("images/cover.svg", "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"1\" height=\"1\"/>")
+        ]);
+        let outputs = build_test_site("file-post-cover", &files, &[]);
+        let cover = text(&outputs, "posts/covered.html");
+        assert!(cover.starts_with("/assets/") && cover.ends_with(".svg"), "{}", cover);
+        assert!(outputs.contains_key(&cover[1..]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_directories_that_overlap_are_refused() {
+        use std::os::unix::fs::symlink;
+        let dir = test_dir("symlinked-dirs");
+        let check = |in_dir: &Path, out_dir: &Path| {
+            let dirs = [in_dir.to_str().unwrap(), out_dir.to_str().unwrap()];
+            check_dirs(&Args::parse_from(["static-site-gen"].into_iter().chain(dirs)))
+        };
+        let (site, elsewhere) = (dir.join("site"), dir.join("elsewhere"));
+        std::fs::create_dir_all(site.join("static/public")).unwrap();
+        std::fs::create_dir_all(elsewhere.join("shared/public")).unwrap();
+        std::fs::create_dir_all(elsewhere.join("out")).unwrap();
+
+        // The output is the input
+        symlink(&site, dir.join("site-link")).unwrap();
+        assert!(check(&site, &dir.join("site-link")).is_err());
+        // The output is in the input's `static`
+        symlink(site.join("static/public"), dir.join("public-link")).unwrap();
+        assert!(check(&site, &dir.join("public-link")).is_err());
+        // The input's `templates` is a symlink to a directory the output is in
+        symlink(elsewhere.join("shared"), site.join("templates")).unwrap();
+        assert!(check(&site, &elsewhere.join("shared/public")).is_err());
+        // The output is a symlink to a directory the input is in
+        symlink(&dir, elsewhere.join("out/parent")).unwrap();
+        assert!(check(&site, &elsewhere.join("out/parent")).is_err());
+        // Symlinks that don't overlap are fine
+        symlink(elsewhere.join("out"), dir.join("out-link")).unwrap();
+        assert_eq!(check(&dir.join("site-link"), &dir.join("out-link")), Ok(()));
+    }
+
+    #[test]
+    fn consecutive_builds_are_byte_identical() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("site.toml", "base_url = \"https://example.com\""),
+            ("posts/2024-01-01-first.md", "+++\ntitle = \"First\"\ntags = [\"b\", \"a\"]\n+++\nText with a footnote[^1].\n\n[^1]: Note"),
+            ("posts/second/index.md", "+++\ntitle = \"Second\"\ndate = 2024-02-01\ntags = [\"a\", \"c\"]\n+++\n![A dot](dot.svg)\n\n```rust\nfn main() {}\n```"),
+            ("posts/second/dot.svg", "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"1\" height=\"1\"/>"),
+            ("static/style.css", "body { margin: 0 }")
+        ]);
+        let first = build_test_site("reproducible", &files, &[]);
+        // The second build reuses the post cache of the first, which must not change the output either
+        let second = rebuild_test_site("reproducible", &[]);
+        assert!(first.len() > 5, "{:?}", first.keys());
+        assert_eq!(first.keys().collect::<Vec<_>>(), second.keys().collect::<Vec<_>>());
+        for (path, content) in &first {
+            assert!(&second[path] == content, "`{}` differs between builds", path);
+        }
+    }
+
+    #[test]
+    fn reading_time_counts_code_by_the_line_and_leaves_out_footnotes() {
+        // With the alt text 460 words, two minutes at 230 a minute, and 40 lines of code two more
+        let prose = "word ".repeat(458);
+        let code = "let x = 1;\n".repeat(40);
+        let post = format!(
+            "+++\ntitle = \"Long\"\ndate = 2024-01-01\n+++\n{}[^1]\n\n![Alt text](dot.svg)\n\n```rust\n{}```\n\n[^1]: A footnote of seven words in it.",
+            prose, code
+        );
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "{{ post.words }}|{{ post.reading_minutes }}"),
+            ("templates/index.html", "{% for post in posts %}{{ post.reading_minutes }} {% endfor %}"),
+            ("posts/long/index.md", post.as_str()),
+            ("posts/long/dot.svg", "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"1\" height=\"1\"/>"),
+            ("posts/short.md", "+++\ntitle = \"Short\"\ndate = 2024-01-02\n+++\nA few words.\n\n```\ncode\n```")
+        ]);
+        let outputs = build_test_site("reading-time", &files, &[]);
+        assert_eq!(text(&outputs, "posts/long.html"), "460|4");
+        // Short posts still take a minute
+        assert_eq!(text(&outputs, "posts/short.html"), "3|1");
+        assert_eq!(text(&outputs, "index.html"), "1 4 ");
+    }
+
+    #[test]
+    fn posts_are_newest_first_whatever_their_file_names() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/tag.html", "{% for post in posts %}{{ post.url }} {% endfor %}| {{ all_posts | length }}"),
+            ("posts/b.md", "+++\ntitle = \"B\"\ndate = 2024-03-01\ntags = [\"t\"]\n+++\nText"),
+            ("posts/c.md", "+++\ntitle = \"C\"\ndate = 2024-01-01\ntags = [\"t\"]\n+++\nText"),
+            ("posts/a.md", "+++\ntitle = \"A\"\ndate = 2024-02-01\ntags = [\"u\"]\n+++\nText")
+        ]);
+        let outputs = build_test_site("post-order", &files, &[]);
+        assert_eq!(text(&outputs, "index.html"), "/posts/b.html\n/posts/a.html\n/posts/c.html\n");
+        assert_eq!(text(&outputs, "tags/t.html"), "/posts/b.html /posts/c.html | 3");
+        assert_eq!(text(&outputs, "tags/u.html"), "/posts/a.html | 3");
+    }
+
+    #[test]
+    fn date_prefixes_date_posts_unless_invalid_or_overridden() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "{{ post.published[:10] }}"),
+            ("posts/2024-03-01-named.md", "+++\ntitle = \"Named\"\n+++\nText"),
+            ("posts/2024-03-01_overridden.md", "+++\ntitle = \"Overridden\"\ndate = 2023-05-05\n+++\nText"),
+            ("posts/2024-02-30-invalid.md", "+++\ntitle = \"Invalid\"\n+++\nText"),
+            ("posts/2024-04-01_dir/index.md", "+++\ntitle = \"Directory\"\n+++\nText")
+        ]);
+        let outputs = build_test_site("date-prefixes", &files, &[]);
+        assert_eq!(text(&outputs, "posts/named.html"), "2024-03-01");
+        assert_eq!(text(&outputs, "posts/dir.html"), "2024-04-01");
+        // The front matter wins, but the prefix still comes off the URL
+        assert_eq!(text(&outputs, "posts/overridden.html"), "2023-05-05");
+        // An invalid date is part of the name, and the post is dated by its file as without a prefix
+        let invalid = text(&outputs, "posts/2024-02-30-invalid.html");
+        assert!(!invalid.starts_with("2024-02-30") && !invalid.starts_with("2024-03-01"), "{}", invalid);
+    }
+
+    /// The text of each element of the well-formed `xml` by its path, like `rss/channel/title`,
+    /// with references resolved and CDATA sections joined.
+    fn xml_texts(xml: &str) -> Vec<(String, String)> {
+        use xmlparser::{ElementEnd, FromSpan, TextUnescape, Token, Tokenizer, XmlSpace};
+        let (mut path, mut texts) = (Vec::<String>::new(), Vec::new());
+        let mut text = String::new();
+        for token in Tokenizer::from_str(xml) {
+            match token.unwrap_or_else(|e| panic!("malformed XML: {}\n{}", e, xml)) {
+                Token::ElementStart(name) => { path.push(name.to_str().to_string()); text.clear(); },
+                Token::ElementEnd(ElementEnd::Close(name)) => {
+                    assert_eq!(path.last().map(String::as_str), Some(name.to_str()), "mismatched tags in\n{}", xml);
+                    texts.push((path.join("/"), std::mem::take(&mut text)));
+                    path.pop();
+                },
+                Token::ElementEnd(ElementEnd::Empty) => { path.pop(); },
+                Token::Text(span) | Token::Whitespaces(span) => text.push_str(&TextUnescape::unescape(span.to_str(), XmlSpace::Preserve)),
+                Token::Cdata(span) => text.push_str(span.to_str()),
+                _ => ()
+            }
+        }
+        assert!(path.is_empty(), "unclosed elements in\n{}", xml);
+        texts
+    }
+
+    #[test]
+    fn feeds_are_well_formed_with_markup_in_titles_and_bodies() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("site.toml", "base_url = \"https://example.com\""),
+            ("posts/fish.md", "+++\ntitle = \"Fish & Chips <3\"\ndate = 2024-01-01\ntags = [\"a & b\"]\n+++\n<div>]]></div>\n\nSalt & vinegar"),
+            ("posts/older.md", "+++\ntitle = \"Older\"\ndate = 2023-01-01\n+++\nText"),
+            ("posts/unlisted.md", "+++\ntitle = \"Unlisted\"\ndate = 2024-02-01\nunlisted = true\n+++\nText")
+        ]);
+        let outputs = build_test_site("feeds", &files, &[]);
+        let find = |texts: &[(String, String)], path: &str| -> Vec<String> {
+            texts.iter().filter(|(p, _)| p == path).map(|(_, text)| text.clone()).collect()
+        };
+
+        let rss = xml_texts(&text(&outputs, "feed.xml"));
+        assert_eq!(find(&rss, "rss/channel/item/title"), ["Fish & Chips <3", "Older"]);
+        assert_eq!(find(&rss, "rss/channel/item/category"), ["a & b"]);
+        let description = &find(&rss, "rss/channel/item/description")[0];
+        assert!(description.contains("<div>]]></div>") && description.contains("Salt &amp; vinegar"), "{}", description);
+
+        let atom = xml_texts(&text(&outputs, "atom.xml"));
+        assert_eq!(find(&atom, "feed/entry/title"), ["Fish & Chips <3", "Older"]);
+        let content = &find(&atom, "feed/entry/content")[0];
+        assert!(content.contains("<div>]]></div>") && content.contains("Salt &amp; vinegar"), "{}", content);
+    }
+
+    #[test]
+    fn posts_override_the_line_break_policy_of_the_site() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "{{ post.source }}"),
+            ("site.toml", "hardbreaks = true"),
+            ("posts/poem.md", "+++\ntitle = \"Poem\"\ndate = 2024-01-01\n+++\nRoses are red\nviolets are blue"),
+            ("posts/prose.md", "+++\ntitle = \"Prose\"\ndate = 2024-01-01\nhardbreaks = false\ncollapse_hardbreaks = true\n+++\nOne\ntwo\\\nthree")
+        ]);
+        let outputs = build_test_site("break-policy", &files, &[]);
+        assert!(text(&outputs, "posts/poem.html").contains("Roses are red<br />\nviolets are blue"));
+        assert!(text(&outputs, "posts/prose.html").contains("One\ntwo three"));
+    }
+
+    #[cfg(feature = "dev")]
+    #[test]
+    fn tags_merge_by_case_and_alias_with_counts() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "{{ post.meta.tags | join(\",\") }}"),
+            ("templates/tags.html", "{% for tag in tags %}{{ tag.name }}={{ tag.count }} {% endfor %}"),
+            ("templates/tag.html", "{% for post in posts %}{{ post.id }} {% endfor %}"),
+            ("templates/dashboard.html", concat!(
+                "{% for lint in tag_lints %}{% if lint.kind == \"spellings\" %}{{ lint.tag }}:",
+                "{% for spelling, ids in lint.spellings | items %} {{ spelling }}={{ ids | join(\"+\") }}{% endfor %}",
+                "{% else %}{{ lint.a }}~{{ lint.b }}: {{ lint.a_posts | join(\"+\") }} {{ lint.b_posts | join(\"+\") }}{% endif %}\n{% endfor %}"
+            )),
+            ("site.toml", "[tag_aliases]\nrustlang = \"rust\""),
+            ("posts/a.md", "+++\ntitle = \"A\"\ndate = 2024-01-01\ntags = [\"Rust\"]\n+++\nText"),
+            ("posts/b.md", "+++\ntitle = \"B\"\ndate = 2024-01-02\ntags = [\"rust\"]\n+++\nText"),
+            ("posts/c.md", "+++\ntitle = \"C\"\ndate = 2024-01-03\ntags = [\"rustlang\"]\n+++\nText"),
+            ("posts/d.md", "+++\ntitle = \"D\"\ndate = 2024-01-04\ntags = [\"Rust\", \"golang\"]\n+++\nText"),
+            ("posts/e.md", "+++\ntitle = \"E\"\ndate = 2024-01-05\ntags = [\"gollang\"]\n+++\nText")
+        ]);
+        let outputs = build_test_site("tag-merging", &files, &["--dev"]);
+        assert_eq!(text(&outputs, "tags/index.html"), "rust=4 golang=1 gollang=1 ");
+        assert_eq!(text(&outputs, "tags/rust.html"), "d c b a ");
+        assert!(!outputs.contains_key("tags/rustlang.html"));
+        assert_eq!(
+            text(&outputs, "__dashboard.html"),
+            "rust: Rust=d+a rust=b rustlang=c\ngolang~gollang: d e\n"
+        );
+        // Posts keep their own spelling unless told otherwise
+        assert_eq!(text(&outputs, "posts/c.html"), "rustlang");
+
+        files.push(("site.toml", "canonicalize_display = true\n[tag_aliases]\nrustlang = \"rust\""));
+        let outputs = build_test_site("tag-merging-display", &files, &["--dev"]);
+        assert_eq!(text(&outputs, "posts/c.html"), "rust");
+        assert_eq!(text(&outputs, "posts/d.html"), "rust,golang");
+    }
+
+    #[test]
+    fn stats_leave_out_drafts_and_unlisted_posts() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/stats.html", "{{ stats.posts }} {{ stats.words }} {{ stats.longest.id }}"),
+            ("templates/index.html", "{{ stats.posts }}"),
+            ("posts/listed.md", "+++\ntitle = \"Listed\"\ndate = 2024-01-01\n+++\nTwo words"),
+            ("posts/unlisted.md", "+++\ntitle = \"Unlisted\"\ndate = 2024-01-02\nunlisted = true\n+++\nA few more words"),
+            ("posts/draft.md", "+++\ntitle = \"Draft\"\ndate = 2024-01-03\ndraft = true\n+++\nMany more words than the others")
+        ]);
+        let outputs = build_test_site("stats-listed", &files, &["--drafts"]);
+        assert_eq!(text(&outputs, "stats/index.html"), "1 2 listed");
+        assert_eq!(text(&outputs, "index.html"), "1");
+    }
+
+    #[test]
+    fn one_slug_config_names_posts_tags_and_anchors() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "{{ post.source }}"),
+            ("templates/tag.html", "{{ tag }}"),
+            ("site.toml", "[slugs]\ntransliterate = \"expand-umlauts\""),
+            ("posts/Grüße aus Köln.md", "+++\ntitle = \"Grüße\"\ndate = 2024-01-01\ntags = [\"Übung\"]\n+++\n## Über uns\nText"),
+            ("posts/日本語.md", "+++\ntitle = \"日本語\"\ndate = 2024-01-02\ntags = [\"東京\"]\n+++\nText")
+        ]);
+        let outputs = build_test_site("slug-config", &files, &[]);
+        assert!(text(&outputs, "posts/gruesse-aus-koeln.html").contains("id=\"ueber-uns\""));
+        assert_eq!(text(&outputs, "tags/uebung.html"), "Übung");
+        // Unicode slugs stay as they are in file names, and are percent-encoded in URLs
+        assert_eq!(text(&outputs, "tags/東京.html"), "東京");
+        assert_eq!(text(&outputs, "index.html"), "/posts/%E6%97%A5%E6%9C%AC%E8%AA%9E.html\n/posts/gruesse-aus-koeln.html\n");
+    }
+
+    #[test]
+    fn format_datetime_takes_every_shape_of_date() {
+        let mut env = minijinja::Environment::new();
+        env.add_filter("format_datetime", |s: &minijinja::State<'_, '_>, value: minijinja::Value, format: Option<String>| {
+            format_datetime_function(s, value, format, "%Y-%m-%d")
+        });
+        let date: toml_datetime::Datetime = "2024-03-01T12:30:00+02:00".parse().unwrap();
+        let local_date: toml_datetime::Datetime = "2024-03-01".parse().unwrap();
+        let ctx = context! { date => date, local_date => local_date, none => None::<String>, rfc => "2024-03-01T12:30:00Z", bad => "yesterday" };
+        let render = |template: &str| env.render_str(template, &ctx).unwrap();
+        let time = "<time datetime=\"2024-03-01T12:30:00+02:00\">";
+        assert_eq!(render("{{ date | format_datetime }}"), format!("{}2024-03-01</time>", time));
+        assert_eq!(render("{{ date | format_datetime(\"%H:%M\") }}"), format!("{}12:30</time>", time));
+        assert_eq!(render("{{ rfc | format_datetime(\"%H:%M\") }}"), "<time datetime=\"2024-03-01T12:30:00+00:00\">12:30</time>");
+        assert_eq!(render("{{ \"2024-03-01\" | format_datetime }}"), render("{{ local_date | format_datetime }}"));
+        assert!(render("{{ local_date | format_datetime }}").ends_with(">2024-03-01</time>"));
+        // The template's own default, and the format given in the call over it
+        assert_eq!(render("{% set FORMAT_DATETIME = \"%d.%m.\" %}{{ date | format_datetime }}"), format!("{}01.03.</time>", time));
+        assert_eq!(render("{% set FORMAT_DATETIME = \"%d.%m.\" %}{{ date | format_datetime(\"%Y\") }}"), format!("{}2024</time>", time));
+        // What can't be formatted renders as the placeholder, empty unless set
+        for value in ["none", "missing", "bad"] {
+            assert_eq!(render(&format!("{{{{ {} | format_datetime }}}}", value)), "", "{}", value);
+            assert_eq!(render(&format!("{{% set DATETIME_PLACEHOLDER = \"-\" %}}{{{{ {} | format_datetime }}}}", value)), "-", "{}", value);
+        }
+    }
+
+    #[test]
+    fn section_reading_times_add_up_in_built_posts() {
+        let body = format!(
+            "{}\n\n## Prose\n\n{}\n\n## Code\n\n```rust\n{}```\n\n### Both\n\n{}\n\n```\nx\n```",
+            "intro ".repeat(30), "word ".repeat(400), "let x = 1;\n".repeat(25), "more ".repeat(50)
+        );
+        let post = format!("+++\ntitle = \"Long\"\ndate = 2024-01-01\n+++\n{}", body);
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "{% for e in post.toc %}{{ e.anchor }} {{ e.words }} {{ e.code_lines }} {{ e.reading_time }} {{ e.start_percent }}\n{% endfor %}{{ post.reading_time }}"),
+            ("posts/long.md", post.as_str())
+        ]);
+        let outputs = build_test_site("section-times", &files, &[]);
+        let page = text(&outputs, "posts/long.html");
+        let lines: Vec<Vec<&str>> = page.lines().map(|l| l.split(' ').collect()).collect();
+        let anchors: Vec<&str> = lines[..3].iter().map(|l| l[0]).collect();
+        assert_eq!(anchors, ["prose", "code", "both"]);
+        assert_eq!(lines[1][1..3], ["0", "25"]);
+        let total: f64 = lines[3][0].parse().unwrap();
+        let intro = 30.0 / 230.0;
+        let sections: f64 = lines[..3].iter().map(|l| l[3].parse::<f64>().unwrap()).sum();
+        assert!((intro + sections - total).abs() < 1e-9, "{}", page);
+        let first_start: f64 = lines[0][4].parse().unwrap();
+        assert!((first_start - intro / total * 100.0).abs() < 1e-9, "{}", page);
+    }
+
+    #[test]
+    fn posts_inherit_directory_defaults_under_their_own_front_matter() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "post {{ post.meta.tags | join(\",\") }} {{ post.meta.extra.camera }}"),
+            ("templates/photo.html", "photo {{ post.meta.tags | join(\",\") }} {{ post.meta.extra.camera }}"),
+            ("posts/photography/_defaults.toml", "template = \"photo\"\ntags = [\"photography\"]\n[extra]\ncamera = \"X100\""),
+            ("posts/photography/kyoto.md", "+++\ntitle = \"Kyoto\"\ndate = 2024-01-01\ntags = [\"japan\", \"photography\"]\n+++\nText"),
+            ("posts/photography/essay.md", "+++\ntitle = \"Essay\"\ndate = 2024-01-02\ntemplate = \"post\"\n[extra]\ncamera = \"Film\"\n+++\nText"),
+            ("posts/other.md", "+++\ntitle = \"Other\"\ndate = 2024-01-03\n+++\nText")
+        ]);
+        let outputs = build_test_site("post-defaults", &files, &[]);
+        assert_eq!(text(&outputs, "posts/kyoto.html"), "photo photography,japan X100");
+        assert_eq!(text(&outputs, "posts/essay.html"), "post photography Film");
+        assert_eq!(text(&outputs, "posts/other.html"), "post  ");
+    }
+
+    #[test]
+    fn archive_index_snapshot() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("site.toml", "base_url = \"https://example.com/blog\"\narchive_index = true\n[tag_aliases]\nrustlang = \"Rust\""),
+            ("posts/older.md", "+++\ntitle = \"Older *post*\"\ndate = 2024-01-01T10:00:00+02:00\ntags = [\"Rust\", \"rustlang\"]\n+++\nFour words of text."),
+            ("posts/newer.md", concat!(
+                "+++\ntitle = \"Newer\"\ndate = 2024-02-01T08:00:00Z\nupdated = 2024-03-01T08:00:00Z\n",
+                "summary = \"A summary & more\"\n+++\nText"
+            )),
+            ("posts/unlisted.md", "+++\ntitle = \"Unlisted\"\ndate = 2024-03-01T00:00:00Z\nunlisted = true\n+++\nText"),
+            ("posts/draft.md", "+++\ntitle = \"Draft\"\ndate = 2024-04-01T00:00:00Z\ndraft = true\n+++\nText")
+        ]);
+        let outputs = build_test_site("archive-index", &files, &["--drafts"]);
+        let json: serde_json::Value = serde_json::from_slice(&outputs["posts.json"]).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "version": 1,
+            "posts": [
+                {
+                    "id": "newer", "url": "/blog/posts/newer.html", "absolute_url": "https://example.com/blog/posts/newer.html",
+                    "title": "Newer", "date": "2024-02-01T08:00:00+00:00", "updated": "2024-03-01T08:00:00+00:00",
+                    "tags": [], "summary": "A summary & more", "word_count": 1, "reading_time": 1.0 / 230.0
+                },
+                {
+                    "id": "older", "url": "/blog/posts/older.html", "absolute_url": "https://example.com/blog/posts/older.html",
+                    "title": "Older post", "date": "2024-01-01T10:00:00+02:00", "updated": null,
+                    "tags": ["Rust"], "summary": "Four words of text.", "word_count": 4, "reading_time": 4.0 / 230.0
+                }
+            ]
+        }));
+        // The same input gives the same bytes
+        assert_eq!(rebuild_test_site("archive-index", &["--drafts"])["posts.json"], outputs["posts.json"]);
+    }
+
+    #[test]
+    fn datetimes_are_written_the_same_everywhere() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "{{ post.meta.date | format_datetime }}\n{{ post.jsonld }}"),
+            ("site.toml", "base_url = \"https://example.com\"\n[author]\nname = \"Ada\""),
+            ("posts/dated.md", "+++\ntitle = \"Dated\"\ndate = 2024-03-05T07:08:09+02:00\n+++\nText")
+        ]);
+        let outputs = build_test_site("datetime-formats", &files, &[]);
+        let post = text(&outputs, "posts/dated.html");
+        // The default human-readable format doesn't pad the day
+        assert!(post.starts_with("<time datetime=\"2024-03-05T07:08:09+02:00\">March 5 2024 at 07:08</time>\n"), "{}", post);
+        assert!(post.contains("\"datePublished\":\"2024-03-05T07:08:09+02:00\""), "{}", post);
+        assert!(post.contains("\"dateModified\":\"2024-03-05T07:08:09+02:00\""), "{}", post);
+        let rss = text(&outputs, "feed.xml");
+        assert!(rss.contains("<pubDate>Tue, 5 Mar 2024 07:08:09 +0200</pubDate>"), "{}", rss);
+        assert!(rss.contains("<lastBuildDate>Tue, 5 Mar 2024 07:08:09 +0200</lastBuildDate>"), "{}", rss);
+        let atom = text(&outputs, "atom.xml");
+        assert!(atom.contains("<published>2024-03-05T07:08:09+02:00</published>"), "{}", atom);
+        assert!(atom.contains("<updated>2024-03-05T07:08:09+02:00</updated>"), "{}", atom);
+        let sitemap = text(&outputs, "sitemap.xml");
+        assert!(sitemap.contains("<lastmod>2024-03-05T07:08:09+02:00</lastmod>"), "{}", sitemap);
+    }
+
+    #[test]
+    fn rfc3339_keeps_the_offset_and_drops_zero_fractions() {
+        let dt = chrono::DateTime::parse_from_rfc3339("2024-03-05T07:08:09-05:30").unwrap();
+        assert_eq!(rfc3339(&dt), "2024-03-05T07:08:09-05:30");
+        let dt = chrono::DateTime::parse_from_rfc3339("2024-03-05T07:08:09.250Z").unwrap();
+        assert_eq!(rfc3339(&dt), "2024-03-05T07:08:09.250+00:00");
+    }
+
+    #[test]
+    fn ghcomment_keys_become_one_normalized_structure() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "{% set c = post.meta.ghcomment %}{% if c %}{{ c.issue }} {{ c.authors | join(\",\") }} {{ c.url }}{% else %}none{% endif %}"),
+            ("posts/full.md", concat!(
+                "+++\ntitle = \"Full\"\ndate = 2024-01-01\nghcommentid = 12\n",
+                "ghcommentauthors = [\"Alice\", \"bad--name\", \"BOB\"]\nghcommenturl = \"https://github.com/a/b/issues/12\"\n+++\nText"
+            )),
+            ("posts/bare.md", "+++\ntitle = \"Bare\"\ndate = 2024-01-01\nghcommentid = 3\n+++\nText"),
+            ("posts/orphan.md", "+++\ntitle = \"Orphan\"\ndate = 2024-01-01\nghcommentauthors = [\"alice\"]\n+++\nText")
+        ]);
+        let outputs = build_test_site("ghcomment", &files, &[]);
+        assert_eq!(text(&outputs, "posts/full.html"), "12 alice,bob https://github.com/a/b/issues/12");
+        assert_eq!(text(&outputs, "posts/bare.html"), "3  none");
+        assert_eq!(text(&outputs, "posts/orphan.html"), "none");
+        // Cached posts come back with the same structure
+        let rebuilt = rebuild_test_site("ghcomment", &[]);
+        for post in ["posts/full.html", "posts/bare.html", "posts/orphan.html"] {
+            assert_eq!(rebuilt[post], outputs[post], "{}", post);
+        }
+    }
+
+    #[test]
+    fn diff_blocks_mark_lines_and_pair_before_after() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "{{ post.source }}"),
+            ("posts/rust.md", "+++\ntitle = \"Rust\"\ndate = 2024-01-01\n+++\n```rust diff\n fn main() {\n-    let x = 1;\n+    let x = 2;\n }\n```\n"),
+            ("posts/unknown.md", "+++\ntitle = \"Unknown\"\ndate = 2024-01-01\n+++\n```nosuchlang diff\n keep <this>\n-old & gone\n+new\n```\n"),
+            ("posts/paired.md", "+++\ntitle = \"Paired\"\ndate = 2024-01-01\n+++\n```text before\nold\n```\n```text after\nnew\n```\n\n```text before\nalone\n```\n")
+        ]);
+        let outputs = build_test_site("diff-blocks", &files, &[]);
+        let marker = |c: char| format!("<span class=\"diff-marker\" aria-hidden=\"true\">{}</span>", c);
+
+        let rust = text(&outputs, "posts/rust.html");
+        // The markers are taken off before highlighting, so the code is coloured as Rust
+        assert_eq!(rust.lines().collect::<Vec<_>>(), [
+            format!("<pre><code class=\"language-rust\"><a-lf></a-lf>{}<a-k>fn</a-k> <a-f>main</a-f><a-p>()</a-p> <a-p>{{</a-p>", marker(' ')),
+            format!("<a-lf></a-lf><span class=\"removed\">{}    <a-k>let</a-k> x = <a-co>1</a-co><a-p>;</a-p></span>", marker('-')),
+            format!("<a-lf></a-lf><span class=\"added\">{}    <a-k>let</a-k> x = <a-co>2</a-co><a-p>;</a-p></span>", marker('+')),
+            format!("<a-lf></a-lf>{}<a-p>}}</a-p></code></pre>", marker(' '))
+        ]);
+
+        let unknown = text(&outputs, "posts/unknown.html");
+        assert!(unknown.contains(&format!("<a-lf></a-lf>{}keep &lt;this&gt;\n", marker(' '))), "{}", unknown);
+        assert!(unknown.contains(&format!("<a-lf></a-lf><span class=\"removed\">{}old &amp; gone</span>\n", marker('-'))), "{}", unknown);
+        assert!(unknown.contains(&format!("<a-lf></a-lf><span class=\"added\">{}new</span>", marker('+'))), "{}", unknown);
+
+        let paired = text(&outputs, "posts/paired.html");
+        assert_eq!(paired.matches("<figure class=\"code-comparison\">").count(), 1, "{}", paired);
+        let figure = &paired[paired.find("<figure").unwrap()..paired.find("</figure>").unwrap()];
+        assert!(figure.find("<div class=\"code-before\">").unwrap() < figure.find("old").unwrap(), "{}", paired);
+        assert!(figure.find("<div class=\"code-after\">").unwrap() < figure.find("new").unwrap(), "{}", paired);
+        assert!(paired.contains("alone") && !figure.contains("alone"), "{}", paired);
+    }
+
+    #[cfg(feature = "dev")]
+    #[test]
+    fn serve_defaults_to_port_8080() {
+        let dir = test_dir("serve-args");
+        let dir = dir.to_str().unwrap();
+        let args = Args::parse_from(["static-site-gen", dir, dir, "--serve"]);
+        assert!(args.serve && !args.dev && !args.watch);
+        assert_eq!(args.port, 8080);
+        let args = Args::parse_from(["static-site-gen", dir, dir, "--serve", "--port", "9000"]);
+        assert_eq!(args.port, 9000);
+        assert!(!Args::parse_from(["static-site-gen", dir, dir]).serve);
+    }
+
+    #[test]
+    fn moved_posts_redirect_once_aliases_are_written() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("posts/hello.md", "+++\ntitle = \"Hello\"\ndate = 2024-01-01\n+++\nText"),
+            ("posts/café.md", "+++\ntitle = \"Café\"\ndate = 2024-01-02\n+++\nText")
+        ]);
+        let first = build_test_site("url-migration", &files, &[]);
+        assert!(first.contains_key("posts/hello.html") && first.contains_key("posts/café.html"));
+
+        // A rename and a change of slug format
+        let in_dir = test_path("url-migration").join("in");
+        std::fs::write(in_dir.join("posts/hello.md"), "+++\ntitle = \"Hello\"\ndate = 2024-01-01\nslug = \"greetings\"\n+++\nText").unwrap();
+        std::fs::write(in_dir.join("site.toml"), "[slugs]\ntransliterate = \"strip-accents\"").unwrap();
+        let reported = rebuild_test_site("url-migration", &[]);
+        assert!(reported.contains_key("posts/greetings.html") && reported.contains_key("posts/cafe.html"));
+        assert!(!reported.contains_key("posts/hello.html") && !reported.contains_key("posts/café.html"));
+
+        let aliased = rebuild_test_site("url-migration", &["--write-aliases"]);
+        assert!(text(&aliased, "posts/hello.html").contains("<meta http-equiv=\"refresh\" content=\"0; url=/posts/greetings.html\">"));
+        assert!(text(&aliased, "posts/café.html").contains("<meta http-equiv=\"refresh\" content=\"0; url=/posts/cafe.html\">"));
+
+        // Later builds keep the redirects without being asked again
+        assert_eq!(rebuild_test_site("url-migration", &[]), aliased);
+    }
+
+    #[test]
+    fn slugs_name_posts_and_clashing_ones_are_errors() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/export.html", "{{ post.title_plain }}"),
+            ("posts/Why I Don't Like YAML.md", "+++\ntitle = \"YAML\"\ndate = 2024-01-01\n+++\nText"),
+            ("posts/b.md", "+++\ntitle = \"Custom\"\ndate = 2024-01-02\nslug = \"Custom Name\"\n+++\nText"),
+            ("posts/c.md", "+++\ntitle = \"Clash\"\ndate = 2024-01-03\nslug = \"why-i-don-t-like-yaml\"\n+++\nText")
+        ]);
+        let outputs = build_test_site("slugs", &files, &[]);
+        assert_eq!(text(&outputs, "posts/custom-name.html"), "Custom");
+        assert!(!outputs.contains_key("posts/b.html"));
+        // One post of the clashing slug is skipped rather than overwriting the other
+        assert!(["Clash", "YAML"].contains(&text(&outputs, "posts/why-i-don-t-like-yaml.html").as_str()));
+        assert_eq!(outputs.keys().filter(|path| path.starts_with("posts/")).count(), 2, "{:?}", outputs.keys());
+
+        let dir = test_path("slugs");
+        let (in_dir, out_dir) = (dir.join("in"), dir.join("out"));
+        let args = Args::parse_from(["static-site-gen", in_dir.to_str().unwrap(), out_dir.to_str().unwrap(), "--strict"]);
+        let result = build_to_sink(&args, &output::MemorySink::default());
+        assert!(result.failed(true), "{:?}", result.errors);
+        assert!(result.errors.iter().any(|e| e.contains("has the same id `why-i-don-t-like-yaml`")), "{:?}", result.errors);
+
+        // Cached rebuilds and exports find posts by their slug too
+        assert_eq!(rebuild_test_site("slugs", &[]), outputs);
+        let exported = dir.join("custom.html");
+        export_post(&args, "custom-name", &exported).unwrap();
+        assert_eq!(std::fs::read_to_string(exported).unwrap(), "Custom");
+        assert!(export_post(&args, "b", &dir.join("b.html")).is_err());
+    }
+
+    #[test]
+    fn exports_inline_images_and_leave_the_asset_manifest_alone() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("posts/post.md", "+++\ntitle = \"Exported\"\ndate = 2024-01-01\n+++\nText\n\n![Pixel](../images/pixel.png)"),
+            ("posts/other.md", "+++\ntitle = \"Other\"\ndate = 2024-01-02\n+++\n![Other](../images/other.png)")
+        ]);
+        build_test_site("export", &files, &[]);
+        let dir = test_path("export");
+        let in_dir = dir.join("in");
+        std::fs::create_dir_all(in_dir.join("images")).unwrap();
+        image::RgbImage::from_pixel(4, 4, image::Rgb([200, 100, 50])).save(in_dir.join("images/pixel.png")).unwrap();
+        image::RgbImage::from_pixel(4, 4, image::Rgb([50, 100, 200])).save(in_dir.join("images/other.png")).unwrap();
+        let outputs = rebuild_test_site("export", &[]);
+        assert!(outputs.keys().any(|path| path.starts_with("assets/")), "{:?}", outputs.keys());
+        let manifest = std::fs::read_to_string(in_dir.join(".ssg-cache/assets.json")).unwrap();
+
+        let args = Args::parse_from(["static-site-gen", in_dir.to_str().unwrap(), dir.join("out").to_str().unwrap()]);
+        let exported = dir.join("exported.html");
+        export_post(&args, "post", &exported).unwrap();
+        let document = std::fs::read_to_string(&exported).unwrap();
+        // Without an `export` template the built-in one is used
+        assert!(document.starts_with("<!DOCTYPE html>") && document.contains("<title>Exported</title>"), "{}", document);
+        assert!(document.contains("src=\"data:image/"), "{}", document);
+        assert!(!document.contains("src=\"/assets/"), "{}", document);
+        assert_eq!(std::fs::read_to_string(in_dir.join(".ssg-cache/assets.json")).unwrap(), manifest);
+    }
+
+    #[test]
+    fn reports_list_scheduled_posts_and_the_next_publish_time() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("site.toml", "timezone = \"+02:00\""),
+            ("posts/past.md", "+++\ntitle = \"Past\"\ndate = 2024-05-01\n+++\nText"),
+            ("posts/later.md", "+++\ntitle = \"Later\"\ndate = 2024-07-01\n+++\nText"),
+            ("posts/soon.md", "+++\ntitle = \"Soon\"\ndate = 2024-06-15T12:00:00Z\n+++\nText")
+        ]);
+        let report = test_path("report.json");
+        build_test_site("scheduled-report", &files, &["--now", "2024-06-01T00:00:00Z", "--report", report.to_str().unwrap()]);
+        let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+        assert_eq!(json["scheduled"], serde_json::json!([
+            { "id": "soon", "title": "Soon", "publish": "2024-06-15T12:00:00+00:00" },
+            { "id": "later", "title": "Later", "publish": "2024-07-01T00:00:00+02:00" }
+        ]));
+        assert_eq!(json["next_publish"], "2024-06-15T12:00:00+00:00");
+        assert_eq!(json["posts"].as_array().unwrap().len(), 1);
+
+        build_test_site("scheduled-report", &files[..files.len() - 2], &["--now", "2024-06-01T00:00:00Z", "--report", report.to_str().unwrap()]);
+        let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+        assert_eq!(json["scheduled"], serde_json::json!([]));
+        assert_eq!(json["next_publish"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn footnote_ids_survive_an_earlier_footnote() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("templates/post.html", "{{ post.source }}"),
+            ("posts/notes.md", "+++\ntitle = \"Notes\"\ndate = 2024-01-01\n+++\nAlpha[^first] beta[^Second note].\n\n[^first]: One\n\n[^Second note]: Two\n")
+        ]);
+        // The ids and link targets of the footnotes, in order
+        let anchors = |html: &str| -> Vec<String> {
+            html.split(" id=\"").chain(html.split(" href=\"")).skip(1)
+                .filter_map(|rest| rest.split('"').next().filter(|a| a.contains("fn")).map(str::to_string))
+                .collect()
+        };
+        let before = text(&build_test_site("footnote-ids", &files, &[]), "posts/notes.html");
+        std::fs::write(test_path("footnote-ids").join("in/posts/notes.md"),
+            "+++\ntitle = \"Notes\"\ndate = 2024-01-01\n+++\nZero[^new] alpha[^first] beta[^Second note].\n\n[^new]: Zero\n\n[^first]: One\n\n[^Second note]: Two\n").unwrap();
+        let after = text(&rebuild_test_site("footnote-ids", &[]), "posts/notes.html");
+
+        let mut before_anchors = anchors(&before);
+        before_anchors.sort();
+        assert_eq!(before_anchors, ["#fn-first", "#fn-second-note", "#fnref-first", "#fnref-second-note", "fn-first", "fn-second-note", "fnref-first", "fnref-second-note"]);
+        let mut kept: Vec<String> = anchors(&after).into_iter().filter(|a| !a.contains("new")).collect();
+        kept.sort();
+        assert_eq!(kept, before_anchors, "{}", after);
+        // Only the visible numbers move along
+        assert!(before.contains("<a href=\"#fn-first\">1</a>") && after.contains("<a href=\"#fn-first\">2</a>"), "{}", after);
+        assert!(before.contains("<li id=\"fn-second-note\" value=\"2\">") && after.contains("<li id=\"fn-second-note\" value=\"3\">"), "{}", after);
+    }
+
+    #[test]
+    fn debug_metadata_leaves_out_the_password() {
+        let metadata: toml::Table = toml::from_str("title = \"Closed\"\nencrypt = \"hunter2\"").unwrap();
+        let printed = post::printable_metadata(&metadata);
+        assert!(!printed.contains("hunter2"), "{}", printed);
+        assert!(printed.contains("encrypt = \"<redacted>\"") && printed.contains("title = \"Closed\""), "{}", printed);
+    }
+
+    #[test]
+    fn encrypted_plaintext_is_written_nowhere() {
+        let mut files = TEMPLATES.to_vec();
+        files.extend([
+            ("site.toml", concat!(
+                "base_url = \"https://example.com/\"\nsearch_granularity = \"section\"\nlisting_content = true\narchive_index = true\n",
+                "[author]\nname = \"Ann\"\n"
+            )),
+            ("templates/index.html", "{{ posts }}"),
+            ("templates/post.html", "{{ post }}"),
+            ("templates/tag.html", "{{ posts }}"),
+            ("templates/archive.html", "{{ years }}"),
+            ("templates/search.html", "{{ documents }}"),
+            ("posts/open.md", "+++\ntitle = \"Open\"\ndate = 2024-01-01\ntags = [\"friends\"]\n+++\nNothing to hide."),
+            ("posts/closed.md", concat!(
+                "+++\ntitle = \"Closed\"\ndate = 2024-01-02\ntags = [\"friends\"]\nencrypt = \"hunter2\"\n+++\n",
+                "Sesame plaintext here.\n\n<!-- more -->\n\n## Sesame heading\n\nMore sesame[^note] and `sesame code`.\n\n[^note]: Sesame footnote\n"
+            ))
+        ]);
+        let outputs = build_test_site("encrypted-outputs", &files, &[]);
+        for path in ["index.html", "posts/closed.html", "tags/friends.html", "archive/index.html", "posts.json", "search-index.json", "search.html", "feed.xml", "atom.xml", "sitemap.xml"] {
+            assert!(outputs.contains_key(path), "`{}` was not written", path);
+        }
+        // The same surfaces do show the content of other posts, and the title of this one
+        for path in ["index.html", "search-index.json", "feed.xml", "atom.xml"] {
+            assert!(text(&outputs, path).contains("Nothing to hide"), "{}", path);
+        }
+        assert!(text(&outputs, "index.html").contains("Closed"));
+        for (path, data) in &outputs {
+            let data = String::from_utf8_lossy(data).to_lowercase();
+            assert!(!data.contains("sesame") && !data.contains("hunter2"), "`{}` gives the plaintext away: {}", path, data);
+        }
+    }
+
+    #[test]
+    fn local_midnight_publishes_at_midnight_in_the_site_timezone() {
+        for (timezone, midnight, second_before) in [
+            ("+02:00", "2024-02-29T22:00:00Z", "2024-02-29T21:59:59Z"),
+            ("-05:00", "2024-03-01T05:00:00Z", "2024-03-01T04:59:59Z")
+        ] {
+            let mut files = TEMPLATES.to_vec();
+            let site = format!("timezone = \"{}\"", timezone);
+            files.extend([
+                ("site.toml", site.as_str()),
+                ("posts/midnight.md", "+++\ntitle = \"Midnight\"\ndate = 2024-03-01\n+++\nText")
+            ]);
+            let report = test_path("midnight-report.json");
+            let flags = |now| ["--now", now, "--report", report.to_str().unwrap()];
+
+            let outputs = build_test_site("midnight", &files, &flags(second_before));
+            assert!(!outputs.contains_key("posts/midnight.html"), "{}", timezone);
+            let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+            assert_eq!(json["next_publish"], format!("2024-03-01T00:00:00{}", timezone));
+
+            let outputs = rebuild_test_site("midnight", &flags(midnight));
+            assert_eq!(text(&outputs, "posts/midnight.html"), "Midnight", "{}", timezone);
+            let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+            assert_eq!(json["next_publish"], serde_json::Value::Null);
+            assert_eq!(json["posts"][0]["date"], format!("2024-03-01T00:00:00{}", timezone));
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use serde::Serialize;
+use crate::{html, output::OutputSink, post::Post};
+
+/// A problem with the tags used across posts
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TagLint {
+    /// One tag written in different ways, with the ids of the posts using each spelling
+    Spellings { tag: String, spellings: BTreeMap<String, Vec<String>> },
+    /// Two tags that are probably meant to be the same
+    Similar { a: String, b: String, a_posts: Vec<String>, b_posts: Vec<String> }
+}
+
+/// The tag `tag` is an alias of, ignoring case. Of aliases that differ only in case, the first in
+/// sorted order applies.
+pub fn resolve_alias<'a>(tag: &'a str, aliases: &'a BTreeMap<String, String>) -> Option<&'a str> {
+    let lower = tag.to_lowercase();
+    aliases.iter()
+        .find(|(alias, _)| alias.to_lowercase() == lower)
+        .map(|(_, target)| target.as_str())
+}
+
+/// Groups tag spellings by the (lowercase, alias-resolved) tag they refer to,
+/// recording the ids of the posts that use each spelling.
+pub fn tag_variants<'a>(posts: &'a [Post], aliases: &'a BTreeMap<String, String>) -> BTreeMap<String, BTreeMap<&'a str, Vec<&'a str>>> {
+    let mut groups: BTreeMap<String, BTreeMap<&str, Vec<&str>>> = BTreeMap::new();
+    for post in posts {
+        for tag in &post.meta.tags {
+            let key = resolve_alias(tag, aliases).unwrap_or(tag).to_lowercase();
+            groups.entry(key).or_default()
+                .entry(tag.as_str()).or_default()
+                .push(post.id.as_str());
+        }
+    }
+    groups
+}
+
+/// Picks the name of the tag page for a group of spellings: the alias target if
+/// one applies, otherwise the most used spelling (ties broken alphabetically).
+pub fn canonical_tag<'a>(variants: &BTreeMap<&'a str, Vec<&str>>, aliases: &'a BTreeMap<String, String>) -> &'a str {
+    if let Some(target) = variants.keys().find_map(|v| resolve_alias(v, aliases)) {
+        return target
+    }
+    variants.iter()
+        .max_by(|(a, ap), (b, bp)| ap.len().cmp(&bp.len()).then(b.cmp(a)))
+        .map(|(v, _)| *v)
+        .unwrap_or_default()
+}
+
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == cb { prev } else { 1 + prev.min(row[j]).min(cur) };
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+/// Prints and returns the problems with the tags used by `posts`.
+pub fn lint_tags(posts: &[Post], aliases: &BTreeMap<String, String>) -> Vec<TagLint> {
+    let groups = tag_variants(posts, aliases);
+    let mut lints = Vec::new();
+
+    for (key, variants) in &groups {
+        if variants.len() < 2 { continue }
+        let mut message = format!("warning: tag `{}` is written in {} different ways:", key, variants.len());
+        for (variant, ids) in variants {
+            message.push_str(&format!("\n    `{}` used by {} post(s): {}", variant, ids.len(), ids.join(", ")));
+        }
+        log!("{message}");
+        lints.push(TagLint::Spellings {
+            tag: key.clone(),
+            spellings: variants.iter()
+                .map(|(variant, ids)| (variant.to_string(), ids.iter().map(|id| id.to_string()).collect()))
+                .collect()
+        });
+    }
+
+    let keys: Vec<&String> = groups.keys().collect();
+    for (i, a) in keys.iter().enumerate() {
+        for b in &keys[i + 1..] {
+            if a.chars().count() < 4 || b.chars().count() < 4 || edit_distance(a, b) > 1 { continue }
+            let ids = |key: &String| groups[key].values().flatten().map(|id| id.to_string()).collect::<Vec<_>>();
+            let (a_posts, b_posts) = (ids(a), ids(b));
+            log!(
+                "warning: tags `{}` and `{}` look similar, consider adding an entry to `tag_aliases`:\n    `{}` used by: {}\n    `{}` used by: {}",
+                a, b, a, a_posts.join(", "), b, b_posts.join(", ")
+            );
+            lints.push(TagLint::Similar { a: a.to_string(), b: b.to_string(), a_posts, b_posts });
+        }
+    }
+    lints
+}
+
+/// Finds the local links in each HTML page of `outputs` that don't point to another output.
+pub fn broken_links(sink: &dyn OutputSink, outputs: &BTreeMap<String, u64>, base_path: &str) -> BTreeMap<String, Vec<String>> {
+    let mut broken = BTreeMap::new();
+    for page in outputs.keys().filter(|page| page.ends_with(".html")) {
+        let Ok(source) = sink.read(page)
+            .inspect_err(|e| log!("error: could not read output `{}`: {}", page, e))
+            else { continue };
+        let source = String::from_utf8_lossy(&source);
+
+        let links: Vec<String> = html::link_attributes(&source).into_iter()
+            .filter(|link| html::resolve_local_link(page, link, base_path).is_some_and(|path| !outputs.contains_key(&path)))
+            .map(|link| link.to_string())
+            .collect();
+        if !links.is_empty() {
+            broken.insert(page.clone(), links);
+        }
+    }
+    broken
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_differing_in_case_resolve_the_same_way_every_time() {
+        let aliases = BTreeMap::from([
+            ("rust".to_string(), "Rust".to_string()),
+            ("RUST".to_string(), "Rust language".to_string())
+        ]);
+        for _ in 0..8 {
+            assert_eq!(resolve_alias("Rust", &aliases), Some("Rust language"));
+        }
+        assert_eq!(resolve_alias("go", &aliases), None);
+    }
+
+    #[test]
+    fn canonical_tags_prefer_aliases_then_the_most_used_spelling() {
+        let aliases = BTreeMap::from([("rustlang".to_string(), "rust".to_string())]);
+        let variants = BTreeMap::from([("Rust", vec!["a", "d"]), ("rust", vec!["b"]), ("RUST", vec!["c"])]);
+        assert_eq!(canonical_tag(&variants, &BTreeMap::new()), "Rust");
+        let tied = BTreeMap::from([("Rust", vec!["a"]), ("rust", vec!["b"])]);
+        assert_eq!(canonical_tag(&tied, &BTreeMap::new()), "Rust");
+        let aliased = BTreeMap::from([("Rust", vec!["a", "d"]), ("rustlang", vec!["c"])]);
+        assert_eq!(canonical_tag(&aliased, &aliases), "rust");
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("golang", "golang"), 0);
+        assert_eq!(edit_distance("golang", "gollang"), 1);
+        assert_eq!(edit_distance("golang", "goland"), 1);
+        assert_eq!(edit_distance("rust", "rusty"), 1);
+        assert_eq!(edit_distance("sää", "saa"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+}
@@ -0,0 +1,183 @@
+use std::{io::Write, path::{Path, PathBuf}};
+use serde::{Deserialize, Serialize};
+
+/// Who holds a build lock, as recorded in the lock file.
+#[derive(Debug, Serialize, Deserialize)]
+struct LockOwner {
+    pid: u32,
+    started: String
+}
+
+/// Keeps other builds out of the output directory until dropped.
+#[derive(Debug)]
+pub struct BuildLock {
+    path: PathBuf
+}
+
+/// How old a lock whose process can't be checked has to be before it is taken over. Long enough
+/// for a dev server left running overnight.
+const MAX_UNCHECKED_AGE: chrono::TimeDelta = chrono::TimeDelta::hours(24);
+
+/// Whether a process with this id is running, `None` where that can't be told.
+#[cfg(unix)]
+fn process_exists(pid: u32) -> Option<bool> {
+    let pid = libc::pid_t::try_from(pid).ok()?;
+    // Signal 0 only checks whether the process could be signalled
+    if unsafe { libc::kill(pid, 0) } == 0 { return Some(true) }
+    match std::io::Error::last_os_error().raw_os_error() {
+        Some(libc::ESRCH) => Some(false),
+        // Running, but as another user
+        Some(libc::EPERM) => Some(true),
+        _ => None
+    }
+}
+
+/// Whether a process with this id is running, `None` where that can't be told.
+#[cfg(windows)]
+fn process_exists(pid: u32) -> Option<bool> {
+    let output = std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
+        .output().ok()?;
+    if !output.status.success() { return None }
+    Some(String::from_utf8_lossy(&output.stdout).contains(&format!("\"{}\"", pid)))
+}
+
+/// Whether a process with this id is running, `None` where that can't be told.
+#[cfg(not(any(unix, windows)))]
+fn process_exists(_pid: u32) -> Option<bool> {
+    None
+}
+
+/// Whether the lock of `holder` can be taken over: its process is gone, or it can't be checked
+/// and the lock is older than `MAX_UNCHECKED_AGE`.
+fn is_stale(holder: &LockOwner, running: Option<bool>, now: chrono::DateTime<chrono::FixedOffset>) -> bool {
+    match running {
+        Some(running) => !running,
+        None => chrono::DateTime::parse_from_rfc3339(&holder.started).is_ok_and(|started| now - started > MAX_UNCHECKED_AGE)
+    }
+}
+
+impl BuildLock {
+    /// Creates the lock file at `path`. An existing lock is taken over if `force` is set or its
+    /// process is known to be gone, otherwise this fails with who holds it.
+    pub fn acquire(path: &Path, force: bool) -> Result<BuildLock, String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("could not create `{}`: {}", dir.display(), e))?;
+        }
+        let owner = LockOwner { pid: std::process::id(), started: crate::rfc3339(&chrono::Local::now().fixed_offset()) };
+        let contents = serde_json::to_vec(&owner).map_err(|e| e.to_string())?;
+
+        // Once for a free lock, and once more after removing a stale one
+        for _ in 0..2 {
+            match std::fs::OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    file.write_all(&contents).map_err(|e| format!("could not write `{}`: {}", path.display(), e))?;
+                    return Ok(BuildLock { path: path.to_path_buf() })
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => (),
+                Err(e) => return Err(format!("could not create `{}`: {}", path.display(), e))
+            }
+
+            // A lock that can't be read is most likely still being written
+            let holder = std::fs::read(path).ok().and_then(|data| serde_json::from_slice::<LockOwner>(&data).ok());
+            let now = chrono::Local::now().fixed_offset();
+            let stale = holder.as_ref().is_some_and(|h| is_stale(h, process_exists(h.pid), now));
+            if !force && !stale {
+                return Err(match holder {
+                    Some(h) => format!(
+                        "another build appears to be running, started at {} by pid {}. If it isn't, remove `{}` or build with --break-lock",
+                        h.started, h.pid, path.display()
+                    ),
+                    None => format!("another build appears to be running, `{}` exists. If it isn't, remove it or build with --break-lock", path.display())
+                })
+            }
+            match &holder {
+                Some(h) if stale => log!("warning: removing the lock of build {} started at {}, which is no longer running or too old to check", h.pid, h.started),
+                _ => log!("warning: breaking the lock at `{}`", path.display())
+            }
+            std::fs::remove_file(path)
+                .or_else(|e| if e.kind() == std::io::ErrorKind::NotFound { Ok(()) } else { Err(e) })
+                .map_err(|e| format!("could not remove `{}`: {}", path.display(), e))?;
+        }
+        Err(format!("could not take the lock at `{}`, another build took it first", path.display()))
+    }
+}
+
+impl Drop for BuildLock {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            log!("error: could not remove the build lock `{}`: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_lock(path: &Path, pid: u32, started: &str) {
+        std::fs::write(path, serde_json::to_vec(&LockOwner { pid, started: started.into() }).unwrap()).unwrap();
+    }
+
+    /// The id of a process that has already exited.
+    fn exited_pid() -> u32 {
+        let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+            .arg("--list")
+            .stdout(std::process::Stdio::null())
+            .spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        pid
+    }
+
+    #[test]
+    fn held_lock_is_refused() {
+        let path = crate::test_dir("lock-held").join("build.lock");
+        let lock = BuildLock::acquire(&path, false).unwrap();
+        let second = BuildLock::acquire(&path, false).unwrap_err();
+        assert!(second.contains("another build appears to be running"), "{}", second);
+        drop(lock);
+        assert!(!path.exists());
+        BuildLock::acquire(&path, false).unwrap();
+    }
+
+    #[test]
+    fn lock_of_exited_process_is_taken_over() {
+        let path = crate::test_dir("lock-exited").join("build.lock");
+        let pid = exited_pid();
+        assert_eq!(process_exists(pid), Some(false));
+        write_lock(&path, pid, "2024-01-01T00:00:00+00:00");
+        let lock = BuildLock::acquire(&path, false).unwrap();
+        let owner: LockOwner = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(owner.pid, std::process::id());
+        drop(lock);
+    }
+
+    #[test]
+    fn lock_of_running_process_is_refused() {
+        let path = crate::test_dir("lock-running").join("build.lock");
+        assert_eq!(process_exists(std::process::id()), Some(true));
+        // Written by hand so that dropping a lock doesn't remove it
+        write_lock(&path, std::process::id(), "2024-01-01T00:00:00+00:00");
+        let error = BuildLock::acquire(&path, false).unwrap_err();
+        assert!(error.contains(&format!("pid {}", std::process::id())), "{}", error);
+        assert!(path.exists());
+
+        let lock = BuildLock::acquire(&path, true).unwrap();
+        drop(lock);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn unchecked_locks_expire() {
+        let now = chrono::DateTime::parse_from_rfc3339("2024-01-02T12:00:00+00:00").unwrap();
+        let old = LockOwner { pid: 1, started: "2024-01-01T00:00:00+00:00".into() };
+        let recent = LockOwner { pid: 1, started: "2024-01-02T10:00:00+02:00".into() };
+        let unreadable = LockOwner { pid: 1, started: "yesterday".into() };
+        assert!(is_stale(&old, None, now));
+        assert!(!is_stale(&recent, None, now));
+        assert!(!is_stale(&unreadable, None, now));
+        assert!(!is_stale(&old, Some(true), now));
+        assert!(is_stale(&recent, Some(false), now));
+    }
+}
@@ -0,0 +1,134 @@
+use std::{io::{IsTerminal, Write}, sync::{Mutex, atomic::{AtomicU8, Ordering}}};
+
+/// How important a line of output is, from its prefix like `warning:`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Error,
+    Warning,
+    Info,
+    /// Requests served by the dev server
+    Request,
+    Debug
+}
+
+impl Level {
+    const ALL: [Level; 5] = [Level::Error, Level::Warning, Level::Info, Level::Request, Level::Debug];
+
+    fn prefix(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Info => "info",
+            Level::Request => "request",
+            Level::Debug => "debug"
+        }
+    }
+
+    /// ANSI color of the prefix
+    fn color(self) -> &'static str {
+        match self {
+            Level::Error => "\x1b[1;31m",
+            Level::Warning => "\x1b[1;33m",
+            Level::Info => "\x1b[32m",
+            Level::Request => "\x1b[36m",
+            Level::Debug => "\x1b[2m"
+        }
+    }
+
+    /// The level of `message` and the rest of it after the prefix, `None` for lines without one
+    fn of(message: &str) -> Option<(Level, &str)> {
+        Level::ALL.into_iter().find_map(|level| {
+            let rest = message.strip_prefix(level.prefix())?.strip_prefix(':')?;
+            Some((level, rest))
+        })
+    }
+}
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Request as u8);
+
+/// The `error:` and `warning:` messages printed since `take_problems` was last called
+static PROBLEMS: Mutex<Problems> = Mutex::new(Problems { errors: Vec::new(), warnings: Vec::new() });
+
+/// Errors and warnings printed while `record` runs, with their prefixes
+static RECORDING: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+/// Errors and warnings printed, without their prefixes, oldest first
+#[derive(Debug, Default)]
+pub struct Problems {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>
+}
+
+/// Leaves out lines less important than `level`. Lines without a level are always printed.
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Whether lines at `level` are printed, to skip building the ones that wouldn't be.
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Prints a line of output, warnings and errors to stderr and everything else to stdout, with
+/// a colored prefix if that is a terminal. See `progress::print_above` for lines printed during a build.
+pub fn print(message: &str) {
+    let Some((level, rest)) = Level::of(message) else {
+        crate::progress::print_above(false, || { let _ = writeln!(std::io::stdout().lock(), "{}", message); });
+        return
+    };
+    if matches!(level, Level::Error | Level::Warning) {
+        let mut problems = PROBLEMS.lock().unwrap_or_else(|e| e.into_inner());
+        let list = if level == Level::Error { &mut problems.errors } else { &mut problems.warnings };
+        list.push(rest.trim_start().to_string());
+        if let Some(recording) = RECORDING.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            recording.push(message.to_string());
+        }
+    }
+    if !enabled(level) { return }
+
+    let write = |out: &mut dyn Write, colored: bool| {
+        let _ = if colored {
+            writeln!(out, "{}{}\x1b[0m:{}", level.color(), level.prefix(), rest)
+        } else {
+            writeln!(out, "{}", message)
+        };
+    };
+    crate::progress::print_above(matches!(level, Level::Info | Level::Debug), || match level {
+        Level::Error | Level::Warning => {
+            let stderr = std::io::stderr();
+            write(&mut stderr.lock(), stderr.is_terminal())
+        },
+        _ => {
+            let stdout = std::io::stdout();
+            write(&mut stdout.lock(), stdout.is_terminal())
+        }
+    });
+}
+
+/// Runs `f`, returning the errors and warnings it printed, to print them again later.
+pub fn record<T>(f: impl FnOnce() -> T) -> (T, Vec<String>) {
+    *RECORDING.lock().unwrap_or_else(|e| e.into_inner()) = Some(Vec::new());
+    let result = f();
+    let recorded = RECORDING.lock().unwrap_or_else(|e| e.into_inner()).take().unwrap_or_default();
+    (result, recorded)
+}
+
+/// The errors and warnings printed since the last call, even those left out by `set_max_level`.
+pub fn take_problems() -> Problems {
+    std::mem::take(&mut PROBLEMS.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_come_from_prefixes() {
+        assert_eq!(Level::of("debug: ignoring change"), Some((Level::Debug, " ignoring change")));
+        assert_eq!(Level::of("request: GET /"), Some((Level::Request, " GET /")));
+        assert_eq!(Level::of("debugging"), None);
+        // Debug lines are left out at the default level and below
+        assert!(Level::Debug > Level::Request && Level::Request > Level::Info);
+    }
+}
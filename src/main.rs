@@ -1,301 +1,155 @@
-mod post;
 #[cfg(feature = "dev")]
 mod server;
 
-use std::{collections::{HashMap, HashSet}, io::Read, path::PathBuf};
+use std::process::ExitCode;
 use clap::Parser;
-use minijinja::context;
-use serde::Serialize;
-use post::{Post, PostBuilder};
-
-fn parse_dir(s: &str) -> Result<PathBuf, String> {
-    let path = std::fs::canonicalize(s).map_err(|err| err.to_string())?;
-    if path.is_dir() {
-        Ok(path)
-    } else {
-        Err("The provided path must be a directory".into())
-    }
-}
-
-#[derive(Parser, Debug, Clone)]
-#[command(version, about)]
-struct Args {
-    #[arg(help="Directory for input files", value_parser=parse_dir)]
-    in_dir: PathBuf,
-    #[arg(help="Directory for output files", value_parser=parse_dir)]
-    out_dir: PathBuf,
-    #[cfg(feature = "dev")]
-    #[arg(short, long, help="Watch for changes to the input directory and recompile")]
-    watch: bool,
-    #[cfg(feature = "dev")]
-    #[arg(short, long, help="Start dev server and watch for changes")]
-    dev: bool,
-    #[cfg(feature = "dev")]
-    #[arg(short, long, help="Port to use for dev server", default_value="8080")]
-    port: u16
-}
-
-#[derive(Debug)]
-pub struct SiteBuilder<'a> {
-    args: &'a Args,
-    assets: HashMap<u64, (Vec<u8>, String)>,
-    posts: Vec<Post>,
-    env: minijinja::Environment<'static>
-}
-
-impl<'a> SiteBuilder<'a> {
-    fn asset_path(hash: u64, ext: &str) -> String {
-        format!("assets/{:016x}.{}", hash, ext)
-    }
-
-    pub fn store_asset(&mut self, asset: Vec<u8>, ext: &str) -> String {
-        let hash = {
-            use std::hash::Hasher;
-            let mut hasher = std::hash::DefaultHasher::new();
-            hasher.write(&asset);
-            hasher.finish()
-        };
-
-        let ext = &self.assets.entry(hash)
-            .or_insert_with(|| (asset, ext.to_string())).1;
-        Self::asset_path(hash, ext)
-    }
-
-    fn build_posts(&mut self) {
-        let Ok(posts_dir) = self.args.in_dir.join("posts").read_dir()
-            .inspect_err(|e| {
-                println!("error: cannot read posts directory: {e}");
-                println!("warning: continuing with no posts");
-            }) else { return };
-
-        for entry in posts_dir {
-            let Ok(entry) = entry.map(|e| e.path())
-                .inspect_err(|e| {
-                    println!("error: cannot read post: {e}")
-                }) else { continue };
+use static_site_gen::{Args, Command, ConfigCommand, check_dirs, dump_config, export_post, import_posts, lock_build, log, logging::{self, Level}, new_post, recompile};
+#[cfg(feature = "dev")]
+use static_site_gen::{watch, watch_filter};
 
-            let builder = if entry.is_dir() {
-                let index = entry.join("index.md");
-                if index.is_file() {
-                    PostBuilder { site: self, file: index, dir: Some(entry), meta: None }
-                } else {
-                    println!("error: unknown post type for: `{}`", index.display());
-                    continue
-                }
-            } else if entry.is_file() && entry.extension().and_then(|e| e.to_str()) == Some("md") {
-                PostBuilder { site: self, file: entry, dir: None, meta: None }
-            } else {
-                println!("error: unknown post type for `{}`", entry.display());
-                continue
-            };
+fn main() -> ExitCode {
+    let args = Args::parse();
+    logging::set_max_level(if args.quiet { Level::Error } else if args.verbose { Level::Debug } else { Level::Request });
 
-            if let Some(post) = builder.build() {
-                self.posts.push(post);
-            }
+    if let Some(Command::Export { id, out }) = &args.command {
+        let out = out.clone().unwrap_or_else(|| format!("{}.html", id).into());
+        if let Err(e) = export_post(&args, id, &out) {
+            log!("error: could not export post: {}", e);
+            return ExitCode::FAILURE
         }
+        return ExitCode::SUCCESS
     }
 
-    fn load_templates(&mut self) {
-        let Ok(templates_dir) = self.args.in_dir.join("templates").read_dir()
-            .inspect_err(|e| {
-                println!("error: cannot read templates directory: {e}");
-            }) else { return };
-        
-        for entry in templates_dir {
-            let Ok(entry) = entry.map(|e| e.path())
-                .inspect_err(|e| {
-                    println!("error: cannot read template: {e}")
-                }) else { continue };
-
-            let Some(name) = entry.file_name()
-                .and_then(|s| s.to_str())
-                .map(|s| s.trim_end_matches(".html")) else {
-                    println!("error: unknown template name for: `{}`", entry.display());
-                    continue
-                };
-
-            println!("info: processing template `{}` at `{}`", name, entry.display());
-
-            let mut source = String::new();
-            let Ok(_) = std::fs::File::open(&entry)
-                .and_then(|mut file| file.read_to_string(&mut source))
-                .inspect_err(|e| {
-                    println!("error: cannot read template: {e}")
-                }) else { continue };
-
-            if let Err(e) = self.env.add_template_owned(name.to_string(), source) {
-                println!("error: cannot parse template: {e}");
+    if let Some(Command::New { title, dir, tags }) = &args.command {
+        match new_post(&args, title, *dir, tags) {
+            Ok(file) => log!("info: created post `{}`", file.display()),
+            Err(e) => {
+                log!("error: could not create post: {}", e);
+                return ExitCode::FAILURE
             }
         }
-
-        fn format_datetime_function(s: &minijinja::State<'_, '_>, dt: minijinja::value::ViaDeserialize<toml_datetime::Datetime>) -> String {
-            let format_value = s.lookup("FORMAT_DATETIME");
-            let format = format_value
-                .as_ref()
-                .and_then(|v| v.as_str())
-                .unwrap_or("%B %e %Y at %H:%M");
-
-            let cdt = dt_toml_to_chrono(&dt);
-            let readable = cdt.format(format);
-            let timestamp = cdt.to_rfc3339();
-            format!("<time datetime=\"{}\">{}</time>", timestamp, readable)
-        }
-        self.env.add_filter("format_datetime", format_datetime_function);
-        self.env.add_filter("urlencode", |s: String| urlencoding::encode(&s).to_string());
+        return ExitCode::SUCCESS
     }
 
-    fn write_to_output(&self, outpath: &str, content: &[u8]) {
-        let target = self.args.out_dir.join(outpath);
-        if let Some(parent) = target.parent() {
-            let Ok(()) = std::fs::create_dir_all(parent)
-                .inspect_err(|e| println!("error: could not write output `{}`: {}", target.display(), e))
-                else { return };
+    if let Some(Command::Import { source, force }) = &args.command {
+        if let Err(e) = import_posts(&args, source, *force) {
+            log!("error: could not import posts: {}", e);
+            return ExitCode::FAILURE
         }
-        let Ok(_) = std::fs::File::create(&target)
-            .and_then(|mut file| {
-                use std::io::Write;
-                file.write_all(content)
-            })
-            .inspect_err(|e| println!("error: could not write output `{}`: {}", target.display(), e))
-            else { return };
+        return ExitCode::SUCCESS
     }
 
-    fn build_pages(&self) {
-        self.build_page("index", "index.html", context! { posts => &self.posts });
-        
-        let mut tags = HashSet::new();
-        for post in &self.posts {
-            self.build_page("post", &format!("posts/{}.html", post.id), context! { post => post });
-
-            for tag in &post.meta.tags {
-                tags.insert(tag.clone());
-            }
+    if let Some(Command::Config { action: ConfigCommand::DumpDefaults }) = &args.command {
+        // Info lines go to stdout too, which has to hold nothing but the config
+        if !args.quiet {
+            logging::set_max_level(Level::Warning);
         }
-
-        for tag in tags {
-            self.build_page("tag", &format!("tags/{}.html", tag), context! { posts => &self.posts, tag => tag });
-        }
-
-        for (&hash, (content, ext)) in &self.assets {
-            println!("info: writing asset {:016x} of type `{}`", hash, ext);
-            self.write_to_output(&Self::asset_path(hash, ext), content);
+        if let Err(e) = dump_config(&args) {
+            log!("error: could not print the site config: {}", e);
+            return ExitCode::FAILURE
         }
+        return ExitCode::SUCCESS
     }
 
-    fn build_page<C: Serialize>(&self, tname: &str, outpath: &str, context: C) {
-        println!("info: rendering page `{}` with template `{}`", outpath, tname);
-
-        let Ok(template) = self.env.get_template(tname)
-            .inspect_err(|e| println!("error: cannot read template `{}`: {}", tname, e))
-            else { return };
-
-        let Ok(source) = template.render(context)
-            .inspect_err(|e| println!("error: could not render template `{}`: {}", tname, e))
-            else { return };
-
-        self.write_to_output(outpath, source.as_bytes());        
+    if let Err(e) = check_dirs(&args) {
+        log!("error: {}, not building", e);
+        return ExitCode::FAILURE
     }
 
-    fn copy_static(&self) {
-        let static_in_dir = self.args.in_dir.join("static");
-        if !static_in_dir.is_dir() { return }
-
-        let static_out_dir = self.args.out_dir.join("static");
-        let Ok(()) = std::fs::create_dir_all(&static_out_dir)
-            .inspect_err(|e| println!("error: could not create static directory: {e}"))
-            else { return };
-        
-        for entry in walkdir::WalkDir::new(&static_in_dir) {
-            let Ok(entry) = entry
-                .inspect_err(|e| {
-                    println!("error: could not read static asset: {e}")
-                }) else { continue };
-            if !entry.file_type().is_file() { continue }
-
-            println!("info: copying static asset `{}`", entry.path().display());
-
-            let Ok(relpath) = entry.path().strip_prefix(&static_in_dir) else { continue };
-            let target = static_out_dir.join(relpath);
-
-            if entry.depth() > 1 && let Some(parent) = target.parent() {
-                let Ok(()) = std::fs::create_dir_all(parent)
-                    .inspect_err(|e| println!("error: could not copy static asset: {e}"))
-                    else { continue };
-            }
-            if let Err(e) = std::fs::copy(entry.path(), &target) {
-                println!("error: could not copy static asset: {e}");
-            }
+    // Held until `main` returns, across all rebuilds in watch mode. `main` returns rather than
+    // calling `exit` so that the lock is dropped, and removed, on failure too
+    let _lock = match lock_build(&args) {
+        Ok(lock) => lock,
+        Err(e) => {
+            log!("error: {}", e);
+            return ExitCode::FAILURE
         }
-    }
-}
-
-pub fn dt_toml_to_chrono(dt: &toml_datetime::Datetime) -> chrono::DateTime<chrono::FixedOffset> {
-    (|| {
-        let date = chrono::NaiveDate::from_ymd_opt(dt.date?.year as i32, dt.date?.month as u32, dt.date?.day as u32)?;
-        let datetime = (|| date.and_hms_opt(dt.time?.hour as u32, dt.time?.minute as u32, dt.time?.second as u32))()
-            .unwrap_or(date.and_time(chrono::NaiveTime::MIN));
-        let mapped = (|| datetime.and_local_timezone(chrono::FixedOffset::east_opt(match dt.offset? {
-            toml_datetime::Offset::Z => 0,
-            toml_datetime::Offset::Custom { minutes } => (minutes as i32) * 60
-        })?).single())().unwrap_or(datetime.and_utc().fixed_offset());
-        Some(mapped)
-    })().unwrap_or(chrono::DateTime::UNIX_EPOCH.fixed_offset())
-}
+    };
 
+    let built = recompile(&args);
+    let failed = built.failed(args.strict);
 
-fn recompile(args: &Args) {
-    let mut builder = SiteBuilder { args, assets: HashMap::new(), posts: Vec::new(), env: minijinja::Environment::new() };
-    builder.build_posts();
-    builder.load_templates();
-    builder.build_pages();
-    builder.copy_static();
-}
-
-fn main() {
-    let args = Args::parse();
-
-    recompile(&args);
+    #[cfg(not(feature = "dev"))]
+    if failed {
+        return ExitCode::FAILURE
+    }
 
     #[cfg(feature = "dev")] {
-        if args.dev {
-            server::start_server(args.out_dir.clone(), args.port);
+        if failed && !(args.dev || args.serve || args.watch) {
+            return ExitCode::FAILURE
+        }
+        let site = std::sync::Arc::new(std::sync::RwLock::new(built));
+        let server = (args.dev || args.serve).then(|| {
+            let record = args.record.clone().map(|dir| (dir, args.record_max));
+            server::start_server(args.out_dir.clone(), args.port, site.clone(), record)
+        });
+        if let Some(server) = server && !(args.watch || args.dev) {
+            if server.join().is_err() {
+                log!("error: server: could not serve on port {}", args.port);
+                return ExitCode::FAILURE
+            }
+            return ExitCode::SUCCESS
         }
 
         if args.watch || args.dev {
             let (tx, rx) = std::sync::mpsc::channel();
             let Ok(mut watcher) = notify_debouncer_full::new_debouncer(std::time::Duration::from_millis(250), None, tx)
-                .inspect_err(|e| println!("error: could not watch input directory: {e:?}")) else { return };
+                .inspect_err(|e| log!("error: could not watch input directory: {e:?}")) else { return ExitCode::FAILURE };
             if let Err(e) = watcher.watch(&args.in_dir, notify_debouncer_full::notify::RecursiveMode::Recursive) {
-                println!("error: could not watch input directory: {e:?}");
-                return
+                log!("error: could not watch input directory: {e:?}");
+                return ExitCode::FAILURE
             }
 
-            for event in rx {
-                match event {
-                    Ok(events) => {
-                        'outer: for event in events {
-                            let notify_debouncer_full::DebouncedEvent { event: notify_debouncer_full::notify::Event {
-                                kind: notify_debouncer_full::notify::EventKind::Modify(_) | notify_debouncer_full::notify::EventKind::Create(_),
-                                paths, ..
-                            }, .. } = event else { continue };
+            let mut filter = watch_filter(&args);
+            while let Ok(event) = rx.recv() {
+                let Some(trigger) = triggering_path(event, &filter) else { continue };
 
-                            for path in paths {
-                                if path.starts_with(&args.out_dir) { continue; }
+                // Things like `git checkout` touch many files over a longer time than the debouncer
+                // waits, so hold off until the input directory has been quiet for a while
+                let quiet = std::time::Duration::from_millis(500);
+                while let Ok(event) = rx.recv_timeout(quiet) {
+                    triggering_path(event, &filter);
+                }
 
-                                let Ok(path) = path.strip_prefix(&args.in_dir) else { continue };
-                                let is_hidden = path.components().flat_map(|c| c.as_os_str().to_str())
-                                    .any(|c| c.starts_with('.'));
-                                if is_hidden { continue }
+                let trigger = trigger.strip_prefix(&args.in_dir).unwrap_or(&trigger);
+                log!("info: recompiling due to `{}`", trigger.display());
+                let mut built = recompile(&args);
+                if let Ok(mut site) = site.write() {
+                    watch::report_template_changes(&site.templates, &built.templates);
+                    watch::remove_stale_outputs(&args.out_dir, &site.outputs, &mut built);
+                    *site = built;
+                }
+                filter = watch_filter(&args);
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
 
-                                println!("info: recompiling due to `{}`", path.display());
-                                recompile(&args);
-                                break 'outer
-                            }
-                        }
-                    },
-                    Err(e) => println!("error: could not watch input directory: {e:?}")
+/// Logs which changes in a batch of watcher events are ignored, returning the first one that should trigger a rebuild.
+#[cfg(feature = "dev")]
+fn triggering_path(
+    events: notify_debouncer_full::DebounceEventResult,
+    filter: &watch::WatchFilter
+) -> Option<std::path::PathBuf> {
+    use notify_debouncer_full::notify::EventKind;
+    let events = events
+        .inspect_err(|e| log!("error: could not watch input directory: {e:?}"))
+        .ok()?;
+
+    let mut trigger = None;
+    let debug = logging::enabled(Level::Debug);
+    for event in events {
+        if !matches!(event.kind, EventKind::Modify(_) | EventKind::Create(_) | EventKind::Remove(_)) { continue }
+        for path in &event.paths {
+            match filter.ignore_reason(path) {
+                Some(reason) => if debug { log!("debug: ignoring change to `{}`, {}", path.display(), reason) },
+                None => {
+                    if debug { log!("debug: change to `{}` triggers a rebuild", path.display()) }
+                    trigger.get_or_insert_with(|| path.clone());
                 }
             }
         }
     }
+    trigger
 }
@@ -0,0 +1,584 @@
+use std::{cell::RefCell, collections::{BTreeMap, HashMap, HashSet}, io::{self, Write}, path::{Path, PathBuf}, sync::Mutex, time::Duration};
+use serde::{Deserialize, Serialize};
+use crate::slug::SlugConfig;
+
+/// How many times a durable write is attempted before giving up
+const DURABLE_ATTEMPTS: u32 = 4;
+
+/// Destination for the files produced by a build, addressed by paths relative to the output root.
+pub trait OutputSink: std::fmt::Debug {
+    fn write(&self, path: &str, content: &[u8]) -> io::Result<()>;
+
+    /// Like `write`, but makes sure the content has reached the disk before returning.
+    fn write_durable(&self, path: &str, content: &[u8]) -> io::Result<()> {
+        self.write(path, content)
+    }
+
+    fn read(&self, path: &str) -> io::Result<Vec<u8>>;
+
+    /// Removes the output at `path`, which may not exist.
+    fn remove(&self, path: &str) -> io::Result<()>;
+
+    fn copy(&self, path: &str, source: &Path) -> io::Result<u64> {
+        let content = std::fs::read(source)?;
+        self.write(path, &content)?;
+        Ok(content.len() as u64)
+    }
+
+    /// The file `path` is written to, for sinks that can have large files copied into them
+    /// from another thread. Parent directories are created.
+    fn file_target(&self, _path: &str) -> Option<io::Result<PathBuf>> {
+        None
+    }
+}
+
+/// Bytes read and written at a time when copying large files
+const COPY_CHUNK: usize = 1 << 20;
+/// How often progress is logged when copying large files
+const PROGRESS_BYTES: u64 = 64 << 20;
+
+/// Hashes the first `len` bytes of `file`.
+fn prefix_hash(file: &Path, len: u64) -> io::Result<u64> {
+    use std::{hash::Hasher, io::Read};
+    let mut reader = std::fs::File::open(file)?.take(len);
+    let mut hasher = std::hash::DefaultHasher::new();
+    let mut buffer = vec![0; COPY_CHUNK];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 { break }
+        hasher.write(&buffer[..read]);
+    }
+    Ok(hasher.finish())
+}
+
+/// Copies `source` to `target` in chunks, logging progress. If `target` holds the start of
+/// `source`, like after an interrupted copy, only the rest is appended. A finished copy gets the
+/// modification time of `source`, so that while the size and time of both match it is taken to
+/// be complete without hashing them again.
+pub fn copy_resumable(source: &Path, target: &Path) -> io::Result<u64> {
+    use std::io::{Read, Seek};
+    let source_meta = std::fs::metadata(source)?;
+    let total = source_meta.len();
+    let target_meta = std::fs::metadata(target).ok();
+    if let Some(target_meta) = &target_meta && target_meta.len() == total
+        && source_meta.modified().ok().is_some_and(|m| target_meta.modified().ok() == Some(m))
+    {
+        log!("info: `{}` is already copied", source.display());
+        return Ok(total)
+    }
+    let existing = target_meta.map(|m| m.len()).unwrap_or(0);
+    let resume_from = if existing > 0 && existing <= total && prefix_hash(source, existing)? == prefix_hash(target, existing)? {
+        existing
+    } else {
+        0
+    };
+    if resume_from == total {
+        log!("info: `{}` is already copied", source.display());
+        stamp(target, &source_meta);
+        return Ok(total)
+    }
+    if resume_from > 0 {
+        log!("info: resuming copy of `{}` at {:.1} of {:.1} MiB", source.display(), resume_from as f64 / 1048576.0, total as f64 / 1048576.0);
+    }
+
+    let mut reader = std::fs::File::open(source)?;
+    reader.seek(io::SeekFrom::Start(resume_from))?;
+    let mut writer = std::fs::OpenOptions::new().create(true).write(true).truncate(resume_from == 0).open(target)?;
+    writer.seek(io::SeekFrom::Start(resume_from))?;
+
+    let mut buffer = vec![0; COPY_CHUNK];
+    let mut copied = resume_from;
+    let mut next_report = (copied / PROGRESS_BYTES + 1) * PROGRESS_BYTES;
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 { break }
+        writer.write_all(&buffer[..read])?;
+        copied += read as u64;
+        if copied >= next_report && copied < total {
+            log!("info: copying `{}`: {:.0}%", source.display(), copied as f64 * 100.0 / total as f64);
+            next_report += PROGRESS_BYTES;
+        }
+    }
+    writer.flush()?;
+    if copied != total {
+        return Err(io::Error::other(format!("copied {} bytes but expected {}", copied, total)))
+    }
+    drop(writer);
+    stamp(target, &source_meta);
+    log!("info: copied `{}`, {:.1} MiB", source.display(), total as f64 / 1048576.0);
+    Ok(total)
+}
+
+/// Gives the finished copy `target` the modification time of its source, to recognize it later.
+fn stamp(target: &Path, source_meta: &std::fs::Metadata) {
+    let Ok(modified) = source_meta.modified() else { return };
+    let result = std::fs::File::options().write(true).open(target).and_then(|file| file.set_modified(modified));
+    if let Err(e) = result {
+        log!("warning: could not set the modification time of `{}`, it will be hashed again next build: {}", target.display(), e);
+    }
+}
+
+#[derive(Debug)]
+pub struct FsSink {
+    pub dir: PathBuf
+}
+
+impl FsSink {
+    fn target(&self, path: &str) -> io::Result<PathBuf> {
+        let target = self.dir.join(path);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(target)
+    }
+}
+
+fn is_transient(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// Whether `target` already holds exactly `content`, so that it can be left alone and keep its mtime.
+fn unchanged(target: &Path, content: &[u8]) -> bool {
+    std::fs::metadata(target).is_ok_and(|m| m.len() == content.len() as u64)
+        && std::fs::read(target).is_ok_and(|existing| existing == content)
+}
+
+/// Writes `content` to a temporary file next to `target` and renames it into place, so that
+/// `target` is never seen half-written. With `sync` the content has reached the disk on return.
+fn write_atomic(target: &Path, content: &[u8], sync: bool) -> io::Result<()> {
+    let name = target.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    // Concurrent builds each write their own temporary file
+    let temp = target.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+    let result = (|| {
+        let mut file = std::fs::File::create(&temp)?;
+        file.write_all(content)?;
+        if sync {
+            file.sync_all()?;
+        }
+        std::fs::rename(&temp, target)?;
+        if !sync { return Ok(()) }
+        #[cfg(unix)]
+        if let Some(parent) = target.parent() {
+            std::fs::File::open(parent)?.sync_all()?;
+        }
+
+        let written = std::fs::metadata(target)?.len();
+        if written != content.len() as u64 {
+            return Err(io::Error::other(format!("wrote {} bytes but expected {}", written, content.len())))
+        }
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+impl OutputSink for FsSink {
+    fn write(&self, path: &str, content: &[u8]) -> io::Result<()> {
+        let target = self.target(path)?;
+        if unchanged(&target, content) {
+            log!("info: `{}` unchanged, skipped", path);
+            return Ok(())
+        }
+        write_atomic(&target, content, false)
+    }
+
+    fn write_durable(&self, path: &str, content: &[u8]) -> io::Result<()> {
+        let target = self.target(path)?;
+        if unchanged(&target, content) {
+            log!("info: `{}` unchanged, skipped", path);
+            return Ok(())
+        }
+        let mut delay = Duration::from_millis(50);
+        let mut result = write_atomic(&target, content, true);
+        for _ in 1..DURABLE_ATTEMPTS {
+            let Err(e) = &result else { break };
+            if !is_transient(e) { break }
+            log!("warning: writing `{}` failed: {}, retrying in {}ms", path, e, delay.as_millis());
+            std::thread::sleep(delay);
+            delay *= 2;
+            result = write_atomic(&target, content, true);
+        }
+        result
+    }
+
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        std::fs::read(self.dir.join(path))
+    }
+
+    fn remove(&self, path: &str) -> io::Result<()> {
+        match std::fs::remove_file(self.dir.join(path)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result
+        }
+    }
+
+    fn file_target(&self, path: &str) -> Option<io::Result<PathBuf>> {
+        Some(self.target(path))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    outputs: RefCell<BTreeMap<String, Vec<u8>>>
+}
+
+impl MemorySink {
+    pub fn into_outputs(self) -> BTreeMap<String, Vec<u8>> {
+        self.outputs.into_inner()
+    }
+}
+
+impl OutputSink for MemorySink {
+    fn write(&self, path: &str, content: &[u8]) -> io::Result<()> {
+        self.outputs.borrow_mut().insert(path.to_string(), content.to_vec());
+        Ok(())
+    }
+
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        self.outputs.borrow().get(path).cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no output at `{}`", path)))
+    }
+
+    fn remove(&self, path: &str) -> io::Result<()> {
+        self.outputs.borrow_mut().remove(path);
+        Ok(())
+    }
+}
+
+/// How files under `assets/` are named
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetNaming {
+    /// `{hash}.{ext}`
+    #[default]
+    Hash,
+    /// `{post id}-{source file stem}-{hash16}.{ext}`, leaving out the parts that aren't known
+    Descriptive
+}
+
+/// Hash of an asset's content that its name is made from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetHashAlgorithm {
+    /// SipHash-1-3 with zero keys, 16 hex digits, what `DefaultHasher` gave before this was configurable
+    #[default]
+    Siphash,
+    /// 64 hex digits, for names that effectively never collide
+    Sha256
+}
+
+impl AssetHashAlgorithm {
+    fn digest(self, content: &[u8]) -> Vec<u8> {
+        match self {
+            AssetHashAlgorithm::Siphash => {
+                use std::hash::Hasher;
+                let mut hasher = siphasher::sip::SipHasher13::new_with_keys(0, 0);
+                hasher.write(content);
+                hasher.finish().to_be_bytes().to_vec()
+            },
+            AssetHashAlgorithm::Sha256 => {
+                use sha2::Digest;
+                sha2::Sha256::digest(content).to_vec()
+            }
+        }
+    }
+
+    /// Hex digits in a full hash
+    fn max_length(self) -> usize {
+        match self {
+            AssetHashAlgorithm::Siphash => 16,
+            AssetHashAlgorithm::Sha256 => 64
+        }
+    }
+}
+
+/// Fewest hex digits of the hash kept in asset names, 64 bits so that names of different assets
+/// practically never clash, though `AssetStore::store` still checks
+pub const MIN_HASH_LENGTH: usize = 16;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AssetHashConfig {
+    pub algorithm: AssetHashAlgorithm,
+    /// Hex digits of the hash kept in asset names, the full hash for `hash` naming and 16 for `descriptive` if unset
+    pub length: Option<usize>
+}
+
+/// What decides the names of assets, recorded in the asset manifest to notice changes between builds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AssetNameSettings {
+    pub naming: AssetNaming,
+    pub algorithm: AssetHashAlgorithm,
+    pub length: usize
+}
+
+impl std::fmt::Display for AssetNameSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let naming = format!("{:?}", self.naming).to_lowercase();
+        let algorithm = format!("{:?}", self.algorithm).to_lowercase();
+        write!(f, "`{}` names with {} digits of `{}`", naming, self.length, algorithm)
+    }
+}
+
+impl AssetNameSettings {
+    /// The settings for `naming` and `config`, with the length brought within what the algorithm gives.
+    pub fn new(naming: AssetNaming, config: &AssetHashConfig) -> AssetNameSettings {
+        let max = config.algorithm.max_length();
+        let default = if naming == AssetNaming::Hash { max } else { MIN_HASH_LENGTH };
+        let length = config.length.unwrap_or(default);
+        if length < MIN_HASH_LENGTH {
+            log!("warning: asset hash length {} is too short, using {} digits", length, MIN_HASH_LENGTH);
+        } else if length > max {
+            log!("warning: asset hash length {} is longer than the whole hash, using {} digits", length, max);
+        }
+        AssetNameSettings { naming, algorithm: config.algorithm, length: length.clamp(MIN_HASH_LENGTH, max) }
+    }
+}
+
+#[derive(Debug)]
+struct StoredAsset {
+    content: Vec<u8>,
+    path: String,
+    owners: Vec<String>,
+    sources: Vec<PathBuf>
+}
+
+/// Which posts use an asset and which files it was made from
+#[derive(Debug, Serialize)]
+pub struct AssetUsage<'a> {
+    pub owners: &'a [String],
+    pub sources: &'a [PathBuf]
+}
+
+#[derive(Debug, Serialize)]
+struct AssetManifest<'a> {
+    settings: AssetNameSettings,
+    assets: BTreeMap<&'a str, AssetUsage<'a>>
+}
+
+/// The part of an earlier asset manifest needed to tell whether asset names changed
+#[derive(Debug, Deserialize)]
+pub struct PreviousAssetManifest {
+    pub settings: AssetNameSettings,
+    pub assets: BTreeMap<String, serde::de::IgnoredAny>
+}
+
+#[derive(Debug, Default)]
+struct Assets {
+    /// Assets by full hash, more than one only if different contents have the same hash
+    by_hash: HashMap<Vec<u8>, Vec<StoredAsset>>,
+    paths: HashSet<String>
+}
+
+/// Files stored by content hash under `assets/`, shared by posts and template functions
+/// and written out once all pages are rendered.
+#[derive(Debug)]
+pub struct AssetStore {
+    settings: AssetNameSettings,
+    slugs: SlugConfig,
+    assets: Mutex<Assets>
+}
+
+impl AssetStore {
+    pub fn new(settings: AssetNameSettings, slugs: SlugConfig) -> AssetStore {
+        AssetStore { settings, slugs, assets: Mutex::new(Assets::default()) }
+    }
+
+    pub fn settings(&self) -> AssetNameSettings {
+        self.settings
+    }
+
+    fn path(&self, hash: &str, ext: &str, owner: Option<&str>, source: Option<&Path>) -> String {
+        if self.settings.naming == AssetNaming::Hash {
+            return format!("assets/{}.{}", hash, ext)
+        }
+        let stem = source.and_then(|s| s.file_stem()).map(|s| self.slugs.slugify(&s.to_string_lossy()));
+        let mut name: Vec<String> = owner.map(String::from).into_iter()
+            .chain(stem.filter(|s| !s.is_empty()))
+            .collect();
+        name.push(hash.to_string());
+        format!("assets/{}.{}", name.join("-"), ext)
+    }
+
+    /// A path for a new asset with the hash `hex` that no other asset has. More digits of the hash
+    /// are used if the configured ones are taken, then a counter if the whole hash is.
+    fn unused_path(&self, paths: &HashSet<String>, hex: &str, ext: &str, owner: Option<&str>, source: Option<&Path>) -> String {
+        let path = self.path(&hex[..self.settings.length], ext, owner, source);
+        if !paths.contains(&path) { return path }
+        for length in self.settings.length + 1..=hex.len() {
+            let longer = self.path(&hex[..length], ext, owner, source);
+            if !paths.contains(&longer) {
+                log!("warning: asset `{}` would have the same name as another one, naming it `{}` instead", path, longer);
+                return longer
+            }
+        }
+        let unused = (2..).map(|n| self.path(&format!("{}-{}", hex, n), ext, owner, source))
+            .find(|p| !paths.contains(p))
+            .expect("a counter suffix is always free");
+        log!("warning: asset `{}` has the same hash as another one, naming it `{}` instead", path, unused);
+        unused
+    }
+
+    /// Stores `asset` unless an identical one is already stored, returning its output path.
+    /// `owner` is the id of the post using it and `source` the file it was made from, if any.
+    pub fn store(&self, asset: Vec<u8>, ext: &str, owner: Option<&str>, source: Option<&Path>) -> String {
+        let hash = self.settings.algorithm.digest(&asset);
+        let hex: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+
+        let mut guard = self.assets.lock().unwrap_or_else(|e| e.into_inner());
+        let Assets { by_hash, paths } = &mut *guard;
+        let same_hash = by_hash.entry(hash).or_default();
+        // Only identical contents are shared, in case of a hash collision
+        let index = match same_hash.iter().position(|stored| stored.content == asset) {
+            Some(index) => index,
+            None => {
+                let path = self.unused_path(paths, &hex, ext, owner, source);
+                paths.insert(path.clone());
+                same_hash.push(StoredAsset { path, content: asset, owners: Vec::new(), sources: Vec::new() });
+                same_hash.len() - 1
+            }
+        };
+        let stored = &mut same_hash[index];
+        if let Some(owner) = owner && !stored.owners.iter().any(|o| o == owner) {
+            if let Some(first) = stored.owners.first() && self.settings.naming == AssetNaming::Descriptive {
+                log!("info: asset `{}` of post `{}` is also used by post `{}`, keeping its name", stored.path, first, owner);
+            }
+            stored.owners.push(owner.to_string());
+        }
+        if let Some(source) = source && !stored.sources.iter().any(|s| s == source) {
+            stored.sources.push(source.to_path_buf());
+        }
+        stored.path.clone()
+    }
+
+    /// Calls `f` with the path and content of each asset, in order of path.
+    pub fn for_each(&self, mut f: impl FnMut(&str, &[u8])) {
+        let assets = self.assets.lock().unwrap_or_else(|e| e.into_inner());
+        let mut sorted: Vec<&StoredAsset> = assets.by_hash.values().flatten().collect();
+        sorted.sort_by(|a, b| a.path.cmp(&b.path));
+        for stored in sorted {
+            f(&stored.path, &stored.content);
+        }
+    }
+
+    /// The naming settings, and the posts using each asset and the files it was made from.
+    pub fn manifest(&self) -> Result<String, String> {
+        let assets = self.assets.lock().unwrap_or_else(|e| e.into_inner());
+        let manifest = AssetManifest {
+            settings: self.settings,
+            assets: assets.by_hash.values().flatten()
+                .map(|stored| (stored.path.as_str(), AssetUsage { owners: &stored.owners, sources: &stored.sources }))
+                .collect()
+        };
+        serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_copies_resume_and_restart() {
+        let dir = crate::test_dir("copy-resumable");
+        let (source, target) = (dir.join("video.bin"), dir.join("out.bin"));
+        let content: Vec<u8> = (0..3 * COPY_CHUNK + 123).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&source, &content).unwrap();
+
+        // Interrupted after the first chunk
+        std::fs::write(&target, &content[..COPY_CHUNK]).unwrap();
+        assert_eq!(copy_resumable(&source, &target).unwrap(), content.len() as u64);
+        assert_eq!(std::fs::read(&target).unwrap(), content);
+
+        // A partial copy of another file is started over
+        let mut other = content[..COPY_CHUNK].to_vec();
+        other[10] ^= 1;
+        std::fs::write(&target, &other).unwrap();
+        copy_resumable(&source, &target).unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), content);
+
+        // A longer file is started over too
+        std::fs::write(&target, [content.as_slice(), b"extra"].concat()).unwrap();
+        copy_resumable(&source, &target).unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), content);
+    }
+
+    #[test]
+    fn finished_copies_are_recognized_by_size_and_time() {
+        let dir = crate::test_dir("copy-stamped");
+        let (source, target) = (dir.join("video.bin"), dir.join("out.bin"));
+        std::fs::write(&source, vec![7; COPY_CHUNK + 1]).unwrap();
+        copy_resumable(&source, &target).unwrap();
+        let modified = |path: &Path| std::fs::metadata(path).unwrap().modified().unwrap();
+        assert_eq!(modified(&target), modified(&source));
+
+        // Not hashed again, so a change that keeps the size and time goes unnoticed
+        let mut changed = vec![7; COPY_CHUNK + 1];
+        changed[0] = 8;
+        std::fs::write(&target, &changed).unwrap();
+        std::fs::File::options().write(true).open(&target).unwrap().set_modified(modified(&source)).unwrap();
+        copy_resumable(&source, &target).unwrap();
+        assert_eq!(std::fs::read(&target).unwrap()[0], 8);
+
+        // A source edited since is copied again
+        std::fs::File::options().write(true).open(&source).unwrap()
+            .set_modified(modified(&source) + Duration::from_secs(60)).unwrap();
+        copy_resumable(&source, &target).unwrap();
+        assert_eq!(std::fs::read(&target).unwrap()[0], 7);
+    }
+
+    fn store(algorithm: AssetHashAlgorithm, length: Option<usize>, naming: AssetNaming) -> AssetStore {
+        AssetStore::new(AssetNameSettings::new(naming, &AssetHashConfig { algorithm, length }), SlugConfig::default())
+    }
+
+    #[test]
+    fn both_hash_algorithms_name_assets_stably() {
+        let siphash = store(AssetHashAlgorithm::Siphash, None, AssetNaming::Hash);
+        let path = siphash.store(b"hello".to_vec(), "txt", None, None);
+        // Names must not change between builds or Rust releases
+        assert_eq!(path, "assets/e2e77b41cb4e1f9e.txt");
+        assert_eq!(store(AssetHashAlgorithm::Siphash, None, AssetNaming::Hash).store(b"hello".to_vec(), "txt", None, None), path);
+
+        let sha256 = store(AssetHashAlgorithm::Sha256, None, AssetNaming::Hash);
+        assert_eq!(
+            sha256.store(b"hello".to_vec(), "txt", None, None),
+            "assets/2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824.txt"
+        );
+    }
+
+    #[test]
+    fn hashes_are_truncated_within_bounds() {
+        let path = |length| store(AssetHashAlgorithm::Sha256, Some(length), AssetNaming::Hash).store(b"hello".to_vec(), "txt", None, None);
+        assert_eq!(path(20), "assets/2cf24dba5fb0a30e26e8.txt");
+        assert_eq!(path(4), "assets/2cf24dba5fb0a30e.txt");
+        assert_eq!(path(100), "assets/2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824.txt");
+        let descriptive = store(AssetHashAlgorithm::Sha256, None, AssetNaming::Descriptive)
+            .store(b"hello".to_vec(), "txt", Some("post"), Some(Path::new("Notes File.txt")));
+        assert_eq!(descriptive, "assets/post-notes-file-2cf24dba5fb0a30e.txt");
+    }
+
+    #[test]
+    fn clashing_names_are_lengthened_and_identical_contents_shared() {
+        let assets = store(AssetHashAlgorithm::Sha256, Some(16), AssetNaming::Hash);
+        let first = assets.store(b"hello".to_vec(), "txt", Some("a"), None);
+        assert_eq!(assets.store(b"hello".to_vec(), "txt", Some("b"), None), first);
+
+        // Another asset whose truncated hash is taken gets more digits, then a counter
+        let hex = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let mut paths = HashSet::from([first.clone()]);
+        let longer = assets.unused_path(&paths, hex, "txt", None, None);
+        assert_eq!(longer, "assets/2cf24dba5fb0a30e2.txt");
+        paths.extend((16..=64).map(|length| format!("assets/{}.txt", &hex[..length])));
+        assert_eq!(assets.unused_path(&paths, hex, "txt", None, None), format!("assets/{}-2.txt", hex));
+    }
+
+    #[test]
+    fn descriptive_names_keep_64_bits_of_the_hash() {
+        let sha256 = AssetHashConfig { algorithm: AssetHashAlgorithm::Sha256, length: None };
+        assert_eq!(AssetNameSettings::new(AssetNaming::Descriptive, &sha256).length, 16);
+        let short = AssetHashConfig { algorithm: AssetHashAlgorithm::Sha256, length: Some(8) };
+        assert_eq!(AssetNameSettings::new(AssetNaming::Descriptive, &short).length, 16);
+    }
+}
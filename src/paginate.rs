@@ -0,0 +1,36 @@
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct Pagination {
+    pub current: usize,
+    pub total: usize,
+    pub url: String,
+    pub prev: Option<String>,
+    pub next: Option<String>
+}
+
+#[derive(Debug)]
+pub struct Page<'a, T> {
+    pub items: &'a [T],
+    pub outpath: String,
+    pub pagination: Pagination
+}
+
+/// Splits `items` into pages of `per_page` items. `url` maps a page number (starting at 1)
+/// to the page's URL relative to the site root; URLs ending in `/`, or empty for the root itself,
+/// are written to `index.html`. The URLs given to templates start with `base_path`.
+pub fn paginate<'a, T>(items: &'a [T], per_page: usize, base_path: &str, url: impl Fn(usize) -> String) -> Vec<Page<'a, T>> {
+    let chunks: Vec<&[T]> = if items.is_empty() { vec![items] } else { items.chunks(per_page.max(1)).collect() };
+    let total = chunks.len();
+    chunks.into_iter().enumerate().map(|(i, items)| {
+        let current = i + 1;
+        let path = url(current);
+        let outpath = if path.is_empty() || path.ends_with('/') { format!("{}index.html", path) } else { path.clone() };
+        Page { items, outpath, pagination: Pagination {
+            current, total,
+            url: format!("{}/{}", base_path, path),
+            prev: (current > 1).then(|| format!("{}/{}", base_path, url(current - 1))),
+            next: (current < total).then(|| format!("{}/{}", base_path, url(current + 1)))
+        }}
+    }).collect()
+}
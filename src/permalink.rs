@@ -0,0 +1,63 @@
+/// Output path pattern of posts unless the config sets `permalink`
+pub const DEFAULT_PATTERN: &str = "posts/{slug}.html";
+
+/// What the tokens of a pattern stand for, for one post
+pub struct Tokens<'a> {
+    /// The day the post is published, for `{year}`, `{month}` and `{day}`
+    pub date: chrono::NaiveDate,
+    pub slug: &'a str,
+    /// Slug of the post's first tag, for `{tags[0]}`
+    pub first_tag: Option<&'a str>
+}
+
+/// The output path a pattern like `{year}/{month}/{slug}/` gives, relative to the output
+/// directory, as `output_path` makes it.
+pub fn expand(pattern: &str, tokens: &Tokens) -> Result<String, String> {
+    use chrono::Datelike;
+    let mut path = String::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        path.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else { return Err(format!("unclosed `{{` in `{}`", pattern)) };
+        let token = &rest[start + 1..start + end];
+        match token {
+            "year" => path.push_str(&format!("{:04}", tokens.date.year())),
+            "month" => path.push_str(&format!("{:02}", tokens.date.month())),
+            "day" => path.push_str(&format!("{:02}", tokens.date.day())),
+            "slug" => path.push_str(tokens.slug),
+            "tags[0]" => path.push_str(tokens.first_tag.ok_or("`{tags[0]}` is used, but the post has no tags")?),
+            _ => return Err(format!("unknown token `{{{}}}` in `{}`, expected `{{year}}`, `{{month}}`, `{{day}}`, `{{slug}}` or `{{tags[0]}}`", token, pattern))
+        }
+        rest = &rest[start + end + 1..];
+    }
+    path.push_str(rest);
+    output_path(&path)
+}
+
+/// The output path a site-relative path like `2019/old-post/` is served from: `index.html` for
+/// paths ending in `/`, and with `.html` added to others that don't end in it.
+pub fn output_path(path: &str) -> Result<String, String> {
+    let mut path = path.trim_start_matches('/').to_string();
+    if path.is_empty() || path.ends_with('/') {
+        path.push_str("index.html");
+    } else if !path.ends_with(".html") {
+        path.push_str(".html");
+    }
+    if path.contains('\\') || path.split('/').any(|segment| matches!(segment, "" | "." | "..")) {
+        return Err(format!("`{}` is not a path inside the output directory", path))
+    }
+    Ok(path)
+}
+
+/// Checks a pattern against made-up tokens, for errors that don't depend on the post.
+pub fn check(pattern: &str) -> Result<(), String> {
+    let date = chrono::NaiveDate::from_ymd_opt(2000, 1, 1).unwrap();
+    expand(pattern, &Tokens { date, slug: "slug", first_tag: Some("tag") }).map(|_| ())
+}
+
+/// The public path of an output path, without the `index.html` of clean URLs.
+pub fn url(output: &str) -> String {
+    let output = output.strip_suffix("index.html").filter(|dir| dir.is_empty() || dir.ends_with('/')).unwrap_or(output);
+    let segments: Vec<_> = output.split('/').map(urlencoding::encode).collect();
+    format!("/{}", segments.join("/"))
+}
@@ -1,24 +1,202 @@
-use std::{collections::VecDeque, io::Read, path::PathBuf};
-use serde::{Serialize, Deserialize};
+use std::{collections::{BTreeMap, HashMap, VecDeque}, io::Read, path::{Path, PathBuf}};
+use serde::{Deserialize, Deserializer, Serialize};
 use pulldown_cmark as cmark;
 use pulldown_latex as latex;
-use crate::SiteBuilder;
+use crate::{SiteBuilder, slug::SlugConfig};
 
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Post {
     pub age: i64,
+    /// Like `age`, for `meta.updated`
+    pub updated_age: Option<i64>,
     pub id: String,
+    /// Public path of the post, like `/posts/hello.html` or `/2024/03/hello/`
+    #[serde(default)]
+    pub url: String,
+    /// Output path of the post, like `posts/hello.html`, given once all posts are built
+    #[serde(skip)]
+    pub output: String,
     pub source: String,
-    pub meta: PostMeta
+    /// The markdown after a thematic break followed by `<!-- colophon -->`, rendered apart from
+    /// `source` for templates to put outside the article
+    pub colophon_html: Option<String>,
+    /// The title rendered as inline markdown
+    pub title_html: String,
+    /// The title with markdown stripped, not HTML-escaped
+    pub title_plain: String,
+    /// The `summary` from the front matter rendered as inline markdown
+    pub summary_html: Option<String>,
+    /// The summary with markdown stripped, not HTML-escaped
+    pub summary_plain: Option<String>,
+    /// Number of words in the body, excluding code blocks and math
+    pub words: usize,
+    pub toc: Vec<crate::toc::TocEntry>,
+    /// Estimated reading time in minutes, the sum of the reading times of its sections
+    pub reading_time: f64,
+    /// The start of the body, up to a `<!--more-->` comment or else its first paragraph
+    pub excerpt: String,
+    /// The excerpt as plain text, cut short for meta descriptions
+    pub excerpt_text: String,
+    /// Plain text for meta descriptions and social cards: the `description` from the front
+    /// matter, else the summary, else `excerpt_text`
+    pub description: String,
+    /// URL of the stored `cover` image, if the post has one
+    pub cover_url: Option<String>,
+    /// When the post is published, as RFC 3339
+    pub published: String,
+    /// `reading_time` rounded up to whole minutes, at least one, for "7 min read"
+    pub reading_minutes: u32,
+    /// Whether the post is time-sensitive and hasn't been modified in a long time
+    pub is_stale: bool,
+    /// URLs of the raster images in the post, in order
+    pub images: Vec<String>,
+    /// Article structured data, serialized and safe to put in a `<script>` tag
+    pub jsonld: Option<String>,
+    /// The encrypted body, if the post has a passphrase. `source` then only holds the form to decrypt it
+    pub encrypted: Option<crate::encrypt::EncryptedContent>,
+    pub meta: PostMeta,
+    #[serde(skip)]
+    pub sections: Vec<Section>,
+    /// Problems found while building the post
+    #[serde(skip)]
+    pub diagnostics: Vec<Diagnostic>,
+    #[serde(skip)]
+    pub file: PathBuf
 }
 
-#[derive(Debug, Serialize)]
+/// A post as listing pages see it, with its body only if asked for
+#[derive(Debug, Clone, Serialize)]
+pub struct PostSummary<'a> {
+    pub age: i64,
+    pub updated_age: Option<i64>,
+    pub id: &'a str,
+    pub url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<&'a str>,
+    pub title_html: &'a str,
+    pub title_plain: &'a str,
+    pub summary_html: Option<&'a str>,
+    pub summary_plain: Option<&'a str>,
+    pub words: usize,
+    pub reading_time: f64,
+    pub reading_minutes: u32,
+    pub excerpt: &'a str,
+    pub excerpt_text: &'a str,
+    pub description: &'a str,
+    pub cover_url: Option<&'a str>,
+    pub published: &'a str,
+    pub is_stale: bool,
+    pub images: &'a [String],
+    pub meta: &'a PostMeta
+}
+
+impl<'a> PostSummary<'a> {
+    pub fn new(post: &'a Post, with_source: bool) -> PostSummary<'a> {
+        PostSummary {
+            age: post.age,
+            updated_age: post.updated_age,
+            id: &post.id,
+            url: &post.url,
+            source: with_source.then_some(post.source.as_str()),
+            title_html: &post.title_html,
+            title_plain: &post.title_plain,
+            summary_html: post.summary_html.as_deref(),
+            summary_plain: post.summary_plain.as_deref(),
+            words: post.words,
+            reading_time: post.reading_time,
+            reading_minutes: post.reading_minutes,
+            excerpt: &post.excerpt,
+            excerpt_text: &post.excerpt_text,
+            description: &post.description,
+            cover_url: post.cover_url.as_deref(),
+            published: &post.published,
+            is_stale: post.is_stale,
+            images: &post.images,
+            meta: &post.meta
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct PostMeta {
     pub title: String,
+    /// Short description of the post, as inline markdown
+    pub summary: Option<String>,
+    /// Plain text description for search engines and social cards
+    pub description: Option<String>,
+    /// Image for social cards, relative to the post's directory, or the one its file is in
+    pub cover: Option<String>,
     pub date: toml_datetime::Datetime,
+    /// When the post was last changed, if it says or `updated_from_mtime` is set
+    pub updated: Option<toml_datetime::Datetime>,
     pub tags: Vec<String>,
-    pub ghcomment: Option<(u32, Vec<String>)>
+    /// The one category the post is filed under, if any
+    pub category: Option<String>,
+    /// Names of the post's authors, the configured `author` if it doesn't name any
+    pub authors: Vec<String>,
+    pub ghcomment: Option<GhComment>,
+    pub hardbreaks: bool,
+    pub collapse_hardbreaks: bool,
+    /// Used for the id instead of the file or directory name
+    pub slug: Option<String>,
+    /// Output path instead of the one the `permalink` pattern gives, which may use its tokens
+    pub path: Option<String>,
+    /// Earlier URLs of the post, like `old-name.html` or `2019/old-path/`, that redirect to it
+    pub aliases: Vec<String>,
+    /// Template to render the post with instead of `post`
+    pub template: Option<String>,
+    /// Language of the post, like `en` or `fr-CA`
+    pub lang: Option<String>,
+    /// Only built with `--drafts`
+    pub draft: bool,
+    /// Rendered, but left out of listings, navigation and the search index
+    pub unlisted: bool,
+    /// Name of the series the post is part of
+    pub series: Option<String>,
+    /// Position of the post in its series, from 1
+    pub series_part: Option<u32>,
+    /// Keys of the `[extra]` table, passed through to templates as they are
+    pub extra: toml::Table,
+    /// Passphrase to encrypt the body with, never given to templates. Only the body is encrypted:
+    /// the images it refers to are still written in plain
+    #[serde(skip)]
+    pub encrypt: Option<String>
+}
+
+/// The GitHub issue whose comments are shown under a post
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GhComment {
+    pub issue: u32,
+    /// Lowercase logins of the authors whose comments are shown
+    pub authors: Vec<String>,
+    /// Link to the issue, for readers to comment on
+    pub url: Option<String>
+}
+
+/// Whether `login` is a possible GitHub username: up to 39 ASCII letters, digits and single
+/// hyphens, not starting or ending with a hyphen.
+pub fn valid_github_login(login: &str) -> bool {
+    !login.is_empty() && login.len() <= 39
+        && login.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        && !login.starts_with('-') && !login.ends_with('-') && !login.contains("--")
+}
+
+/// A problem found while building a post, attributed to it
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    /// `warning` or `error`
+    pub level: &'static str,
+    pub message: String
+}
+
+impl<'de> Deserialize<'de> for Diagnostic {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Owned { level: String, message: String }
+        let Owned { level, message } = Owned::deserialize(deserializer)?;
+        Ok(Diagnostic { level: if level == "error" { "error" } else { "warning" }, message })
+    }
 }
 
 #[derive(Debug)]
@@ -26,108 +204,741 @@ pub struct PostBuilder<'a, 'b> {
     pub site: &'a mut SiteBuilder<'b>,
     pub file: PathBuf,
     pub dir: Option<PathBuf>,
-    pub meta: Option<PostMeta>
+    /// Metadata from `_defaults.toml` files, overridden by the post's own
+    pub defaults: toml::Table,
+    pub meta: Option<PostMeta>,
+    pub images: Vec<String>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Files the post referenced and hashes of their contents, for the post cache
+    pub inputs: BTreeMap<PathBuf, Option<u64>>,
+    /// Assets the post stored, to store them again when it is reused from the cache
+    pub assets: Vec<crate::cache::RecordedAsset>,
+    /// Whether this is a standalone page from `pages/`, which needs no date and whose id only has
+    /// to be unique among pages
+    pub page: bool,
+    /// Whether rendering went wrong in a way `--strict` doesn't publish the post past, like a
+    /// failed processor
+    pub unpublishable: bool
+}
+
+/// `metadata` as TOML for `--debug-metadata`, with the password of an encrypted post left out,
+/// since build logs tend to end up somewhere public.
+pub(crate) fn printable_metadata(metadata: &toml::Table) -> String {
+    let mut metadata = metadata.clone();
+    if let Some(password) = metadata.get_mut("encrypt") {
+        *password = toml::Value::String("<redacted>".into());
+    }
+    metadata.to_string()
 }
 
 impl<'a, 'b> PostBuilder<'a, 'b> {
-    fn resolve_file(&self, path: &str) -> Option<PathBuf> {
-        let dir = self.dir.as_ref()?;
+    /// Prints a warning and records it against the post.
+    fn warn(&mut self, message: String) {
+        log!("warning: {}", message);
+        self.diagnostics.push(Diagnostic { level: "warning", message });
+    }
+
+    /// Prints an error and records it against the post.
+    fn error(&mut self, message: String) {
+        log!("error: {}", message);
+        self.diagnostics.push(Diagnostic { level: "error", message });
+    }
+
+    /// Resolves `path` relative to the post's directory, or the directory of its file for single-file posts.
+    fn resolve_file(&mut self, path: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_deref().or_else(|| self.file.parent())?;
         let dpath = dir.join(path);
+        // Missing files are recorded too, so that adding them invalidates the cached post
+        self.inputs.insert(dpath.clone(), crate::cache::file_hash(&dpath));
         dpath.is_file().then_some(dpath)
     }
 
-    fn get_file_name(&self) -> String {
-        if let Some(dir) = &self.dir {
-            dir.file_name().and_then(|s| s.to_str())
-                .unwrap_or("unnamed-post")
-                .to_string()
-        } else {
-            self.file.file_name().and_then(|s| s.to_str())
-                .unwrap_or("unnamed-post")
-                .trim_end_matches(".md")
-                .to_string()
+    /// Stores an asset owned by the post `owner`, recording it for the post cache.
+    fn store_asset(&mut self, asset: Vec<u8>, ext: &str, owner: &str, source: Option<&Path>) -> String {
+        self.assets.push(crate::cache::RecordedAsset {
+            content: asset.clone(),
+            asset: crate::cache::CachedAsset { ext: ext.to_string(), source: source.map(Path::to_path_buf) }
+        });
+        self.site.store_asset(asset, ext, owner, source)
+    }
+
+    /// Stores the `cover` image of the post `id`, converted like the images in its body, and
+    /// gives its URL.
+    fn store_cover(&mut self, cover: &str, id: &str) -> Option<String> {
+        let Some(path) = self.resolve_file(cover) else {
+            self.error(format!("could not resolve cover image `{}`", cover));
+            return None
+        };
+        if path.extension().and_then(|e| e.to_str()) == Some("svg") {
+            let Ok(svg) = std::fs::read(&path)
+                .inspect_err(|e| self.error(format!("could not read cover image `{}`: {}", path.display(), e)))
+                else { return None };
+            return Some(self.store_asset(svg, "svg", id, Some(&path)))
         }
+        match self.site.images.encode(&path, "", None) {
+            Ok(encoded) => Some(self.store_asset(encoded.data.clone(), encoded.ext, id, Some(&path))),
+            Err(e) => { self.error(e); None }
+        }
+    }
+
+    fn get_file_name(&self) -> String {
+        post_name(&self.file, self.dir.as_deref())
     }
 
-    fn get_default_title(&self) -> String {
-        println!("warning: post does not have a title, using file/directory name");
+    fn get_default_title(&mut self) -> String {
+        self.warn("post does not have a title, using file/directory name".into());
         self.get_file_name()
     }
 
-    fn get_default_date(&self) -> toml_datetime::Datetime {
-        use chrono::{Datelike, Timelike};
-        println!("warning: post does not have a date, using the file creation time");
-        let systime = self.file.metadata()
-            .and_then(|m| m.created())
-            .inspect_err(|e| println!("error: could not get file creation time: {e}"))
-            .unwrap_or(std::time::SystemTime::UNIX_EPOCH);
-        let cdt = chrono::DateTime::<chrono::Local>::from(systime);
-        let date = toml_datetime::Date { year: cdt.year() as u16, month: cdt.month() as u8, day: cdt.day() as u8 };
-        let time = toml_datetime::Time { hour: cdt.hour() as u8, minute: cdt.minute() as u8, second: cdt.second() as u8, nanosecond: cdt.nanosecond() };
-        let offset = if cdt.offset().local_minus_utc() == 0 {
-            toml_datetime::Offset::Z
-        } else {
-            toml_datetime::Offset::Custom { minutes: (cdt.offset().local_minus_utc() / 60) as i16 }
+    fn get_default_date(&mut self) -> toml_datetime::Datetime {
+        if let Some((date, _)) = date_prefix(&source_name(&self.file, self.dir.as_deref())) {
+            log!("info: dating `{}` by its name, {}", self.file.display(), date);
+            return toml_datetime::Datetime { date: Some(date), time: None, offset: None }
+        }
+        let cdt = match crate::source_date_epoch() {
+            Some(epoch) => {
+                if !self.page {
+                    self.warn("post does not have a date, using SOURCE_DATE_EPOCH".into());
+                }
+                epoch
+            },
+            None => {
+                let (source, systime) = file_time(self.file.metadata().map(|m| (m.created(), m.modified())));
+                if !self.page {
+                    self.warn(format!("post does not have a date, using {}", source));
+                }
+                chrono::DateTime::<chrono::Local>::from(systime).fixed_offset()
+            }
         };
-        toml_datetime::Datetime { date: Some(date), time: Some(time), offset: Some(offset) }
+        chrono_to_toml(&cdt)
+    }
+
+    /// The modification time of the post's file, if `updated_from_mtime` asks for it.
+    fn get_default_updated(&self) -> Option<toml_datetime::Datetime> {
+        if !self.site.config.updated_from_mtime { return None }
+        let systime = self.file.metadata()
+            .and_then(|m| m.modified())
+            .inspect_err(|e| log!("error: could not get file modification time: {e}"))
+            .ok()?;
+        Some(chrono_to_toml(&chrono::DateTime::<chrono::Local>::from(systime).fixed_offset()))
     }
 
-    fn default_metadata(&self) -> PostMeta {
+    fn default_metadata(&mut self) -> PostMeta {
         let meta = PostMeta {
             title: self.get_default_title(),
+            summary: None,
+            description: None,
+            cover: None,
             date: self.get_default_date(),
+            updated: self.get_default_updated(),
             tags: Vec::new(),
-            ghcomment: None
+            category: None,
+            authors: self.authors(None, None),
+            ghcomment: None,
+            hardbreaks: self.site.config.hardbreaks,
+            collapse_hardbreaks: self.site.config.collapse_hardbreaks,
+            slug: None,
+            path: None,
+            aliases: Vec::new(),
+            template: None,
+            lang: None,
+            draft: false,
+            unlisted: false,
+            series: None,
+            series_part: None,
+            extra: toml::Table::new(),
+            encrypt: None
         };
-        println!(
+        log!(
             "warning: post does not have metadata, using defaults:\n    title = {:?},\n    date = {},\n    tags = {:?}\n    ghcomment = {:?}", 
             meta.title, meta.date, meta.tags, meta.ghcomment
         );
         meta
     }
 
-    pub fn build(mut self) -> Option<Post> {
-        println!("info: processing post `{}`", self.file.display());
-        let Ok(contents) = std::fs::File::open(&self.file)
-            .inspect_err(|e| println!("error: cannot read post: {e}")) 
-            .and_then(|mut f| { let mut buf = String::new(); f.read_to_string(&mut buf)?; Ok(buf) })
-            else { return None };
-        
-        let opts = cmark::Options::ENABLE_GFM 
-            | cmark::Options::ENABLE_FOOTNOTES 
-            | cmark::Options::ENABLE_STRIKETHROUGH
-            | cmark::Options::ENABLE_SMART_PUNCTUATION
-            | cmark::Options::ENABLE_MATH
-            | cmark::Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS;
-        let parser = cmark::Parser::new_ext(&contents, opts);
+    /// Trims tags and drops empty ones and repeats, keeping the first spelling of tags that are
+    /// the same ignoring case and aliases.
+    fn normalize_tags(&mut self, tags: Vec<String>) -> Vec<String> {
+        let mut kept: Vec<String> = Vec::new();
+        let mut keys: Vec<String> = Vec::new();
+        for tag in tags {
+            let trimmed = tag.trim();
+            if trimmed.is_empty() {
+                self.warn(format!("dropping empty tag {:?}", tag));
+                continue
+            }
+            let aliases = &self.site.config.tag_aliases;
+            let key = crate::lint::resolve_alias(trimmed, aliases).unwrap_or(trimmed).to_lowercase();
+            if let Some(i) = keys.iter().position(|k| *k == key) {
+                self.warn(format!("dropping tag `{}`, the post is already tagged `{}`", trimmed, kept[i]));
+                continue
+            }
+            keys.push(key);
+            kept.push(trimmed.to_string());
+        }
+        kept
+    }
+
+    /// Combines the `authors` and `author` front matter keys, trimming names and dropping empty
+    /// ones and repeats. Posts without either get the configured author, if there is one.
+    fn authors(&mut self, authors: Option<Vec<String>>, author: Option<String>) -> Vec<String> {
+        if authors.is_none() && author.is_none() {
+            return self.site.config.author.iter().map(|a| a.name.clone()).collect()
+        }
+        let mut names: Vec<String> = Vec::new();
+        for name in author.into_iter().chain(authors.unwrap_or_default()) {
+            let trimmed = name.trim();
+            if trimmed.is_empty() {
+                self.warn("dropping empty author name".into());
+            } else if !names.iter().any(|n| n == trimmed) {
+                names.push(trimmed.to_string());
+            }
+        }
+        names
+    }
+
+    /// Combines the `ghcomment*` front matter keys, normalizing the author logins to lowercase
+    /// and dropping the ones that can't be GitHub usernames.
+    fn ghcomment(&mut self, issue: Option<u32>, authors: Option<Vec<String>>, url: Option<String>) -> Option<GhComment> {
+        let Some(issue) = issue else {
+            if authors.is_some() || url.is_some() {
+                self.warn("`ghcommentauthors` and `ghcommenturl` are ignored without `ghcommentid`".into());
+            }
+            return None
+        };
+        let mut logins = Vec::new();
+        for author in authors.unwrap_or_default() {
+            let login = author.trim().trim_start_matches('@').to_lowercase();
+            if !valid_github_login(&login) {
+                self.warn(format!("`{}` in `ghcommentauthors` is not a valid GitHub username, ignoring it", author));
+            } else if !logins.contains(&login) {
+                logins.push(login);
+            }
+        }
+        if logins.is_empty() {
+            self.warn(format!("`ghcommentid` {} has no valid `ghcommentauthors`, no comments will be shown", issue));
+        }
+        Some(GhComment { issue, authors: logins, url })
+    }
+
+    /// Reads the post's front matter, merged over the defaults from `_defaults.toml` files.
+    fn read_metadata(&mut self, contents: &str, opts: cmark::Options) {
+        let front_matter = match front_matter(contents, opts) {
+            Some(table) => table,
+            None if !self.defaults.is_empty() => toml::Table::new(),
+            None => return
+        };
+        let mut merged = self.defaults.clone();
+        crate::defaults::merge(&mut merged, front_matter);
+        if self.site.args.debug_metadata {
+            log!("info: effective metadata of `{}`:\n{}", self.file.display(), printable_metadata(&merged));
+        }
+
+        // Going through the TOML text keeps datetimes intact, which `Value::try_into` doesn't
+        let Ok(meta_raw) = toml::from_str::<'_, PostMetaIncomplete>(&merged.to_string())
+            .inspect_err(|e| {
+                log!("error: could not parse metadata: {}", e);
+            }) else { return };
+
+        if meta_raw.series_part.is_some() && meta_raw.series.is_none() {
+            self.warn("`series_part` is ignored without `series`".into());
+        }
+        let meta = PostMeta {
+            title: meta_raw.title.unwrap_or_else(|| self.get_default_title()),
+            summary: meta_raw.summary,
+            description: meta_raw.description.map(|d| d.split_whitespace().collect::<Vec<_>>().join(" ")).filter(|d| !d.is_empty()),
+            cover: meta_raw.cover,
+            date: meta_raw.date.unwrap_or_else(|| self.get_default_date()),
+            updated: meta_raw.updated.or_else(|| self.get_default_updated()),
+            tags: self.normalize_tags(meta_raw.tags.unwrap_or_default()),
+            category: meta_raw.category.map(|c| c.trim().to_string()).filter(|c| !c.is_empty()),
+            authors: self.authors(meta_raw.authors, meta_raw.author),
+            ghcomment: self.ghcomment(meta_raw.ghcommentid, meta_raw.ghcommentauthors, meta_raw.ghcommenturl),
+            hardbreaks: meta_raw.hardbreaks.unwrap_or(self.site.config.hardbreaks),
+            collapse_hardbreaks: meta_raw.collapse_hardbreaks.unwrap_or(self.site.config.collapse_hardbreaks),
+            slug: meta_raw.slug,
+            path: meta_raw.path,
+            aliases: meta_raw.aliases.unwrap_or_default(),
+            template: meta_raw.template,
+            lang: meta_raw.lang,
+            draft: meta_raw.draft.unwrap_or(false),
+            unlisted: meta_raw.unlisted.unwrap_or(false),
+            series_part: meta_raw.series_part.filter(|_| meta_raw.series.is_some()),
+            series: meta_raw.series,
+            extra: meta_raw.extra,
+            encrypt: meta_raw.encrypt
+        };
+        for key in meta_raw.unknown.keys() {
+            self.warn(format!("unknown metadata key `{}`, put custom keys in an `[extra]` table", key));
+        }
+        log!(
+            "info: got post metadata:\n    title = {:?},\n    date = {},\n    tags = {:?}\n    ghcomment = {:?}", 
+            meta.title, meta.date, meta.tags, meta.ghcomment
+        );
+        self.meta = Some(meta);
+    }
+
+    /// Renders `markdown`, all or part of the post's `contents`, returning the HTML and the sections
+    /// of its text. `id` owns the assets it stores.
+    fn render(&mut self, markdown: &str, contents: &str, id: &str, opts: cmark::Options) -> (String, Vec<Section>) {
+        let (hardbreaks, collapse_hardbreaks) = self.meta.as_ref()
+            .map(|m| (m.hardbreaks, m.collapse_hardbreaks))
+            .unwrap_or_default();
+        let french = self.meta.as_ref()
+            .and_then(|m| m.lang.as_deref())
+            .is_some_and(|lang| lang == "fr" || lang.starts_with("fr-"));
+
+        let slugs = self.site.config.slugs.clone();
+        let figure_row_max = self.site.config.figure_row_max;
+        let typography = self.site.config.typography.clone();
+        let code = self.site.config.code.clone();
+
+        let parser = cmark::Parser::new_ext(markdown, opts);
         let c_im_stream = CodeImageProcessor { 
             iter: cmark::TextMergeStream::new(parser), 
-            post: &mut self,
+            post: self,
+            contents,
+            owner: id,
             highlighter: arborium::Highlighter::new(), 
             buffer: VecDeque::new() 
         };
-        let stream = MathProcessor { iter: c_im_stream, storage: latex::Storage::new() };
+        let fr_stream = FigureRowProcessor { iter: c_im_stream.peekable(), max: figure_row_max, buffer: VecDeque::new() };
+        let cc_stream = CodeComparisonProcessor { iter: fr_stream.peekable(), buffer: VecDeque::new() };
+        let cl_stream = CodeLayoutProcessor { iter: cc_stream, config: &code, buffer: VecDeque::new(), wrapped: false };
+        let br_stream = BreakProcessor { iter: cl_stream, hardbreaks, collapse_hardbreaks };
+        let i_stream = InlineFootnoteProcessor { iter: br_stream, buffer: VecDeque::new(), count: 0, in_code: false };
+        let m_stream = MathProcessor { iter: i_stream, storage: latex::Storage::new() };
+        let t_stream = TypographyProcessor { iter: m_stream, config: typography.as_ref(), french, held: Vec::new(), buffer: VecDeque::new(), skip_depth: 0 };
+        let mut footnote_errors = Vec::new();
+        let f_stream = FootnoteProcessor { iter: t_stream, slugs: &slugs, errors: &mut footnote_errors, output: None };
+        let mut sections = Vec::new();
+        let stream = SectionProcessor { iter: f_stream, slugs: &slugs, sections: &mut sections, buffer: VecDeque::new(), in_code: false, in_footnotes: false };
         let mut buffer = String::new();
         cmark::html::push_html(&mut buffer, stream);
+        for error in footnote_errors {
+            self.error(error);
+        }
+        (buffer, sections)
+    }
+
+    /// Builds the post, leaving what it referenced and stored in `inputs` and `assets`.
+    pub fn build(&mut self) -> Option<Post> {
+        log!("info: processing {} `{}`", if self.page { "page" } else { "post" }, self.file.display());
+        let Ok(contents) = std::fs::File::open(&self.file)
+            .inspect_err(|e| log!("error: cannot read post: {e}")) 
+            .and_then(|mut f| { let mut buf = String::new(); f.read_to_string(&mut buf)?; Ok(buf) })
+            else { return None };
         
-        let id = self.get_file_name();
-        let meta = if let Some(meta) = self.meta { meta } else { self.default_metadata() };
-        let age = crate::dt_toml_to_chrono(&meta.date).signed_duration_since(&chrono::DateTime::UNIX_EPOCH).num_seconds();
+        let opts = POST_OPTIONS;
+        self.read_metadata(&contents, opts);
+        if self.meta.is_none() {
+            self.meta = Some(self.default_metadata());
+        }
+        if self.meta.as_ref().is_some_and(|m| m.draft) && !self.site.args.drafts {
+            log!("info: skipping draft `{}`, build with --drafts to include it", self.file.display());
+            return None
+        }
+        // The id is needed up front to name the assets the post stores
+        let name = self.meta.as_ref().and_then(|m| m.slug.clone()).unwrap_or_else(|| self.get_file_name());
+        let kind = if self.page { "page" } else { "post" };
+        let id = self.site.config.slugs.slugify_or(&name, kind);
+        if let Some(slug) = self.meta.as_ref().and_then(|m| m.slug.as_deref()) && slug != id {
+            log!("warning: `{}` is not a slug, using `{}` for the id", slug, id);
+        }
+        let site = &self.site;
+        let other = match self.page {
+            true => site.pages.iter().find(|p| p.id == id).map(|p| &p.file),
+            false => site.posts.iter().find(|p| p.id == id).map(|p| &p.file)
+                .or_else(|| site.scheduled.iter().find(|p| p.id == id).map(|p| &p.file))
+        };
+        if let Some(other) = other {
+            // Either would overwrite the output of the other
+            log!("error: `{}` has the same id `{}` as `{}`, skipping it", self.file.display(), id, other.display());
+            return None
+        }
+
+        let (body, colophon) = split_colophon(&contents, opts);
+        let (buffer, mut sections) = self.render(body, &contents, &id, opts);
+        // Highlighted code and math are already HTML by the time sections are collected
+        let words = sections.iter().map(|s| s.text.split_whitespace().count()).sum();
+        let (mut toc, reading_time) = crate::toc::table_of_contents(&sections, &self.site.config.reading);
+        // The colophon isn't part of the article, so it is left out of the word count and table of contents
+        let mut colophon_html = colophon.map(|colophon| self.render(colophon, &contents, &id, opts).0);
+        if self.unpublishable { return None }
+
+        let meta = self.meta.take()?;
+        let age = crate::dt_toml_to_chrono(&meta.date).signed_duration_since(chrono::DateTime::UNIX_EPOCH).num_seconds();
+        let updated_age = meta.updated.as_ref()
+            .map(|updated| crate::dt_toml_to_chrono(updated).signed_duration_since(chrono::DateTime::UNIX_EPOCH).num_seconds());
+        if updated_age.is_some_and(|updated_age| updated_age < age) {
+            self.warn(format!("`{}` was updated before it was published", id));
+        }
+        let (title_html, title_plain, blocks) = render_title(&meta.title);
+        if !blocks.is_empty() {
+            self.warn(format!("the title of `{}` can only hold inline markdown, flattening its {}", id, blocks.join(", ")));
+        }
+        let (summary_html, summary_plain) = match &meta.summary {
+            Some(summary) => {
+                let (html, plain, blocks) = render_inline_checked(summary);
+                if !blocks.is_empty() {
+                    self.warn(format!("the summary of `{}` can only hold inline markdown, flattening its {}", id, blocks.join(", ")));
+                }
+                (Some(html), Some(plain))
+            },
+            None => (None, None)
+        };
+
+        // The excerpt would give away an encrypted post
+        let excerpt = if meta.encrypt.is_some() { String::new() } else { excerpt(&buffer).to_string() };
+        let excerpt_text = excerpt_text(&excerpt);
+        let description = meta.description.clone().or_else(|| summary_plain.clone()).unwrap_or_else(|| excerpt_text.clone());
+        let cover_url = meta.cover.as_deref().and_then(|cover| self.store_cover(cover, &id));
+        let published = crate::rfc3339(&self.site.publish_time(&meta.date));
+        let (source, encrypted) = match &meta.encrypt {
+            None => (buffer, None),
+            Some(passphrase) => {
+                // Encrypted with the body rather than given away next to it
+                let buffer = buffer + colophon_html.take().as_deref().unwrap_or_default();
+                let script = self.store_asset(crate::encrypt::DECRYPT_SCRIPT.as_bytes().to_vec(), "js", &id, None);
+                let scope = format!("{}#{}", self.site.config.base_url.as_deref().unwrap_or_default(), id);
+                let Ok(content) = crate::encrypt::encrypt(&buffer, passphrase, &scope, script)
+                    .inspect_err(|e| log!("error: could not encrypt post, skipping it: {}", e))
+                    else { return None };
+                log!("info: encrypted post `{}`", id);
+                if !self.images.is_empty() {
+                    log!("warning: the images of encrypted post `{}` are written unencrypted", id);
+                }
+                // Headings and text would give the content away in the search index and table of contents
+                sections.clear();
+                toc.clear();
+                (crate::encrypt::placeholder(&content), Some(content))
+            }
+        };
 
         Some(Post {
-            source: buffer,
-            meta, id, age
+            source, encrypted, colophon_html,
+            title_html, title_plain, summary_html, summary_plain, words, sections,
+            toc, reading_time,
+            reading_minutes: (reading_time.ceil() as u32).max(1),
+            excerpt, excerpt_text, description, cover_url, published,
+            is_stale: false,
+            images: std::mem::take(&mut self.images),
+            diagnostics: std::mem::take(&mut self.diagnostics),
+            jsonld: None,
+            // Set once every post is built, as the first tag's slug depends on the others
+            url: String::new(),
+            output: String::new(),
+            file: self.file.clone(),
+            meta, id, age, updated_age
         })
     }
 }
 
+/// Longest `excerpt_text`, in characters
+const EXCERPT_TEXT_CHARS: usize = 200;
+
+/// The rendered post `html` up to a `<!--more-->` comment on its own line, or else its first
+/// paragraph. Empty if it has neither.
+fn excerpt(html: &str) -> &str {
+    let marker = ["<!--more-->", "<!-- more -->"].iter()
+        .filter_map(|marker| html.match_indices(marker).find(|&(i, _)| i == 0 || html[..i].ends_with('\n')))
+        .map(|(i, _)| i)
+        .min();
+    if let Some(end) = marker {
+        return html[..end].trim_end()
+    }
+    let Some(start) = html.match_indices("<p>").find(|&(i, _)| i == 0 || html[..i].ends_with('\n')).map(|(i, _)| i)
+        else { return "" };
+    html[start..].find("</p>").map_or("", |end| &html[start..start + end + "</p>".len()])
+}
+
+/// The text of `excerpt`, cut at a word to at most `EXCERPT_TEXT_CHARS` characters.
+fn excerpt_text(excerpt: &str) -> String {
+    let text = crate::html::strip_tags(excerpt);
+    if text.chars().count() <= EXCERPT_TEXT_CHARS { return text }
+    let mut cut = String::new();
+    for word in text.split(' ') {
+        // Leaves room for the ellipsis
+        if cut.chars().count() + word.chars().count() + 1 >= EXCERPT_TEXT_CHARS { break }
+        if !cut.is_empty() { cut.push(' ') }
+        cut.push_str(word);
+    }
+    cut.push('…');
+    cut
+}
+
+/// Splits a post's `contents` at a thematic break followed by `<!-- colophon -->`, returning the
+/// markdown before it and the colophon after it, if there is one. Only a break and comment that
+/// markdown sees as such count, not ones in code blocks.
+fn split_colophon(contents: &str, opts: cmark::Options) -> (&str, Option<&str>) {
+    let mut events = cmark::Parser::new_ext(contents, opts).into_offset_iter().peekable();
+    let mut depth = 0usize;
+    while let Some((event, range)) = events.next() {
+        match event {
+            cmark::Event::Start(_) => depth += 1,
+            cmark::Event::End(_) => depth = depth.saturating_sub(1),
+            cmark::Event::Rule if depth == 0 => {
+                let Some((cmark::Event::Start(cmark::Tag::HtmlBlock), marker)) = events.peek() else { continue };
+                let is_marker = contents[marker.clone()].trim()
+                    .strip_prefix("<!--").and_then(|c| c.strip_suffix("-->"))
+                    .is_some_and(|c| c.trim().eq_ignore_ascii_case("colophon"));
+                if is_marker {
+                    let colophon = &contents[marker.end..];
+                    return (&contents[..range.start], (!colophon.trim().is_empty()).then_some(colophon))
+                }
+            },
+            _ => ()
+        }
+    }
+    (contents, None)
+}
+
+fn front_matter(contents: &str, opts: cmark::Options) -> Option<toml::Table> {
+    let mut parser = cmark::Parser::new_ext(contents, opts);
+    let Some(cmark::Event::Start(cmark::Tag::MetadataBlock(_))) = parser.next() else { return None };
+
+    let mut source = String::new();
+    for ev in parser {
+        match ev {
+            cmark::Event::Text(t) => source.push_str(&t),
+            cmark::Event::End(_) => break,
+            _ => {
+                log!("error: could not parse metadata, found {:?}", ev);
+                return None
+            }
+        }
+    }
+
+    toml::from_str(&source)
+        .inspect_err(|e| log!("error: could not parse metadata: {}", e))
+        .ok()
+}
+
+fn chrono_to_toml(cdt: &chrono::DateTime<chrono::FixedOffset>) -> toml_datetime::Datetime {
+    use chrono::{Datelike, Timelike};
+    let date = toml_datetime::Date { year: cdt.year() as u16, month: cdt.month() as u8, day: cdt.day() as u8 };
+    let time = toml_datetime::Time { hour: cdt.hour() as u8, minute: cdt.minute() as u8, second: cdt.second() as u8, nanosecond: cdt.nanosecond() };
+    let offset = if cdt.offset().local_minus_utc() == 0 {
+        toml_datetime::Offset::Z
+    } else {
+        toml_datetime::Offset::Custom { minutes: (cdt.offset().local_minus_utc() / 60) as i16 }
+    };
+    toml_datetime::Datetime { date: Some(date), time: Some(time), offset: Some(offset) }
+}
+
+/// The `slug` a post sets in its front matter, or in the defaults applied to it.
+pub fn slug_override(file: &Path, defaults: &toml::Table) -> Option<String> {
+    let contents = std::fs::read_to_string(file).ok()?;
+    let slug = front_matter(&contents, POST_OPTIONS)
+        .and_then(|mut table| table.remove("slug"))
+        .or_else(|| defaults.get("slug").cloned())?;
+    slug.as_str().map(str::to_string)
+}
+
+/// The date a post's file or directory name starts with, like `2024-03-01-` or `2024-03-01_`,
+/// and the rest of the name. `None` if it doesn't start with a valid date followed by more.
+pub fn date_prefix(name: &str) -> Option<(toml_datetime::Date, &str)> {
+    let (date, rest) = (name.get(..10)?, name.get(10..)?);
+    let rest = rest.strip_prefix(['-', '_']).filter(|rest| !rest.is_empty())?;
+    let date = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    use chrono::Datelike;
+    Some((toml_datetime::Date { year: date.year() as u16, month: date.month() as u8, day: date.day() as u8 }, rest))
+}
+
+/// The time that dates a file from its creation and modification `times`, and what it is.
+fn file_time(times: std::io::Result<(std::io::Result<std::time::SystemTime>, std::io::Result<std::time::SystemTime>)>) -> (&'static str, std::time::SystemTime) {
+    // Many filesystems, and copies that don't preserve it, have no creation time
+    match times {
+        Ok((Ok(created), _)) => ("the file creation time", created),
+        Ok((Err(_), Ok(modified))) => ("the file modification time, its creation time is unavailable", modified),
+        Ok((Err(e), Err(_))) | Err(e) => {
+            log!("error: could not get file creation or modification time: {e}");
+            ("1970-01-01, its file times are unavailable", std::time::SystemTime::UNIX_EPOCH)
+        }
+    }
+}
+
+/// The name of a post before slugification: its directory name, or its file name without `.md`,
+/// without a leading date.
+pub fn post_name(file: &Path, dir: Option<&Path>) -> String {
+    let name = source_name(file, dir);
+    match date_prefix(&name) {
+        Some((_, rest)) => rest.to_string(),
+        None => name
+    }
+}
+
+/// The directory name of a post, or its file name without `.md`.
+fn source_name(file: &Path, dir: Option<&Path>) -> String {
+    if let Some(dir) = dir {
+        dir.file_name().and_then(|s| s.to_str())
+            .unwrap_or("unnamed-post")
+            .to_string()
+    } else {
+        file.file_name().and_then(|s| s.to_str())
+            .unwrap_or("unnamed-post")
+            .trim_end_matches(".md")
+            .to_string()
+    }
+}
+
+/// Renders a title to HTML and to plain text, as inline markdown even where it starts like a
+/// block, so that `2024. A year` or `# Tag` keep their text. Also returns the kinds of block
+/// elements that had to be flattened anyway, like HTML blocks.
+pub fn render_title(title: &str) -> (String, String, Vec<&'static str>) {
+    render_inline_checked(&escape_block_markers(title))
+}
+
+/// `text` with the markers that would start a block element at the start of its lines escaped.
+fn escape_block_markers(text: &str) -> String {
+    let mut escaped = Vec::new();
+    for line in text.lines() {
+        let line = line.trim_start();
+        let digits = line.bytes().take_while(u8::is_ascii_digit).count();
+        let after = |at: usize| line[at..].chars().next().is_none_or(|c| c == ' ' || c == '\t');
+        let hashes = line.bytes().take_while(|&b| b == b'#').count();
+        let first = line.chars().next();
+        let rule = first.is_some_and(|first| matches!(first, '-' | '*' | '_' | '=')
+            && line.chars().all(|c| c == first || c == ' ' || c == '\t'));
+        let at = if (1..=9).contains(&digits) && matches!(line.as_bytes().get(digits), Some(b'.' | b')')) && after(digits + 1) {
+            Some(digits)
+        } else if (1..=6).contains(&hashes) && after(hashes)
+            || matches!(first, Some('>'))
+            || matches!(first, Some('-' | '+' | '*')) && after(1)
+            || line.starts_with("```") || line.starts_with("~~~")
+            || rule {
+            Some(0)
+        } else {
+            None
+        };
+        escaped.push(match at {
+            Some(at) => format!("{}\\{}", &line[..at], &line[at..]),
+            None => line.to_string()
+        });
+    }
+    escaped.join("\n")
+}
+
+const POST_OPTIONS: cmark::Options = cmark::Options::ENABLE_GFM
+    .union(cmark::Options::ENABLE_FOOTNOTES)
+    .union(cmark::Options::ENABLE_STRIKETHROUGH)
+    .union(cmark::Options::ENABLE_SMART_PUNCTUATION)
+    .union(cmark::Options::ENABLE_MATH)
+    .union(cmark::Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS);
+
+const INLINE_OPTIONS: cmark::Options = cmark::Options::ENABLE_STRIKETHROUGH
+    .union(cmark::Options::ENABLE_SMART_PUNCTUATION)
+    .union(cmark::Options::ENABLE_MATH);
+
+/// Renders a short piece of inline markdown, like a summary, to HTML and to plain text. Block
+/// elements like lists and code blocks are flattened into the surrounding text, and the kinds
+/// of those found are returned too.
+pub fn render_inline_checked(source: &str) -> (String, String, Vec<&'static str>) {
+    let mut blocks = Vec::new();
+    let mut events = Vec::new();
+    for event in cmark::TextMergeStream::new(cmark::Parser::new_ext(source, INLINE_OPTIONS)) {
+        let block = match &event {
+            cmark::Event::Start(tag) => block_kind(tag),
+            cmark::Event::End(end) => if matches!(end,
+                cmark::TagEnd::Emphasis | cmark::TagEnd::Strong | cmark::TagEnd::Strikethrough
+                | cmark::TagEnd::Superscript | cmark::TagEnd::Subscript
+                | cmark::TagEnd::Link | cmark::TagEnd::Image
+            ) { None } else { continue },
+            cmark::Event::Rule => Some("thematic break"),
+            cmark::Event::Html(_) => Some("HTML block"),
+            cmark::Event::DisplayMath(_) => Some("display math"),
+            _ => None
+        };
+        if let Some(kind) = block {
+            if kind != "paragraph" && !blocks.contains(&kind) { blocks.push(kind) }
+            // Keep the words of consecutive blocks apart
+            if !events.is_empty() && !matches!(events.last(), Some(cmark::Event::SoftBreak)) {
+                events.push(cmark::Event::SoftBreak);
+            }
+            match event {
+                cmark::Event::DisplayMath(math) => events.push(cmark::Event::InlineMath(math)),
+                cmark::Event::Html(html) => events.push(cmark::Event::Text(html)),
+                _ => ()
+            }
+            continue
+        }
+        events.push(event);
+    }
+    while matches!(events.last(), Some(cmark::Event::SoftBreak)) {
+        events.pop();
+    }
+    let (html, plain) = render_inline_events(events);
+    // Flattened code blocks and list items leave newlines behind
+    (html, plain.split_whitespace().collect::<Vec<_>>().join(" "), blocks)
+}
+
+/// The kind of block element `tag` starts, `None` for inline elements.
+fn block_kind(tag: &cmark::Tag) -> Option<&'static str> {
+    use cmark::Tag;
+    match tag {
+        Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Superscript | Tag::Subscript
+            | Tag::Link { .. } | Tag::Image { .. } => None,
+        Tag::Paragraph => Some("paragraph"),
+        Tag::Heading { .. } => Some("heading"),
+        Tag::BlockQuote(_) => Some("block quote"),
+        Tag::CodeBlock(_) => Some("code block"),
+        Tag::HtmlBlock => Some("HTML block"),
+        Tag::List(_) | Tag::Item => Some("list"),
+        Tag::FootnoteDefinition(_) => Some("footnote"),
+        Tag::DefinitionList | Tag::DefinitionListTitle | Tag::DefinitionListDefinition => Some("definition list"),
+        Tag::Table(_) | Tag::TableHead | Tag::TableRow | Tag::TableCell => Some("table"),
+        Tag::MetadataBlock(_) => Some("metadata block")
+    }
+}
+
+fn render_inline_events(events: Vec<cmark::Event>) -> (String, String) {
+    let mut plain = String::new();
+    for event in &events {
+        match event {
+            cmark::Event::Text(t) | cmark::Event::Code(t) | cmark::Event::InlineMath(t) | cmark::Event::DisplayMath(t) => plain.push_str(t),
+            cmark::Event::SoftBreak | cmark::Event::HardBreak => plain.push(' '),
+            _ => ()
+        }
+    }
+
+    let mut html = String::new();
+    cmark::html::push_html(&mut html, MathProcessor { iter: events.into_iter(), storage: latex::Storage::new() });
+    (html, plain)
+}
+
 #[derive(Debug, Deserialize)]
 struct PostMetaIncomplete {
     title: Option<String>,
+    summary: Option<String>,
+    description: Option<String>,
+    cover: Option<String>,
     date: Option<toml_datetime::Datetime>,
+    updated: Option<toml_datetime::Datetime>,
     tags: Option<Vec<String>>,
+    category: Option<String>,
+    authors: Option<Vec<String>>,
+    /// A single author, for posts with only one
+    author: Option<String>,
     ghcommentid: Option<u32>,
-    ghcommentauthors: Option<Vec<String>>
+    ghcommentauthors: Option<Vec<String>>,
+    ghcommenturl: Option<String>,
+    hardbreaks: Option<bool>,
+    collapse_hardbreaks: Option<bool>,
+    slug: Option<String>,
+    path: Option<String>,
+    aliases: Option<Vec<String>>,
+    template: Option<String>,
+    lang: Option<String>,
+    draft: Option<bool>,
+    unlisted: Option<bool>,
+    series: Option<String>,
+    series_part: Option<u32>,
+    encrypt: Option<String>,
+    #[serde(default)]
+    extra: toml::Table,
+    /// Keys that aren't any of the above, reported as likely typos
+    #[serde(flatten)]
+    unknown: toml::Table
 }
 
 const WRITE_OPTIONS: svgcleaner::WriteOptions = svgcleaner::WriteOptions {
@@ -184,9 +995,17 @@ const CLEANING_OPTIONS: svgcleaner::CleaningOptions = svgcleaner::CleaningOption
     join_style_attributes: svgcleaner::StyleJoinMode::Some
 };
 
+/// Markers around the figures produced for images, recognized by `FigureRowProcessor`
+const FIGURE_START: &str = "<figure>";
+const FIGURE_END: &str = "</figcaption></figure>";
+
 struct CodeImageProcessor<'a, 'b, 'c, I> {
     iter: I,
     post: &'b mut PostBuilder<'a, 'c>,
+    /// The markdown source of the post, to find code lines in
+    contents: &'b str,
+    /// Id of the post, recorded as the owner of the assets it stores
+    owner: &'b str,
     highlighter: arborium::Highlighter,
     buffer: VecDeque<cmark::Event<'b>>
 }
@@ -195,7 +1014,7 @@ impl<'a, 'b, 'c, I: Iterator<Item=cmark::Event<'b>>> CodeImageProcessor<'a, 'b,
     fn accumulate_plain_text(&mut self, tag: cmark::TagEnd, desc: &str) -> Option<String> {
         let mut text = String::new();
         loop {
-            let Some(ev) = self.iter.next() else { return None; };
+            let ev = self.iter.next()?;
             self.buffer.push_back(ev.clone());
 
             match ev {
@@ -203,7 +1022,7 @@ impl<'a, 'b, 'c, I: Iterator<Item=cmark::Event<'b>>> CodeImageProcessor<'a, 'b,
                 cmark::Event::InlineMath(m) => { text.push('$'); text.push_str(&m); text.push('$'); },
                 cmark::Event::Text(t) => text.push_str(&t),
                 _ => {
-                    println!("error: could not parse {}, found {:?}", desc, ev);
+                    log!("error: could not parse {}, found {:?}", desc, ev);
                     return None
                 }
             }
@@ -211,16 +1030,71 @@ impl<'a, 'b, 'c, I: Iterator<Item=cmark::Event<'b>>> CodeImageProcessor<'a, 'b,
         Some(text)
     }
 
+    /// Warns about lines of the code block `source` longer than `code.max_columns`.
+    fn check_line_lengths(&mut self, source: &str) {
+        let max_columns = self.post.site.config.code.max_columns;
+        for (number, columns) in crate::code::long_lines(source, max_columns) {
+            let Some(line) = source.lines().nth(number - 1) else { continue };
+            // The first occurrence in the post is a good enough guess for repeated lines
+            let file_line = self.contents.find(line).map(|offset| self.contents[..offset].lines().count() + 1);
+            let location = match file_line {
+                Some(file_line) => format!("`{}` line {}", self.post.file.display(), file_line),
+                None => format!("`{}`", self.post.file.display())
+            };
+            self.post.warn(format!("code at {} is {} characters wide, more than `code.max_columns` ({})", location, columns, max_columns));
+        }
+    }
+
+    /// Highlights a diff of `language` code, where lines start with `-`, `+` or a space. The
+    /// markers are taken off before highlighting and put back as their own elements, with removed
+    /// and added lines wrapped in elements of those classes.
+    fn highlight_diff(&mut self, language: &str, source: &str) -> String {
+        let (markers, code): (Vec<char>, Vec<&str>) = source.trim_end().lines()
+            .map(|line| match line.chars().next() {
+                Some(marker @ ('-' | '+' | ' ')) => (marker, &line[1..]),
+                _ => (' ', line)
+            })
+            .unzip();
+        let code = code.join("\n");
+
+        let lines = match self.highlighter.highlight(language, &code) {
+            Ok(html) => crate::html::split_lines(&html),
+            Err(e) => {
+                match e {
+                    arborium::Error::UnsupportedLanguage { language } => log!("warning: syntax highlighting is not supported for {}", language),
+                    e => log!("error: could not highlight code: {}", e)
+                }
+                code.split('\n').map(|line| {
+                    let mut escaped = String::new();
+                    let _ = pulldown_cmark_escape::escape_html_body_text(&mut escaped, line);
+                    escaped
+                }).collect()
+            }
+        };
+
+        markers.iter().zip(lines)
+            .map(|(marker, line)| {
+                let marker_html = format!("<span class=\"diff-marker\" aria-hidden=\"true\">{}</span>", marker);
+                match marker {
+                    '-' => format!("<a-lf></a-lf><span class=\"removed\">{}{}</span>", marker_html, line),
+                    '+' => format!("<a-lf></a-lf><span class=\"added\">{}{}</span>", marker_html, line),
+                    _ => format!("<a-lf></a-lf>{}{}", marker_html, line)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
     fn handle_svg_image(&mut self, path: PathBuf, alt: String, event: cmark::Event<'b>) -> Option<cmark::Event<'b>> {
         let mut source = String::new();
         if let Err(e) = std::fs::File::open(&path)
             .and_then(|mut f| f.read_to_string(&mut source)) {
-            println!("error: could not read image file `{}`: {}", path.display(), e);
+            self.post.error(format!("could not read image file `{}`: {}", path.display(), e));
             return Some(event)
         }
 
         let cleaned = if let Ok(mut document) = svgcleaner::cleaner::parse_data(&source, &Default::default()) {
-            if let None = svgcleaner::cleaner::clean_doc(&mut document, &CLEANING_OPTIONS, &WRITE_OPTIONS)
+            if svgcleaner::cleaner::clean_doc(&mut document, &CLEANING_OPTIONS, &WRITE_OPTIONS)
                 .ok().and_then(|_| {
                     let mut svg = document.svg_element()?;
                     svg.set_attribute_checked(("role", "img")).ok()?;
@@ -228,9 +1102,9 @@ impl<'a, 'b, 'c, I: Iterator<Item=cmark::Event<'b>>> CodeImageProcessor<'a, 'b,
                     title.append(&document.create_node(svgdom::NodeType::Text, &alt));
                     svg.prepend(&title);
                     Some(())
-                }) 
+                }).is_none()
             {
-                println!("warning: svg optimization failed for `{}`", path.display());
+                self.post.warn(format!("svg optimization failed for `{}`", path.display()));
                 source
             } else {
                 let hash = {
@@ -251,35 +1125,43 @@ impl<'a, 'b, 'c, I: Iterator<Item=cmark::Event<'b>>> CodeImageProcessor<'a, 'b,
                 String::from_utf8_lossy(&cleaned).into()
             }
         } else {
-            println!("warning: svg optimization failed for `{}`", path.display());
+            self.post.warn(format!("svg optimization failed for `{}`", path.display()));
             source
         };
 
-        println!("info: inlined svg image `{}`", path.display());
+        log!("info: inlined svg image `{}`", path.display());
         self.buffer.pop_back();
-        self.buffer.push_back(cmark::Event::Html("</figcaption></figure>".into()));
+        self.buffer.push_back(cmark::Event::Html(FIGURE_END.into()));
         self.buffer.push_front(cmark::Event::Html("<figcaption>".into()));
         self.buffer.push_front(cmark::Event::Html(cleaned.into()));
-        Some(cmark::Event::Html("<figure>".into()))
+        Some(cmark::Event::Html(FIGURE_START.into()))
     }
 
-    fn handle_raster_image(&mut self, path: PathBuf, alt: String, event: cmark::Event<'b>) -> Option<cmark::Event<'b>> {
-        let Ok(im) = image::open(&path)
-            .inspect_err(|e| println!("error: could not read image file `{}`: {}", path.display(), e))
-            else { return Some(event); };
-        let mut buffer = Vec::new();
-        let codec = image::codecs::webp::WebPEncoder::new_lossless(&mut buffer);
-        println!("info: transcoding image file `{}`", path.display());
-        let Ok(()) = im.write_with_encoder(codec)
-            .inspect_err(|e| println!("error: could not reencode image file `{}`: {}", path.display(), e))
-            else { return Some(event); };
-        let url = format!("/{}", self.post.site.store_asset(buffer, "webp"));
+    fn handle_raster_image(&mut self, path: PathBuf, alt: String, title: &str, event: cmark::Event<'b>) -> Option<cmark::Event<'b>> {
+        let encoded = match self.post.site.images.encode(&path, title, None) {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                self.post.error(e);
+                return Some(event)
+            }
+        };
+        let url = self.post.store_asset(encoded.data.clone(), encoded.ext, self.owner, Some(&path));
+        self.post.images.push(url.clone());
+        let fallback_url = match self.post.site.images.fallback(&path, title, None) {
+            Some(Ok(fallback)) => Some(self.post.store_asset(fallback.data.clone(), fallback.ext, self.owner, Some(&path))),
+            Some(Err(e)) => { self.post.warn(e); None },
+            None => None
+        };
 
+        let mut alt_escaped = String::new();
+        let _ = pulldown_cmark_escape::escape_html(&mut alt_escaped, &alt);
         self.buffer.pop_back();
-        self.buffer.push_back(cmark::Event::Html("</figcaption></figure>".into()));
+        self.buffer.push_back(cmark::Event::Html(FIGURE_END.into()));
         self.buffer.push_front(cmark::Event::Html("<figcaption>".into()));
-        self.buffer.push_front(cmark::Event::Html(format!("<img src=\"{}\" alt=\"{}\">", url, alt).into()));
-        Some(cmark::Event::Html("<figure>".into()))
+        self.buffer.push_front(cmark::Event::Html(
+            crate::raster::img_markup(&url, fallback_url.as_deref(), encoded.width, encoded.height, &alt_escaped).into()
+        ));
+        Some(cmark::Event::Html(FIGURE_START.into()))
     }
 }
 
@@ -287,75 +1169,275 @@ impl<'a, 'b, 'c, I: Iterator<Item=cmark::Event<'b>>> Iterator for CodeImageProce
     type Item = cmark::Event<'b>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.buffer.len() > 0 { return self.buffer.pop_front() }
-        let Some(event) = self.iter.next() else { return None };
+        if !self.buffer.is_empty() { return self.buffer.pop_front() }
+        let event = self.iter.next()?;
         match &event {
             cmark::Event::Start(cmark::Tag::CodeBlock(cmark::CodeBlockKind::Fenced(language))) => {
                 let Some(source) = self.accumulate_plain_text(cmark::TagEnd::CodeBlock, "code block") 
                     else { return Some(event); };
 
-                match self.highlighter.highlight(&language, source.trim_end()) {
+                self.check_line_lengths(&source);
+                let processor_lang = language.split_whitespace().next().unwrap_or_default();
+                match self.post.site.run_processor(processor_lang, &source) {
+                    Some(Ok(html)) => {
+                        self.buffer.clear();
+                        // No longer a code block for `CodeLayoutProcessor` to see, so wrapped here
+                        if let Some(open) = crate::code::wrapper(language, &self.post.site.config.code) {
+                            self.buffer.push_back(cmark::Event::Html(html.into()));
+                            self.buffer.push_back(cmark::Event::Html("</div>\n".into()));
+                            return Some(cmark::Event::Html(open.into()))
+                        }
+                        return Some(cmark::Event::Html(html.into()))
+                    },
+                    Some(Err(e)) if self.post.site.args.strict => {
+                        self.post.error(format!("processor for `{}` failed, skipping the post: {}", processor_lang, e));
+                        self.post.unpublishable = true;
+                    },
+                    Some(Err(e)) => self.post.error(format!("processor for `{}` failed, highlighting the code instead: {}", processor_lang, e)),
+                    None => ()
+                }
+
+                // Words after the language are flags like `diff` or `before`
+                let mut info = language.split_whitespace();
+                let language = info.next().unwrap_or_default();
+                if info.any(|flag| flag == "diff") {
+                    let html = self.highlight_diff(language, &source);
+                    self.buffer.clear();
+                    self.buffer.push_back(cmark::Event::Html(html.into()));
+                    self.buffer.push_back(cmark::Event::End(cmark::TagEnd::CodeBlock));
+                    return Some(event)
+                }
+
+                match self.highlighter.highlight(language, source.trim_end()) {
                     Ok(html) => {
                         let html = format!("<a-lf></a-lf>{}", html.replace('\n', "\n<a-lf></a-lf>"));
                         self.buffer.clear();
                         self.buffer.push_back(cmark::Event::Html(html.into()));
                         self.buffer.push_back(cmark::Event::End(cmark::TagEnd::CodeBlock));
                     },
-                    Err(arborium::Error::UnsupportedLanguage { language }) => println!("warning: syntax highlighting is not supported for {}", language),
-                    Err(e) => println!("error: could not highlight code: {}", e)
+                    Err(arborium::Error::UnsupportedLanguage { language }) => log!("warning: syntax highlighting is not supported for {}", language),
+                    Err(e) => log!("error: could not highlight code: {}", e)
                 }
 
                 Some(event)
             },
-            cmark::Event::Start(cmark::Tag::Image { dest_url, .. }) => {
+            cmark::Event::Start(cmark::Tag::Image { dest_url, title, .. }) => {
                 let Some(alt) = self.accumulate_plain_text(cmark::TagEnd::Image, "image") 
                     else { return Some(event); };
+                if alt.trim().is_empty() {
+                    self.post.warn(format!("image `{}` has no alt text", dest_url));
+                }
 
-                let Err(url::ParseError::RelativeUrlWithoutBase) = url::Url::parse(&dest_url)
+                let Err(url::ParseError::RelativeUrlWithoutBase) = url::Url::parse(dest_url)
                     .inspect_err(|e| if !matches!(e, url::ParseError::RelativeUrlWithoutBase) { 
-                        println!("error: cannot parse image url `{}`: {}", dest_url, e); 
+                        log!("error: cannot parse image url `{}`: {}", dest_url, e); 
                     }) else { return Some(event) };
                 
-                let Some(path) = self.post.resolve_file(&dest_url) else {
-                    println!("error: could not resolve relative file `{}`", dest_url);
+                let Some(path) = self.post.resolve_file(dest_url) else {
+                    self.post.error(format!("could not resolve relative file `{}`", dest_url));
                     return Some(event)
                 };
 
                 if path.extension().and_then(|e| e.to_str()) == Some("svg") {
                     self.handle_svg_image(path, alt, event)
                 } else {
-                    self.handle_raster_image(path, alt, event)
+                    let title = title.to_string();
+                    self.handle_raster_image(path, alt, &title, event)
                 }
             },
             cmark::Event::Start(cmark::Tag::MetadataBlock(cmark::MetadataBlockKind::PlusesStyle)) => {
-                let Some(source) = self.accumulate_plain_text(cmark::TagEnd::MetadataBlock(cmark::MetadataBlockKind::PlusesStyle), "metadata")
+                // Metadata is read up front by `PostBuilder::read_metadata`
+                let Some(_) = self.accumulate_plain_text(cmark::TagEnd::MetadataBlock(cmark::MetadataBlockKind::PlusesStyle), "metadata")
                     else { return Some(event); };
 
-                let Ok(meta_raw) = toml::from_str::<'_, PostMetaIncomplete>(&source)
-                    .inspect_err(|e| {
-                        println!("error: could not parse metadata: {}", e);
-                    }) else { return Some(event); };
+                self.buffer.clear();
+                self.next()
+            },
+            _ => Some(event)
+        }
+    }
+}
+
+struct FigureRowProcessor<'a, I: Iterator<Item=cmark::Event<'a>>> {
+    iter: std::iter::Peekable<I>,
+    max: usize,
+    buffer: VecDeque<cmark::Event<'a>>
+}
 
-                let meta = PostMeta {
-                    title: meta_raw.title.unwrap_or_else(|| self.post.get_default_title()),
-                    date: meta_raw.date.unwrap_or_else(|| self.post.get_default_date()),
-                    tags: meta_raw.tags.unwrap_or(Vec::new()),
-                    ghcomment: meta_raw.ghcommentid.zip(meta_raw.ghcommentauthors)
-                };
-                println!(
-                    "info: got post metadata:\n    title = {:?},\n    date = {},\n    tags = {:?}\n    ghcomment = {:?}", 
-                    meta.title, meta.date, meta.tags, meta.ghcomment
-                );
-                self.post.meta = Some(meta);
+impl<'a, I: Iterator<Item=cmark::Event<'a>>> FigureRowProcessor<'a, I> {
+    /// Reads the rest of a paragraph, returning its figures if it holds nothing else.
+    fn read_paragraph(&mut self) -> (Vec<cmark::Event<'a>>, Option<Vec<Vec<cmark::Event<'a>>>>) {
+        let mut events = vec![cmark::Event::Start(cmark::Tag::Paragraph)];
+        let mut figures: Vec<Vec<cmark::Event<'a>>> = Vec::new();
+        let (mut in_figure, mut image_only) = (false, true);
+        for event in self.iter.by_ref() {
+            events.push(event.clone());
+            match &event {
+                cmark::Event::End(cmark::TagEnd::Paragraph) => break,
+                cmark::Event::Html(html) if !in_figure && html.as_ref() == FIGURE_START => {
+                    in_figure = true;
+                    figures.push(vec![event]);
+                },
+                _ if in_figure => {
+                    in_figure = !matches!(&event, cmark::Event::Html(html) if html.as_ref() == FIGURE_END);
+                    figures.last_mut().unwrap().push(event);
+                },
+                cmark::Event::SoftBreak => (),
+                cmark::Event::Text(text) if text.trim().is_empty() => (),
+                _ => image_only = false
+            }
+        }
+        let image_only = image_only && !in_figure && !figures.is_empty();
+        (events, image_only.then_some(figures))
+    }
 
-                self.buffer.clear();
-                self.iter.next()
+    fn group(&mut self, first: Vec<cmark::Event<'a>>, mut figures: Vec<Vec<cmark::Event<'a>>>) {
+        let mut paragraphs = 1;
+        let mut rest = Vec::new();
+        while let Some(cmark::Event::Start(cmark::Tag::Paragraph)) = self.iter.peek() {
+            self.iter.next();
+            match self.read_paragraph() {
+                (_, Some(more)) => { figures.extend(more); paragraphs += 1 },
+                (events, None) => { rest = events; break }
+            }
+        }
+
+        if paragraphs == 1 && figures.len() == 1 {
+            self.buffer.extend(first);
+        } else {
+            for row in figures.chunks(self.max) {
+                if let [figure] = row {
+                    self.buffer.push_back(cmark::Event::Start(cmark::Tag::Paragraph));
+                    self.buffer.extend(figure.iter().cloned());
+                    self.buffer.push_back(cmark::Event::End(cmark::TagEnd::Paragraph));
+                } else {
+                    self.buffer.push_back(cmark::Event::Html("<div class=\"figure-row\">".into()));
+                    self.buffer.extend(row.iter().flatten().cloned());
+                    self.buffer.push_back(cmark::Event::Html("</div>\n".into()));
+                }
+            }
+        }
+        self.buffer.extend(rest);
+    }
+}
+
+impl<'a, I: Iterator<Item=cmark::Event<'a>>> Iterator for FigureRowProcessor<'a, I> {
+    type Item = cmark::Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.buffer.is_empty() { return self.buffer.pop_front() }
+        let event = self.iter.next()?;
+        if self.max == 0 || !matches!(event, cmark::Event::Start(cmark::Tag::Paragraph)) {
+            return Some(event)
+        }
+
+        match self.read_paragraph() {
+            (events, Some(figures)) => self.group(events, figures),
+            (events, None) => self.buffer.extend(events)
+        }
+        self.buffer.pop_front()
+    }
+}
+
+/// Places a code block marked `before` and one marked `after` right after it side by side.
+struct CodeComparisonProcessor<'a, I: Iterator<Item=cmark::Event<'a>>> {
+    iter: std::iter::Peekable<I>,
+    buffer: VecDeque<cmark::Event<'a>>
+}
+
+/// Whether `event` starts a fenced code block with `flag` among the words after its language.
+fn is_code_start_with(event: &cmark::Event, flag: &str) -> bool {
+    matches!(event, cmark::Event::Start(cmark::Tag::CodeBlock(cmark::CodeBlockKind::Fenced(info)))
+        if info.split_whitespace().skip(1).any(|f| f == flag))
+}
+
+impl<'a, I: Iterator<Item=cmark::Event<'a>>> CodeComparisonProcessor<'a, I> {
+    /// Reads the rest of the code block started by `start`.
+    fn read_block(&mut self, start: cmark::Event<'a>) -> Vec<cmark::Event<'a>> {
+        let mut events = vec![start];
+        for event in self.iter.by_ref() {
+            let end = matches!(event, cmark::Event::End(cmark::TagEnd::CodeBlock));
+            events.push(event);
+            if end { break }
+        }
+        events
+    }
+}
+
+impl<'a, I: Iterator<Item=cmark::Event<'a>>> Iterator for CodeComparisonProcessor<'a, I> {
+    type Item = cmark::Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.buffer.is_empty() { return self.buffer.pop_front() }
+        let event = self.iter.next()?;
+        if !is_code_start_with(&event, "before") { return Some(event) }
+
+        let before = self.read_block(event);
+        let Some(after) = self.iter.next_if(|e| is_code_start_with(e, "after")) else {
+            log!("warning: code block marked `before` is not followed by one marked `after`");
+            self.buffer.extend(before);
+            return self.buffer.pop_front()
+        };
+        let after = self.read_block(after);
+        self.buffer.push_back(cmark::Event::Html("<figure class=\"code-comparison\"><div class=\"code-before\">".into()));
+        self.buffer.extend(before);
+        self.buffer.push_back(cmark::Event::Html("</div><div class=\"code-after\">".into()));
+        self.buffer.extend(after);
+        self.buffer.push_back(cmark::Event::Html("</div></figure>\n".into()));
+        self.buffer.pop_front()
+    }
+}
+
+/// Puts code blocks that wrap or scroll in an element telling CSS so, see `code::wrapper`.
+struct CodeLayoutProcessor<'a, 'c, I: Iterator<Item=cmark::Event<'a>>> {
+    iter: I,
+    config: &'c crate::code::CodeConfig,
+    buffer: VecDeque<cmark::Event<'a>>,
+    /// Whether the current code block is wrapped and needs the element closed after it
+    wrapped: bool
+}
+
+impl<'a, 'c, I: Iterator<Item=cmark::Event<'a>>> Iterator for CodeLayoutProcessor<'a, 'c, I> {
+    type Item = cmark::Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.buffer.is_empty() { return self.buffer.pop_front() }
+        let event = self.iter.next()?;
+        match &event {
+            cmark::Event::Start(cmark::Tag::CodeBlock(cmark::CodeBlockKind::Fenced(info))) => {
+                let Some(open) = crate::code::wrapper(info, self.config) else { return Some(event) };
+                self.wrapped = true;
+                self.buffer.push_back(event);
+                Some(cmark::Event::Html(open.into()))
+            },
+            cmark::Event::End(cmark::TagEnd::CodeBlock) if self.wrapped => {
+                self.wrapped = false;
+                self.buffer.push_back(cmark::Event::Html("</div>\n".into()));
+                Some(event)
             },
             _ => Some(event)
         }
     }
 }
 
+struct BreakProcessor<I> {
+    iter: I,
+    hardbreaks: bool,
+    collapse_hardbreaks: bool
+}
+
+impl<'a, I: Iterator<Item=cmark::Event<'a>>> Iterator for BreakProcessor<I> {
+    type Item = cmark::Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(match self.iter.next()? {
+            cmark::Event::SoftBreak if self.hardbreaks => cmark::Event::HardBreak,
+            cmark::Event::HardBreak if self.collapse_hardbreaks => cmark::Event::Text(" ".into()),
+            event => event
+        })
+    }
+}
+
 struct MathProcessor<I> {
     iter: I,
     storage: latex::Storage
@@ -365,17 +1447,20 @@ impl<'a, I: Iterator<Item=cmark::Event<'a>>> Iterator for MathProcessor<I> {
     type Item = cmark::Event<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let Some(event) = self.iter.next() else { return None };
+        let event = self.iter.next()?;
         match &event {
             cmark::Event::DisplayMath(math) | cmark::Event::InlineMath(math) => {
-                let parser = latex::Parser::new(&math, &self.storage);
+                let parser = latex::Parser::new(math, &self.storage);
                 let mut buffer = String::new();
                 let mut config = latex::RenderConfig::default();
                 config.display_mode = match event { 
                     cmark::Event::DisplayMath(_) => latex::config::DisplayMode::Block,
                     _ => latex::config::DisplayMode::Inline
                 };
-                config.annotation = Some(&math);
+                // The annotation is copied as is, so it is escaped up front
+                let mut annotation = String::new();
+                let _ = pulldown_cmark_escape::escape_html_body_text(&mut annotation, math);
+                config.annotation = Some(&annotation);
                 let mut found_mathml_error = Ok(());
                 let parser = parser.inspect(|e| {
                     if let Err(e) = e { 
@@ -384,10 +1469,10 @@ impl<'a, I: Iterator<Item=cmark::Event<'a>>> Iterator for MathProcessor<I> {
                 });
                 if let Err(e) = latex::push_mathml(&mut buffer, parser, config)
                     .map_err(|e| e.to_string()).and(found_mathml_error) {
-                    println!("error: cannot render math block: {}", e);
+                    log!("error: cannot render math block: {}", e);
                     self.iter.next()
                 } else {
-                    Some(cmark::Event::Html(buffer.into()))
+                    Some(cmark::Event::Html(crate::html::escape_mathml_text(&buffer).into()))
                 }
             },
             _ => Some(event)
@@ -395,3 +1480,724 @@ impl<'a, I: Iterator<Item=cmark::Event<'a>>> Iterator for MathProcessor<I> {
     }
 }
 
+/// Makes the spaces that shouldn't break a line non-breaking, like the one in `10 GB`, also
+/// where the words on either side are in different events, like in `10 *GB*`.
+struct TypographyProcessor<'a, 'c, I> {
+    iter: I,
+    config: Option<&'c crate::typography::TypographyConfig>,
+    french: bool,
+    /// The last text event, and the inline tags that followed it, held until the next text
+    /// event shows whether the space between them should be non-breaking
+    held: Vec<cmark::Event<'a>>,
+    buffer: VecDeque<cmark::Event<'a>>,
+    /// Depth of code blocks and autolinks, whose text is left alone
+    skip_depth: usize
+}
+
+impl<'a, 'c, I: Iterator<Item=cmark::Event<'a>>> TypographyProcessor<'a, 'c, I> {
+    fn is_inline_tag(event: &cmark::Event) -> bool {
+        use cmark::{Event, Tag, TagEnd};
+        matches!(event,
+            Event::Start(Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Superscript | Tag::Subscript | Tag::Link { .. })
+            | Event::End(TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough | TagEnd::Superscript | TagEnd::Subscript | TagEnd::Link)
+        )
+    }
+
+    /// Makes the space between the held text and `next` non-breaking if the words around it call for one.
+    fn join(&mut self, config: &crate::typography::TypographyConfig, next: &mut String) {
+        let Some(cmark::Event::Text(held)) = self.held.first_mut() else { return };
+        let mut previous = held.to_string();
+        let last = previous.split_whitespace().next_back().unwrap_or_default();
+        if let Some(trimmed) = previous.strip_suffix(' ') && !trimmed.ends_with(char::is_whitespace) {
+            let first = next.split_whitespace().next().unwrap_or_default();
+            if !next.starts_with(char::is_whitespace) && let Some(space) = config.binding_space(last, first, self.french) {
+                previous = format!("{}{}", trimmed, space);
+            }
+        } else if let Some(rest) = next.strip_prefix(' ') && !rest.starts_with(char::is_whitespace) && !previous.ends_with(char::is_whitespace) {
+            let first = rest.split_whitespace().next().unwrap_or_default();
+            if let Some(space) = config.binding_space(last, first, self.french) {
+                *next = format!("{}{}", space, rest);
+            }
+        }
+        *held = previous.into();
+    }
+}
+
+impl<'a, 'c, I: Iterator<Item=cmark::Event<'a>>> Iterator for TypographyProcessor<'a, 'c, I> {
+    type Item = cmark::Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some(config) = self.config else { return self.iter.next() };
+        loop {
+            if let Some(event) = self.buffer.pop_front() { return Some(event) }
+            let Some(event) = self.iter.next() else {
+                self.buffer.extend(self.held.drain(..));
+                return self.buffer.pop_front()
+            };
+
+            match &event {
+                cmark::Event::Start(cmark::Tag::CodeBlock(_) | cmark::Tag::Link { link_type: cmark::LinkType::Autolink | cmark::LinkType::Email, .. }) => {
+                    self.skip_depth += 1;
+                },
+                cmark::Event::End(cmark::TagEnd::CodeBlock) => self.skip_depth = self.skip_depth.saturating_sub(1),
+                cmark::Event::End(cmark::TagEnd::Link) if self.skip_depth > 0 => self.skip_depth -= 1,
+                cmark::Event::Text(text) if self.skip_depth == 0 => {
+                    let mut text = config.apply(text, self.french);
+                    self.join(config, &mut text);
+                    self.buffer.extend(self.held.drain(..));
+                    self.held.push(cmark::Event::Text(text.into()));
+                    continue
+                },
+                _ if !self.held.is_empty() && Self::is_inline_tag(&event) => {
+                    self.held.push(event);
+                    continue
+                },
+                _ => ()
+            }
+            self.buffer.extend(self.held.drain(..));
+            self.buffer.push_back(event);
+        }
+    }
+}
+
+/// Turns `^[inline footnotes]` into footnote references and definitions for `FootnoteProcessor`.
+struct InlineFootnoteProcessor<'a, I> {
+    iter: I,
+    buffer: VecDeque<cmark::Event<'a>>,
+    count: usize,
+    in_code: bool
+}
+
+impl<'a, I: Iterator<Item=cmark::Event<'a>>> InlineFootnoteProcessor<'a, I> {
+    fn is_inline(event: &cmark::Event) -> bool {
+        use cmark::{Event, Tag, TagEnd};
+        matches!(event,
+            Event::Text(_) | Event::Code(_) | Event::InlineMath(_) | Event::InlineHtml(_)
+            | Event::SoftBreak | Event::HardBreak | Event::FootnoteReference(_)
+            | Event::Start(Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link { .. } | Tag::Image { .. } | Tag::Superscript | Tag::Subscript)
+            | Event::End(TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough | TagEnd::Link | TagEnd::Image | TagEnd::Superscript | TagEnd::Subscript)
+        )
+    }
+
+    /// Position of the `]` that brings `depth` to zero, if it is in `text`.
+    fn find_close(text: &str, depth: &mut usize) -> Option<usize> {
+        for (i, c) in text.char_indices() {
+            match c {
+                '[' => *depth += 1,
+                ']' => {
+                    *depth -= 1;
+                    if *depth == 0 { return Some(i) }
+                },
+                _ => ()
+            }
+        }
+        None
+    }
+
+    /// Collects the events of a note whose text starts with `text`, returning them and the text
+    /// following the closing bracket, or all events consumed if the paragraph ends first.
+    fn collect_note(&mut self, mut text: String) -> Result<(Vec<cmark::Event<'a>>, String), Vec<cmark::Event<'a>>> {
+        let mut depth = 1;
+        let mut note = Vec::new();
+        loop {
+            if let Some(end) = Self::find_close(&text, &mut depth) {
+                if end > 0 {
+                    note.push(cmark::Event::Text(text[..end].to_string().into()));
+                }
+                return Ok((note, text[end + 1..].to_string()))
+            }
+            if !text.is_empty() {
+                note.push(cmark::Event::Text(text.into()));
+            }
+
+            text = String::new();
+            match self.iter.next() {
+                Some(cmark::Event::Text(t)) => text = t.into_string(),
+                Some(event) if Self::is_inline(&event) => note.push(event),
+                Some(event) => {
+                    note.push(event);
+                    return Err(note)
+                },
+                None => return Err(note)
+            }
+        }
+    }
+
+    fn split(&mut self, text: cmark::CowStr<'a>) {
+        let mut rest = text.into_string();
+        while let Some(start) = rest.find("^[") {
+            if start > 0 {
+                self.buffer.push_back(cmark::Event::Text(rest[..start].to_string().into()));
+            }
+            match self.collect_note(rest[start + 2..].to_string()) {
+                Ok((note, after)) => {
+                    self.count += 1;
+                    // Markdown can't hold a NUL, so no footnote written in it has this label
+                    let label: cmark::CowStr = format!("\0inline-{}", self.count).into();
+                    self.buffer.push_back(cmark::Event::FootnoteReference(label.clone()));
+                    self.buffer.push_back(cmark::Event::Start(cmark::Tag::FootnoteDefinition(label)));
+                    self.buffer.push_back(cmark::Event::Start(cmark::Tag::Paragraph));
+                    self.buffer.extend(note);
+                    self.buffer.push_back(cmark::Event::End(cmark::TagEnd::Paragraph));
+                    self.buffer.push_back(cmark::Event::End(cmark::TagEnd::FootnoteDefinition));
+                    rest = after;
+                },
+                Err(events) => {
+                    // Unclosed, so it was never a footnote
+                    self.buffer.push_back(cmark::Event::Text("^[".into()));
+                    self.buffer.extend(events);
+                    return
+                }
+            }
+        }
+        if !rest.is_empty() {
+            self.buffer.push_back(cmark::Event::Text(rest.into()));
+        }
+    }
+}
+
+impl<'a, I: Iterator<Item=cmark::Event<'a>>> Iterator for InlineFootnoteProcessor<'a, I> {
+    type Item = cmark::Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.buffer.pop_front() { return Some(event) }
+
+        let event = self.iter.next()?;
+        match event {
+            cmark::Event::Start(cmark::Tag::CodeBlock(_) | cmark::Tag::MetadataBlock(_)) => self.in_code = true,
+            cmark::Event::End(cmark::TagEnd::CodeBlock | cmark::TagEnd::MetadataBlock(_)) => self.in_code = false,
+            cmark::Event::Text(text) if !self.in_code && text.contains("^[") => {
+                self.split(text);
+                return self.buffer.pop_front()
+            },
+            _ => ()
+        }
+        Some(event)
+    }
+}
+
+/// Markers around the footnotes collected at the end of a post, recognized by `SectionProcessor`
+const FOOTNOTES_START: &str = "<section class=\"footnotes\"><ol>\n";
+const FOOTNOTES_END: &str = "</ol></section>\n";
+
+struct FootnoteDefinition<'a> {
+    slug: String,
+    events: Vec<cmark::Event<'a>>
+}
+
+struct FootnoteProcessor<'a, 's, I> {
+    iter: I,
+    slugs: &'s SlugConfig,
+    /// Problems with the footnotes, recorded against the post once it is rendered
+    errors: &'s mut Vec<String>,
+    output: Option<VecDeque<cmark::Event<'a>>>
+}
+
+impl<'a, 's, I: Iterator<Item=cmark::Event<'a>>> FootnoteProcessor<'a, 's, I> {
+    fn process(&mut self) -> VecDeque<cmark::Event<'a>> {
+        let mut output = VecDeque::new();
+        // Footnote slug => (visible number, number of references)
+        let mut numbers: HashMap<String, (usize, usize)> = HashMap::new();
+        // Labels match ignoring case, distinct labels get distinct slugs even if they slugify alike
+        let mut slugs: HashMap<String, String> = HashMap::new();
+        let mut slug_of = |label: &str| {
+            let key = label.to_lowercase();
+            if let Some(slug) = slugs.get(&key) { return slug.clone() }
+            let slug = self.slugs.unique(label, "footnote", |s| slugs.values().any(|taken| taken == s));
+            slugs.insert(key, slug.clone());
+            slug
+        };
+        let mut definitions: Vec<FootnoteDefinition<'a>> = Vec::new();
+        let mut current: Option<FootnoteDefinition<'a>> = None;
+        let mut duplicate = false;
+
+        for mut event in self.iter.by_ref() {
+            match &event {
+                cmark::Event::Start(cmark::Tag::FootnoteDefinition(label)) => {
+                    let slug = slug_of(label);
+                    duplicate = definitions.iter().any(|d| d.slug == slug);
+                    if duplicate {
+                        self.errors.push(format!("duplicate footnote definition `{}`, ignoring it", label));
+                    }
+                    current = Some(FootnoteDefinition { slug, events: Vec::new() });
+                    continue
+                },
+                cmark::Event::End(cmark::TagEnd::FootnoteDefinition) => {
+                    if let Some(definition) = current.take() && !duplicate {
+                        definitions.push(definition);
+                    }
+                    continue
+                },
+                cmark::Event::FootnoteReference(label) => {
+                    let slug = slug_of(label);
+                    let next_number = numbers.len() + 1;
+                    let (number, refs) = numbers.entry(slug.clone()).or_insert((next_number, 0));
+                    *refs += 1;
+                    let ref_id = if *refs == 1 { format!("fnref-{}", slug) } else { format!("fnref-{}_{}", slug, refs) };
+                    event = cmark::Event::InlineHtml(format!(
+                        "<sup class=\"footnote-reference\" id=\"{}\"><a href=\"#fn-{}\">{}</a></sup>", ref_id, slug, number
+                    ).into());
+                },
+                _ => ()
+            }
+
+            if let Some(definition) = &mut current {
+                definition.events.push(event);
+            } else {
+                output.push_back(event);
+            }
+        }
+
+        if definitions.is_empty() { return output }
+
+        // Definitions that are never referenced go last, in document order
+        for definition in &definitions {
+            let next_number = numbers.len() + 1;
+            numbers.entry(definition.slug.clone()).or_insert((next_number, 0));
+        }
+        definitions.sort_by_key(|d| numbers[&d.slug].0);
+
+        output.push_back(cmark::Event::Html(FOOTNOTES_START.into()));
+        for FootnoteDefinition { slug, mut events } in definitions {
+            let (number, refs) = numbers[&slug];
+            let backlinks = (1..=refs).map(|i| {
+                let ref_id = if i == 1 { format!("fnref-{}", slug) } else { format!("fnref-{}_{}", slug, i) };
+                format!(" <a href=\"#{}\" class=\"footnote-backref\">↩</a>", ref_id)
+            }).collect::<String>();
+
+            output.push_back(cmark::Event::Html(format!("<li id=\"fn-{}\" value=\"{}\">", slug, number).into()));
+            let last_paragraph = matches!(events.last(), Some(cmark::Event::End(cmark::TagEnd::Paragraph)));
+            let end = events.pop_if(|_| last_paragraph);
+            output.extend(events);
+            output.push_back(cmark::Event::InlineHtml(backlinks.into()));
+            output.extend(end);
+            output.push_back(cmark::Event::Html("</li>\n".into()));
+        }
+        output.push_back(cmark::Event::Html(FOOTNOTES_END.into()));
+        output
+    }
+}
+
+impl<'a, 's, I: Iterator<Item=cmark::Event<'a>>> Iterator for FootnoteProcessor<'a, 's, I> {
+    type Item = cmark::Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.output.is_none() {
+            self.output = Some(self.process());
+        }
+        self.output.as_mut()?.pop_front()
+    }
+}
+
+/// A run of body text starting at a heading, or at the top of the post.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Section {
+    /// Anchor of the heading that starts the section, `None` for text before the first heading
+    pub anchor: Option<String>,
+    /// Heading level from 1 to 6, 0 for text before the first heading
+    pub level: u8,
+    pub heading: String,
+    /// Plain body text, with code blocks left out
+    pub text: String,
+    pub code_lines: usize
+}
+
+/// Gives headings slugified anchors and collects the plain text of each section.
+struct SectionProcessor<'a, 's, I> {
+    iter: I,
+    slugs: &'s SlugConfig,
+    sections: &'s mut Vec<Section>,
+    buffer: VecDeque<cmark::Event<'a>>,
+    in_code: bool,
+    /// Whether the events are footnotes, which belong to no section
+    in_footnotes: bool
+}
+
+impl<'a, 's, I: Iterator<Item=cmark::Event<'a>>> SectionProcessor<'a, 's, I> {
+    fn anchor_heading(&mut self, start: cmark::Event<'a>) {
+        let mut events = vec![start];
+        let mut heading = String::new();
+        for event in self.iter.by_ref() {
+            match &event {
+                cmark::Event::Text(t) | cmark::Event::Code(t) => heading.push_str(t),
+                cmark::Event::SoftBreak | cmark::Event::HardBreak => heading.push(' '),
+                _ => ()
+            }
+            let end = matches!(event, cmark::Event::End(cmark::TagEnd::Heading(_)));
+            events.push(event);
+            if end { break }
+        }
+
+        let sections = &self.sections;
+        let anchor = self.slugs.unique(&heading, "section", |a| sections.iter().any(|s| s.anchor.as_deref() == Some(a)));
+
+        let mut level = 0;
+        if let cmark::Event::Start(cmark::Tag::Heading { id, level: heading_level, .. }) = &mut events[0] {
+            level = *heading_level as u8;
+            if id.is_none() {
+                *id = Some(anchor.clone().into());
+            }
+        }
+        self.sections.push(Section { anchor: Some(anchor), level, heading, ..Section::default() });
+        self.buffer.extend(events);
+    }
+
+    fn current(&mut self) -> &mut Section {
+        if self.sections.is_empty() {
+            self.sections.push(Section::default());
+        }
+        self.sections.last_mut().unwrap()
+    }
+}
+
+impl<'a, 's, I: Iterator<Item=cmark::Event<'a>>> Iterator for SectionProcessor<'a, 's, I> {
+    type Item = cmark::Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.buffer.pop_front() { return Some(event) }
+
+        let event = self.iter.next()?;
+        match &event {
+            cmark::Event::Html(html) if html.as_ref() == FOOTNOTES_START => self.in_footnotes = true,
+            cmark::Event::Html(html) if html.as_ref() == FOOTNOTES_END => self.in_footnotes = false,
+            _ if self.in_footnotes => (),
+            cmark::Event::Start(cmark::Tag::Heading { .. }) => {
+                self.anchor_heading(event);
+                return self.buffer.pop_front()
+            },
+            cmark::Event::Start(cmark::Tag::CodeBlock(_)) => self.in_code = true,
+            cmark::Event::End(cmark::TagEnd::CodeBlock) => self.in_code = false,
+            cmark::Event::Text(t) if self.in_code => self.current().code_lines += t.lines().count(),
+            // Highlighted code marks each line with an empty `a-lf` element
+            cmark::Event::Html(html) if self.in_code => self.current().code_lines += html.matches("<a-lf>").count(),
+            cmark::Event::Text(t) | cmark::Event::Code(t) => self.current().text.push_str(t),
+            cmark::Event::SoftBreak | cmark::Event::HardBreak
+                | cmark::Event::End(cmark::TagEnd::Paragraph | cmark::TagEnd::Item | cmark::TagEnd::TableCell) => self.current().text.push(' '),
+            _ => ()
+        }
+        Some(event)
+    }
+}
+
+/// A post with the id `id` dated `date`, like `2024-01-01`, and nothing else to it, for tests of
+/// what is computed from built posts.
+#[cfg(test)]
+pub(crate) fn test_post(id: &str, date: &str) -> Post {
+    let date: toml_datetime::Datetime = date.parse().unwrap();
+    Post {
+        age: crate::dt_toml_to_chrono(&date).timestamp(), updated_age: None,
+        id: id.to_string(), url: format!("/posts/{}.html", id), output: format!("posts/{}.html", id),
+        source: String::new(), colophon_html: None,
+        title_html: id.to_uppercase(), title_plain: id.to_uppercase(), summary_html: None, summary_plain: None,
+        words: 0, toc: Vec::new(), reading_time: 0.0, excerpt: String::new(), excerpt_text: String::new(),
+        description: String::new(), cover_url: None, published: date.to_string(), reading_minutes: 1,
+        is_stale: false, images: Vec::new(), jsonld: None, encrypted: None,
+        meta: PostMeta {
+            title: id.to_uppercase(), summary: None, description: None, cover: None, date, updated: None,
+            tags: Vec::new(), category: None, authors: Vec::new(), ghcomment: None,
+            hardbreaks: false, collapse_hardbreaks: false, slug: None, path: None, aliases: Vec::new(),
+            template: None, lang: None, draft: false, unlisted: false, series: None, series_part: None,
+            extra: toml::Table::new(), encrypt: None
+        },
+        sections: Vec::new(), diagnostics: Vec::new(), file: PathBuf::from(format!("posts/{}.md", id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typeset(markdown: &str, french: bool) -> String {
+        let config = crate::typography::TypographyConfig::default();
+        let stream = TypographyProcessor {
+            iter: cmark::Parser::new(markdown), config: Some(&config), french,
+            held: Vec::new(), buffer: VecDeque::new(), skip_depth: 0
+        };
+        let mut html = String::new();
+        cmark::html::push_html(&mut html, stream);
+        html
+    }
+
+    fn footnotes(markdown: &str) -> String {
+        let slugs = SlugConfig::default();
+        let parser = cmark::Parser::new_ext(markdown, cmark::Options::ENABLE_FOOTNOTES);
+        let mut errors = Vec::new();
+        let stream = FootnoteProcessor { iter: InlineFootnoteProcessor { iter: cmark::TextMergeStream::new(parser), buffer: VecDeque::new(), count: 0, in_code: false }, slugs: &slugs, errors: &mut errors, output: None };
+        let mut html = String::new();
+        cmark::html::push_html(&mut html, stream);
+        html
+    }
+
+    #[test]
+    fn footnotes_belong_to_no_section() {
+        let slugs = SlugConfig::default();
+        let parser = cmark::Parser::new_ext("# One\n\nFirst[^a]\n\n# Two\n\nSecond\n\n[^a]: Note text\n", cmark::Options::ENABLE_FOOTNOTES);
+        let mut errors = Vec::new();
+        let stream = FootnoteProcessor { iter: parser, slugs: &slugs, errors: &mut errors, output: None };
+        let mut sections = Vec::new();
+        let stream = SectionProcessor { iter: stream, slugs: &slugs, sections: &mut sections, buffer: VecDeque::new(), in_code: false, in_footnotes: false };
+        let mut html = String::new();
+        cmark::html::push_html(&mut html, stream);
+        assert!(html.contains("Note text"), "{}", html);
+        let texts: Vec<&str> = sections.iter().map(|s| s.text.trim()).collect();
+        assert_eq!(texts, ["First", "Second"]);
+    }
+
+    #[test]
+    fn footnotes_with_the_same_slug_stay_apart() {
+        let html = footnotes("One[^a.b] two[^a-b] three[^A.B]\n\n[^a.b]: First\n\n[^a-b]: Second\n");
+        assert!(html.contains("<li id=\"fn-a-b\" value=\"1\">"), "{}", html);
+        assert!(html.contains("<li id=\"fn-a-b-2\" value=\"2\">"), "{}", html);
+        assert!(html.contains("First"), "{}", html);
+        assert!(html.contains("Second"), "{}", html);
+        // Labels match ignoring case
+        assert!(html.contains("<a href=\"#fn-a-b\">1</a>"), "{}", html);
+        assert!(html.contains("id=\"fnref-a-b_2\"><a href=\"#fn-a-b\">1</a>"), "{}", html);
+        // Slugs never have underscores, so ids of repeated references can't clash with the first
+        assert!(html.contains("id=\"fnref-a-b-2\"><a href=\"#fn-a-b-2\">2</a>"), "{}", html);
+    }
+
+    #[test]
+    fn duplicate_footnote_definitions_are_reported() {
+        let slugs = SlugConfig::default();
+        let parser = cmark::Parser::new_ext("One[^a]\n\n[^a]: First\n\n[^A]: Second\n", cmark::Options::ENABLE_FOOTNOTES);
+        let mut errors = Vec::new();
+        let mut html = String::new();
+        cmark::html::push_html(&mut html, FootnoteProcessor { iter: parser, slugs: &slugs, errors: &mut errors, output: None });
+        assert!(html.contains("First") && !html.contains("Second"), "{}", html);
+        assert_eq!(errors, ["duplicate footnote definition `A`, ignoring it"]);
+    }
+
+    #[test]
+    fn inline_footnotes_stay_apart_from_written_ones() {
+        let html = footnotes("One[^inline-1] two^[Inline]\n\n[^inline-1]: Written\n");
+        assert!(html.contains("<li id=\"fn-inline-1\" value=\"1\">\n<p>Written"), "{}", html);
+        assert!(html.contains("<li id=\"fn-inline-1-2\" value=\"2\">\n<p>Inline"), "{}", html);
+        assert!(!html.contains('\0'), "{}", html);
+    }
+
+    #[test]
+    fn typography_within_text() {
+        assert_eq!(typeset("It takes 10 GB and see fig. 3", false), "<p>It takes 10\u{a0}GB and see fig.\u{a0}3</p>\n");
+        assert_eq!(typeset("Vraiment ?", true), "<p>Vraiment\u{202f}?</p>\n");
+        assert_eq!(typeset("Vraiment ?", false), "<p>Vraiment ?</p>\n");
+    }
+
+    #[test]
+    fn typography_across_events() {
+        assert_eq!(typeset("It takes 10 *GB*", false), "<p>It takes 10\u{a0}<em>GB</em></p>\n");
+        assert_eq!(typeset("It takes **10** GB", false), "<p>It takes <strong>10</strong>\u{a0}GB</p>\n");
+        assert_eq!(typeset("See fig. [3](#f3)", false), "<p>See fig.\u{a0}<a href=\"#f3\">3</a></p>\n");
+        assert_eq!(typeset("Vraiment **?**", true), "<p>Vraiment\u{202f}<strong>?</strong></p>\n");
+        // Only the space between the words binds, not the words of the next event
+        assert_eq!(typeset("10 *GB of data*", false), "<p>10\u{a0}<em>GB of data</em></p>\n");
+    }
+
+    #[test]
+    fn typography_leaves_code_and_urls_alone() {
+        assert_eq!(typeset("`10 GB`", false), "<p><code>10 GB</code></p>\n");
+        assert_eq!(typeset("```\n10 GB\n```", false), "<pre><code>10 GB\n</code></pre>\n");
+        assert_eq!(typeset("<https://example.com/10> GB", false), "<p><a href=\"https://example.com/10\">https://example.com/10</a> GB</p>\n");
+    }
+
+    #[test]
+    fn inline_markdown_to_plain_text() {
+        let (html, plain, blocks) = render_inline_checked("See [the *docs*](https://example.com/?a=1&b=2) for **more**");
+        assert_eq!(html, "See <a href=\"https://example.com/?a=1&amp;b=2\">the <em>docs</em></a> for <strong>more</strong>");
+        assert_eq!(plain, "See the docs for more");
+        assert!(blocks.is_empty());
+
+        // The plain text is unescaped, for templates and feeds to escape where they put it
+        let (html, plain, _) = render_inline_checked("Fish & chips < 5 \"quid\" `a<b>`");
+        assert_eq!(html, "Fish &amp; chips &lt; 5 “quid” <code>a&lt;b&gt;</code>");
+        assert_eq!(plain, "Fish & chips < 5 “quid” a<b>");
+
+        // Inline HTML is kept in the HTML but is no text of its own
+        let (html, plain, _) = render_inline_checked("A <em>real</em> tag");
+        assert_eq!(html, "A <em>real</em> tag");
+        assert_eq!(plain, "A real tag");
+    }
+
+    #[test]
+    fn block_markdown_in_inline_text_is_flattened() {
+        let (html, plain, blocks) = render_inline_checked("Before\n\n```\ncode\n```\n\n- item");
+        assert_eq!(blocks, ["code block", "list"]);
+        assert!(!html.contains("<pre") && !html.contains("<li"), "{}", html);
+        assert_eq!(plain, "Before code item");
+    }
+
+    #[test]
+    fn titles_keep_block_markers_as_text() {
+        for title in ["2024. A year", "1) First", "# Foo", "## Two", "- x", "+ y", "* z", "> Quoted", "***", "___", "```code", "~~~"] {
+            let (html, plain, blocks) = render_title(title);
+            assert_eq!(plain, title, "{}", html);
+            assert!(blocks.is_empty(), "{}: {:?}", title, blocks);
+            assert!(!html.contains("<ol") && !html.contains("<ul") && !html.contains("<h") && !html.contains("<blockquote"), "{}", html);
+        }
+    }
+
+    #[test]
+    fn titles_are_not_thematic_breaks() {
+        let (html, _, blocks) = render_title("---");
+        assert!(blocks.is_empty() && !html.contains("<hr"), "{}", html);
+    }
+
+    #[test]
+    fn titles_keep_inline_markdown() {
+        let (html, plain, _) = render_title("*Emphasis* and `code`");
+        assert_eq!(html, "<em>Emphasis</em> and <code>code</code>");
+        assert_eq!(plain, "Emphasis and code");
+        let (_, plain, _) = render_title("#hashtag 2024 - the year");
+        assert_eq!(plain, "#hashtag 2024 - the year");
+        let (_, plain, _) = render_title("2024.5 releases");
+        assert_eq!(plain, "2024.5 releases");
+    }
+
+    #[test]
+    fn titles_report_what_is_flattened() {
+        let (_, plain, blocks) = render_title("<div>Boxed</div>");
+        assert_eq!(blocks, ["HTML block"]);
+        assert!(plain.contains("Boxed"));
+    }
+
+    #[test]
+    fn date_prefixes_must_be_valid_dates_followed_by_a_name() {
+        let date = |year, month, day| toml_datetime::Date { year, month, day };
+        assert_eq!(date_prefix("2024-03-01-foo"), Some((date(2024, 3, 1), "foo")));
+        assert_eq!(date_prefix("2024-03-01_foo-bar"), Some((date(2024, 3, 1), "foo-bar")));
+        assert_eq!(date_prefix("2024-02-30-foo"), None);
+        assert_eq!(date_prefix("2024-13-01-foo"), None);
+        assert_eq!(date_prefix("2024-3-01-foo"), None);
+        assert_eq!(date_prefix("2024-03-01"), None);
+        assert_eq!(date_prefix("2024-03-01-"), None);
+        assert_eq!(date_prefix("2024-03-01foo"), None);
+        assert_eq!(post_name(Path::new("posts/2024-03-01-foo.md"), None), "foo");
+        assert_eq!(post_name(Path::new("posts/2024-03-01_foo/index.md"), Some(Path::new("posts/2024-03-01_foo"))), "foo");
+        assert_eq!(post_name(Path::new("posts/2024-02-30-foo.md"), None), "2024-02-30-foo");
+    }
+
+    #[test]
+    fn file_times_fall_back_to_modification() {
+        let unsupported = || Err(std::io::Error::from(std::io::ErrorKind::Unsupported));
+        let (created, modified) = (std::time::UNIX_EPOCH + std::time::Duration::from_secs(100), std::time::UNIX_EPOCH + std::time::Duration::from_secs(200));
+        assert_eq!(file_time(Ok((Ok(created), Ok(modified)))), ("the file creation time", created));
+        assert_eq!(file_time(Ok((unsupported(), Ok(modified)))).1, modified);
+        assert_eq!(file_time(Ok((unsupported(), unsupported()))).1, std::time::UNIX_EPOCH);
+        assert_eq!(file_time(Err(std::io::ErrorKind::NotFound.into())).1, std::time::UNIX_EPOCH);
+    }
+
+    fn breaks(markdown: &str, hardbreaks: bool, collapse_hardbreaks: bool) -> String {
+        let parser = cmark::Parser::new_ext(markdown, POST_OPTIONS);
+        let mut html = String::new();
+        cmark::html::push_html(&mut html, BreakProcessor { iter: parser, hardbreaks, collapse_hardbreaks });
+        html
+    }
+
+    #[test]
+    fn soft_breaks_become_hard() {
+        let poem = "Roses are red\nviolets are blue";
+        assert_eq!(breaks(poem, false, false), "<p>Roses are red\nviolets are blue</p>\n");
+        assert_eq!(breaks(poem, true, false), "<p>Roses are red<br />\nviolets are blue</p>\n");
+        assert_eq!(breaks("- one\n  two\n- three", true, false), "<ul>\n<li>one<br />\ntwo</li>\n<li>three</li>\n</ul>\n");
+        assert_eq!(breaks("> one\n> two", true, false), "<blockquote>\n<p>one<br />\ntwo</p>\n</blockquote>\n");
+        // Breaks in code stay as they are
+        assert_eq!(breaks("```\none\ntwo\n```", true, false), breaks("```\none\ntwo\n```", false, false));
+    }
+
+    #[test]
+    fn hard_breaks_collapse_to_spaces() {
+        let address = "Main Street 1\\\nHelsinki  \nFinland";
+        assert_eq!(breaks(address, false, false), "<p>Main Street 1<br />\nHelsinki<br />\nFinland</p>\n");
+        assert_eq!(breaks(address, false, true), "<p>Main Street 1 Helsinki Finland</p>\n");
+        assert_eq!(breaks("- one\\\n  two", false, true), "<ul>\n<li>one two</li>\n</ul>\n");
+        // Both at once: soft breaks become hard ones, written hard breaks spaces
+        assert_eq!(breaks("a\nb\\\nc", true, true), "<p>a<br />\nb c</p>\n");
+    }
+
+    #[test]
+    fn titles_escape_code_comparisons_and_math() {
+        let (html, plain, _) = render_title("Faster `HashMap<K, V>` lookups");
+        assert_eq!(html, "Faster <code>HashMap&lt;K, V&gt;</code> lookups");
+        assert_eq!(plain, "Faster HashMap<K, V> lookups");
+        let (html, plain, _) = render_title("When a < b & c > d");
+        assert_eq!(html, "When a &lt; b &amp; c &gt; d");
+        assert_eq!(plain, "When a < b & c > d");
+        let (html, plain, _) = render_title("Why $x < y$ holds");
+        assert_eq!(html, concat!(
+            "Why <math display=\"inline\"><semantics><mrow><mi>x</mi><mo>&lt;</mo><mi>y</mi></mrow>",
+            "<annotation encoding=\"application/x-tex\">x &lt; y</annotation></semantics></math> holds"
+        ));
+        assert_eq!(plain, "Why x < y holds");
+        let (html, _, _) = render_title("$a \\text{ & } b > c$");
+        assert!(html.contains("<mtext>&nbsp;&amp;&nbsp;</mtext>") && html.contains("<mo>&gt;</mo>"), "{}", html);
+    }
+
+    #[test]
+    fn github_logins() {
+        for login in ["a", "octocat", "Octo-Cat", "a1-b2-c3", &"x".repeat(39)] {
+            assert!(valid_github_login(login), "{}", login);
+        }
+        for login in ["", "-octocat", "octocat-", "octo--cat", "octo_cat", "octo cat", "@octocat", "öctocat", &"x".repeat(40)] {
+            assert!(!valid_github_login(login), "{}", login);
+        }
+    }
+
+    #[test]
+    fn slug_overrides_come_from_front_matter_then_defaults() {
+        let dir = crate::test_dir("slug-override");
+        let (own, bare) = (dir.join("own.md"), dir.join("bare.md"));
+        std::fs::write(&own, "+++\nslug = \"mine\"\n+++\nText").unwrap();
+        std::fs::write(&bare, "+++\ntitle = \"Bare\"\n+++\nText").unwrap();
+        let defaults: toml::Table = toml::from_str("slug = \"shared\"").unwrap();
+        assert_eq!(slug_override(&own, &defaults).as_deref(), Some("mine"));
+        assert_eq!(slug_override(&bare, &defaults).as_deref(), Some("shared"));
+        assert_eq!(slug_override(&bare, &toml::Table::new()), None);
+        assert_eq!(slug_override(&dir.join("missing.md"), &defaults), None);
+    }
+
+    /// The tags `normalize_tags` keeps of `tags`, and what it warned about
+    fn normalize_tags(tags: &[&str], aliases: &[(&str, &str)]) -> (Vec<String>, Vec<String>) {
+        use clap::Parser;
+        // Nothing is read or written there
+        let dir = std::env::temp_dir();
+        let args = crate::Args::parse_from(["static-site-gen", dir.to_str().unwrap(), dir.to_str().unwrap()]);
+        let sink = crate::output::MemorySink::default();
+        let config = crate::config::SiteConfig {
+            tag_aliases: aliases.iter().map(|(alias, tag)| (alias.to_string(), tag.to_string())).collect(),
+            ..Default::default()
+        };
+        let mut site = SiteBuilder::new(&args, &sink, config);
+        let mut builder = PostBuilder {
+            site: &mut site, file: dir.join("post.md"), dir: None, defaults: toml::Table::new(), meta: None, images: Vec::new(),
+            diagnostics: Vec::new(), inputs: BTreeMap::new(), assets: Vec::new(), page: false, unpublishable: false
+        };
+        let kept = builder.normalize_tags(tags.iter().map(|t| t.to_string()).collect());
+        (kept, builder.diagnostics.into_iter().map(|d| d.message).collect())
+    }
+
+    #[test]
+    fn tags_are_trimmed_and_empty_ones_dropped() {
+        let (kept, warnings) = normalize_tags(&["  rust ", "", "   ", "\tweb"], &[]);
+        assert_eq!(kept, ["rust", "web"]);
+        assert_eq!(warnings, ["dropping empty tag \"\"", "dropping empty tag \"   \""]);
+    }
+
+    #[test]
+    fn tags_differing_in_case_or_by_alias_are_one_tag() {
+        let (kept, warnings) = normalize_tags(&["Rust", "rust", "RUST", "rustlang", "Go"], &[("rustlang", "Rust")]);
+        assert_eq!(kept, ["Rust", "Go"]);
+        assert_eq!(warnings, [
+            "dropping tag `rust`, the post is already tagged `Rust`",
+            "dropping tag `RUST`, the post is already tagged `Rust`",
+            "dropping tag `rustlang`, the post is already tagged `Rust`"
+        ]);
+    }
+
+    #[test]
+    fn tags_keep_the_order_they_are_first_given_in() {
+        let (kept, warnings) = normalize_tags(&["zig", "Ada", "c", "ada", "Zig", "b"], &[]);
+        assert_eq!(kept, ["zig", "Ada", "c", "b"]);
+        assert_eq!(warnings.len(), 2);
+        assert!(normalize_tags(&[], &[]).0.is_empty());
+    }
+}
@@ -0,0 +1,76 @@
+use std::{io::{Read, Write}, path::Path, process::{Command, Stdio}, time::{Duration, Instant}};
+use crate::config::ProcessorConfig;
+
+fn cache_key(processor: &ProcessorConfig, input: &str) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = crate::cache::stable_hasher();
+    processor.command.hash(&mut hasher);
+    input.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn spawn(processor: &ProcessorConfig, input: &str) -> Result<String, String> {
+    let Some((program, args)) = processor.command.split_first()
+        else { return Err("no command given".into()) };
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not start `{}`: {}", program, e))?;
+
+    // Reading and writing happens on separate threads so a chatty command can't deadlock on a full pipe
+    let mut stdin = child.stdin.take();
+    let input = input.to_string();
+    let writer = std::thread::spawn(move || stdin.as_mut().map(|s| s.write_all(input.as_bytes())));
+    let mut stdout = child.stdout.take();
+    let reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        stdout.as_mut().map(|s| s.read_to_end(&mut buf)).map(|_| buf)
+    });
+    let mut stderr = child.stderr.take();
+    let err_reader = std::thread::spawn(move || {
+        let mut buf = String::new();
+        stderr.as_mut().map(|s| s.read_to_string(&mut buf)).map(|_| buf)
+    });
+
+    let start = Instant::now();
+    let timeout = Duration::from_secs(processor.timeout);
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if start.elapsed() > timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("`{}` timed out after {}s", program, processor.timeout))
+            },
+            Ok(None) => std::thread::sleep(Duration::from_millis(10)),
+            Err(e) => return Err(format!("could not wait for `{}`: {}", program, e))
+        }
+    };
+
+    let _ = writer.join();
+    let stdout = reader.join().ok().flatten().unwrap_or_default();
+    let stderr = err_reader.join().ok().flatten().unwrap_or_default();
+    if !status.success() {
+        return Err(format!("`{}` exited with {}: {}", program, status, stderr.trim()))
+    }
+    String::from_utf8(stdout).map_err(|e| format!("`{}` produced invalid utf-8: {}", program, e))
+}
+
+pub fn run(processor: &ProcessorConfig, input: &str, cache_dir: &Path) -> Result<String, String> {
+    let cached = cache_dir.join(format!("{:016x}.html", cache_key(processor, input)));
+    if let Ok(output) = std::fs::read_to_string(&cached) {
+        log!("info: using cached output of processor for `{}`", processor.language);
+        return Ok(output)
+    }
+
+    log!("info: running processor for `{}`", processor.language);
+    let output = spawn(processor, input)?;
+    if let Err(e) = std::fs::create_dir_all(cache_dir).and_then(|_| std::fs::write(&cached, &output)) {
+        log!("warning: could not cache processor output `{}`: {}", cached.display(), e);
+    }
+    Ok(output)
+}
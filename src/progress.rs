@@ -0,0 +1,129 @@
+use std::{io::Write, sync::Mutex, time::{Duration, Instant}};
+
+/// Shortest time between redraws of the progress line
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Longest `current` item shown, so the line fits on one row of most terminals
+const MAX_CURRENT_CHARS: usize = 40;
+
+/// A step of the build shown on the progress line
+#[derive(Debug)]
+struct Phase {
+    name: &'static str,
+    total: Option<usize>,
+    done: usize,
+    current: String,
+    started: Instant
+}
+
+#[derive(Debug)]
+struct State {
+    enabled: bool,
+    phase: Option<Phase>,
+    images: usize,
+    /// Whether the progress line is on screen and has to be cleared before printing
+    drawn: bool,
+    last_draw: Option<Instant>
+}
+
+static STATE: Mutex<State> = Mutex::new(State { enabled: false, phase: None, images: 0, drawn: false, last_draw: None });
+
+fn state() -> std::sync::MutexGuard<'static, State> {
+    // Output is still worth printing if another thread panicked while holding the lock
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl State {
+    fn line(&self) -> Option<String> {
+        let phase = self.phase.as_ref()?;
+        let mut line = match phase.total {
+            Some(total) => format!("{} {}/{}", phase.name, phase.done, total),
+            None => format!("{} {}", phase.name, phase.done)
+        };
+        if !phase.current.is_empty() {
+            let mut current: String = phase.current.chars().take(MAX_CURRENT_CHARS).collect();
+            if current.len() < phase.current.len() { current.push('…') }
+            line.push_str(&format!(" (current: {})", current));
+        }
+        if self.images > 0 {
+            line.push_str(&format!(", transcoded {} images", self.images));
+        }
+        Some(line)
+    }
+
+    fn clear(&mut self, out: &mut impl Write) {
+        if self.drawn {
+            let _ = write!(out, "\r\x1b[2K");
+            self.drawn = false;
+        }
+    }
+
+    fn draw(&mut self, out: &mut impl Write) {
+        let Some(line) = self.line() else { return };
+        self.clear(out);
+        let _ = write!(out, "{}", line);
+        let _ = out.flush();
+        self.drawn = true;
+        self.last_draw = Some(Instant::now());
+    }
+
+    /// Redraws the line if it hasn't been redrawn recently.
+    fn tick(&mut self) {
+        if !self.enabled || self.last_draw.is_some_and(|at| at.elapsed() < REDRAW_INTERVAL) { return }
+        self.draw(&mut std::io::stdout().lock());
+    }
+}
+
+/// Turns the progress line on or off for the following phases.
+pub fn enable(enabled: bool) {
+    state().enabled = enabled;
+}
+
+/// Runs `print` with the progress line cleared, then draws the line again. While a phase is
+/// shown, `print` is skipped if `hideable`, for the lines a progress line replaces.
+pub fn print_above(hideable: bool, print: impl FnOnce()) {
+    let mut state = state();
+    let showing = state.enabled && state.phase.is_some();
+    if hideable && showing { return }
+    let mut out = std::io::stdout().lock();
+    state.clear(&mut out);
+    let _ = out.flush();
+    print();
+    if showing {
+        state.draw(&mut out);
+    }
+}
+
+/// Starts showing `name` on the progress line, with `total` items to go through if known.
+pub fn begin(name: &'static str, total: Option<usize>) {
+    let mut state = state();
+    state.phase = Some(Phase { name, total, done: 0, current: String::new(), started: Instant::now() });
+    state.images = 0;
+    state.last_draw = None;
+    state.tick();
+}
+
+/// Marks the start of the next item of the current phase.
+pub fn step(current: &str) {
+    let mut state = state();
+    let Some(phase) = &mut state.phase else { return };
+    phase.done += 1;
+    phase.current = current.to_string();
+    state.tick();
+}
+
+/// Counts an image transcoded during the current phase.
+pub fn image_transcoded() {
+    let mut state = state();
+    if state.phase.is_none() { return }
+    state.images += 1;
+    state.tick();
+}
+
+/// Stops showing the current phase, returning its name, how many items it went through and how long it took.
+pub fn end() -> Option<(&'static str, usize, Duration)> {
+    let mut state = state();
+    let phase = state.phase.take()?;
+    state.clear(&mut std::io::stdout().lock());
+    Some((phase.name, phase.done, phase.started.elapsed()))
+}
@@ -0,0 +1,511 @@
+use std::{collections::HashMap, io::Cursor, path::{Path, PathBuf}, sync::{Arc, Condvar, Mutex}};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    Passthrough,
+    WebpLossless,
+    Jpeg,
+    Png
+}
+
+impl OutputFormat {
+    fn parse(s: &str) -> Option<OutputFormat> {
+        OutputFormat::deserialize(serde::de::value::StrDeserializer::<serde::de::value::Error>::new(s)).ok()
+    }
+
+    fn mime(self, ext: &str) -> &'static str {
+        match (self, ext) {
+            (OutputFormat::WebpLossless, _) | (_, "webp") => "image/webp",
+            (OutputFormat::Jpeg, _) | (_, "jpg" | "jpeg") => "image/jpeg",
+            (_, "gif") => "image/gif",
+            _ => "image/png"
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Passthrough => "passthrough",
+            OutputFormat::WebpLossless => "webp-lossless",
+            OutputFormat::Jpeg => "jpeg",
+            OutputFormat::Png => "png"
+        }
+    }
+}
+
+/// What kind of image a source file is, used to pick its output format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageKind {
+    Animation,
+    Tiny,
+    Photo,
+    Graphic
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImageConfig {
+    /// JPEG quality used when re-encoding, from 1 to 100
+    pub quality: u8,
+    /// Images with at most this many pixels are not re-encoded
+    pub tiny_pixels: u64,
+    pub animation: OutputFormat,
+    pub tiny: OutputFormat,
+    /// JPEG by default rather than WebP: `image` can only encode lossless WebP, which makes photos
+    /// several times larger than a JPEG at `quality`. Set to `webp-lossless` to get WebP anyway
+    pub photo: OutputFormat,
+    pub graphic: OutputFormat,
+    /// Also store a PNG copy of every WebP image for readers that can't show WebP
+    pub webp_fallback: bool
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        ImageConfig {
+            quality: 80,
+            tiny_pixels: 64 * 64,
+            animation: OutputFormat::Passthrough,
+            tiny: OutputFormat::Passthrough,
+            photo: OutputFormat::Jpeg,
+            graphic: OutputFormat::WebpLossless,
+            webp_fallback: false
+        }
+    }
+}
+
+impl ImageConfig {
+    fn format_for(&self, kind: ImageKind) -> OutputFormat {
+        match kind {
+            ImageKind::Animation => self.animation,
+            ImageKind::Tiny => self.tiny,
+            ImageKind::Photo => self.photo,
+            ImageKind::Graphic => self.graphic
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct EncodedImage {
+    pub data: Vec<u8>,
+    pub ext: &'static str,
+    pub format: OutputFormat,
+    pub width: u32,
+    pub height: u32
+}
+
+/// Counts the frames of a GIF up to `max` by walking its blocks, without decoding any of them.
+/// Returns `None` if the data ends early or has an unknown block.
+fn gif_frames(data: &[u8], max: usize) -> Option<usize> {
+    // Skips a chain of sub-blocks starting at `pos`, returning the position after the terminator
+    fn skip_sub_blocks(data: &[u8], mut pos: usize) -> Option<usize> {
+        loop {
+            let len = *data.get(pos)? as usize;
+            pos += 1 + len;
+            if len == 0 { return Some(pos) }
+        }
+    }
+    // Size of the color table following a packed field byte, if it has one
+    let table = |packed: u8| if packed & 0x80 != 0 { 3 << ((packed & 0x07) + 1) } else { 0 };
+
+    let mut pos = 13 + table(*data.get(10)?);
+    let mut frames = 0;
+    while frames < max {
+        match *data.get(pos)? {
+            0x21 => pos = skip_sub_blocks(data, pos + 2)?,
+            0x2C => {
+                frames += 1;
+                pos = skip_sub_blocks(data, pos + 10 + table(*data.get(pos + 9)?) + 1)?;
+            },
+            0x3B => break,
+            _ => return None
+        }
+    }
+    Some(frames)
+}
+
+fn is_animated(data: &[u8], format: image::ImageFormat) -> bool {
+    match format {
+        image::ImageFormat::Gif => gif_frames(data, 2).is_some_and(|frames| frames > 1),
+        image::ImageFormat::Png => image::codecs::png::PngDecoder::new(Cursor::new(data))
+            .and_then(|d| d.is_apng())
+            .unwrap_or(false),
+        image::ImageFormat::WebP => image::codecs::webp::WebPDecoder::new(Cursor::new(data))
+            .map(|d| d.has_animation())
+            .unwrap_or(false),
+        _ => false
+    }
+}
+
+/// Picks an output format for the image from a `format=...` token in its title,
+/// falling back to the configured format for its kind.
+pub fn choose_format(config: &ImageConfig, kind: ImageKind, title: &str) -> OutputFormat {
+    let requested = title.split_whitespace()
+        .find_map(|token| token.strip_prefix("format="));
+    if let Some(requested) = requested {
+        match OutputFormat::parse(requested) {
+            Some(format) => return format,
+            None => log!("warning: unknown image format `{}` requested in title, using defaults", requested)
+        }
+    }
+    config.format_for(kind)
+}
+
+/// Limits how many images are decoded and encoded at once, since a decoded photo can take hundreds of MiB.
+#[derive(Debug)]
+pub struct ImageSlots {
+    limit: usize,
+    in_use: Mutex<usize>,
+    freed: Condvar
+}
+
+/// A taken slot, given back when dropped
+pub struct ImageSlot<'a>(&'a ImageSlots);
+
+impl Drop for ImageSlot<'_> {
+    fn drop(&mut self) {
+        *self.0.in_use.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// Memory set aside for each image in flight when picking the limit automatically
+const MEMORY_PER_IMAGE: u64 = 512 << 20;
+
+impl ImageSlots {
+    /// `limit` slots, or if 0 one per core, fewer if the available memory can't fit that many images.
+    pub fn new(limit: usize) -> ImageSlots {
+        let limit = if limit > 0 { limit } else {
+            let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+            let by_memory = available_memory().map_or(cores, |bytes| (bytes / MEMORY_PER_IMAGE) as usize);
+            cores.min(by_memory).max(1)
+        };
+        ImageSlots { limit, in_use: Mutex::new(0), freed: Condvar::new() }
+    }
+
+    /// Waits for a free slot and takes it.
+    pub fn acquire(&self) -> ImageSlot<'_> {
+        let mut in_use = self.in_use.lock().unwrap_or_else(|e| e.into_inner());
+        while *in_use >= self.limit {
+            in_use = self.freed.wait(in_use).unwrap_or_else(|e| e.into_inner());
+        }
+        *in_use += 1;
+        ImageSlot(self)
+    }
+
+    /// Runs `work` holding a slot, giving it back only once `work` has returned.
+    pub fn run<T>(&self, work: impl FnOnce() -> T) -> T {
+        let _slot = self.acquire();
+        work()
+    }
+}
+
+/// Memory available to new allocations in bytes, where the system reports it.
+fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|line| line.starts_with("MemAvailable:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Encodes an image as configured, scaling it down to `max_width` pixels wide if it is wider.
+/// Only re-encoding takes one of `slots`, images passed through are never decoded.
+pub fn process(data: Vec<u8>, config: &ImageConfig, slots: &ImageSlots, title: &str, max_width: Option<u32>, desc: &str) -> Result<EncodedImage, String> {
+    let format = image::guess_format(&data).map_err(|e| e.to_string())?;
+    let (width, height) = image::ImageReader::with_format(Cursor::new(&data), format)
+        .into_dimensions()
+        .map_err(|e| e.to_string())?;
+
+    let kind = if is_animated(&data, format) {
+        ImageKind::Animation
+    } else if (width as u64) * (height as u64) <= config.tiny_pixels {
+        ImageKind::Tiny
+    } else if format == image::ImageFormat::Jpeg {
+        ImageKind::Photo
+    } else {
+        ImageKind::Graphic
+    };
+    let mut output = choose_format(config, kind, title);
+    let web_safe = matches!(format, image::ImageFormat::Png | image::ImageFormat::Jpeg | image::ImageFormat::Gif | image::ImageFormat::WebP);
+    if output == OutputFormat::Passthrough && !web_safe {
+        // Browsers can't display formats like PNM or BMP, so these always get re-encoded
+        output = match config.graphic { OutputFormat::Passthrough => OutputFormat::WebpLossless, graphic => graphic };
+    }
+    // Resizing would drop all but the first frame of an animation
+    let resize_to = max_width.filter(|&w| w < width && kind != ImageKind::Animation);
+    if resize_to.is_some() && output == OutputFormat::Passthrough {
+        output = match format {
+            image::ImageFormat::Jpeg => OutputFormat::Jpeg,
+            image::ImageFormat::WebP => OutputFormat::WebpLossless,
+            _ => OutputFormat::Png
+        };
+    }
+
+    let source_size = data.len();
+    let (data, ext, width, height) = match output {
+        OutputFormat::Passthrough => (data, format.extensions_str().first().copied().unwrap_or("bin"), width, height),
+        _ => {
+            let (buffer, width, height) = slots.run(|| reencode(&data, format, output, resize_to, config.quality))?;
+            let ext = match output { OutputFormat::WebpLossless => "webp", OutputFormat::Jpeg => "jpg", _ => "png" };
+            (buffer, ext, width, height)
+        }
+    };
+
+    log!(
+        "info: image `{}` ({:?}, {:?}) => {}, {}x{}, {:.1} KiB -> {:.1} KiB",
+        desc, format, kind, output.name(), width, height, source_size as f64 / 1024.0, data.len() as f64 / 1024.0
+    );
+    Ok(EncodedImage { data, ext, format: output, width, height })
+}
+
+/// Decodes `data`, scales it down to `resize_to` pixels wide and encodes it as `output`, returning
+/// the encoded bytes and their dimensions. The decoded image is dropped before this returns.
+fn reencode(data: &[u8], format: image::ImageFormat, output: OutputFormat, resize_to: Option<u32>, quality: u8) -> Result<(Vec<u8>, u32, u32), String> {
+    let mut im = image::load_from_memory_with_format(data, format).map_err(|e| e.to_string())?;
+    if let Some(w) = resize_to {
+        im = im.resize(w, u32::MAX, image::imageops::FilterType::Lanczos3);
+    }
+    let mut buffer = Vec::new();
+    match output {
+        OutputFormat::WebpLossless => im.write_with_encoder(image::codecs::webp::WebPEncoder::new_lossless(&mut buffer)),
+        OutputFormat::Jpeg => image::DynamicImage::ImageRgb8(im.to_rgb8())
+            .write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, quality.clamp(1, 100))),
+        _ => im.write_with_encoder(image::codecs::png::PngEncoder::new(&mut buffer))
+    }.map_err(|e| e.to_string())?;
+    Ok((buffer, im.width(), im.height()))
+}
+
+/// `<img>` markup for an image, wrapped in `<picture>` with a WebP source when `fallback_src` is a
+/// fallback for older readers.
+pub fn img_markup(src: &str, fallback_src: Option<&str>, width: u32, height: u32, alt_escaped: &str) -> String {
+    match fallback_src {
+        Some(fallback) => format!(
+            "<picture><source type=\"image/webp\" srcset=\"{}\"><img src=\"{}\" width=\"{}\" height=\"{}\" alt=\"{}\"></picture>",
+            src, fallback, width, height, alt_escaped
+        ),
+        None => format!("<img src=\"{}\" width=\"{}\" height=\"{}\" alt=\"{}\">", src, width, height, alt_escaped)
+    }
+}
+
+/// Source file, title and maximum width of an encoded image
+type ImageKey = (PathBuf, String, Option<u32>);
+
+/// Encodes images for posts and templates, reusing the result when the same file
+/// is requested again with the same title and width during a build.
+#[derive(Debug)]
+pub struct ImagePipeline {
+    config: ImageConfig,
+    slots: ImageSlots,
+    cache: Mutex<HashMap<ImageKey, Result<Arc<EncodedImage>, String>>>
+}
+
+impl ImagePipeline {
+    pub fn new(config: ImageConfig, max_concurrent: usize) -> ImagePipeline {
+        ImagePipeline { config, slots: ImageSlots::new(max_concurrent), cache: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn encode(&self, path: &Path, title: &str, max_width: Option<u32>) -> Result<Arc<EncodedImage>, String> {
+        let key = (path.to_path_buf(), title.to_string(), max_width);
+        if let Some(cached) = self.cache.lock().map_err(|e| e.to_string())?.get(&key) {
+            return cached.clone()
+        }
+
+        let result = std::fs::read(path)
+            .map_err(|e| format!("could not read image file `{}`: {}", path.display(), e))
+            .and_then(|data| process(data, &self.config, &self.slots, title, max_width, &path.display().to_string())
+                .map_err(|e| format!("could not reencode image file `{}`: {}", path.display(), e)))
+            .map(Arc::new);
+        crate::progress::image_transcoded();
+        self.cache.lock().map_err(|e| e.to_string())?.insert(key, result.clone());
+        result
+    }
+
+    /// A PNG copy of the image `encode` gives for the same arguments, if that is WebP and
+    /// `webp_fallback` is set. Animations only keep their first frame.
+    pub fn fallback(&self, path: &Path, title: &str, max_width: Option<u32>) -> Option<Result<Arc<EncodedImage>, String>> {
+        if !self.config.webp_fallback { return None }
+        let primary = match self.encode(path, title, max_width) {
+            Ok(primary) if primary.ext == "webp" => primary,
+            Ok(_) => return None,
+            Err(e) => return Some(Err(e))
+        };
+        // The first `format=` token of the title wins
+        let fallback = match self.encode(path, &format!("format=png {}", title), max_width) {
+            Ok(fallback) => fallback,
+            Err(e) => return Some(Err(e))
+        };
+        if (fallback.width, fallback.height) != (primary.width, primary.height) {
+            return Some(Err(format!(
+                "the fallback of `{}` is {}x{}, not {}x{} like the WebP image",
+                path.display(), fallback.width, fallback.height, primary.width, primary.height
+            )))
+        }
+        log!(
+            "info: image `{}` has a PNG fallback, {:.1} KiB on top of {:.1} KiB",
+            path.display(), fallback.data.len() as f64 / 1024.0, primary.data.len() as f64 / 1024.0
+        );
+        Some(Ok(fallback))
+    }
+
+    /// Builds `<picture>` markup with a variant of the image for each of `widths`, storing the
+    /// variants with `store`, which returns their URLs.
+    pub fn picture(&self, path: &Path, widths: &[u32], alt: &str, mut store: impl FnMut(&EncodedImage) -> String) -> Result<String, String> {
+        let mut requested: Vec<Option<u32>> = widths.iter().map(|&w| Some(w)).collect();
+        requested.sort_unstable();
+        if requested.is_empty() { requested.push(None) }
+        let mut variants: Vec<(String, Arc<EncodedImage>)> = Vec::new();
+        for width in requested {
+            let image = self.encode(path, "", width)?;
+            // Widths past the size of the source all give the same image
+            if variants.iter().any(|(_, v)| v.width == image.width) { continue }
+            variants.push((store(&image), image));
+        }
+        let Some((src, largest)) = variants.last() else { return Err(format!("no variants of `{}`", path.display())) };
+
+        let srcset = variants.iter()
+            .map(|(url, image)| format!("{} {}w", url, image.width))
+            .collect::<Vec<_>>()
+            .join(", ");
+        // Readers that skip the WebP source get the fallback of the largest variant
+        let src = match self.fallback(path, "", widths.iter().max().copied()) {
+            Some(fallback) => { let fallback = fallback?; store(&fallback) },
+            None => src.clone()
+        };
+        let mut alt_escaped = String::new();
+        pulldown_cmark_escape::escape_html(&mut alt_escaped, alt).map_err(|e| e.to_string())?;
+        Ok(format!(
+            "<picture><source type=\"{}\" srcset=\"{}\" sizes=\"(max-width: {}px) 100vw, {}px\"><img src=\"{}\" width=\"{}\" height=\"{}\" alt=\"{}\"></picture>",
+            largest.format.mime(largest.ext), srcset, largest.width, largest.width, src, largest.width, largest.height, alt_escaped
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A noisy JPEG photo, too big to count as tiny
+    fn photo() -> Vec<u8> {
+        let im = image::RgbImage::from_fn(200, 150, |x, y| image::Rgb([(x * 7 % 256) as u8, (y * 13 % 256) as u8, ((x * y) % 256) as u8]));
+        let mut jpeg = Vec::new();
+        image::DynamicImage::ImageRgb8(im).write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 90)).unwrap();
+        jpeg
+    }
+
+    #[test]
+    fn photos_are_jpeg_unless_configured_otherwise() {
+        let slots = ImageSlots::new(1);
+        let config = ImageConfig::default();
+        assert_eq!(process(photo(), &config, &slots, "", None, "photo").unwrap().ext, "jpg");
+        assert_eq!(process(photo(), &config, &slots, "format=webp-lossless", None, "photo").unwrap().ext, "webp");
+        let config = ImageConfig { photo: OutputFormat::WebpLossless, ..ImageConfig::default() };
+        assert_eq!(process(photo(), &config, &slots, "", None, "photo").unwrap().ext, "webp");
+    }
+
+    fn encode(im: image::DynamicImage, format: image::ImageFormat) -> Vec<u8> {
+        let mut data = Vec::new();
+        im.write_to(&mut Cursor::new(&mut data), format).unwrap();
+        data
+    }
+
+    /// A GIF of `frames` frames, 100 pixels square
+    fn gif(frames: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        {
+            let mut encoder = image::codecs::gif::GifEncoder::new(&mut data);
+            encoder.encode_frames((0..frames).map(|i| image::Frame::new(image::RgbaImage::from_pixel(100, 100, image::Rgba([i as u8 * 80, 0, 0, 255]))))).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn animations_pass_through_unresized() {
+        let slots = ImageSlots::new(1);
+        let config = ImageConfig::default();
+        let animated = process(gif(3), &config, &slots, "", Some(50), "animation").unwrap();
+        assert_eq!((animated.ext, animated.format, animated.width), ("gif", OutputFormat::Passthrough, 100));
+        // A single frame is a graphic
+        assert_eq!(process(gif(1), &config, &slots, "", None, "still").unwrap().ext, "webp");
+    }
+
+    #[test]
+    fn tiny_images_pass_through_unless_browsers_cant_show_them() {
+        let slots = ImageSlots::new(1);
+        let config = ImageConfig::default();
+        let icon = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(16, 16, image::Rgb([0, 128, 255])));
+        let png = encode(icon.clone(), image::ImageFormat::Png);
+        let tiny = process(png.clone(), &config, &slots, "", None, "icon").unwrap();
+        assert_eq!((tiny.ext, tiny.format), ("png", OutputFormat::Passthrough));
+        assert_eq!(tiny.data, png);
+        assert_eq!(process(encode(icon, image::ImageFormat::Bmp), &config, &slots, "", None, "icon").unwrap().ext, "webp");
+    }
+
+    #[test]
+    fn graphics_with_alpha_are_lossless() {
+        let slots = ImageSlots::new(1);
+        let im = image::RgbaImage::from_fn(100, 100, |x, y| image::Rgba([x as u8, y as u8, 0, if x < 50 { 0 } else { 255 }]));
+        let png = encode(image::DynamicImage::ImageRgba8(im.clone()), image::ImageFormat::Png);
+        let graphic = process(png, &ImageConfig::default(), &slots, "", None, "graphic").unwrap();
+        assert_eq!((graphic.ext, graphic.format), ("webp", OutputFormat::WebpLossless));
+        let decoded = image::load_from_memory(&graphic.data).unwrap().to_rgba8();
+        assert_eq!(decoded, im);
+    }
+
+    #[test]
+    fn titles_override_the_format_of_one_image() {
+        let slots = ImageSlots::new(1);
+        let config = ImageConfig::default();
+        assert_eq!(process(photo(), &config, &slots, "A photo format=png", None, "photo").unwrap().ext, "png");
+        assert_eq!(process(photo(), &config, &slots, "format=passthrough", None, "photo").unwrap().format, OutputFormat::Passthrough);
+        // The first token wins, and unknown formats fall back to the configured one
+        assert_eq!(process(photo(), &config, &slots, "format=png format=jpeg", None, "photo").unwrap().ext, "png");
+        assert_eq!(process(photo(), &config, &slots, "format=avif", None, "photo").unwrap().ext, "jpg");
+        assert_eq!(choose_format(&config, ImageKind::Tiny, "format=webp-lossless"), OutputFormat::WebpLossless);
+    }
+
+    #[test]
+    fn slots_are_held_for_the_whole_work() {
+        let slots = ImageSlots::new(2);
+        let (running, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| slots.run(|| {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(std::time::Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                }));
+            }
+        });
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+    }
+
+    /// Peak resident memory of the process in KiB, from `/proc`
+    fn peak_rss_kib() -> Option<u64> {
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        status.lines().find(|l| l.starts_with("VmHWM:"))?.split_whitespace().nth(1)?.parse().ok()
+    }
+
+    #[test]
+    #[ignore = "encodes several large images, run with --ignored"]
+    fn large_images_stay_within_memory_bound() {
+        // 7000x6000 RGB takes 120 MiB decoded
+        let (width, height) = (7000, 6000);
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |x, y| image::Rgb([x as u8, y as u8, (x ^ y) as u8])))
+            .write_with_encoder(image::codecs::png::PngEncoder::new(&mut png))
+            .unwrap();
+        let Some(before) = peak_rss_kib() else { return };
+
+        let (config, slots) = (ImageConfig::default(), ImageSlots::new(1));
+        std::thread::scope(|scope| {
+            for _ in 0..6 {
+                scope.spawn(|| process(png.clone(), &config, &slots, "", None, "large").unwrap());
+            }
+        });
+        // One image in flight at a time, with room for the encoder and the copies of the source
+        let grown = peak_rss_kib().unwrap().saturating_sub(before);
+        assert!(grown < 600 << 10, "peak memory grew by {} KiB", grown);
+    }
+}
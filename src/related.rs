@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::post::Post;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelatedConfig {
+    /// Most related posts given to each post, 0 for none
+    pub count: usize,
+    /// Give posts that share no tags with any other the most recent posts instead
+    pub fallback_recent: bool
+}
+
+impl Default for RelatedConfig {
+    fn default() -> Self {
+        RelatedConfig { count: 5, fallback_recent: true }
+    }
+}
+
+/// A post related to another, as the `post` template sees it
+#[derive(Debug, Serialize)]
+pub struct RelatedPost<'a> {
+    pub id: &'a str,
+    pub url: &'a str,
+    pub title_html: &'a str,
+    pub title_plain: &'a str,
+    pub date: &'a toml_datetime::Datetime,
+    /// Number of tags it shares with the post, 0 for recent posts given as a fallback
+    pub shared_tags: usize
+}
+
+/// The tags of `post` under their canonical names, without repeats.
+fn canonical_tags<'a>(post: &'a Post, tag_names: &'a HashMap<String, String>) -> Vec<&'a str> {
+    let mut tags: Vec<&str> = Vec::new();
+    for tag in post.meta.tags.iter().map(|t| tag_names.get(t).unwrap_or(t).as_str()) {
+        if !tags.contains(&tag) { tags.push(tag) }
+    }
+    tags
+}
+
+/// The posts of `candidates` related to each post of `posts`, keyed by id: the ones sharing the
+/// most tags, under their canonical names from `tag_names`, with the newer one first on a tie.
+pub fn related<'a>(
+    posts: &'a [Post],
+    candidates: &[&'a Post],
+    tag_names: &HashMap<String, String>,
+    config: &RelatedConfig
+) -> HashMap<&'a str, Vec<RelatedPost<'a>>> {
+    let candidate_tags: Vec<Vec<&str>> = candidates.iter().map(|post| canonical_tags(post, tag_names)).collect();
+    let mut newest_first: Vec<&&Post> = candidates.iter().collect();
+    newest_first.sort_by(|a, b| b.age.cmp(&a.age).then_with(|| a.id.cmp(&b.id)));
+
+    posts.iter().map(|post| {
+        let tags = canonical_tags(post, tag_names);
+        let mut ranked: Vec<(usize, &Post)> = candidates.iter().zip(&candidate_tags)
+            .filter(|(other, _)| other.id != post.id)
+            .map(|(other, other_tags)| (other_tags.iter().filter(|t| tags.contains(t)).count(), *other))
+            .filter(|&(shared, _)| shared > 0)
+            .collect();
+        ranked.sort_by(|(a_shared, a), (b_shared, b)| b_shared.cmp(a_shared).then(b.age.cmp(&a.age)).then_with(|| a.id.cmp(&b.id)));
+        if ranked.is_empty() && config.fallback_recent {
+            ranked = newest_first.iter().filter(|other| other.id != post.id).map(|other| (0, **other)).collect();
+        }
+        let related = ranked.into_iter().take(config.count).map(|(shared_tags, other)| RelatedPost {
+            id: &other.id,
+            url: &other.url,
+            title_html: &other.title_html,
+            title_plain: &other.title_plain,
+            date: &other.meta.date,
+            shared_tags
+        }).collect();
+        (post.id.as_str(), related)
+    }).collect()
+}
@@ -0,0 +1,124 @@
+use std::{collections::BTreeMap, path::Path};
+use serde::{Deserialize, Serialize};
+use crate::output::OutputSink;
+
+/// An HTML response served by the dev server, kept for comparing against later builds.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Recording {
+    pub time: String,
+    /// Request path, as sent by the browser
+    pub path: String,
+    pub headers: BTreeMap<String, String>,
+    /// Output path the response was read from
+    pub output: String,
+    pub body: String
+}
+
+/// Writes `recording` to a new file in `dir`, removing the oldest files beyond `max`, unless it is 0.
+pub fn record(dir: &Path, max: usize, recording: &Recording) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let name: String = recording.output.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '-' })
+        .collect();
+    // UTC, so that names sort chronologically across DST and timezone changes
+    let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ");
+    let json = serde_json::to_vec_pretty(recording).map_err(std::io::Error::other)?;
+    std::fs::write(dir.join(format!("{}-{}.json", stamp, name)), json)?;
+
+    let mut files = recordings(dir)?;
+    if max > 0 && files.len() > max {
+        files.sort();
+        for old in &files[..files.len() - max] {
+            std::fs::remove_file(old)?;
+        }
+    }
+    Ok(())
+}
+
+fn recordings(dir: &Path) -> std::io::Result<Vec<std::path::PathBuf>> {
+    Ok(dir.read_dir()?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("json"))
+        .collect())
+}
+
+/// Compares the outputs in `sink` against the newest recording of each path in `dir`.
+pub fn check(dir: &Path, sink: &dyn OutputSink) {
+    let Ok(mut files) = recordings(dir)
+        .inspect_err(|e| log!("error: cannot read recordings in `{}`: {}", dir.display(), e))
+        else { return };
+    files.sort();
+
+    // Newest recording of each output wins
+    let mut latest: BTreeMap<String, (std::path::PathBuf, Recording)> = BTreeMap::new();
+    for file in files {
+        let Ok(recording) = std::fs::read(&file)
+            .map_err(|e| e.to_string())
+            .and_then(|data| serde_json::from_slice::<Recording>(&data).map_err(|e| e.to_string()))
+            .inspect_err(|e| log!("error: cannot read recording `{}`: {}", file.display(), e))
+            else { continue };
+        latest.insert(recording.output.clone(), (file, recording));
+    }
+
+    let mut changed = 0;
+    for (output, (file, recording)) in &latest {
+        let current = match sink.read(output) {
+            Ok(current) => String::from_utf8_lossy(&current).into_owned(),
+            Err(e) => {
+                log!("warning: replay: `{}` was recorded in `{}` but is not in the build: {}", output, file.display(), e);
+                changed += 1;
+                continue
+            }
+        };
+        if current == recording.body { continue }
+
+        changed += 1;
+        let line = current.lines().zip(recording.body.lines())
+            .position(|(a, b)| a != b)
+            .unwrap_or_else(|| current.lines().count().min(recording.body.lines().count()));
+        log!(
+            "warning: replay: `{}` differs from the recording in `{}` from line {}:\n    recorded: {:?}\n    built:    {:?}",
+            output, file.display(), line + 1,
+            recording.body.lines().nth(line).unwrap_or_default(), current.lines().nth(line).unwrap_or_default()
+        );
+    }
+    log!("info: replay: {} of {} recorded pages unchanged", latest.len() - changed, latest.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording(output: &str) -> Recording {
+        Recording { time: String::new(), path: format!("/{}", output), headers: BTreeMap::new(), output: output.into(), body: String::new() }
+    }
+
+    #[test]
+    fn recordings_are_pruned_to_max_unless_it_is_zero() {
+        let dir = crate::test_dir("recordings");
+        for output in ["a.html", "b.html", "c.html"] {
+            record(&dir, 2, &recording(output)).unwrap();
+        }
+        let files = recordings(&dir).unwrap();
+        assert_eq!(files.len(), 2);
+        assert!(!files.iter().any(|f| f.to_string_lossy().ends_with("a.html.json")));
+
+        let dir = crate::test_dir("recordings-unlimited");
+        for output in ["a.html", "b.html", "c.html"] {
+            record(&dir, 0, &recording(output)).unwrap();
+        }
+        assert_eq!(recordings(&dir).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn recordings_are_named_by_utc_time() {
+        let dir = crate::test_dir("recordings-utc");
+        record(&dir, 0, &recording("a.html")).unwrap();
+        let file = recordings(&dir).unwrap().remove(0);
+        let name = file.file_name().unwrap().to_string_lossy().into_owned();
+        let stamp = name.strip_suffix("-a.html.json").unwrap();
+        let time = chrono::NaiveDateTime::parse_from_str(stamp, "%Y%m%dT%H%M%S%.3fZ").unwrap().and_utc();
+        assert!((chrono::Utc::now() - time).num_seconds().abs() < 60, "{}", name);
+    }
+}
@@ -0,0 +1,61 @@
+use std::{collections::BTreeMap, path::Path};
+use serde::Serialize;
+
+/// What a build produced, written as JSON with `--report` for deploy scripts to compare between builds
+#[derive(Debug, Serialize)]
+pub struct BuildReport {
+    pub posts: Vec<ReportPost>,
+    /// Posts dated in the future, soonest first
+    pub scheduled: Vec<ReportScheduled>,
+    /// When the soonest scheduled post publishes as RFC 3339, for rebuilding the site then
+    pub next_publish: Option<String>,
+    /// Size in bytes of each asset stored by posts and templates, keyed by its output path
+    pub assets: BTreeMap<String, u64>,
+    /// Size in bytes of every output, assets and static files included, keyed by its path
+    pub outputs: BTreeMap<String, u64>,
+    pub phases: Vec<ReportPhase>,
+    /// Wall-clock duration of the whole build
+    pub duration_ms: f64,
+    /// Messages without their `warning:` or `error:` prefix, in the order they were printed
+    pub warnings: Vec<String>,
+    pub errors: Vec<String>
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReportPost {
+    pub id: String,
+    pub title: String,
+    /// Publication time as RFC 3339
+    pub date: String,
+    pub tags: Vec<String>,
+    /// Markdown file of the post, relative to the input directory
+    pub source: String,
+    pub output: String,
+    /// Size of the output, `None` if it couldn't be rendered
+    pub bytes: Option<u64>
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReportScheduled {
+    pub id: String,
+    pub title: String,
+    /// Publication time as RFC 3339
+    pub publish: String
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReportPhase {
+    pub name: &'static str,
+    pub items: usize,
+    pub duration_ms: f64
+}
+
+pub fn write(path: &Path, report: &BuildReport) {
+    let Ok(json) = serde_json::to_vec_pretty(report)
+        .inspect_err(|e| log!("error: could not serialize build report: {}", e))
+        else { return };
+    match std::fs::write(path, json) {
+        Ok(()) => log!("info: wrote build report to `{}`", path.display()),
+        Err(e) => log!("error: could not write build report `{}`: {}", path.display(), e)
+    }
+}
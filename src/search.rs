@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+use crate::post::{Post, Section};
+
+/// Words per passage, and how many of them are repeated at the start of the next one.
+pub const PASSAGE_WORDS: usize = 200;
+pub const PASSAGE_OVERLAP: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchGranularity {
+    /// One entry per post
+    Page,
+    /// One entry per heading
+    Section,
+    /// Overlapping passages of about `PASSAGE_WORDS` words, never spanning two sections
+    Passage
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchEntry<'a> {
+    /// Id of the post the entry is from
+    pub id: &'a str,
+    pub url: String,
+    pub title: &'a str,
+    pub heading: Option<&'a str>,
+    /// Canonical names of the post's tags
+    pub tags: &'a [&'a str],
+    /// When the post is published, as RFC 3339
+    pub date: &'a str,
+    pub text: String
+}
+
+/// What every entry of a post shares, besides what the post itself gives
+pub struct PostInfo<'a> {
+    pub tags: &'a [&'a str],
+    pub date: &'a str,
+    /// Longest text of an entry in bytes, 0 for no limit
+    pub max_bytes: usize
+}
+
+/// Splits text into windows of `size` words, each overlapping the previous one by `overlap` words.
+pub fn passages(text: &str, size: usize, overlap: usize) -> Vec<String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let size = size.max(1);
+    let step = size.saturating_sub(overlap).max(1);
+
+    let mut passages = Vec::new();
+    let mut start = 0;
+    while start < words.len() {
+        let end = (start + size).min(words.len());
+        passages.push(words[start..end].join(" "));
+        if end == words.len() { break }
+        start += step;
+    }
+    passages
+}
+
+/// `text` cut short at the last whole word within `max` bytes, or all of it if `max` is 0.
+pub fn cap(text: String, max: usize) -> String {
+    if max == 0 || text.len() <= max { return text }
+    let mut end = max;
+    while !text.is_char_boundary(end) { end -= 1 }
+    // Unless the cut falls right before a space, drop the word it splits
+    if !text[end..].starts_with(char::is_whitespace) {
+        end = text[..end].rfind(char::is_whitespace).unwrap_or(end);
+    }
+    text[..end].trim_end().to_string()
+}
+
+fn entry<'a>(post: &'a Post, info: &PostInfo<'a>, url: String, heading: Option<&'a str>, text: String) -> SearchEntry<'a> {
+    let text = cap(text, info.max_bytes);
+    SearchEntry { id: &post.id, url, title: &post.title_plain, heading, tags: info.tags, date: info.date, text }
+}
+
+fn section_entry<'a>(post: &'a Post, info: &PostInfo<'a>, section: &'a Section, text: String) -> SearchEntry<'a> {
+    let url = match &section.anchor {
+        Some(anchor) => format!("{}#{}", post.url, urlencoding::encode(anchor)),
+        None => post.url.clone()
+    };
+    let heading = section.anchor.is_some().then_some(section.heading.as_str());
+    entry(post, info, url, heading, text)
+}
+
+pub fn entries<'a>(post: &'a Post, info: &PostInfo<'a>, granularity: SearchGranularity) -> Vec<SearchEntry<'a>> {
+    match granularity {
+        SearchGranularity::Page => {
+            let text = post.sections.iter()
+                .flat_map(|s| s.heading.split_whitespace().chain(s.text.split_whitespace()))
+                .collect::<Vec<_>>()
+                .join(" ");
+            vec![entry(post, info, post.url.clone(), None, text)]
+        },
+        SearchGranularity::Section => post.sections.iter()
+            .map(|s| section_entry(post, info, s, s.text.split_whitespace().collect::<Vec<_>>().join(" ")))
+            .collect(),
+        SearchGranularity::Passage => post.sections.iter()
+            .flat_map(|s| {
+                let passages = match passages(&s.text, PASSAGE_WORDS, PASSAGE_OVERLAP) {
+                    // Keep empty sections so their headings are still searchable
+                    p if p.is_empty() => vec![String::new()],
+                    p => p
+                };
+                passages.into_iter().map(move |p| section_entry(post, info, s, p))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(name: &str, site: &str, post: &str) -> Vec<serde_json::Value> {
+        let mut files = crate::tests::TEMPLATES.to_vec();
+        files.extend([("site.toml", site), ("posts/post.md", post)]);
+        let outputs = crate::build_test_site(name, &files, &[]);
+        serde_json::from_slice(&outputs["search-index.json"]).unwrap()
+    }
+
+    #[test]
+    fn caps_end_at_a_whole_word() {
+        assert_eq!(cap("one two three".into(), 0), "one two three");
+        assert_eq!(cap("one two three".into(), 13), "one two three");
+        assert_eq!(cap("one two three".into(), 9), "one two");
+        assert_eq!(cap("one two three".into(), 7), "one two");
+        assert_eq!(cap("one two three".into(), 6), "one");
+        // A single word longer than the cap is cut where it has to be, but not inside a letter
+        assert_eq!(cap("ääää".into(), 3), "ä");
+    }
+
+    #[test]
+    fn index_has_the_text_of_posts_without_code_or_math() {
+        let entries = index("search-index", "search_granularity = \"page\"", concat!(
+            "+++\ntitle = \"Searching *Text*\"\ndate = 2024-01-02T03:04:05Z\ntags = [\"Rust\"]\n+++\n",
+            "Some words and `inline code`.\n\n```rust\nfn hidden() {}\n```\n\nMath $x^2$ here.\n\n## Later\n\nMore words."
+        ));
+        assert_eq!(entries, [serde_json::json!({
+            "id": "post", "url": "/posts/post.html", "title": "Searching Text", "heading": null,
+            "tags": ["Rust"], "date": "2024-01-02T03:04:05+00:00",
+            "text": "Some words and inline code. Math here. Later More words."
+        })]);
+    }
+
+    #[test]
+    fn index_texts_are_capped() {
+        let entries = index("search-cap", "search_granularity = \"page\"\nsearch_max_bytes = 20",
+            "+++\ntitle = \"Capped\"\ndate = 2024-01-01\n+++\nThe quick brown fox jumps over the lazy dog.");
+        assert_eq!(entries[0]["text"], "The quick brown fox");
+    }
+
+    #[test]
+    fn no_index_without_a_granularity() {
+        let mut files = crate::tests::TEMPLATES.to_vec();
+        files.push(("posts/post.md", "+++\ntitle = \"Post\"\ndate = 2024-01-01\n+++\nText"));
+        assert!(!crate::build_test_site("search-none", &files, &[]).contains_key("search-index.json"));
+    }
+
+    #[test]
+    fn drafts_are_left_out_of_the_index() {
+        let mut files = crate::tests::TEMPLATES.to_vec();
+        files.extend([
+            ("site.toml", "search_granularity = \"page\""),
+            ("templates/search.html", "{{ documents }}"),
+            ("posts/post.md", "+++\ntitle = \"Post\"\ndate = 2024-01-01\n+++\nPublished text"),
+            ("posts/draft.md", "+++\ntitle = \"Draft\"\ndate = 2024-01-02\ndraft = true\n+++\nUnfinished text")
+        ]);
+        let outputs = crate::build_test_site("search-drafts", &files, &["--drafts"]);
+        assert!(outputs.contains_key("posts/draft.html"));
+        for path in ["search-index.json", "search.html"] {
+            let text = String::from_utf8_lossy(&outputs[path]);
+            assert!(text.contains("Published text") && !text.contains("Unfinished"), "{}: {}", path, text);
+        }
+    }
+
+    #[test]
+    fn long_sections_split_into_overlapping_passages_within_the_section() {
+        let words: Vec<String> = (0..450).map(|i| format!("w{}", i)).collect();
+        let passages = passages(&words.join(" "), PASSAGE_WORDS, PASSAGE_OVERLAP);
+        let bounds: Vec<(&str, &str)> = passages.iter()
+            .map(|p| (p.split(' ').next().unwrap(), p.rsplit(' ').next().unwrap()))
+            .collect();
+        assert_eq!(bounds, [("w0", "w199"), ("w150", "w349"), ("w300", "w449")]);
+
+        let entries = index("search-passages", "search_granularity = \"passage\"", &format!(
+            "+++\ntitle = \"Long\"\ndate = 2024-01-01\n+++\n## First\n\n{}\n\n## Second\n\nShort one.\n\n## Empty\n",
+            words.join(" ")
+        ));
+        let found: Vec<(&str, usize)> = entries.iter()
+            .map(|e| (e["heading"].as_str().unwrap(), e["text"].as_str().unwrap().split_whitespace().count()))
+            .collect();
+        assert_eq!(found, [("First", 200), ("First", 200), ("First", 150), ("Second", 2), ("Empty", 0)]);
+        assert_eq!(entries[1]["url"], "/posts/post.html#first");
+    }
+
+    #[test]
+    fn long_sections_are_capped_at_a_word_boundary() {
+        let text = "alpha beta gamma delta ".repeat(20);
+        let entries = index("search-section-cap", "search_granularity = \"section\"\nsearch_max_bytes = 30",
+            &format!("+++\ntitle = \"Capped\"\ndate = 2024-01-01\n+++\n## Long\n\n{}", text));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["text"], "alpha beta gamma delta alpha");
+    }
+
+    #[test]
+    fn posts_without_headings_are_one_section() {
+        let entries = index("search-no-headings", "search_granularity = \"section\"",
+            "+++\ntitle = \"Flat\"\ndate = 2024-01-01\n+++\nJust a paragraph.\n\nAnd another.");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["heading"], serde_json::Value::Null);
+        assert_eq!(entries[0]["url"], "/posts/post.html");
+        assert_eq!(entries[0]["text"], "Just a paragraph. And another.");
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::{BTreeMap, HashMap};
+use serde::Serialize;
+use crate::post::Post;
+
+/// A series a post is part of, as the `post` template sees it
+#[derive(Debug, Clone, Serialize)]
+pub struct Series<'a> {
+    pub name: &'a str,
+    /// Every part, in reading order
+    pub parts: Vec<SeriesPart<'a>>,
+    /// Position of the post in `parts`
+    pub index: usize
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SeriesPart<'a> {
+    pub id: &'a str,
+    pub url: &'a str,
+    pub title_html: &'a str,
+    pub title_plain: &'a str,
+    /// The `series_part` of the post, if it has one
+    pub part: Option<u32>
+}
+
+/// Groups `posts` by their `series`, keyed by post id. Parts are ordered by `series_part`, then
+/// by date, with unnumbered parts last.
+pub fn series<'a>(posts: &[&'a Post]) -> HashMap<&'a str, Series<'a>> {
+    let mut groups: BTreeMap<&str, Vec<&Post>> = BTreeMap::new();
+    for &post in posts {
+        if let Some(name) = post.meta.series.as_deref() {
+            groups.entry(name).or_default().push(post);
+        }
+    }
+
+    let mut series = HashMap::new();
+    for (name, mut members) in groups {
+        members.sort_by_key(|post| (post.meta.series_part.is_none(), post.meta.series_part, post.age, post.id.as_str()));
+        for pair in members.windows(2) {
+            if let Some(part) = pair[0].meta.series_part && pair[1].meta.series_part == Some(part) {
+                log!("warning: posts `{}` and `{}` are both part {} of series `{}`", pair[0].id, pair[1].id, part, name);
+            }
+        }
+        let parts: Vec<SeriesPart> = members.iter().map(|post| SeriesPart {
+            id: &post.id,
+            url: &post.url,
+            title_html: &post.title_html,
+            title_plain: &post.title_plain,
+            part: post.meta.series_part
+        }).collect();
+        for (index, post) in members.iter().enumerate() {
+            series.insert(post.id.as_str(), Series { name, parts: parts.clone(), index });
+        }
+    }
+    series
+}
@@ -1,8 +1,12 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::{Arc, RwLock}};
 use simple_server::{Request, ResponseBuilder, ResponseResult};
+use static_site_gen::{BuildResult, log, replay};
 
 struct Server {
-    dir: PathBuf
+    dir: PathBuf,
+    site: Arc<RwLock<BuildResult>>,
+    /// Directory to record served pages to, and how many recordings to keep
+    record: Option<(PathBuf, usize)>
 }
 
 impl Server {
@@ -24,9 +28,75 @@ impl Server {
         "#, title, detail).into_bytes()
     }
 
+    /// Content type for files whose extension doesn't give one, like `.well-known/webfinger`.
+    fn fallback_content_type(path: &std::path::Path, contents: &[u8]) -> Option<mime_guess::Mime> {
+        let mime = match path.file_name().and_then(|n| n.to_str()) {
+            Some("webfinger") => "application/jrd+json",
+            Some("host-meta") => "application/xrd+xml",
+            Some("host-meta.json") => "application/json",
+            _ if std::str::from_utf8(contents).is_ok() => "text/plain; charset=utf-8",
+            _ => "application/octet-stream"
+        };
+        mime.parse().ok()
+    }
+
+    fn record(&self, request: &Request<Vec<u8>>, path: &std::path::Path, contents: &[u8], dir: &std::path::Path, max: usize) {
+        let recording = replay::Recording {
+            time: chrono::Local::now().to_rfc3339(),
+            path: request.uri().path().to_string(),
+            headers: request.headers().iter()
+                .map(|(name, value)| (name.to_string(), String::from_utf8_lossy(value.as_bytes()).into_owned()))
+                .collect(),
+            output: path.strip_prefix(&self.dir).unwrap_or(path).to_string_lossy().replace('\\', "/"),
+            body: String::from_utf8_lossy(contents).into_owned()
+        };
+        if let Err(e) = replay::record(dir, max, &recording) {
+            log!("error: server: could not record `{}`: {}", recording.path, e);
+        }
+    }
+
+    fn handle_api_request(&self, request: &Request<Vec<u8>>, endpoint: &str, mut response: ResponseBuilder) -> ResponseResult {
+        response.header("Cache-Control", "no-store");
+        let Ok(site) = self.site.read() else {
+            log!("request: {} {} => 500 internal server error: build state is poisoned", request.method(), request.uri().path());
+            return Ok(response.status(500)
+                .body(Self::error_message("500 Internal Server Error", "The build state is unavailable"))?
+            )
+        };
+
+        let json = match endpoint {
+            "posts" => serde_json::to_vec(&site.posts),
+            "outputs" => serde_json::to_vec(&site.outputs),
+            _ => {
+                log!("request: {} {} => 404 not found", request.method(), request.uri().path());
+                return Ok(response.status(404)
+                    .body(Self::error_message("404 Not Found", &format!(
+                        "Unknown API endpoint: {:?}", endpoint
+                    )))?
+                )
+            }
+        };
+
+        match json {
+            Ok(json) => {
+                log!("request: {} {} => 200 okay, {} bytes, api", request.method(), request.uri().path(), json.len());
+                response.header("Content-Type", "application/json");
+                response.status(200);
+                let body = if request.method().as_str() == "HEAD" { Vec::new() } else { json };
+                Ok(response.body(body)?)
+            },
+            Err(e) => {
+                log!("request: {} {} => 500 internal server error: {}", request.method(), request.uri().path(), e);
+                Ok(response.status(500)
+                    .body(Self::error_message("500 Internal Server Error", &format!("{}", e)))?
+                )
+            }
+        }
+    }
+
     fn handle_request(&self, request: Request<Vec<u8>>, mut response: ResponseBuilder) -> ResponseResult {
         if request.method().as_str() != "GET" && request.method().as_str() != "HEAD" {
-            println!("info: server: {} {} => 405 method not allowed", request.method(), request.uri().path());
+            log!("request: {} {} => 405 method not allowed", request.method(), request.uri().path());
             return Ok(response.status(405)
                 .header("Allow", "GET, HEAD")
                 .body(Self::error_message("405 Method Not Allowed", &format!(
@@ -37,19 +107,28 @@ impl Server {
 
         let Ok(path) = urlencoding::decode(request.uri().path())
             else { 
-                println!("info: server: {} {} => 400 bad request: could not decode path", request.method(), request.uri().path());
+                log!("request: {} {} => 400 bad request: could not decode path", request.method(), request.uri().path());
                 return Ok(response.status(400)
                     .body(Self::error_message("400 Bad Request", &format!(
                         "The path could not be decoded: {:?}", request.uri().path()
                     )))?
                 )
             };
-        let path = if path == "/" { "/index.html" } else { &path };
+        if let Some(endpoint) = path.strip_prefix("/__api/") {
+            return self.handle_api_request(&request, endpoint, response)
+        }
+
+        // Clean URLs like `/2024/hello/` are served from the directory's `index.html`
+        let path = if path.ends_with('/') { format!("{}index.html", path) } else { path.to_string() };
         let path = path.trim_start_matches("/");
         let path = self.dir.join(path);
 
         if !path.is_file() {
-            println!("info: server: {} {} => 404 not found", request.method(), request.uri().path());
+            log!("request: {} {} => 404 not found", request.method(), request.uri().path());
+            // The site's own not-found page, as static hosts would serve it
+            if let Ok(page) = std::fs::read(self.dir.join("404.html")) {
+                return Ok(response.status(404).header("Content-Type", "text/html; charset=utf-8").body(page)?)
+            }
             return Ok(response.status(404)
                 .body(Self::error_message("404 Not Found", &format!(
                     "Requested: {:?}", request.uri().path()
@@ -59,7 +138,7 @@ impl Server {
 
         match std::fs::read(&path) {
             Err(e) => {
-                println!("info: server: {} {} => 500 internal server error: {}", request.method(), request.uri().path(), e);
+                log!("request: {} {} => 500 internal server error: {}", request.method(), request.uri().path(), e);
                 Ok(response.status(500)
                     .body(Self::error_message("500 Internal Server Error", &format!("{}", e)))?
                 )
@@ -78,12 +157,13 @@ impl Server {
                     .header("Vary", "Accept-Encoding");
 
                 if let Some(mtag) = request.headers().get("if-none-match") && etag.as_bytes() == mtag.as_bytes() {
-                    println!("info: server: {} {} => 304 not modified, etag {}", request.method(), request.uri().path(), etag);
+                    log!("request: {} {} => 304 not modified, etag {}", request.method(), request.uri().path(), etag);
                     response.status(304);
                     return Ok(response.body(Vec::new())?)
                 }
 
-                let content_type = mime_guess::from_path(&path).first();
+                let content_type = mime_guess::from_path(&path).first()
+                    .or_else(|| Self::fallback_content_type(&path, &contents));
                 let should_compress = if let Some(mime) = &content_type {
                     response.header("Content-Type", mime.as_ref());
                     mime.type_() == "text" || [
@@ -92,28 +172,32 @@ impl Server {
                 } else { false };
 
                 if request.method().as_str() == "HEAD" {
-                    println!("info: server: {} {} => 200 okay", request.method(), request.uri().path());
+                    log!("request: {} {} => 200 okay", request.method(), request.uri().path());
                     return Ok(response.body(Vec::new())?);
                 }   
 
+                if let Some((record_dir, max)) = &self.record && content_type.as_ref().is_some_and(|m| m.essence_str() == "text/html") {
+                    self.record(&request, &path, &contents, record_dir, *max);
+                }
+
                 if should_compress && let Some(enc) = request.headers().get("accept-encoding") && enc.to_str().map(|s| s.contains("gzip")).unwrap_or(false) {
                     use std::io::Write;
                     let mut buffer = Vec::new();
                     {
                         let mut encoder = flate2::write::GzEncoder::new(&mut buffer, flate2::Compression::fast());
                         if let Err(e) = encoder.write_all(&contents) {
-                            println!("info: server: {} {} => 500 internal server error: {}", request.method(), request.uri().path(), e);
+                            log!("request: {} {} => 500 internal server error: {}", request.method(), request.uri().path(), e);
                             return Ok(response.status(500)
                                 .body(Self::error_message("500 Internal Server Error", &format!("{}", e)))?
                             )
                         }
                     }
-                    println!("info: server: {} {} => 200 okay, gzipped, {} bytes, content-type: {:?}", request.method(), request.uri().path(), buffer.len(), content_type);
+                    log!("request: {} {} => 200 okay, gzipped, {} bytes, content-type: {:?}", request.method(), request.uri().path(), buffer.len(), content_type);
                     response.header("Content-Encoding", "gzip");
                     response.status(200);
                     Ok(response.body(buffer)?)
                 } else {
-                    println!("info: server: {} {} => 200 okay, {} bytes, content-type: {:?}", request.method(), request.uri().path(), contents.len(), content_type);
+                    log!("request: {} {} => 200 okay, {} bytes, content-type: {:?}", request.method(), request.uri().path(), contents.len(), content_type);
                     response.status(200);
                     Ok(response.body(contents)?)
                 }
@@ -123,11 +207,64 @@ impl Server {
     }
 }
 
-pub fn start_server(dir: PathBuf, port: u16) {
-    let server = Server { dir };
+/// Serves `dir` on a background thread, which only ends if the server fails to start.
+pub fn start_server(dir: PathBuf, port: u16, site: Arc<RwLock<BuildResult>>, record: Option<(PathBuf, usize)>) -> std::thread::JoinHandle<()> {
+    let server = Server { dir, site, record };
     std::thread::spawn(move || {
         let server = simple_server::Server::new(move |req, resp| server.handle_request(req, resp));
-        println!("info: server: listening on localhost:{port}");
+        log!("info: server: listening on http://localhost:{port}/");
         server.listen("localhost", &format!("{}", port))
-    });
-}
\ No newline at end of file
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("static-site-gen-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn get(port: u16, path: &str) -> String {
+        let mut stream = (0..50)
+            .find_map(|_| std::net::TcpStream::connect(("localhost", port))
+                .inspect_err(|_| std::thread::sleep(std::time::Duration::from_millis(20))).ok())
+            .expect("server did not start");
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+        stream.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path).as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_the_output_directory() {
+        let dir = test_dir("serve");
+        std::fs::write(dir.join("index.html"), "<p>Home</p>").unwrap();
+        std::fs::create_dir(dir.join("post")).unwrap();
+        std::fs::write(dir.join("post/index.html"), "<p>Post</p>").unwrap();
+        let port = std::net::TcpListener::bind("localhost:0").unwrap().local_addr().unwrap().port();
+        let server = start_server(dir.clone(), port, Arc::default(), None);
+
+        let home = get(port, "/");
+        assert!(home.starts_with("HTTP/1.1 200"), "{}", home);
+        assert!(home.ends_with("<p>Home</p>"), "{}", home);
+        assert!(get(port, "/post/").ends_with("<p>Post</p>"));
+        assert!(get(port, "/missing.html").starts_with("HTTP/1.1 404"));
+        // The server keeps running for as long as it is served
+        assert!(!server.is_finished());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn server_thread_ends_when_the_port_is_taken() {
+        let taken = std::net::TcpListener::bind("localhost:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let server = start_server(test_dir("serve-taken"), port, Arc::default(), None);
+        assert!(server.join().is_err());
+    }
+}
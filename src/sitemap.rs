@@ -0,0 +1,23 @@
+/// A page as the sitemap lists it
+pub struct SitemapEntry {
+    /// Absolute URL of the page
+    pub loc: String,
+    /// When the page last changed, for posts and standalone pages
+    pub lastmod: Option<chrono::DateTime<chrono::FixedOffset>>
+}
+
+/// A sitemap listing `entries` in their order.
+pub fn sitemap(entries: &[SitemapEntry]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for entry in entries {
+        let mut loc = String::new();
+        let _ = pulldown_cmark_escape::escape_html(&mut loc, &entry.loc);
+        xml.push_str(&format!("<url><loc>{}</loc>", loc));
+        if let Some(lastmod) = &entry.lastmod {
+            xml.push_str(&format!("<lastmod>{}</lastmod>", crate::rfc3339(lastmod)));
+        }
+        xml.push_str("</url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
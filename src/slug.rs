@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transliteration {
+    /// Keep letters as they are
+    #[default]
+    None,
+    /// Drop diacritics, so `ä` becomes `a` (the usual Finnish convention)
+    StripAccents,
+    /// Spell out umlauts, so `ä` becomes `ae` (the usual German convention), then drop other diacritics
+    ExpandUmlauts
+}
+
+/// How slugs for post ids, tag pages and heading anchors are produced.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SlugConfig {
+    pub transliterate: Transliteration,
+    /// Replacements applied to the text before anything else, e.g. `"&" = "and"`
+    pub replace: HashMap<String, String>,
+    /// Keep non-ASCII letters and digits, percent-encoded where they appear in URLs
+    pub unicode: bool
+}
+
+impl Default for SlugConfig {
+    fn default() -> Self {
+        SlugConfig { transliterate: Transliteration::None, replace: HashMap::new(), unicode: true }
+    }
+}
+
+/// Letters that don't decompose into a base letter and a combining mark
+fn special_letter(c: char) -> Option<&'static str> {
+    Some(match c {
+        'ß' => "ss", 'æ' => "ae", 'œ' => "oe", 'ø' => "o",
+        'đ' => "d", 'ð' => "d", 'ł' => "l", 'þ' => "th",
+        _ => return None
+    })
+}
+
+impl SlugConfig {
+    fn transliterate(&self, s: &str) -> String {
+        let expanded: String = match self.transliterate {
+            Transliteration::None => return s.to_string(),
+            Transliteration::StripAccents => s.to_string(),
+            Transliteration::ExpandUmlauts => s.chars().map(|c| match c {
+                'ä' => "ae".into(), 'ö' => "oe".into(), 'ü' => "ue".into(),
+                c => c.to_string()
+            }).collect()
+        };
+        expanded.nfd()
+            .filter(|&c| !is_combining_mark(c))
+            .map(|c| special_letter(c).map(str::to_string).unwrap_or_else(|| c.to_string()))
+            .collect()
+    }
+
+    pub fn slugify(&self, s: &str) -> String {
+        let mut replaced = s.to_string();
+        // Longest patterns first, so that e.g. `C++` wins over `+`
+        let mut patterns: Vec<(&String, &String)> = self.replace.iter().collect();
+        patterns.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.0.cmp(b.0)));
+        for (from, to) in patterns {
+            replaced = replaced.replace(from.as_str(), to);
+        }
+
+        let lower: String = replaced.chars().flat_map(char::to_lowercase).collect();
+        let mut slug = String::new();
+        for c in self.transliterate(&lower).chars() {
+            if c.is_ascii_alphanumeric() || (self.unicode && c.is_alphanumeric()) {
+                slug.push(c);
+            } else if !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            }
+        }
+        slug.trim_end_matches('-').to_string()
+    }
+
+    /// Like `slugify`, but uses `fallback` if nothing of `s` is left.
+    pub fn slugify_or(&self, s: &str, fallback: &str) -> String {
+        match self.slugify(s) { slug if slug.is_empty() => fallback.to_string(), slug => slug }
+    }
+
+    /// Like `slugify_or`, but appends `-2`, `-3`, ... until `taken` no longer matches.
+    pub fn unique(&self, s: &str, fallback: &str, taken: impl Fn(&str) -> bool) -> String {
+        let slug = self.slugify_or(s, fallback);
+        let mut unique = slug.clone();
+        for n in 2.. {
+            if !taken(&unique) { break }
+            unique = format!("{}-{}", slug, n);
+        }
+        unique
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(transliterate: Transliteration, unicode: bool) -> SlugConfig {
+        SlugConfig { transliterate, unicode, ..SlugConfig::default() }
+    }
+
+    #[test]
+    fn finnish_titles() {
+        let strip = config(Transliteration::StripAccents, false);
+        assert_eq!(strip.slugify("Sää on kaunis"), "saa-on-kaunis");
+        assert_eq!(strip.slugify("Öljyä ja ÅÄÖ"), "oljya-ja-aao");
+        // Collisions are found after transliteration
+        let taken = strip.slugify("saa");
+        assert_eq!(strip.unique("sää", "post", |slug| slug == taken), "saa-2");
+        assert_eq!(config(Transliteration::None, true).slugify("Sää"), "sää");
+        assert_eq!(config(Transliteration::None, false).slugify("Sää on"), "s-on");
+    }
+
+    #[test]
+    fn german_titles() {
+        let expand = config(Transliteration::ExpandUmlauts, false);
+        assert_eq!(expand.slugify("Grüße aus Köln"), "gruesse-aus-koeln");
+        assert_eq!(expand.slugify("ÄRGER über Café"), "aerger-ueber-cafe");
+        assert_eq!(config(Transliteration::StripAccents, false).slugify("Grüße aus Köln"), "grusse-aus-koln");
+    }
+
+    #[test]
+    fn cjk_titles() {
+        let unicode = config(Transliteration::None, true);
+        assert_eq!(unicode.slugify("日本語のタイトル"), "日本語のタイトル");
+        assert_eq!(unicode.slugify("Rust と 日本語"), "rust-と-日本語");
+        // Transliteration leaves letters without diacritics alone
+        assert_eq!(config(Transliteration::StripAccents, true).slugify("中文 标题"), "中文-标题");
+        let ascii = config(Transliteration::None, false);
+        assert_eq!(ascii.slugify("日本語"), "");
+        assert_eq!(ascii.slugify_or("日本語", "post"), "post");
+        assert_eq!(ascii.slugify("Rust と 日本語"), "rust");
+    }
+
+    #[test]
+    fn replacements_come_first_and_longest_first() {
+        let mut config = config(Transliteration::StripAccents, false);
+        config.replace = HashMap::from([("+".into(), " plus ".into()), ("C++".into(), "cpp".into()), ("&".into(), "and".into())]);
+        assert_eq!(config.slugify("C++ & C+"), "cpp-and-c-plus");
+    }
+}
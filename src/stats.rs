@@ -0,0 +1,118 @@
+use std::collections::{BTreeMap, HashMap};
+use chrono::Datelike;
+use serde::Serialize;
+use crate::post::Post;
+
+#[derive(Debug, Serialize)]
+pub struct YearStats {
+    pub year: i32,
+    pub posts: usize,
+    pub words: usize
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagCount<'a> {
+    pub tag: &'a str,
+    pub count: usize
+}
+
+#[derive(Debug, Serialize)]
+pub struct LongestPost<'a> {
+    pub id: &'a str,
+    pub title: &'a str,
+    pub words: usize
+}
+
+#[derive(Debug, Serialize)]
+pub struct SiteStats<'a> {
+    pub posts: usize,
+    pub words: usize,
+    pub average_reading_minutes: f64,
+    /// Newest year first
+    pub years: Vec<YearStats>,
+    /// Most used tag first, ties broken by name
+    pub tags: Vec<TagCount<'a>>,
+    pub longest: Option<LongestPost<'a>>
+}
+
+/// Aggregates over the given posts; `tag_names` maps tag spellings to their canonical tag.
+pub fn site_stats<'a>(posts: &[&'a Post], tag_names: &'a HashMap<String, String>) -> SiteStats<'a> {
+    let words: usize = posts.iter().map(|p| p.words).sum();
+
+    let mut years: BTreeMap<i32, YearStats> = BTreeMap::new();
+    let mut tags: BTreeMap<&str, usize> = BTreeMap::new();
+    for &post in posts {
+        let year = crate::dt_toml_to_chrono(&post.meta.date).year();
+        let stats = years.entry(year).or_insert(YearStats { year, posts: 0, words: 0 });
+        stats.posts += 1;
+        stats.words += post.words;
+
+        let mut seen = Vec::new();
+        for tag in &post.meta.tags {
+            let tag = tag_names.get(tag).unwrap_or(tag).as_str();
+            if !seen.contains(&tag) {
+                seen.push(tag);
+                *tags.entry(tag).or_default() += 1;
+            }
+        }
+    }
+
+    let mut tags: Vec<TagCount> = tags.into_iter().map(|(tag, count)| TagCount { tag, count }).collect();
+    tags.sort_by(|a, b| b.count.cmp(&a.count).then(a.tag.cmp(b.tag)));
+
+    SiteStats {
+        posts: posts.len(),
+        words,
+        average_reading_minutes: if posts.is_empty() { 0.0 } else {
+            posts.iter().map(|p| p.reading_time).sum::<f64>() / posts.len() as f64
+        },
+        years: years.into_values().rev().collect(),
+        tags,
+        longest: posts.iter()
+            .max_by(|a, b| a.words.cmp(&b.words).then(b.id.cmp(&a.id)))
+            .map(|&p| LongestPost { id: &p.id, title: &p.meta.title, words: p.words })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::post::test_post;
+
+    fn post(id: &str, date: &str, words: usize, tags: &[&str]) -> Post {
+        let mut post = test_post(id, date);
+        post.words = words;
+        post.reading_time = words as f64 / 200.0;
+        post.meta.tags = tags.iter().map(|t| t.to_string()).collect();
+        post
+    }
+
+    #[test]
+    fn stats_of_no_posts() {
+        let tag_names = HashMap::new();
+        let stats = site_stats(&[], &tag_names);
+        assert_eq!((stats.posts, stats.words, stats.average_reading_minutes), (0, 0, 0.0));
+        assert!(stats.years.is_empty() && stats.tags.is_empty() && stats.longest.is_none());
+    }
+
+    #[test]
+    fn stats_by_year_and_tag() {
+        let posts = [
+            post("a", "2023-05-01", 100, &["rust", "Rust"]),
+            post("b", "2024-01-01", 300, &["rust", "go"]),
+            post("c", "2024-12-31T23:00:00+00:00", 200, &["go", "web"]),
+            post("d", "2022-02-02", 300, &[])
+        ];
+        let tag_names = HashMap::from([("Rust".to_string(), "rust".to_string())]);
+        let stats = site_stats(&posts.iter().collect::<Vec<_>>(), &tag_names);
+        assert_eq!((stats.posts, stats.words, stats.average_reading_minutes), (4, 900, 1.125));
+        let years: Vec<_> = stats.years.iter().map(|y| (y.year, y.posts, y.words)).collect();
+        assert_eq!(years, [(2024, 2, 500), (2023, 1, 100), (2022, 1, 300)]);
+        // Spellings of one tag count once per post
+        let tags: Vec<_> = stats.tags.iter().map(|t| (t.tag, t.count)).collect();
+        assert_eq!(tags, [("go", 2), ("rust", 2), ("web", 1)]);
+        // Of posts just as long, the one whose id sorts first
+        let longest = stats.longest.unwrap();
+        assert_eq!((longest.id, longest.title, longest.words), ("b", "B", 300));
+    }
+}
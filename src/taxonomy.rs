@@ -0,0 +1,214 @@
+use std::{collections::{BTreeMap, HashMap}, path::Path};
+use chrono::Datelike;
+use serde::Serialize;
+use crate::post::Post;
+
+#[derive(Debug, Serialize)]
+pub struct TagSummary<'a> {
+    pub name: &'a str,
+    pub count: usize,
+    pub slug: &'a str,
+    pub url: String
+}
+
+#[derive(Debug, Serialize)]
+pub struct LetterGroup<'a> {
+    pub letter: String,
+    pub tags: Vec<&'a TagSummary<'a>>
+}
+
+/// The heading a tag is listed under in an alphabetical index. Latin letters get their
+/// own group (so `Ä` is separate from `A`), everything else is collected under `#`.
+fn initial(name: &str) -> String {
+    match name.chars().next() {
+        Some(c) if c.is_alphabetic() && (c as u32) < 0x250 => c.to_uppercase().collect(),
+        _ => "#".into()
+    }
+}
+
+pub fn alphabetical_groups<'a>(tags: &'a [TagSummary<'a>]) -> Vec<LetterGroup<'a>> {
+    let mut sorted: Vec<&TagSummary> = tags.iter().collect();
+    sorted.sort_by_key(|t| (initial(t.name) == "#", initial(t.name), t.name.to_lowercase()));
+
+    let mut groups: Vec<LetterGroup> = Vec::new();
+    for tag in sorted {
+        let letter = initial(tag.name);
+        match groups.last_mut() {
+            Some(group) if group.letter == letter => group.tags.push(tag),
+            _ => groups.push(LetterGroup { letter, tags: vec![tag] })
+        }
+    }
+    groups
+}
+
+#[derive(Debug, Serialize)]
+pub struct SectionSummary {
+    pub name: String,
+    pub count: usize
+}
+
+#[derive(Debug, Serialize)]
+pub struct YearSummary {
+    pub year: i32,
+    pub count: usize,
+    /// URL of the year's archive page, which is only built with a `year` template
+    pub url: String
+}
+
+/// Site-wide lists for navigation menus, given to every template as `nav`
+#[derive(Debug, Serialize)]
+pub struct Navigation<'a> {
+    /// Tags sorted by name, ignoring case
+    pub tags: Vec<TagSummary<'a>>,
+    /// Top-level directories of the posts directory that hold posts, sorted by name
+    pub sections: Vec<SectionSummary>,
+    /// Newest first
+    pub years: Vec<YearSummary>
+}
+
+/// The top-level directory of `posts_dir` that `post` is in, if it isn't a post directory itself.
+fn section(post: &Post, posts_dir: &Path) -> Option<String> {
+    let relative = post.file.strip_prefix(posts_dir).ok()?;
+    let mut dirs = relative.parent()?.components();
+    let first = dirs.next()?;
+    // `posts/name/index.md` is a post directory, not a section
+    let is_post_dir = dirs.next().is_none() && relative.file_name().is_some_and(|n| n == "index.md");
+    (!is_post_dir).then(|| first.as_os_str().to_string_lossy().into_owned())
+}
+
+/// Counts `posts` by canonical tag, section and year. `base_path` is the path of `base_url`, put
+/// before the URLs.
+pub fn navigation<'a>(
+    posts: &[&Post],
+    posts_dir: &Path,
+    tag_names: &'a HashMap<String, String>,
+    tag_slugs: &'a HashMap<String, String>,
+    base_path: &str
+) -> Navigation<'a> {
+    let mut tags: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut sections: BTreeMap<String, usize> = BTreeMap::new();
+    let mut years: BTreeMap<i32, usize> = BTreeMap::new();
+    for post in posts {
+        let mut seen = Vec::new();
+        for tag in &post.meta.tags {
+            let Some(tag) = tag_names.get(tag) else { continue };
+            if !seen.contains(&tag) {
+                seen.push(tag);
+                *tags.entry(tag).or_default() += 1;
+            }
+        }
+        if let Some(section) = section(post, posts_dir) {
+            *sections.entry(section).or_default() += 1;
+        }
+        *years.entry(crate::dt_toml_to_chrono(&post.meta.date).year()).or_default() += 1;
+    }
+
+    let mut tags: Vec<TagSummary> = tags.into_iter()
+        .map(|(name, count)| {
+            let slug = tag_slugs.get(name).map_or(name, String::as_str);
+            TagSummary { name, count, slug, url: format!("{}/tags/{}.html", base_path, urlencoding::encode(slug)) }
+        })
+        .collect();
+    tags.sort_by_key(|t| t.name.to_lowercase());
+    Navigation {
+        tags,
+        sections: sections.into_iter().map(|(name, count)| SectionSummary { name, count }).collect(),
+        years: years.into_iter().rev()
+            .map(|(year, count)| YearSummary { year, count, url: format!("{}/archive/{}.html", base_path, year) })
+            .collect()
+    }
+}
+
+/// A way of grouping posts, like tags, with a page for each of its terms
+pub struct Taxonomy<'a> {
+    /// Template of the term pages, and the name they are given the term under
+    pub template: &'static str,
+    /// Output directory of the term pages
+    pub dir: &'static str,
+    /// Slug of each term
+    pub slugs: &'a HashMap<String, String>,
+    /// Inline markdown describing terms, matched to them ignoring case
+    pub descriptions: &'a BTreeMap<String, String>
+}
+
+impl Taxonomy<'_> {
+    /// Output path of the first page of `term`.
+    pub fn outpath(&self, term: &str) -> String {
+        format!("{}/{}.html", self.dir, self.slugs[term])
+    }
+}
+
+/// Groups `posts` by the terms `terms` gives each, keeping their order. A post is listed once
+/// under a term even if it gives it more than once.
+pub fn group<'a>(posts: impl IntoIterator<Item=&'a Post>, terms: impl Fn(&'a Post) -> Vec<&'a str>) -> BTreeMap<&'a str, Vec<&'a Post>> {
+    let mut groups: BTreeMap<&str, Vec<&Post>> = BTreeMap::new();
+    for post in posts {
+        for term in terms(post) {
+            let posts = groups.entry(term).or_default();
+            if !posts.iter().any(|p| std::ptr::eq(*p, post)) {
+                posts.push(post);
+            }
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(name: &str) -> TagSummary<'_> {
+        TagSummary { name, count: 1, slug: name, url: String::new() }
+    }
+
+    #[test]
+    fn initials_of_latin_letters_only() {
+        assert_eq!(initial("apple"), "A");
+        assert_eq!(initial("Äpfel"), "Ä");
+        assert_eq!(initial("émile"), "É");
+        assert_eq!(initial("東京"), "#");
+        assert_eq!(initial("Привет"), "#");
+        assert_eq!(initial("1password"), "#");
+        assert_eq!(initial(""), "#");
+    }
+
+    #[test]
+    fn groups_keep_umlauts_apart_and_non_latin_last() {
+        let tags: Vec<TagSummary> = ["東京", "beta", "Äpfel", "alpha", "Apple", "ärger", "Привет", "2024"].into_iter().map(summary).collect();
+        let groups = alphabetical_groups(&tags);
+        let groups: Vec<(&str, Vec<&str>)> = groups.iter()
+            .map(|g| (g.letter.as_str(), g.tags.iter().map(|t| t.name).collect()))
+            .collect();
+        assert_eq!(groups, [
+            ("A", vec!["alpha", "Apple"]),
+            ("B", vec!["beta"]),
+            ("Ä", vec!["Äpfel", "ärger"]),
+            ("#", vec!["2024", "Привет", "東京"])
+        ]);
+    }
+
+    #[test]
+    fn tags_paginate_only_past_one_page() {
+        let post = |name: &str, day: u32, tags: &str| (
+            format!("posts/{}.md", name),
+            format!("+++\ntitle = \"{}\"\ndate = 2024-01-{:02}\ntags = [{}]\n+++\nText", name, day, tags)
+        );
+        let posts = [post("a", 1, "\"one\", \"many\""), post("b", 2, "\"many\""), post("c", 3, "\"many\", \"Über\"")];
+        let mut files = crate::tests::TEMPLATES.to_vec();
+        files.extend([
+            ("site.toml", "tag_paginate = 2"),
+            ("templates/tag.html", "{% for post in posts %}{{ post.id }} {% endfor %}{{ pagination.current }}/{{ pagination.total }}"),
+            ("templates/tags.html", "{% for group in groups %}{{ group.letter }}:{% for tag in group.tags %} {{ tag.name }}{% endfor %}\n{% endfor %}")
+        ]);
+        files.extend(posts.iter().map(|(path, contents)| (path.as_str(), contents.as_str())));
+        let outputs = crate::build_test_site("tag-pagination", &files, &[]);
+        let text = |path: &str| String::from_utf8(outputs[path].clone()).unwrap();
+
+        assert_eq!(text("tags/one.html"), "a 1/1");
+        assert!(!outputs.contains_key("tags/one/page/2.html"));
+        assert_eq!(text("tags/many.html"), "c b 1/2");
+        assert_eq!(text("tags/many/page/2.html"), "a 2/2");
+        assert!(!outputs.contains_key("tags/many/page/3.html"));
+        assert_eq!(text("tags/index.html"), "M: many\nO: one\nÜ: Über\n");
+    }
+}
@@ -0,0 +1,148 @@
+use std::{collections::HashMap, io, time::Duration};
+use serde::Serialize;
+
+/// How long a page took to render, including building its context
+#[derive(Debug)]
+pub struct PageTiming {
+    pub page: String,
+    pub template: String,
+    pub duration: Duration,
+    /// Size of the context serialized as JSON, an estimate of how much data the template was given
+    pub context_bytes: u64
+}
+
+/// How long a post took to build, or to take from the post cache
+#[derive(Debug)]
+pub struct PostTiming {
+    pub post: String,
+    pub duration: Duration,
+    pub reused: bool
+}
+
+/// What a build produced, counted as it goes for the summary at its end
+#[derive(Debug, Default)]
+pub struct BuildCounts {
+    pub posts: usize,
+    /// Posts taken from the post cache instead of built again
+    pub reused: usize,
+    pub scheduled: usize,
+    pub pages: usize,
+    pub assets: usize,
+    pub asset_bytes: u64,
+    pub static_files: usize
+}
+
+/// How long a phase of the build took, like building posts
+#[derive(Debug)]
+pub struct PhaseTiming {
+    pub name: &'static str,
+    pub items: usize,
+    pub duration: Duration
+}
+
+struct ByteCounter(u64);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+/// Size of `context` serialized as JSON, without keeping the serialized copy around.
+pub fn context_size<C: Serialize>(context: &C) -> u64 {
+    let mut counter = ByteCounter(0);
+    match serde_json::to_writer(&mut counter, context) {
+        Ok(()) => counter.0,
+        Err(e) => {
+            log!("warning: could not estimate context size: {}", e);
+            0
+        }
+    }
+}
+
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Prints what the build produced, its warnings and errors, and how long each phase and the whole build took.
+pub fn summary(counts: &BuildCounts, phases: &[PhaseTiming], warnings: usize, errors: usize, total: Duration) {
+    let mut message = String::from("info: summary:");
+    let mut row = |label: &str, count: usize, note: String| {
+        message.push_str(&format!("\n    {:<14}{:>6}{}", label, count, note));
+    };
+    let mut notes = Vec::new();
+    if counts.reused > 0 { notes.push(format!("{} reused", counts.reused)) }
+    if counts.scheduled > 0 { notes.push(format!("{} scheduled", counts.scheduled)) }
+    row("posts", counts.posts, if notes.is_empty() { String::new() } else { format!("  ({})", notes.join(", ")) });
+    row("pages", counts.pages, String::new());
+    row("assets", counts.assets, format!("  ({:.1} KiB)", counts.asset_bytes as f64 / 1024.0));
+    row("static files", counts.static_files, String::new());
+    row("warnings", warnings, String::new());
+    row("errors", errors, String::new());
+    for phase in phases {
+        message.push_str(&format!("\n    {:<22}{:>10.2} ms", phase.name, millis(phase.duration)));
+    }
+    message.push_str(&format!("\n    {:<22}{:>10.2} ms", "total", millis(total)));
+    log!("{message}");
+}
+
+/// Prints the time taken by each phase, the `count` slowest posts and pages, and the cumulative
+/// render time of each template.
+pub fn report(timings: &[PageTiming], posts: &[PostTiming], phases: &[PhaseTiming], count: usize) {
+    if !phases.is_empty() {
+        let mut message = String::from("info: timings: phases:");
+        for phase in phases {
+            message.push_str(&format!("\n    {:>8.2} ms  {} ({} items)", millis(phase.duration), phase.name, phase.items));
+        }
+        log!("{message}");
+    }
+    if !posts.is_empty() {
+        let mut slowest: Vec<&PostTiming> = posts.iter().collect();
+        slowest.sort_by(|a, b| b.duration.cmp(&a.duration).then(a.post.cmp(&b.post)));
+        let mut message = String::from("info: timings: slowest posts:");
+        for timing in slowest.iter().take(count.max(1)) {
+            let reused = if timing.reused { " (reused)" } else { "" };
+            message.push_str(&format!("\n    {:>8.2} ms  {}{}", millis(timing.duration), timing.post, reused));
+        }
+        log!("{message}");
+    }
+    if timings.is_empty() { return }
+    let total: Duration = timings.iter().map(|t| t.duration).sum();
+    let bytes: u64 = timings.iter().map(|t| t.context_bytes).sum();
+    log!(
+        "info: timings: rendered {} pages in {:.1} ms, with {:.1} KiB of context",
+        timings.len(), millis(total), bytes as f64 / 1024.0
+    );
+
+    let mut slowest: Vec<&PageTiming> = timings.iter().collect();
+    slowest.sort_by(|a, b| b.duration.cmp(&a.duration).then(a.page.cmp(&b.page)));
+    let mut message = String::from("info: timings: slowest pages:");
+    for timing in slowest.iter().take(count.max(1)) {
+        message.push_str(&format!(
+            "\n    {:>8.2} ms  {:>9.1} KiB context  {} ({})",
+            millis(timing.duration), timing.context_bytes as f64 / 1024.0, timing.page, timing.template
+        ));
+    }
+    log!("{message}");
+
+    let mut templates: HashMap<&str, (Duration, usize, u64)> = HashMap::new();
+    for timing in timings {
+        let entry = templates.entry(&timing.template).or_default();
+        entry.0 += timing.duration;
+        entry.1 += 1;
+        entry.2 += timing.context_bytes;
+    }
+    let mut templates: Vec<_> = templates.into_iter().collect();
+    templates.sort_by(|a, b| b.1.0.cmp(&a.1.0).then(a.0.cmp(b.0)));
+    let mut message = String::from("info: timings: templates by total time:");
+    for (template, (duration, pages, bytes)) in templates {
+        message.push_str(&format!(
+            "\n    {:>8.2} ms  {:>9.1} KiB context  {} ({} pages)",
+            millis(duration), bytes as f64 / 1024.0, template, pages
+        ));
+    }
+    log!("{message}");
+}
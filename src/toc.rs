@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use crate::post::Section;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReadingConfig {
+    pub words_per_minute: f64,
+    /// Code is read more slowly than prose, but shouldn't count as nothing
+    pub code_lines_per_minute: f64
+}
+
+impl Default for ReadingConfig {
+    fn default() -> Self {
+        ReadingConfig { words_per_minute: 230.0, code_lines_per_minute: 20.0 }
+    }
+}
+
+impl ReadingConfig {
+    /// Estimated reading time of a section, in minutes.
+    pub fn minutes(&self, section: &Section) -> f64 {
+        let words = section.text.split_whitespace().count() as f64;
+        words / self.words_per_minute.max(1.0) + section.code_lines as f64 / self.code_lines_per_minute.max(1.0)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TocEntry {
+    pub level: u8,
+    pub anchor: String,
+    pub title: String,
+    pub words: usize,
+    pub code_lines: usize,
+    /// Estimated reading time in minutes
+    pub reading_time: f64,
+    /// Share of the post's reading time before this section starts, from 0 to 100
+    pub start_percent: f64
+}
+
+/// Builds the table of contents, returning it and the reading time of the whole post in minutes.
+/// Text before the first heading has no entry, but counts towards the total and the offsets.
+pub fn table_of_contents(sections: &[Section], config: &ReadingConfig) -> (Vec<TocEntry>, f64) {
+    let total: f64 = sections.iter().map(|s| config.minutes(s)).sum();
+
+    let mut entries = Vec::new();
+    let mut elapsed = 0.0;
+    for section in sections {
+        let minutes = config.minutes(section);
+        if let Some(anchor) = &section.anchor {
+            entries.push(TocEntry {
+                level: section.level,
+                anchor: anchor.clone(),
+                title: section.heading.clone(),
+                words: section.text.split_whitespace().count(),
+                code_lines: section.code_lines,
+                reading_time: minutes,
+                start_percent: if total > 0.0 { elapsed / total * 100.0 } else { 0.0 }
+            });
+        }
+        elapsed += minutes;
+    }
+    (entries, total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(anchor: Option<&str>, words: usize, code_lines: usize) -> Section {
+        Section {
+            anchor: anchor.map(str::to_string), level: if anchor.is_some() { 2 } else { 0 },
+            heading: anchor.unwrap_or_default().to_uppercase(), text: "word ".repeat(words), code_lines
+        }
+    }
+
+    #[test]
+    fn section_times_sum_to_the_post_total() {
+        let config = ReadingConfig { words_per_minute: 200.0, code_lines_per_minute: 20.0 };
+        let sections = [section(None, 100, 0), section(Some("a"), 300, 0), section(Some("b"), 0, 40), section(Some("c"), 100, 10)];
+        let (toc, total) = table_of_contents(&sections, &config);
+        assert_eq!(total, 0.5 + 1.5 + 2.0 + 1.0);
+        let times: Vec<f64> = toc.iter().map(|e| e.reading_time).collect();
+        assert_eq!(times, [1.5, 2.0, 1.0]);
+        // The intro has no entry but still counts
+        assert_eq!(times.iter().sum::<f64>() + config.minutes(&sections[0]), total);
+        let starts: Vec<f64> = toc.iter().map(|e| e.start_percent).collect();
+        assert_eq!(starts, [10.0, 40.0, 80.0]);
+        // Code only sections take time
+        assert_eq!((toc[1].words, toc[1].code_lines), (0, 40));
+    }
+
+    #[test]
+    fn empty_posts_take_no_time() {
+        let (toc, total) = table_of_contents(&[section(Some("a"), 0, 0)], &ReadingConfig::default());
+        assert_eq!(total, 0.0);
+        assert_eq!((toc[0].reading_time, toc[0].start_percent), (0.0, 0.0));
+        assert_eq!(table_of_contents(&[], &ReadingConfig::default()).1, 0.0);
+    }
+}
@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+
+pub const NBSP: char = '\u{a0}';
+/// French typography puts a narrow space before `? ! ;`, and a full one before `:`
+pub const NARROW_NBSP: char = '\u{202f}';
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TypographyConfig {
+    /// Words that stay on the same line as a number before them, like `GB` in `10 GB`
+    pub units: Vec<String>,
+    /// Abbreviations that stay on the same line as a number after them, like `fig.` in `fig. 3`, ignoring case
+    pub prefixes: Vec<String>,
+    /// Phrases that are never broken across lines
+    pub phrases: Vec<String>
+}
+
+impl Default for TypographyConfig {
+    fn default() -> Self {
+        let words = |words: &[&str]| words.iter().map(|w| w.to_string()).collect();
+        TypographyConfig {
+            units: words(&["B", "KB", "MB", "GB", "TB", "KiB", "MiB", "GiB", "TiB", "ms", "s", "min", "h",
+                "mm", "cm", "m", "km", "g", "kg", "Hz", "kHz", "MHz", "GHz", "W", "kW", "V", "%", "°C", "°F", "px"]),
+            prefixes: words(&["fig.", "p.", "pp.", "no.", "vol.", "ch.", "sec.", "eq."]),
+            phrases: Vec::new()
+        }
+    }
+}
+
+fn is_url(token: &str) -> bool {
+    token.contains("://") || token.starts_with("www.")
+}
+
+impl TypographyConfig {
+    /// The space that should separate two adjacent words, if it shouldn't be breakable.
+    pub fn binding_space(&self, before: &str, after: &str, french: bool) -> Option<char> {
+        if before.is_empty() || after.is_empty() || is_url(before) || is_url(after) { return None }
+
+        if french && let Some(c) = after.chars().next() && matches!(c, '?' | '!' | ';' | ':') {
+            return Some(if c == ':' { NBSP } else { NARROW_NBSP })
+        }
+        let unit = after.trim_end_matches(['.', ',', ';', ':', '!', '?', ')']);
+        if before.ends_with(|c: char| c.is_ascii_digit()) && self.units.iter().any(|u| u == unit) {
+            return Some(NBSP)
+        }
+        if after.starts_with(|c: char| c.is_ascii_digit())
+            && self.prefixes.iter().any(|p| p.eq_ignore_ascii_case(before.trim_start_matches('(')))
+        {
+            return Some(NBSP)
+        }
+        None
+    }
+
+    /// Replaces the spaces in `text` that shouldn't break a line with non-breaking ones.
+    pub fn apply(&self, text: &str, french: bool) -> String {
+        let mut output = String::with_capacity(text.len());
+        for (i, c) in text.char_indices() {
+            let (before, after) = (&text[..i], &text[i + c.len_utf8()..]);
+            let joined = c == ' ' && !before.ends_with(char::is_whitespace) && !after.starts_with(char::is_whitespace);
+            let space = joined.then(|| self.binding_space(
+                before.split_whitespace().next_back().unwrap_or_default(),
+                after.split_whitespace().next().unwrap_or_default(),
+                french
+            )).flatten();
+            output.push(space.unwrap_or(c));
+        }
+
+        for phrase in &self.phrases {
+            let bound = phrase.replace(' ', &NBSP.to_string());
+            if phrase.contains(' ') && output.contains(phrase.as_str()) {
+                output = output.replace(phrase.as_str(), &bound);
+            }
+        }
+        output
+    }
+}
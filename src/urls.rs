@@ -0,0 +1,207 @@
+use std::{collections::BTreeMap, path::Path};
+use serde::{Deserialize, Serialize};
+
+/// Where each post was written by a build, kept to notice posts moving between builds
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UrlManifest {
+    /// Keyed by the post's markdown file, relative to the input directory
+    pub posts: BTreeMap<String, PostUrls>
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PostUrls {
+    /// Output path of the post, like `posts/hello.html`
+    pub output: String,
+    /// Earlier output paths that redirect to `output`
+    pub aliases: Vec<String>
+}
+
+/// A post whose output path changed since the last build
+#[derive(Debug)]
+pub struct Move<'a> {
+    pub source: &'a str,
+    pub old: &'a str,
+    pub new: &'a str
+}
+
+/// Reads the manifest of the last build, or `None` if there was none or it can't be read.
+pub fn load(path: &Path) -> Option<UrlManifest> {
+    let data = std::fs::read(path).ok()?;
+    serde_json::from_slice(&data)
+        .inspect_err(|e| log!("warning: ignoring unreadable URL manifest `{}`: {}", path.display(), e))
+        .ok()
+}
+
+pub fn save(path: &Path, manifest: &UrlManifest) {
+    let Ok(json) = serde_json::to_vec_pretty(manifest)
+        .inspect_err(|e| log!("error: could not serialize URL manifest: {}", e))
+        else { return };
+    if let Err(e) = path.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|_| std::fs::write(path, json)) {
+        log!("error: could not write URL manifest `{}`: {}", path.display(), e);
+    }
+}
+
+/// Posts built both times whose output path changed between `old` and `new`.
+pub fn moves<'a>(old: &'a UrlManifest, new: &'a BTreeMap<String, String>) -> Vec<Move<'a>> {
+    new.iter()
+        .filter_map(|(source, output)| {
+            let previous = old.posts.get(source)?;
+            (previous.output != *output).then_some(Move { source, old: &previous.output, new: output })
+        })
+        .collect()
+}
+
+/// The manifest for a build that wrote each post in `built` to its output path. Aliases are
+/// carried over, and the old paths of `moves` become aliases if `add_aliases` is set. Otherwise
+/// moved posts keep their old entries, so that they are reported again until aliased. Posts left
+/// out of this build, like drafts, keep their entries while `exists` says their file is there.
+pub fn next_manifest(
+    old: &UrlManifest,
+    built: &BTreeMap<String, String>,
+    moves: &[Move],
+    add_aliases: bool,
+    exists: impl Fn(&str) -> bool
+) -> UrlManifest {
+    let mut posts: BTreeMap<String, PostUrls> = old.posts.iter()
+        .filter(|(source, _)| !built.contains_key(*source) && exists(source))
+        .map(|(source, urls)| (source.clone(), urls.clone()))
+        .collect();
+    for (source, output) in built {
+        let moved = moves.iter().find(|m| m.source == source);
+        if moved.is_some() && !add_aliases && let Some(urls) = old.posts.get(source) {
+            posts.insert(source.clone(), urls.clone());
+            continue
+        }
+        let mut aliases = old.posts.get(source).map(|urls| urls.aliases.clone()).unwrap_or_default();
+        if let Some(moved) = moved && !aliases.iter().any(|a| a == moved.old) {
+            aliases.push(moved.old.to_string());
+        }
+        // A post that moved back to an old path doesn't redirect to itself
+        aliases.retain(|alias| alias != output);
+        posts.insert(source.clone(), PostUrls { output: output.clone(), aliases });
+    }
+    UrlManifest { posts }
+}
+
+/// Prints the posts that moved, old path first.
+pub fn report(moves: &[Move], aliases_written: bool) {
+    if moves.is_empty() { return }
+    let mut message = format!("warning: {} post URL(s) changed since the last build:", moves.len());
+    for moved in moves {
+        message.push_str(&format!("\n    /{} -> /{}  ({})", moved.old, moved.new, moved.source));
+    }
+    if aliases_written {
+        message.push_str("\n    the old URLs now redirect to the new ones");
+    } else {
+        message.push_str("\n    links to the old URLs will break, pass --write-aliases to redirect them or remove `.ssg-cache/urls.json` to accept the change");
+    }
+    log!("{message}");
+}
+
+/// A page that sends readers on to `target`.
+pub fn redirect_page(target: &str) -> String {
+    let mut escaped = String::new();
+    let _ = pulldown_cmark_escape::escape_href(&mut escaped, target);
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<meta http-equiv=\"refresh\" content=\"0; url={0}\">\n<link rel=\"canonical\" href=\"{0}\">\n<title>Moved</title>\n</head>\n<body>\n<p>This page has moved to <a href=\"{0}\">{0}</a>.</p>\n</body>\n</html>\n",
+        escaped
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn built(posts: &[(&str, &str)]) -> BTreeMap<String, String> {
+        posts.iter().map(|(source, output)| (source.to_string(), output.to_string())).collect()
+    }
+
+    fn manifest(posts: &[(&str, &str, &[&str])]) -> UrlManifest {
+        UrlManifest { posts: posts.iter()
+            .map(|(source, output, aliases)| (source.to_string(), PostUrls {
+                output: output.to_string(),
+                aliases: aliases.iter().map(|a| a.to_string()).collect()
+            }))
+            .collect()
+        }
+    }
+
+    fn entries(manifest: &UrlManifest) -> Vec<(&str, &str, Vec<&str>)> {
+        manifest.posts.iter()
+            .map(|(source, urls)| (source.as_str(), urls.output.as_str(), urls.aliases.iter().map(String::as_str).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn rebuilds_without_changes_move_nothing() {
+        let old = manifest(&[("posts/a.md", "posts/a.html", &["posts/old-a.html"]), ("posts/b.md", "posts/b.html", &[])]);
+        let new = built(&[("posts/a.md", "posts/a.html"), ("posts/b.md", "posts/b.html")]);
+        let moves = moves(&old, &new);
+        assert!(moves.is_empty());
+        for add_aliases in [false, true] {
+            assert_eq!(entries(&next_manifest(&old, &new, &moves, add_aliases, |_| true)), entries(&old));
+        }
+    }
+
+    #[test]
+    fn renamed_posts_get_aliases_only_when_asked() {
+        let old = manifest(&[("posts/a.md", "posts/a.html", &[]), ("posts/b.md", "posts/b.html", &[])]);
+        let new = built(&[("posts/a.md", "posts/renamed.html"), ("posts/b.md", "posts/b.html"), ("posts/c.md", "posts/c.html")]);
+        let moves = moves(&old, &new);
+        assert_eq!(moves.iter().map(|m| (m.source, m.old, m.new)).collect::<Vec<_>>(), [("posts/a.md", "posts/a.html", "posts/renamed.html")]);
+
+        // Left as it was, to be reported again
+        assert_eq!(entries(&next_manifest(&old, &new, &moves, false, |_| true)), [
+            ("posts/a.md", "posts/a.html", vec![]),
+            ("posts/b.md", "posts/b.html", vec![]),
+            ("posts/c.md", "posts/c.html", vec![])
+        ]);
+        assert_eq!(entries(&next_manifest(&old, &new, &moves, true, |_| true)), [
+            ("posts/a.md", "posts/renamed.html", vec!["posts/a.html"]),
+            ("posts/b.md", "posts/b.html", vec![]),
+            ("posts/c.md", "posts/c.html", vec![])
+        ]);
+    }
+
+    #[test]
+    fn format_changes_move_every_post_and_keep_earlier_aliases() {
+        let old = manifest(&[("posts/café.md", "posts/café.html", &["posts/cafe-old.html"]), ("posts/ünï.md", "posts/ünï.html", &[])]);
+        let new = built(&[("posts/café.md", "posts/cafe.html"), ("posts/ünï.md", "posts/uni.html")]);
+        let moves = moves(&old, &new);
+        assert_eq!(moves.len(), 2);
+        let next = next_manifest(&old, &new, &moves, true, |_| true);
+        assert_eq!(entries(&next), [
+            ("posts/café.md", "posts/cafe.html", vec!["posts/cafe-old.html", "posts/café.html"]),
+            ("posts/ünï.md", "posts/uni.html", vec!["posts/ünï.html"])
+        ]);
+
+        // Changing the format back doesn't redirect posts to themselves or repeat aliases
+        let back = built(&[("posts/café.md", "posts/café.html"), ("posts/ünï.md", "posts/ünï.html")]);
+        let moves = super::moves(&next, &back);
+        assert_eq!(entries(&next_manifest(&next, &back, &moves, true, |_| true)), [
+            ("posts/café.md", "posts/café.html", vec!["posts/cafe-old.html", "posts/cafe.html"]),
+            ("posts/ünï.md", "posts/ünï.html", vec!["posts/uni.html"])
+        ]);
+    }
+
+    #[test]
+    fn unbuilt_posts_are_kept_while_their_file_is_there() {
+        let old = manifest(&[("posts/draft.md", "posts/draft.html", &["posts/d.html"]), ("posts/gone.md", "posts/gone.html", &[])]);
+        let next = next_manifest(&old, &BTreeMap::new(), &[], true, |source| source == "posts/draft.md");
+        assert_eq!(entries(&next), [("posts/draft.md", "posts/draft.html", vec!["posts/d.html"])]);
+    }
+
+    #[test]
+    fn manifests_round_trip() {
+        let dir = crate::test_dir("url-manifest");
+        let path = dir.join("cache/urls.json");
+        assert!(load(&path).is_none());
+        let old = manifest(&[("posts/a.md", "posts/b.html", &["posts/a.html"])]);
+        save(&path, &old);
+        assert_eq!(entries(&load(&path).unwrap()), entries(&old));
+        std::fs::write(&path, "not json").unwrap();
+        assert!(load(&path).is_none());
+    }
+}
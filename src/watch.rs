@@ -0,0 +1,176 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+/// Decides which changed paths should trigger a rebuild in watch mode.
+#[derive(Debug)]
+pub struct WatchFilter {
+    in_dir: PathBuf,
+    out_dir: PathBuf,
+    cache_dir: PathBuf,
+    globs: GlobSet
+}
+
+/// Suffixes of the temporary files editors write next to the file being edited
+const EDITOR_TEMP_SUFFIXES: &[&str] = &[".swp", ".swo", ".swx", "~", ".tmp", ".bak", ".kate-swp"];
+
+impl WatchFilter {
+    /// `globs` are matched against paths relative to `in_dir`; invalid globs are reported and skipped.
+    pub fn new(in_dir: &Path, out_dir: &Path, cache_dir: &Path, globs: &[String]) -> WatchFilter {
+        let mut builder = GlobSetBuilder::new();
+        for glob in globs {
+            match Glob::new(glob) {
+                Ok(glob) => { builder.add(glob); },
+                Err(e) => log!("error: invalid watch ignore pattern `{}`: {}", glob, e)
+            }
+        }
+        let globs = builder.build()
+            .inspect_err(|e| log!("error: could not build watch ignore patterns: {}", e))
+            .unwrap_or_else(|_| GlobSet::empty());
+        WatchFilter { in_dir: in_dir.to_path_buf(), out_dir: out_dir.to_path_buf(), cache_dir: cache_dir.to_path_buf(), globs }
+    }
+
+    /// Why a change to `path` should not trigger a rebuild, or `None` if it should.
+    pub fn ignore_reason(&self, path: &Path) -> Option<&'static str> {
+        if path.starts_with(&self.out_dir) { return Some("in the output directory") }
+        if path.starts_with(&self.cache_dir) { return Some("in the cache directory") }
+        let Ok(relative) = path.strip_prefix(&self.in_dir) else { return Some("outside the input directory") };
+
+        // Dotfiles under `root/` are copied to the output, so they count
+        let hidden = !relative.starts_with("root") && relative.components().flat_map(|c| c.as_os_str().to_str())
+            .any(|c| c.starts_with('.'));
+        if hidden { return Some("a hidden file") }
+
+        let name = relative.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        // `4913` is the file vim creates to check that a directory is writable
+        let editor_temp = EDITOR_TEMP_SUFFIXES.iter().any(|s| name.ends_with(s))
+            || (name.starts_with('#') && name.ends_with('#'))
+            || name == "4913";
+        if editor_temp { return Some("an editor temporary file") }
+
+        if self.globs.is_match(relative) { return Some("matched by watch_ignore") }
+        None
+    }
+}
+
+/// The templates added, removed and changed between two builds, in that order.
+fn template_changes<'a>(old: &'a BTreeMap<String, u64>, new: &'a BTreeMap<String, u64>) -> [(&'static str, Vec<&'a str>); 3] {
+    let added: Vec<&str> = new.keys().filter(|name| !old.contains_key(*name)).map(String::as_str).collect();
+    let removed: Vec<&str> = old.keys().filter(|name| !new.contains_key(*name)).map(String::as_str).collect();
+    let changed: Vec<&str> = new.iter()
+        .filter(|(name, hash)| old.get(*name).is_some_and(|old_hash| old_hash != *hash))
+        .map(|(name, _)| name.as_str())
+        .collect();
+    [("added", added), ("removed", removed), ("changed", changed)]
+}
+
+/// Logs which templates were added, removed or changed between two builds.
+pub fn report_template_changes(old: &BTreeMap<String, u64>, new: &BTreeMap<String, u64>) {
+    for (kind, names) in template_changes(old, new) {
+        if !names.is_empty() {
+            log!("info: templates {}: {}", kind, names.join(", "));
+        }
+    }
+}
+
+/// Deletes the files a previous build wrote to `out_dir` that the latest one didn't,
+/// such as the pages of a template that was removed. A build with errors may have missed pages
+/// it should have written, so their files are kept and still counted as outputs of `built`.
+pub fn remove_stale_outputs(out_dir: &Path, old: &BTreeMap<String, u64>, built: &mut crate::BuildResult) {
+    if built.fatal || !built.errors.is_empty() {
+        for (path, hash) in old {
+            built.outputs.entry(path.clone()).or_insert(*hash);
+        }
+        return
+    }
+    for path in old.keys().filter(|path| !built.outputs.contains_key(*path)) {
+        log!("info: removing `{}`, which is no longer built", path);
+        if let Err(e) = std::fs::remove_file(out_dir.join(path)) && e.kind() != std::io::ErrorKind::NotFound {
+            log!("error: could not remove `{}`: {}", path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_outputs_survive_failed_builds() {
+        let out_dir = crate::test_dir("stale-outputs");
+        std::fs::write(out_dir.join("kept.html"), "").unwrap();
+        std::fs::write(out_dir.join("gone.html"), "").unwrap();
+        let old = BTreeMap::from([("kept.html".to_string(), 1), ("gone.html".to_string(), 2)]);
+
+        let mut failed = crate::BuildResult { errors: vec!["error: broken".into()], ..Default::default() };
+        failed.outputs.insert("kept.html".into(), 3);
+        remove_stale_outputs(&out_dir, &old, &mut failed);
+        assert!(out_dir.join("gone.html").exists());
+        assert_eq!(failed.outputs.get("gone.html"), Some(&2));
+        assert_eq!(failed.outputs.get("kept.html"), Some(&3));
+
+        let mut built = crate::BuildResult::default();
+        built.outputs.insert("kept.html".into(), 3);
+        remove_stale_outputs(&out_dir, &failed.outputs, &mut built);
+        assert!(!out_dir.join("gone.html").exists());
+        assert!(out_dir.join("kept.html").exists());
+    }
+
+    fn filter(globs: &[&str]) -> WatchFilter {
+        let globs: Vec<String> = globs.iter().map(|g| g.to_string()).collect();
+        WatchFilter::new(Path::new("/site/in"), Path::new("/site/out"), Path::new("/site/in/.ssg-cache"), &globs)
+    }
+
+    #[test]
+    fn ignore_globs_are_relative_to_the_input_directory() {
+        let filter = filter(&["posts/wip/**", "*.psd", "in/static/**", "[invalid"]);
+        assert_eq!(filter.ignore_reason(Path::new("/site/in/posts/wip/idea.md")), Some("matched by watch_ignore"));
+        assert_eq!(filter.ignore_reason(Path::new("/site/in/images/cover.psd")), Some("matched by watch_ignore"));
+        // Patterns don't see the path of the input directory itself
+        assert_eq!(filter.ignore_reason(Path::new("/site/in/static/style.css")), None);
+        assert_eq!(filter.ignore_reason(Path::new("/site/in/posts/done.md")), None);
+    }
+
+    #[test]
+    fn builds_ignore_their_own_output_but_not_content() {
+        let filter = filter(&[]);
+        assert_eq!(filter.ignore_reason(Path::new("/site/out/index.html")), Some("in the output directory"));
+        assert_eq!(filter.ignore_reason(Path::new("/site/in/.ssg-cache/posts.json")), Some("in the cache directory"));
+        assert_eq!(filter.ignore_reason(Path::new("/elsewhere/post.md")), Some("outside the input directory"));
+        assert_eq!(filter.ignore_reason(Path::new("/site/in/posts/.post.md.swp")), Some("a hidden file"));
+        assert_eq!(filter.ignore_reason(Path::new("/site/in/posts/post.md~")), Some("an editor temporary file"));
+        assert_eq!(filter.ignore_reason(Path::new("/site/in/posts/4913")), Some("an editor temporary file"));
+        for path in ["posts/post.md", "templates/post.html", "static/style.css", "root/.well-known/webfinger", "site.toml"] {
+            assert_eq!(filter.ignore_reason(&Path::new("/site/in").join(path)), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn template_changes_between_rebuilds() {
+        let mut files = crate::tests::TEMPLATES.to_vec();
+        files.extend([
+            ("templates/archive.html", "{% for year in years %}{{ year.year }}{% endfor %}"),
+            ("posts/post.md", "+++\ntitle = \"Post\"\ndate = 2024-01-01\ntags = [\"x\"]\n+++\nText")
+        ]);
+        crate::build_test_site("template-changes", &files, &[]);
+        let (first, first_outputs) = crate::rebuild_test_site_result("template-changes", &[]);
+        assert_eq!(first.templates.keys().collect::<Vec<_>>(), ["archive", "index", "post"]);
+        assert!(first_outputs.contains_key("archive/index.html"));
+
+        let templates = crate::test_path("template-changes").join("in/templates");
+        std::fs::write(templates.join("tags.html"), "{% for tag in tags %}{{ tag.name }}{% endfor %}").unwrap();
+        std::fs::write(templates.join("post.html"), "<h1>{{ post.title_plain }}</h1>").unwrap();
+        std::fs::remove_file(templates.join("archive.html")).unwrap();
+        let (second, second_outputs) = crate::rebuild_test_site_result("template-changes", &[]);
+        assert_eq!(template_changes(&first.templates, &second.templates), [
+            ("added", vec!["tags"]), ("removed", vec!["archive"]), ("changed", vec!["post"])
+        ]);
+        assert_eq!(second_outputs["tags/index.html"], b"x");
+        assert_eq!(second_outputs["posts/post.html"], b"<h1>Post</h1>");
+        assert!(!second_outputs.contains_key("archive/index.html"));
+        assert_eq!(second_outputs["index.html"], first_outputs["index.html"]);
+
+        // A rebuild without changes reports none
+        let (third, _) = crate::rebuild_test_site_result("template-changes", &[]);
+        assert!(template_changes(&second.templates, &third.templates).iter().all(|(_, names)| names.is_empty()));
+    }
+}
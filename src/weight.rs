@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+use crate::{config::SiteConfig, html, output::OutputSink};
+
+#[derive(Debug)]
+pub struct PageWeight {
+    pub page: String,
+    pub html: u64,
+    pub assets: BTreeMap<String, u64>
+}
+
+impl PageWeight {
+    pub fn total(&self) -> u64 {
+        self.html + self.assets.values().sum::<u64>()
+    }
+}
+
+/// Computes the weight of every HTML page in `outputs`, counting the page itself
+/// (including inline SVG and data URIs) plus every local file it references.
+pub fn page_weights(sink: &dyn OutputSink, outputs: &BTreeMap<String, u64>, base_path: &str) -> Vec<PageWeight> {
+    let mut weights = Vec::new();
+    for (page, &size) in outputs {
+        if !page.ends_with(".html") { continue }
+        let Ok(source) = sink.read(page)
+            .inspect_err(|e| log!("error: could not read output `{}`: {}", page, e))
+            else { continue };
+        let source = String::from_utf8_lossy(&source);
+
+        let mut assets = BTreeMap::new();
+        for link in html::link_attributes(&source) {
+            let Some(path) = html::resolve_local_link(page, link, base_path) else { continue };
+            if path.ends_with(".html") || assets.contains_key(&path) { continue }
+            if let Some(&size) = outputs.get(&path) {
+                assets.insert(path, size);
+            }
+        }
+        weights.push(PageWeight { page: page.clone(), html: size, assets });
+    }
+    weights.sort_by(|a, b| b.total().cmp(&a.total()).then(a.page.cmp(&b.page)));
+    weights
+}
+
+pub fn report_page_weights(sink: &dyn OutputSink, outputs: &BTreeMap<String, u64>, config: &SiteConfig) {
+    let weights = page_weights(sink, outputs, &config.base_path());
+    if config.heaviest_pages > 0 && !weights.is_empty() {
+        let mut message = String::from("info: heaviest pages:");
+        for weight in weights.iter().take(config.heaviest_pages) {
+            message.push_str(&format!(
+                "\n    {:>8.1} KiB  {} ({:.1} KiB html, {} assets)",
+                weight.total() as f64 / 1024.0, weight.page, weight.html as f64 / 1024.0, weight.assets.len()
+            ));
+        }
+        log!("{message}");
+    }
+
+    for weight in &weights {
+        if let Some(max) = config.max_page_weight_kb && weight.total() > max * 1024 {
+            let mut message = format!(
+                "warning: page `{}` weighs {:.1} KiB, exceeding the budget of {} KiB:\n    {:>8.1} KiB  (html)",
+                weight.page, weight.total() as f64 / 1024.0, max, weight.html as f64 / 1024.0
+            );
+            for (asset, size) in &weight.assets {
+                message.push_str(&format!("\n    {:>8.1} KiB  {}", *size as f64 / 1024.0, asset));
+            }
+            log!("{message}");
+        }
+
+        if let Some(max) = config.max_asset_kb {
+            for (asset, &size) in &weight.assets {
+                if size <= max * 1024 { continue }
+                log!(
+                    "warning: asset `{}` used by page `{}` weighs {:.1} KiB, exceeding the budget of {} KiB",
+                    asset, weight.page, size as f64 / 1024.0, max
+                );
+            }
+        }
+    }
+}